@@ -0,0 +1,103 @@
+//! Measures how much Huffman table building costs the decoder.  The tables
+//! are rebuilt for every block, so decoding the same data split into smaller
+//! blocks spends more of its time building tables.
+//!
+//! Run with `cargo bench --bench tables`.
+
+extern crate lzxd;
+
+use lzxd::{DecoderBuilder, EncoderBuilder};
+use std::cell::Cell;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// ========================================================================= //
+
+const DATA_SIZE: usize = 1 << 22;
+const WINDOW: u16 = 16;
+const NUM_RUNS: usize = 5;
+
+/// Generates text-like data with a skewed mix of symbols, so that the main
+/// tree has a long tail of rare symbols with long codes.
+fn test_data() -> Vec<u8> {
+    let mut state = 0x2545f491u32;
+    let mut data = Vec::with_capacity(DATA_SIZE);
+    while data.len() < DATA_SIZE {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let byte = (state % 251) as u8;
+        let run = (state >> 24) as usize % (1 + byte as usize / 16);
+        data.push(byte.wrapping_mul(byte) >> 1);
+        for _ in 0..run {
+            let back = 1 + (state >> 8) as usize % 40;
+            let byte = data[data.len().saturating_sub(back)];
+            data.push(byte);
+        }
+    }
+    data.truncate(DATA_SIZE);
+    data
+}
+
+fn decode(builder: &DecoderBuilder, stream: &[u8], output: &mut [u8])
+          -> (Duration, usize) {
+    let num_blocks = Rc::new(Cell::new(0));
+    let counter = num_blocks.clone();
+    let mut decoder = builder.build(stream).unwrap();
+    decoder.set_block_callback(move |_| counter.set(counter.get() + 1));
+    let start = Instant::now();
+    decoder.read_exact(output).unwrap();
+    (start.elapsed(), num_blocks.get())
+}
+
+fn main() {
+    let data = test_data();
+    let mut output = vec![0u8; data.len()];
+    let mut baseline: Option<(Duration, usize)> = None;
+    println!("{:>10} {:>8} {:>10} {:>14}",
+             "chunk size",
+             "blocks",
+             "MB/s",
+             "us per block");
+    for &chunk_size in &[32768, 4096, 1024, 256] {
+        let mut encoder =
+            EncoderBuilder::new(WINDOW)
+                .uncompressed_size(data.len() as u64)
+                .chunk_size(chunk_size)
+                .build(Vec::new())
+                .unwrap();
+        encoder.write_all(&data).unwrap();
+        let stream = encoder.into_inner().unwrap();
+        let builder = DecoderBuilder::new(WINDOW, data.len() as u64)
+            .chunk_size(chunk_size);
+        let (elapsed, num_blocks) = (0..NUM_RUNS)
+            .map(|_| decode(&builder, &stream, &mut output))
+            .min()
+            .unwrap();
+        assert!(output == data);
+        let megabytes_per_sec =
+            data.len() as f64 / elapsed.as_secs_f64() / 1e6;
+        // Compared with the largest blocks, the extra time is mostly spent
+        // building tables for the extra blocks.
+        let micros_per_block = match baseline {
+            Some((base_elapsed, base_blocks)) if num_blocks > base_blocks => {
+                let extra = elapsed.as_secs_f64() -
+                    base_elapsed.as_secs_f64();
+                format!("{:.2}",
+                        1e6 * extra / (num_blocks - base_blocks) as f64)
+            }
+            _ => "-".to_string(),
+        };
+        println!("{:>10} {:>8} {:>10.1} {:>14}",
+                 chunk_size,
+                 num_blocks,
+                 megabytes_per_sec,
+                 micros_per_block);
+        if baseline.is_none() {
+            baseline = Some((elapsed, num_blocks));
+        }
+    }
+}
+
+// ========================================================================= //
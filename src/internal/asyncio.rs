@@ -0,0 +1,565 @@
+#[cfg(feature = "futures")]
+use futures_io;
+use internal::builder::{DecoderBuilder, EncoderBuilder};
+use internal::encoder::Encoder;
+use internal::error::Error;
+use internal::sink::DecodeSink;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+#[cfg(feature = "tokio")]
+use tokio;
+
+// ========================================================================= //
+
+const INPUT_BUFFER_SIZE: usize = 0x2000;
+
+// ========================================================================= //
+
+/// An asynchronous LZXD decoder, which reads compressed data from an
+/// underlying asynchronous reader and produces the decompressed data via the
+/// same kind of trait.
+///
+/// With the `futures` feature, this implements `futures::io::AsyncRead` for
+/// readers that do; with the `tokio` feature, it implements
+/// `tokio::io::AsyncRead` for readers that do.
+pub struct AsyncDecoder<R> {
+    reader: R,
+    sink: DecodeSink<Vec<u8>>,
+    input: Vec<u8>,
+    output_start: usize,
+}
+
+impl<R> AsyncDecoder<R> {
+    /// Starts decoding an LZXD stream read from `reader`.  The `window` and
+    /// `uncompressed_size` arguments are the same as for `Decoder::new`.
+    pub fn new(reader: R, window: u16, uncompressed_size: u64)
+               -> AsyncDecoder<R> {
+        let builder = DecoderBuilder::new(window, uncompressed_size);
+        AsyncDecoder::with_builder(reader, builder)
+    }
+
+    /// Like `AsyncDecoder::new`, but creates the decoder with the given
+    /// settings.
+    pub fn with_builder(reader: R, builder: DecoderBuilder)
+                        -> AsyncDecoder<R> {
+        AsyncDecoder {
+            reader,
+            sink: DecodeSink::with_builder(Vec::new(), builder),
+            input: vec![0u8; INPUT_BUFFER_SIZE],
+            output_start: 0,
+        }
+    }
+
+    /// Unwraps this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R { self.reader }
+
+    /// Reads decompressed data into `buf`, using `poll_input` to read
+    /// compressed data from the underlying reader as needed.
+    fn poll_read_with<F>(&mut self, cx: &mut Context, buf: &mut [u8],
+                         mut poll_input: F)
+                         -> Poll<io::Result<usize>>
+        where F: FnMut(Pin<&mut R>, &mut Context, &mut [u8])
+                       -> Poll<io::Result<usize>>,
+              R: Unpin
+    {
+        loop {
+            let output = self.sink.get_mut();
+            if self.output_start < output.len() {
+                let output = &output[self.output_start..];
+                let num_bytes = output.len().min(buf.len());
+                buf[..num_bytes].copy_from_slice(&output[..num_bytes]);
+                self.output_start += num_bytes;
+                return Poll::Ready(Ok(num_bytes));
+            }
+            output.clear();
+            self.output_start = 0;
+            if self.sink.is_finished() || buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let reader = Pin::new(&mut self.reader);
+            match poll_input(reader, cx, &mut self.input) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(Error::Truncated.into()));
+                }
+                Poll::Ready(Ok(num_bytes)) => {
+                    self.sink.write_all(&self.input[..num_bytes])?;
+                }
+                Poll::Ready(Err(ref error))
+                    if error.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<R> futures_io::AsyncRead for AsyncDecoder<R>
+    where R: futures_io::AsyncRead + Unpin
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8])
+                 -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_read_with(cx, buf, |reader, cx, input| {
+                reader.poll_read(cx, input)
+            })
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R> tokio::io::AsyncRead for AsyncDecoder<R>
+    where R: tokio::io::AsyncRead + Unpin
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context,
+                 buf: &mut tokio::io::ReadBuf)
+                 -> Poll<io::Result<()>> {
+        let poll_input = |reader: Pin<&mut R>,
+                          cx: &mut Context,
+                          input: &mut [u8]| {
+            let mut input = tokio::io::ReadBuf::new(input);
+            match reader.poll_read(cx, &mut input) {
+                Poll::Ready(Ok(())) => Poll::Ready(Ok(input.filled().len())),
+                Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+                Poll::Pending => Poll::Pending,
+            }
+        };
+        let result = self.get_mut()
+            .poll_read_with(cx, buf.initialize_unfilled(), poll_input);
+        match result {
+            Poll::Ready(Ok(num_bytes)) => {
+                buf.advance(num_bytes);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// An asynchronous LZXD encoder, which accepts uncompressed data via an
+/// asynchronous writer trait and writes the compressed data to an underlying
+/// writer of the same kind.
+///
+/// With the `futures` feature, this implements `futures::io::AsyncWrite` for
+/// writers that do; with the `tokio` feature, it implements
+/// `tokio::io::AsyncWrite` for writers that do.  Closing (or shutting down)
+/// the encoder finishes the compressed stream (as with `Encoder::finish`)
+/// before closing the underlying writer.
+pub struct AsyncEncoder<W> {
+    writer: W,
+    encoder: Encoder<Vec<u8>>,
+    output_start: usize,
+    finished: bool,
+}
+
+impl<W> AsyncEncoder<W> {
+    /// Starts encoding a stream of exactly `uncompressed_size` bytes, writing
+    /// the compressed data to `writer`.  The `window` argument is the same as
+    /// for `Encoder::new`.
+    pub fn new(writer: W, window: u16, uncompressed_size: u64)
+               -> io::Result<AsyncEncoder<W>> {
+        let builder =
+            EncoderBuilder::new(window).uncompressed_size(uncompressed_size);
+        AsyncEncoder::with_builder(writer, &builder)
+    }
+
+    /// Like `AsyncEncoder::new`, but creates the encoder with the given
+    /// settings.  If the builder doesn't declare an uncompressed size, the
+    /// stream ends when the encoder is closed.
+    pub fn with_builder(writer: W, builder: &EncoderBuilder)
+                        -> io::Result<AsyncEncoder<W>> {
+        Ok(AsyncEncoder {
+               writer,
+               encoder: builder.build(Vec::new())?,
+               output_start: 0,
+               finished: false,
+           })
+    }
+
+    /// Returns the number of uncompressed bytes written so far.
+    pub fn uncompressed_bytes_written(&self) -> u64 {
+        self.encoder.uncompressed_bytes_written()
+    }
+
+    /// Unwraps this encoder, returning the underlying writer.  Any compressed
+    /// data not yet written to it is lost, so close the encoder first.
+    pub fn into_inner(self) -> W { self.writer }
+
+    /// Writes as much buffered compressed data as possible to the underlying
+    /// writer using `poll_output`, returning `Ready` once all of it has been
+    /// written.
+    fn poll_write_output<F>(&mut self, cx: &mut Context, mut poll_output: F)
+                            -> Poll<io::Result<()>>
+        where F: FnMut(Pin<&mut W>, &mut Context, &[u8])
+                       -> Poll<io::Result<usize>>,
+              W: Unpin
+    {
+        loop {
+            let output = self.encoder.get_mut();
+            if self.output_start == output.len() {
+                output.clear();
+                self.output_start = 0;
+                return Poll::Ready(Ok(()));
+            }
+            let writer = Pin::new(&mut self.writer);
+            match poll_output(writer, cx, &output[self.output_start..]) {
+                Poll::Ready(Ok(0)) => {
+                    let error = io::Error::from(io::ErrorKind::WriteZero);
+                    return Poll::Ready(Err(error));
+                }
+                Poll::Ready(Ok(num_bytes)) => self.output_start += num_bytes,
+                Poll::Ready(Err(ref error))
+                    if error.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Encodes data from `buf`, once all previously-buffered compressed data
+    /// has been written.
+    fn poll_write_with<F>(&mut self, cx: &mut Context, buf: &[u8],
+                          poll_output: F)
+                          -> Poll<io::Result<usize>>
+        where F: FnMut(Pin<&mut W>, &mut Context, &[u8])
+                       -> Poll<io::Result<usize>>,
+              W: Unpin
+    {
+        match self.poll_write_output(cx, poll_output) {
+            Poll::Ready(Ok(())) => Poll::Ready(self.encoder.write(buf)),
+            Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Finishes the compressed stream, and writes out all of the remaining
+    /// compressed data.
+    fn poll_finish_with<F>(&mut self, cx: &mut Context, poll_output: F)
+                           -> Poll<io::Result<()>>
+        where F: FnMut(Pin<&mut W>, &mut Context, &[u8])
+                       -> Poll<io::Result<usize>>,
+              W: Unpin
+    {
+        if !self.finished {
+            self.encoder.finish()?;
+            self.finished = true;
+        }
+        self.poll_write_output(cx, poll_output)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<W> futures_io::AsyncWrite for AsyncEncoder<W>
+    where W: futures_io::AsyncWrite + Unpin
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8])
+                  -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_write_with(cx, buf, |writer, cx, output| {
+                writer.poll_write(cx, output)
+            })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context)
+                  -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = this.poll_write_output(cx, |writer, cx, output| {
+            writer.poll_write(cx, output)
+        });
+        match result {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.writer).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context)
+                  -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = this.poll_finish_with(cx, |writer, cx, output| {
+            writer.poll_write(cx, output)
+        });
+        match result {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.writer).poll_close(cx),
+            other => other,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<W> tokio::io::AsyncWrite for AsyncEncoder<W>
+    where W: tokio::io::AsyncWrite + Unpin
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8])
+                  -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_write_with(cx, buf, |writer, cx, output| {
+                writer.poll_write(cx, output)
+            })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context)
+                  -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = this.poll_write_output(cx, |writer, cx, output| {
+            writer.poll_write(cx, output)
+        });
+        match result {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.writer).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context)
+                     -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = this.poll_finish_with(cx, |writer, cx, output| {
+            writer.poll_write(cx, output)
+        });
+        match result {
+            Poll::Ready(Ok(())) => {
+                Pin::new(&mut this.writer).poll_shutdown(cx)
+            }
+            other => other,
+        }
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncDecoder, AsyncEncoder};
+    #[cfg(feature = "futures")]
+    use futures_io;
+    use internal::encoder::compress;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+
+    /// A reader or writer that alternates between returning `Pending` and
+    /// transferring a few bytes.
+    struct Sluggish<T> {
+        inner: T,
+        pending: bool,
+    }
+
+    impl<T> Sluggish<T> {
+        fn new(inner: T) -> Sluggish<T> {
+            Sluggish {
+                inner,
+                pending: false,
+            }
+        }
+
+        fn poll_ready(&mut self, cx: &mut Context) -> Poll<()> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        }
+    }
+
+    impl Sluggish<&[u8]> {
+        fn poll_read_bytes(&mut self, cx: &mut Context, buf: &mut [u8])
+                           -> Poll<io::Result<usize>> {
+            if self.poll_ready(cx).is_pending() {
+                return Poll::Pending;
+            }
+            let num_bytes = buf.len().min(self.inner.len()).min(9);
+            buf[..num_bytes].copy_from_slice(&self.inner[..num_bytes]);
+            self.inner = &self.inner[num_bytes..];
+            Poll::Ready(Ok(num_bytes))
+        }
+    }
+
+    impl Sluggish<Vec<u8>> {
+        fn poll_write_bytes(&mut self, cx: &mut Context, buf: &[u8])
+                            -> Poll<io::Result<usize>> {
+            if self.poll_ready(cx).is_pending() {
+                return Poll::Pending;
+            }
+            let num_bytes = buf.len().min(9);
+            self.inner.extend_from_slice(&buf[..num_bytes]);
+            Poll::Ready(Ok(num_bytes))
+        }
+    }
+
+    #[cfg(feature = "futures")]
+    impl futures_io::AsyncRead for Sluggish<&[u8]> {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8])
+                     -> Poll<io::Result<usize>> {
+            self.get_mut().poll_read_bytes(cx, buf)
+        }
+    }
+
+    #[cfg(feature = "futures")]
+    impl futures_io::AsyncWrite for Sluggish<Vec<u8>> {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8])
+                      -> Poll<io::Result<usize>> {
+            self.get_mut().poll_write_bytes(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context)
+                      -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context)
+                      -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    impl tokio::io::AsyncRead for Sluggish<&[u8]> {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context,
+                     buf: &mut tokio::io::ReadBuf)
+                     -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            match this.poll_read_bytes(cx, buf.initialize_unfilled()) {
+                Poll::Ready(Ok(num_bytes)) => {
+                    buf.advance(num_bytes);
+                    Poll::Ready(Ok(()))
+                }
+                Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+                Poll::Pending => Poll::Pending,
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    impl tokio::io::AsyncWrite for Sluggish<Vec<u8>> {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8])
+                      -> Poll<io::Result<usize>> {
+            self.get_mut().poll_write_bytes(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context)
+                      -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context)
+                         -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Polls `poll` until it's ready, as a minimal executor would.
+    fn block_on<T, F>(mut poll: F) -> T
+        where F: FnMut(&mut Context) -> Poll<T>
+    {
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(value) = poll(&mut cx) {
+                return value;
+            }
+        }
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn async_round_trip() {
+        use futures_io::{AsyncRead, AsyncWrite};
+        let data = b"Compressing asynchronously. ".repeat(3000);
+        let mut encoder =
+            AsyncEncoder::new(Sluggish::new(Vec::new()), 16, data.len() as u64)
+                .unwrap();
+        let mut input = data.as_slice();
+        while !input.is_empty() {
+            let piece = &input[..input.len().min(1000)];
+            let num_bytes =
+                block_on(|cx| Pin::new(&mut encoder).poll_write(cx, piece))
+                    .unwrap();
+            input = &input[num_bytes..];
+        }
+        block_on(|cx| Pin::new(&mut encoder).poll_close(cx)).unwrap();
+        let compressed = encoder.into_inner().inner;
+        assert_eq!(compressed, compress(&data, 16).unwrap());
+
+        let mut decoder = AsyncDecoder::new(Sluggish::new(&compressed[..]),
+                                            16,
+                                            data.len() as u64);
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 777];
+        loop {
+            let num_bytes = block_on(|cx| {
+                                         Pin::new(&mut decoder)
+                                             .poll_read(cx, &mut buffer)
+                                     })
+                .unwrap();
+            if num_bytes == 0 {
+                break;
+            }
+            output.extend_from_slice(&buffer[..num_bytes]);
+        }
+        assert!(output == data);
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn async_truncated_stream() {
+        use futures_io::AsyncRead;
+        let data = b"Not all here. ".repeat(5000);
+        let compressed = compress(&data, 15).unwrap();
+        let truncated = &compressed[..(compressed.len() / 2)];
+        let mut decoder =
+            AsyncDecoder::new(Sluggish::new(truncated), 15, data.len() as u64);
+        let mut buffer = vec![0u8; data.len()];
+        let error = loop {
+            let result = block_on(|cx| {
+                                      Pin::new(&mut decoder)
+                                          .poll_read(cx, &mut buffer)
+                                  });
+            if let Err(error) = result {
+                break error;
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_round_trip() {
+        use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+        let data = b"Compressing with tokio. ".repeat(3000);
+        let mut encoder =
+            AsyncEncoder::new(Sluggish::new(Vec::new()), 16, data.len() as u64)
+                .unwrap();
+        let mut input = data.as_slice();
+        while !input.is_empty() {
+            let piece = &input[..input.len().min(1000)];
+            let num_bytes =
+                block_on(|cx| Pin::new(&mut encoder).poll_write(cx, piece))
+                    .unwrap();
+            input = &input[num_bytes..];
+        }
+        block_on(|cx| Pin::new(&mut encoder).poll_shutdown(cx)).unwrap();
+        let compressed = encoder.into_inner().inner;
+        assert_eq!(compressed, compress(&data, 16).unwrap());
+
+        let mut decoder = AsyncDecoder::new(Sluggish::new(&compressed[..]),
+                                            16,
+                                            data.len() as u64);
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 777];
+        loop {
+            let mut buf = ReadBuf::new(&mut buffer);
+            block_on(|cx| Pin::new(&mut decoder).poll_read(cx, &mut buf))
+                .unwrap();
+            if buf.filled().is_empty() {
+                break;
+            }
+            output.extend_from_slice(buf.filled());
+        }
+        assert!(output == data);
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,191 @@
+//! Decoding an LZXD stream on a background thread, so that the work of
+//! decompressing each chunk overlaps with whatever the caller does with the
+//! previous one (such as writing it to disk).
+
+use internal::builder::DecoderBuilder;
+use internal::decoder::Decoder;
+use std::io::{self, BufRead, Read};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+
+// ========================================================================= //
+
+/// The number of decoded chunks that the background thread may have waiting
+/// in the channel, in addition to the one it is decoding and the one the
+/// caller is reading.
+const READY_CHUNKS: usize = 1;
+
+// ========================================================================= //
+
+/// An LZXD decoder that decompresses on a background thread, and provides
+/// the decompressed data via the `Read` and `BufRead` traits.
+///
+/// Decoded chunks are passed from the background thread through a bounded
+/// channel, so the thread decodes at most a couple of chunks ahead of the
+/// caller; buffers that the caller has finished with are sent back to be
+/// reused.  Dropping the `BackgroundDecoder` stops the thread once its
+/// current chunk is done.
+pub struct BackgroundDecoder {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    spent: Sender<Vec<u8>>,
+    buffer: Vec<u8>,
+    output_start: usize,
+    finished: bool,
+}
+
+impl BackgroundDecoder {
+    /// Starts a background thread that decodes the compressed data read from
+    /// `reader` with the settings of `builder`.  The decoder is created on
+    /// that thread (since a `Decoder` can't be sent between threads), but any
+    /// error from `DecoderBuilder::build` is still returned here; errors
+    /// later in the stream are returned by reads.
+    pub fn new<R>(builder: &DecoderBuilder, reader: R)
+                  -> io::Result<BackgroundDecoder>
+        where R: Read + Send + 'static
+    {
+        let builder = builder.clone();
+        let (started_sender, started) = mpsc::channel();
+        let (chunk_sender, chunks) = mpsc::sync_channel(READY_CHUNKS);
+        let (spent, spent_receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let decoder = match builder.build(reader) {
+                Ok(decoder) => decoder,
+                Err(error) => {
+                    let _ = started_sender.send(Err(error));
+                    return;
+                }
+            };
+            if started_sender.send(Ok(())).is_ok() {
+                decode_chunks(decoder, &chunk_sender, &spent_receiver);
+            }
+        });
+        match started.recv() {
+            Ok(result) => result?,
+            Err(_) => return Err(thread_stopped()),
+        }
+        Ok(BackgroundDecoder {
+               chunks,
+               spent,
+               buffer: Vec::new(),
+               output_start: 0,
+               finished: false,
+           })
+    }
+}
+
+impl Read for BackgroundDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = {
+            let output = self.fill_buf()?;
+            let num_bytes = output.len().min(buf.len());
+            buf[..num_bytes].copy_from_slice(&output[..num_bytes]);
+            num_bytes
+        };
+        self.consume(num_bytes);
+        Ok(num_bytes)
+    }
+}
+
+impl BufRead for BackgroundDecoder {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.output_start == self.buffer.len() && !self.finished {
+            match self.chunks.recv() {
+                Ok(Ok(chunk)) => {
+                    self.finished = chunk.is_empty();
+                    let spent = mem::replace(&mut self.buffer, chunk);
+                    let _ = self.spent.send(spent);
+                    self.output_start = 0;
+                }
+                Ok(Err(error)) => return Err(error),
+                Err(_) => return Err(thread_stopped()),
+            }
+        }
+        Ok(&self.buffer[self.output_start..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.output_start =
+            self.buffer.len().min(self.output_start + amount);
+    }
+}
+
+/// The main loop of the background thread: decodes one chunk at a time into
+/// a reused buffer and sends it to the `BackgroundDecoder`, ending with an
+/// empty buffer at the end of the stream, or with the first error.
+fn decode_chunks<R: Read>(mut decoder: Decoder<R>,
+                          chunks: &SyncSender<io::Result<Vec<u8>>>,
+                          spent: &Receiver<Vec<u8>>) {
+    loop {
+        let mut buffer = spent.try_recv().unwrap_or_default();
+        buffer.clear();
+        let num_bytes = match decoder.fill_buf() {
+            Ok(chunk) => {
+                buffer.extend_from_slice(chunk);
+                chunk.len()
+            }
+            Err(error) => {
+                let _ = chunks.send(Err(error));
+                return;
+            }
+        };
+        decoder.consume(num_bytes);
+        if chunks.send(Ok(buffer)).is_err() || num_bytes == 0 {
+            return;
+        }
+    }
+}
+
+/// The error returned if the background thread stops without reporting the
+/// end of the stream (that is, if it panics).
+fn thread_stopped() -> io::Error {
+    io::Error::other("LZX background decoding thread stopped")
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::BackgroundDecoder;
+    use internal::builder::DecoderBuilder;
+    use internal::encoder::compress;
+    use std::io::{self, BufRead, Read};
+
+    #[test]
+    fn decode_in_background() {
+        let data: Vec<u8> = (0..200_000u32)
+            .map(|n| (n / 7 + n / 1000) as u8)
+            .collect();
+        let compressed = compress(&data, 16).unwrap();
+        let builder = DecoderBuilder::new(16, data.len() as u64);
+        let mut decoder =
+            BackgroundDecoder::new(&builder, io::Cursor::new(compressed))
+                .unwrap();
+        assert_eq!(decoder.fill_buf().unwrap().len(), 0x8000);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+        assert_eq!(decoder.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn background_errors() {
+        let builder = DecoderBuilder::new(40, 100);
+        let error = BackgroundDecoder::new(&builder, io::empty())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let data = vec![0x55u8; 100_000];
+        let compressed = compress(&data, 15).unwrap();
+        let truncated = compressed[..(compressed.len() / 2)].to_vec();
+        let builder = DecoderBuilder::new(15, data.len() as u64);
+        let mut decoder =
+            BackgroundDecoder::new(&builder, io::Cursor::new(truncated))
+                .unwrap();
+        let mut output = Vec::new();
+        assert!(decoder.read_to_end(&mut output).is_err());
+        assert!(output.len() < data.len());
+    }
+}
+
+// ========================================================================= //
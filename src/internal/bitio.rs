@@ -0,0 +1,158 @@
+//! Bit-level I/O in the style used by LZXD and related Microsoft formats.
+//!
+//! Bits are packed most-significant-first into 16-bit little-endian words.
+//! Reads and writes of up to 32 bits at a time are supported; asking for
+//! more is an `InvalidInput` error rather than a panic.
+
+use internal::bits;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+const MAX_BITS: u16 = 32;
+
+fn check_num_bits(num_bits: u16) -> io::Result<()> {
+    if num_bits > MAX_BITS {
+        invalid_input!("Cannot transfer {} bits at once (at most {})",
+                       num_bits,
+                       MAX_BITS);
+    }
+    Ok(())
+}
+
+// ========================================================================= //
+
+/// Reads a bitstream of 16-bit little-endian words.
+pub struct BitReader<R: Read> {
+    inner: bits::BitReader<R>,
+}
+
+impl<R: Read> BitReader<R> {
+    /// Creates a bit reader that reads from the start of `reader`.
+    pub fn new(reader: R) -> BitReader<R> {
+        BitReader { inner: bits::BitReader::new(reader) }
+    }
+
+    /// Reads the next `num_bits` bits (at most 32) as an integer, most
+    /// significant bit first.
+    pub fn read_bits(&mut self, num_bits: u16) -> io::Result<u32> {
+        check_num_bits(num_bits)?;
+        self.inner.read_bits(num_bits)
+    }
+
+    /// Returns the next `num_bits` bits (at most 32) without consuming them.
+    /// Bits past the end of the underlying reader are returned as zeros.
+    pub fn peek_bits(&mut self, num_bits: u16) -> io::Result<u32> {
+        check_num_bits(num_bits)?;
+        if num_bits == 0 {
+            return Ok(0);
+        }
+        self.inner.peek_bits(num_bits)
+    }
+
+    /// Consumes the next `num_bits` bits (at most 32), typically after
+    /// looking at them with `peek_bits()`.
+    pub fn skip_bits(&mut self, num_bits: u16) -> io::Result<()> {
+        check_num_bits(num_bits)?;
+        self.inner.skip_bits(num_bits)
+    }
+
+    /// Skips any remaining bits in the current 16-bit word.
+    pub fn align_to_16(&mut self) -> io::Result<()> {
+        self.inner.align_to_16()
+    }
+
+    /// Discards any bits remaining in the current 16-bit word, and returns
+    /// the underlying reader.
+    pub fn into_inner(self) -> io::Result<R> { self.inner.into_inner() }
+}
+
+// ========================================================================= //
+
+/// Writes a bitstream of 16-bit little-endian words.
+pub struct BitWriter<W: Write> {
+    inner: bits::BitWriter<W>,
+}
+
+impl<W: Write> BitWriter<W> {
+    /// Creates a bit writer that writes to `writer`.
+    pub fn new(writer: W) -> BitWriter<W> {
+        BitWriter { inner: bits::BitWriter::new(writer) }
+    }
+
+    /// Writes the low `num_bits` bits (at most 32) of `bits`, most significant
+    /// bit first.  Any higher bits of `bits` must be zero.
+    pub fn write_bits(&mut self, num_bits: u16, bits: u32) -> io::Result<()> {
+        check_num_bits(num_bits)?;
+        if num_bits < MAX_BITS && bits >> num_bits != 0 {
+            invalid_input!("Value {:#x} does not fit in {} bits",
+                           bits,
+                           num_bits);
+        }
+        self.inner.write_bits(num_bits, bits)
+    }
+
+    /// Pads the current 16-bit word with zero bits.
+    pub fn align_to_16(&mut self) -> io::Result<()> {
+        self.inner.align_to_16()
+    }
+
+    /// Aligns the output to a 16-bit boundary, and returns the underlying
+    /// writer.
+    pub fn into_inner(self) -> io::Result<W> { self.inner.into_inner() }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{BitReader, BitWriter};
+    use std::io;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(3, 0b101).unwrap();
+        writer.write_bits(32, 0xdeadbeef).unwrap();
+        writer.align_to_16().unwrap();
+        writer.write_bits(0, 0).unwrap();
+        writer.write_bits(7, 0x55).unwrap();
+        let output = writer.into_inner().unwrap();
+        assert_eq!(output.len(), 8);
+        let mut reader = BitReader::new(output.as_slice());
+        assert_eq!(reader.peek_bits(0).unwrap(), 0);
+        assert_eq!(reader.peek_bits(3).unwrap(), 0b101);
+        reader.skip_bits(3).unwrap();
+        assert_eq!(reader.read_bits(32).unwrap(), 0xdeadbeef);
+        reader.align_to_16().unwrap();
+        assert_eq!(reader.read_bits(7).unwrap(), 0x55);
+        assert_eq!(reader.peek_bits(32).unwrap(), 0);
+        assert!(reader.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn too_many_bits() {
+        let mut writer = BitWriter::new(Vec::new());
+        let error = writer.write_bits(40, 0).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(),
+                   "Cannot transfer 40 bits at once (at most 32)");
+        let error = writer.write_bits(4, 0x10).unwrap_err();
+        assert_eq!(error.to_string(), "Value 0x10 does not fit in 4 bits");
+        assert!(writer.into_inner().unwrap().is_empty());
+
+        let data = [0xffu8; 16];
+        let mut reader = BitReader::new(&data[..]);
+        for &num_bits in &[33, 48, 60, u16::MAX] {
+            let error = reader.read_bits(num_bits).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            let error = reader.peek_bits(num_bits).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            let error = reader.skip_bits(num_bits).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(reader.read_bits(32).unwrap(), 0xffffffff);
+    }
+}
+
+// ========================================================================= //
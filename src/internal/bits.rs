@@ -1,37 +1,236 @@
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{self, Read, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 
 // ========================================================================= //
 
+/// A saved position within a bitstream being read by a `BitReader`,
+/// including any bits that have been buffered but not yet consumed.
+#[derive(Clone, Copy, Debug)]
+pub struct BitPosition {
+    bytes_read: u64,
+    bit_buffer: u64,
+    bits_in_buffer: u16,
+    bits_mod_16: u16,
+    extra_byte: bool,
+}
+
+impl BitPosition {
+    /// Returns the number of bits consumed before this position.
+    pub fn bit_offset(&self) -> u64 {
+        8 * self.bytes_read - self.bits_in_buffer as u64
+    }
+}
+
+// ========================================================================= //
+
+/// The bytes read from the underlying reader of a `BitReader` since its
+/// rewind point was set, so that they can be read again after rewinding.
+#[derive(Clone)]
+struct Journal {
+    rewind_point: Option<BitPosition>,
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl Journal {
+    /// Discards the bytes that have already been read (or replayed).
+    fn discard_read_bytes(&mut self) {
+        self.bytes.drain(..self.position);
+        self.position = 0;
+    }
+}
+
+// ========================================================================= //
+
+/// Reads a bitstream of 16-bit little-endian words.
+#[derive(Clone)]
 pub struct BitReader<R: Read> {
     reader: R,
+    bytes_read: u64,
     bit_buffer: u64,
     bits_in_buffer: u16,
     bits_mod_16: u16,
+    extra_byte: bool,
+    peek_limit: u64,
+    journal: Option<Box<Journal>>,
 }
 
 impl<R: Read> BitReader<R> {
+    /// Creates a bit reader that reads from the start of `reader`.
     pub fn new(reader: R) -> BitReader<R> {
         BitReader {
-            reader: reader,
+            reader,
+            bytes_read: 0,
             bit_buffer: 0,
             bits_in_buffer: 0,
             bits_mod_16: 0,
+            extra_byte: false,
+            peek_limit: u64::MAX,
+            journal: None,
+        }
+    }
+
+    /// Returns the number of bits consumed from the underlying reader so
+    /// far (not counting bits that have been buffered but not yet read).
+    pub fn bit_position(&self) -> u64 {
+        8 * self.bytes_read - self.bits_in_buffer as u64
+    }
+
+    /// Returns the current position, which can later be returned to with
+    /// `restore_position()`.
+    pub fn save_position(&self) -> BitPosition {
+        BitPosition {
+            bytes_read: self.bytes_read,
+            bit_buffer: self.bit_buffer,
+            bits_in_buffer: self.bits_in_buffer,
+            bits_mod_16: self.bits_mod_16,
+            extra_byte: self.extra_byte,
+        }
+    }
+
+    /// Marks the current position as one that `rewind()` can return to.
+    ///
+    /// From here on, bytes read from the underlying reader are kept until
+    /// the next call to `set_rewind_point()` or `clear_rewind_point()`, so
+    /// that no data is lost if an error from the underlying reader (such as
+    /// `WouldBlock`) interrupts a read partway through.
+    pub fn set_rewind_point(&mut self) {
+        let position = self.save_position();
+        let journal = self.journal.get_or_insert_with(|| {
+            Box::new(Journal {
+                         rewind_point: None,
+                         bytes: Vec::new(),
+                         position: 0,
+                     })
+        });
+        journal.discard_read_bytes();
+        journal.rewind_point = Some(position);
+    }
+
+    /// Forgets the rewind point set by `set_rewind_point()`, and stops
+    /// keeping the bytes read from the underlying reader.
+    pub fn clear_rewind_point(&mut self) {
+        let mut replay_finished = true;
+        if let Some(ref mut journal) = self.journal {
+            journal.discard_read_bytes();
+            journal.rewind_point = None;
+            replay_finished = journal.bytes.is_empty();
+        }
+        if replay_finished {
+            self.journal = None;
+        }
+    }
+
+    /// Returns to the position most recently marked with
+    /// `set_rewind_point()`, so that the same bits can be read again.  Does
+    /// nothing if no rewind point has been set.
+    pub fn rewind(&mut self) {
+        let rewind_point =
+            self.journal.as_ref().and_then(|journal| journal.rewind_point);
+        if let Some(position) = rewind_point {
+            self.set_position(&position);
+            if let Some(ref mut journal) = self.journal {
+                journal.position = 0;
+            }
         }
     }
 
-    fn ensure_buffer_has_at_least(&mut self, num_bits: u16) -> io::Result<()> {
+    fn set_position(&mut self, position: &BitPosition) {
+        self.bytes_read = position.bytes_read;
+        self.bit_buffer = position.bit_buffer;
+        self.bits_in_buffer = position.bits_in_buffer;
+        self.bits_mod_16 = position.bits_mod_16;
+        self.extra_byte = position.extra_byte;
+    }
+
+    /// Reads from the underlying reader, first replaying any bytes that were
+    /// read before the last call to `rewind()`.
+    fn read_raw(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let journal = match self.journal {
+            Some(ref mut journal) => journal,
+            None => return self.reader.read(buf),
+        };
+        if journal.position == journal.bytes.len() {
+            let num_bytes = self.reader.read(buf)?;
+            journal.bytes.extend_from_slice(&buf[..num_bytes]);
+            journal.position += num_bytes;
+            return Ok(num_bytes);
+        }
+        let replay = &journal.bytes[journal.position..];
+        let num_bytes = replay.len().min(buf.len());
+        buf[..num_bytes].copy_from_slice(&replay[..num_bytes]);
+        journal.position += num_bytes;
+        if journal.rewind_point.is_none() &&
+            journal.position == journal.bytes.len()
+        {
+            self.journal = None;
+        }
+        Ok(num_bytes)
+    }
+
+    fn read_exact_raw(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_raw(buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(num_bytes) => buf = &mut buf[num_bytes..],
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_word(&mut self) -> io::Result<u16> {
+        if self.journal.is_none() {
+            return self.reader.read_u16::<LittleEndian>();
+        }
+        let mut word = [0u8; 2];
+        self.read_exact_raw(&mut word)?;
+        Ok(u16::from_le_bytes(word))
+    }
+
+    fn skip_extra_byte(&mut self) -> io::Result<()> {
+        if self.extra_byte {
+            self.read_exact_raw(&mut [0u8])?;
+            self.bytes_read += 1;
+            self.extra_byte = false;
+        }
+        Ok(())
+    }
+
+    /// Makes sure that at least `num_bits` bits (at most 48) are buffered,
+    /// reading more from the underlying reader if needed.  Unlike
+    /// `peek_bits()`, this ignores the peek limit, and fails if the
+    /// underlying reader ends first.
+    pub fn ensure_buffer_has_at_least(&mut self, num_bits: u16)
+                                      -> io::Result<()> {
         debug_assert!(num_bits <= 48);
         if self.bits_in_buffer < num_bits {
-            let next = self.reader.read_u16::<LittleEndian>()? as u64;
+            self.refill(num_bits)?;
+        }
+        Ok(())
+    }
+
+    fn refill(&mut self, num_bits: u16) -> io::Result<()> {
+        self.skip_extra_byte()?;
+        while self.bits_in_buffer < num_bits {
+            let next = self.read_word()? as u64;
+            self.bytes_read += 2;
             self.bit_buffer |= next << (48 - self.bits_in_buffer);
             self.bits_in_buffer += 16;
         }
         Ok(())
     }
 
+    /// Reads the next `num_bits` bits (at most 32) as an integer, most
+    /// significant bit first.
+    #[inline(always)]
     pub fn read_bits(&mut self, num_bits: u16) -> io::Result<u32> {
         debug_assert!(num_bits <= 32);
+        if num_bits == 0 {
+            return Ok(0);
+        }
         self.ensure_buffer_has_at_least(num_bits)?;
         debug_assert!(self.bits_in_buffer >= num_bits);
         let bits = (self.bit_buffer >> (64 - num_bits)) as u32;
@@ -41,23 +240,115 @@ impl<R: Read> BitReader<R> {
         Ok(bits)
     }
 
-    #[allow(dead_code)]
-    fn peek_bits(&mut self, num_bits: u16) -> io::Result<u32> {
+    /// Returns true if the bitstream is at a 16-bit boundary with no more
+    /// data after it (that is, the underlying reader is at end-of-file).
+    /// Otherwise, the next 16-bit word is buffered.
+    pub fn is_at_eof(&mut self) -> io::Result<bool> {
+        if self.bits_in_buffer != 0 {
+            return Ok(false);
+        }
+        self.skip_extra_byte()?;
+        let mut word = [0u8; 2];
+        loop {
+            match self.read_raw(&mut word[..1]) {
+                Ok(0) => return Ok(true),
+                Ok(_) => break,
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        self.read_exact_raw(&mut word[1..])?;
+        self.bytes_read += 2;
+        self.bit_buffer = (u16::from_le_bytes(word) as u64) << 48;
+        self.bits_in_buffer = 16;
+        Ok(false)
+    }
+
+    /// Returns the next `num_bits` bits (at most 32) without consuming them.
+    /// Bits that can't be buffered, because the underlying reader ends first
+    /// or because they lie past the limit set with `set_peek_limit()`, are
+    /// returned as zeros; use `buffered_bits()` to tell how many of the
+    /// returned bits are real.
+    #[inline(always)]
+    pub fn peek_bits(&mut self, num_bits: u16) -> io::Result<u32> {
         debug_assert!(num_bits <= 32);
-        self.ensure_buffer_has_at_least(num_bits)?;
-        debug_assert!(self.bits_in_buffer >= num_bits);
+        if self.bits_in_buffer < num_bits {
+            let mut bytes_left =
+                self.peek_limit.saturating_sub(self.bytes_read);
+            if self.extra_byte {
+                bytes_left = bytes_left.saturating_sub(1);
+            }
+            // Buffer as many whole words as fit (and the limit allows), so
+            // that the next few peeks don't need to refill.
+            let words_left = (bytes_left / 2)
+                .min(((48 - self.bits_in_buffer) / 16) as u64) as u16;
+            let fill_bits = self.bits_in_buffer + 16 * words_left;
+            if self.bits_in_buffer < fill_bits {
+                match self.refill(fill_bits) {
+                    Ok(()) => {}
+                    Err(ref error)
+                        if error.kind() == io::ErrorKind::UnexpectedEof => {}
+                    Err(error) => return Err(error),
+                }
+            }
+        }
         Ok((self.bit_buffer >> (64 - num_bits)) as u32)
     }
 
+    /// Returns the number of bits that have been read from the underlying
+    /// reader but not yet consumed.
+    #[inline(always)]
+    pub fn buffered_bits(&self) -> u16 { self.bits_in_buffer }
+
+    /// Stops `peek_bits()` from reading past the given byte offset (measured
+    /// as for `seek_to_byte()`), so that data after the end of a chunk or
+    /// stream is left in the underlying reader.  `None` removes the limit.
+    pub fn set_peek_limit(&mut self, byte_offset: Option<u64>) {
+        self.peek_limit = byte_offset.unwrap_or(u64::MAX);
+    }
+
+    /// Consumes the next `num_bits` bits (at most 32), typically after
+    /// looking at them with `peek_bits()`.
+    #[inline(always)]
+    pub fn skip_bits(&mut self, num_bits: u16) -> io::Result<()> {
+        self.read_bits(num_bits)?;
+        Ok(())
+    }
+
+    /// Skips any remaining bits in the current 16-bit word (and any padding
+    /// byte after an odd-length run of raw bytes).
     pub fn align_to_16(&mut self) -> io::Result<()> {
         if self.bits_mod_16 != 0 {
             let bits_to_skip = 16 - self.bits_mod_16;
             self.read_bits(bits_to_skip)?;
         }
         debug_assert_eq!(self.bits_in_buffer & 0xf, 0);
+        if self.bits_in_buffer == 0 {
+            self.skip_extra_byte()?;
+        }
         Ok(())
     }
 
+    /// Discards any bits remaining in the current 16-bit word (along with
+    /// any padding byte after an odd-length run of raw bytes), and returns
+    /// the underlying reader.
+    pub fn into_inner(mut self) -> io::Result<R> {
+        self.align_to_16()?;
+        debug_assert_eq!(self.bits_in_buffer, 0);
+        Ok(self.reader)
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R { &self.reader }
+
+    /// Returns a mutable reference to the underlying reader.  Reading from it
+    /// directly will corrupt the bitstream, but appending to it is safe.
+    pub fn get_mut(&mut self) -> &mut R { &mut self.reader }
+
+    /// Returns the underlying reader, discarding any buffered bits.
+    pub fn into_inner_unaligned(self) -> R { self.reader }
+
     fn align_to_8(&mut self) -> io::Result<()> {
         let bits_mod_8 = self.bits_mod_16 & 0x7;
         if bits_mod_8 != 0 {
@@ -68,18 +359,68 @@ impl<R: Read> BitReader<R> {
     }
 }
 
+impl<R: Read + Seek> BitReader<R> {
+    /// Discards any buffered bits and moves to the given byte offset, which
+    /// is measured from where the underlying reader was positioned when this
+    /// bit reader was created.  The offset should be at a 16-bit boundary of
+    /// the bitstream.
+    pub fn seek_to_byte(&mut self, byte_offset: u64) -> io::Result<()> {
+        let delta = byte_offset as i64 - self.bytes_read as i64;
+        let delta = delta -
+            self.journal
+                .as_ref()
+                .map_or(0, |journal| journal.bytes.len() - journal.position) as
+                i64;
+        self.reader.seek(SeekFrom::Current(delta))?;
+        self.bytes_read = byte_offset;
+        self.bit_buffer = 0;
+        self.bits_in_buffer = 0;
+        self.bits_mod_16 = 0;
+        self.extra_byte = false;
+        self.journal = None;
+        Ok(())
+    }
+
+    /// Returns to a position previously saved with `save_position()`.
+    pub fn restore_position(&mut self, position: &BitPosition)
+                            -> io::Result<()> {
+        self.seek_to_byte(position.bytes_read)?;
+        self.set_position(position);
+        Ok(())
+    }
+}
+
 impl<R: Read> Read for BitReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.align_to_8()?;
         let mut bytes_read = 0;
         while self.bits_in_buffer != 0 && bytes_read < buf.len() {
             debug_assert!(self.bits_in_buffer >= 8);
+            if self.bits_mod_16 == 0 {
+                // Buffered 16-bit words are stored in bitstream order, so
+                // swap the bytes of the next word to read it as raw bytes.
+                let word = ((self.bit_buffer >> 48) as u16).swap_bytes();
+                self.bit_buffer = (self.bit_buffer & 0xffff_ffff_ffff) |
+                    ((word as u64) << 48);
+            }
             buf[bytes_read] = self.read_bits(8)? as u8;
+            bytes_read += 1;
         }
         if bytes_read < buf.len() {
-            let num_bytes = self.reader.read(&mut buf[bytes_read..])?;
+            // Bytes taken from the bit buffer above can't be put back, so
+            // don't discard them by returning an error.
+            let num_bytes = loop {
+                match self.read_raw(&mut buf[bytes_read..]) {
+                    Ok(num_bytes) => break num_bytes,
+                    Err(ref error)
+                        if error.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) if bytes_read > 0 => return Ok(bytes_read),
+                    Err(error) => return Err(error),
+                }
+            };
+            self.bytes_read += num_bytes as u64;
             if (num_bytes & 1) != 0 {
-                self.bits_mod_16 ^= 8;
+                self.extra_byte = !self.extra_byte;
             }
             bytes_read += num_bytes;
         }
@@ -89,6 +430,7 @@ impl<R: Read> Read for BitReader<R> {
 
 // ========================================================================= //
 
+/// Writes a bitstream of 16-bit little-endian words.
 pub struct BitWriter<W: Write> {
     writer: W,
     bit_buffer: u64,
@@ -97,9 +439,10 @@ pub struct BitWriter<W: Write> {
 }
 
 impl<W: Write> BitWriter<W> {
+    /// Creates a bit writer that writes to `writer`.
     pub fn new(writer: W) -> BitWriter<W> {
         BitWriter {
-            writer: writer,
+            writer,
             bit_buffer: 0,
             bits_in_buffer: 0,
             extra_byte: false,
@@ -124,11 +467,16 @@ impl<W: Write> BitWriter<W> {
         Ok(())
     }
 
+    /// Writes the low `num_bits` bits (at most 32) of `bits`, most significant
+    /// bit first.  Any higher bits of `bits` must be zero.
     pub fn write_bits(&mut self, num_bits: u16, bits: u32) -> io::Result<()> {
         self.fill_extra_byte()?;
         debug_assert!(num_bits <= 32);
         debug_assert!(self.bits_in_buffer < 16);
-        debug_assert_eq!(bits >> num_bits, 0);
+        debug_assert!(num_bits == 32 || bits >> num_bits == 0);
+        if num_bits == 0 {
+            return Ok(());
+        }
         self.bit_buffer |= (bits as u64) <<
             (64 - num_bits - self.bits_in_buffer);
         self.bits_in_buffer += num_bits;
@@ -141,11 +489,28 @@ impl<W: Write> BitWriter<W> {
         Ok(())
     }
 
+    /// Pads the current 16-bit word with zero bits (and writes any padding
+    /// byte needed after an odd-length run of raw bytes).
     pub fn align_to_16(&mut self) -> io::Result<()> {
         self.purge_bit_buffer()?;
         self.fill_extra_byte()?;
         Ok(())
     }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W { &self.writer }
+
+    /// Returns a mutable reference to the underlying writer.  Writing to it
+    /// directly will corrupt the bitstream, but taking data that has already
+    /// been written out of it is safe.
+    pub fn get_mut(&mut self) -> &mut W { &mut self.writer }
+
+    /// Aligns the output to a 16-bit boundary, and returns the underlying
+    /// writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.align_to_16()?;
+        Ok(self.writer)
+    }
 }
 
 impl<W: Write> Write for BitWriter<W> {
@@ -167,6 +532,8 @@ impl<W: Write> Write for BitWriter<W> {
 mod tests {
     use super::{BitReader, BitWriter};
     use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+    use internal::testutil::FlakyReader;
+    use std::io::{self, Read};
 
     #[test]
     fn bit_reader() {
@@ -180,6 +547,127 @@ mod tests {
         assert_eq!(reader.read_u16::<LittleEndian>().unwrap(), 0x1234);
     }
 
+    #[test]
+    fn bit_reader_position() {
+        let input: &[u8] = b"\xcd\xab\x80\x35\x34\x12\x00\x00";
+        let mut reader = BitReader::new(input);
+        assert_eq!(reader.bit_position(), 0);
+        reader.read_u16::<LittleEndian>().unwrap();
+        assert_eq!(reader.bit_position(), 16);
+        reader.read_bits(3).unwrap();
+        assert_eq!(reader.bit_position(), 19);
+        reader.read_bits(20).unwrap();
+        assert_eq!(reader.bit_position(), 39);
+        reader.align_to_16().unwrap();
+        assert_eq!(reader.bit_position(), 48);
+    }
+
+    #[test]
+    fn bit_reader_eof() {
+        let input: &[u8] = b"\xcd\xab\x34\x12";
+        let mut reader = BitReader::new(input);
+        assert!(!reader.is_at_eof().unwrap());
+        assert_eq!(reader.bit_position(), 0);
+        assert_eq!(reader.read_bits(16).unwrap(), 0xabcd);
+        assert_eq!(reader.read_bits(4).unwrap(), 0x1);
+        assert!(!reader.is_at_eof().unwrap());
+        assert_eq!(reader.read_bits(12).unwrap(), 0x234);
+        assert!(reader.is_at_eof().unwrap());
+        // A lone trailing byte is a truncated word.
+        let mut reader = BitReader::new(&b"\x01"[..]);
+        let error = reader.is_at_eof().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn bit_reader_wide_reads() {
+        let input: &[u8] = b"\x34\x12\x78\x56\x00\x80";
+        let mut reader = BitReader::new(input);
+        assert_eq!(reader.read_bits(0).unwrap(), 0);
+        assert_eq!(reader.read_bits(32).unwrap(), 0x12345678);
+        assert_eq!(reader.read_bits(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn bit_reader_peek_limit() {
+        let input: &[u8] = b"\x34\x12\x78\x56\xbc\x9a";
+        let mut reader = BitReader::new(input);
+        reader.set_peek_limit(Some(3));
+        assert_eq!(reader.peek_bits(20).unwrap(), 0x12340);
+        assert_eq!(reader.buffered_bits(), 16);
+        reader.set_peek_limit(None);
+        assert_eq!(reader.peek_bits(20).unwrap(), 0x12345);
+        // Peeking buffers as many words as it can.
+        assert_eq!(reader.buffered_bits(), 48);
+        assert_eq!(reader.read_bits(28).unwrap(), 0x1234567);
+        // Past the end of the input, peeked bits are zeros.
+        assert_eq!(reader.peek_bits(32).unwrap(), 0x89abc000);
+        assert_eq!(reader.buffered_bits(), 20);
+        assert_eq!(reader.read_bits(20).unwrap(), 0x89abc);
+        assert!(reader.skip_bits(1).is_err());
+    }
+
+    #[test]
+    fn bit_reader_raw_bytes_after_lookahead() {
+        let input: &[u8] = b"\x00\x80\x61\x62\x63\x00\x00\x80";
+        let mut reader = BitReader::new(input);
+        assert_eq!(reader.read_bits(1).unwrap(), 1);
+        reader.peek_bits(32).unwrap();
+        reader.align_to_16().unwrap();
+        let mut buffer = [0u8; 3];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"abc");
+        reader.align_to_16().unwrap();
+        assert_eq!(reader.read_bits(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn bit_reader_retries_interrupted_reads() {
+        let input: &[u8] = b"\x00\x80\x61\x62\x63\x64\x65\x66\x00\x80";
+        for &interrupt in &[false, true] {
+            let mut reader = BitReader::new(FlakyReader {
+                                                interrupt,
+                                                ..FlakyReader::new(input, 1)
+                                            });
+            assert_eq!(reader.read_bits(1).unwrap(), 1);
+            reader.peek_bits(32).unwrap();
+            reader.align_to_16().unwrap();
+            let mut buffer = [0u8; 6];
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer, b"abcdef");
+            assert_eq!(reader.read_bits(1).unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn bit_reader_rewind() {
+        let input: &[u8] = b"\x00\x80\x34\x12\x78\x56\x61\x62";
+        let mut reader = BitReader::new(input);
+        reader.rewind();
+        assert_eq!(reader.read_bits(1).unwrap(), 1);
+        reader.set_rewind_point();
+        assert_eq!(reader.read_bits(15).unwrap(), 0);
+        assert_eq!(reader.read_bits(32).unwrap(), 0x12345678);
+        reader.rewind();
+        assert_eq!(reader.bit_position(), 1);
+        assert_eq!(reader.read_bits(31).unwrap(), 0x1234);
+        reader.rewind();
+        assert_eq!(reader.read_bits(15).unwrap(), 0);
+        let mut buffer = [0u8; 6];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"\x34\x12\x78\x56ab");
+    }
+
+    #[test]
+    fn bit_reader_skips_padding_after_odd_raw_bytes() {
+        let input: &[u8] = b"\x61\x62\x63\x00\x00\x80";
+        let mut reader = BitReader::new(input);
+        let mut buffer = [0u8; 3];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"abc");
+        assert_eq!(reader.read_bits(1).unwrap(), 1);
+    }
+
     #[test]
     fn bit_writer() {
         let mut output = Vec::<u8>::new();
@@ -1,26 +1,35 @@
+use internal::error::Error;
+use std::fmt;
 use std::io;
 
 // ========================================================================= //
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The type of a block within an LZXD stream.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum BlockType {
+    /// A block of literals and matches, encoded with Huffman trees.
     Verbatim,
+    /// Like a verbatim block, but with the low three bits of match offsets
+    /// encoded with an additional Huffman tree.
     AlignedOffset,
+    /// A block of raw, uncompressed bytes.
     Uncompressed,
 }
 
 impl BlockType {
+    /// Parses the three-bit block type field from a block header.
     pub fn from_bits(bits: u32) -> io::Result<BlockType> {
         match bits {
             1 => Ok(BlockType::Verbatim),
             2 => Ok(BlockType::AlignedOffset),
             3 => Ok(BlockType::Uncompressed),
-            _ => invalid_data!("Invalid LZX block type ({})", bits),
+            _ => Err(Error::InvalidBlockType(bits).into()),
         }
     }
 
-    pub fn to_bits(&self) -> u32 {
-        match *self {
+    /// Returns the value of the three-bit block type field for this type.
+    pub fn to_bits(self) -> u32 {
+        match self {
             BlockType::Verbatim => 1,
             BlockType::AlignedOffset => 2,
             BlockType::Uncompressed => 3,
@@ -28,6 +37,16 @@ impl BlockType {
     }
 }
 
+impl fmt::Display for BlockType {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match *self {
+                                BlockType::Verbatim => "verbatim",
+                                BlockType::AlignedOffset => "aligned offset",
+                                BlockType::Uncompressed => "uncompressed",
+                            })
+    }
+}
+
 // ========================================================================= //
 
 
@@ -36,7 +55,7 @@ mod tests {
     use super::BlockType;
 
     #[test]
-    #[should_panic(expected = "Invalid LZX block type (7)")]
+    #[should_panic(expected = "InvalidBlockType(7)")]
     fn invalid_block_type() { BlockType::from_bits(7).unwrap(); }
 
     #[test]
@@ -50,6 +69,13 @@ mod tests {
             assert_eq!(BlockType::from_bits(btype.to_bits()).unwrap(), btype);
         }
     }
+
+    #[test]
+    fn display() {
+        assert_eq!(BlockType::Verbatim.to_string(), "verbatim");
+        assert_eq!(BlockType::AlignedOffset.to_string(), "aligned offset");
+        assert_eq!(BlockType::Uncompressed.to_string(), "uncompressed");
+    }
 }
 
 // ========================================================================= //
@@ -0,0 +1,404 @@
+use internal::consts;
+use internal::decoder::{self, Decoder};
+use internal::encoder::Encoder;
+use internal::error::Error;
+use internal::matcher::MatchParams;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+/// Collects settings for an `Encoder`, so that all of them can be checked
+/// and applied together when the encoder is created.
+#[derive(Clone, Debug)]
+pub struct EncoderBuilder {
+    window: u16,
+    uncompressed_size: Option<u64>,
+    e8_translation: bool,
+    chunk_size: Option<usize>,
+    reset_interval: u32,
+    chunk_index: bool,
+    two_pass: bool,
+    fixed_trees: bool,
+    delta: bool,
+    raw_chunks: bool,
+    flush_partial_chunks: bool,
+    match_params: MatchParams,
+    dictionary: Vec<u8>,
+}
+
+impl EncoderBuilder {
+    /// Starts configuring an encoder with the given window size, which must
+    /// be between the `WINDOW_MIN` and `WINDOW_MAX` constants (inclusive), or
+    /// up to `WINDOW_MAX_DELTA` for an LZX DELTA stream.  All other settings
+    /// start at their defaults.
+    pub fn new(window: u16) -> EncoderBuilder {
+        EncoderBuilder {
+            window,
+            uncompressed_size: None,
+            e8_translation: false,
+            chunk_size: None,
+            reset_interval: 0,
+            chunk_index: false,
+            two_pass: false,
+            fixed_trees: false,
+            delta: false,
+            raw_chunks: false,
+            flush_partial_chunks: false,
+            match_params: MatchParams::default(),
+            dictionary: Vec::new(),
+        }
+    }
+
+    /// Declares the exact size of the uncompressed data, in bytes.  If this
+    /// is not called, the size is treated as unknown (see
+    /// `Encoder::with_unknown_size`).
+    pub fn uncompressed_size(mut self, size: u64) -> EncoderBuilder {
+        self.uncompressed_size = Some(size);
+        self
+    }
+
+    /// See `Encoder::set_e8_translation`.
+    pub fn e8_translation(mut self, enable: bool) -> EncoderBuilder {
+        self.e8_translation = enable;
+        self
+    }
+
+    /// See `Encoder::set_chunk_size`.
+    pub fn chunk_size(mut self, chunk_size: usize) -> EncoderBuilder {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// See `Encoder::set_reset_interval`.
+    pub fn reset_interval(mut self, num_chunks: u32) -> EncoderBuilder {
+        self.reset_interval = num_chunks;
+        self
+    }
+
+    /// See `Encoder::set_chunk_index`.
+    pub fn chunk_index(mut self, enable: bool) -> EncoderBuilder {
+        self.chunk_index = enable;
+        self
+    }
+
+    /// See `Encoder::set_two_pass`.
+    pub fn two_pass(mut self, enable: bool) -> EncoderBuilder {
+        self.two_pass = enable;
+        self
+    }
+
+    /// See `Encoder::set_fixed_trees`.
+    pub fn fixed_trees(mut self, enable: bool) -> EncoderBuilder {
+        self.fixed_trees = enable;
+        self
+    }
+
+    /// See `Encoder::set_delta`.
+    pub fn delta(mut self, enable: bool) -> EncoderBuilder {
+        self.delta = enable;
+        self
+    }
+
+    /// See `Encoder::set_raw_chunks`.
+    pub fn raw_chunks(mut self, enable: bool) -> EncoderBuilder {
+        self.raw_chunks = enable;
+        self
+    }
+
+    /// See `Encoder::set_flush_partial_chunks`.
+    pub fn flush_partial_chunks(mut self, enable: bool) -> EncoderBuilder {
+        self.flush_partial_chunks = enable;
+        self
+    }
+
+    /// See `Encoder::set_match_params`.
+    pub fn match_params(mut self, params: MatchParams) -> EncoderBuilder {
+        self.match_params = params;
+        self
+    }
+
+    /// See `Encoder::set_dictionary`.
+    pub fn dictionary(mut self, dictionary: &[u8]) -> EncoderBuilder {
+        self.dictionary = dictionary.to_vec();
+        self
+    }
+
+    /// Creates an encoder with these settings that writes compressed data to
+    /// `writer`.  Returns an error if any of the settings are invalid.
+    pub fn build<W: Write>(&self, writer: W) -> io::Result<Encoder<W>> {
+        let mut encoder = Encoder::create(writer,
+                                          self.window,
+                                          self.uncompressed_size,
+                                          self.delta)?;
+        if let Some(chunk_size) = self.chunk_size {
+            encoder.set_chunk_size(chunk_size)?;
+        }
+        encoder.set_e8_translation(self.e8_translation)?;
+        encoder.set_raw_chunks(self.raw_chunks)?;
+        encoder.set_reset_interval(self.reset_interval)?;
+        encoder.set_chunk_index(self.chunk_index)?;
+        encoder.set_two_pass(self.two_pass);
+        encoder.set_fixed_trees(self.fixed_trees);
+        encoder.set_flush_partial_chunks(self.flush_partial_chunks);
+        encoder.set_match_params(self.match_params)?;
+        if !self.dictionary.is_empty() {
+            encoder.set_dictionary(&self.dictionary)?;
+        }
+        Ok(encoder)
+    }
+}
+
+// ========================================================================= //
+
+/// Collects settings for a `Decoder`, so that all of them can be checked
+/// and applied together when the decoder is created.
+#[derive(Clone, Debug)]
+pub struct DecoderBuilder {
+    window: u16,
+    uncompressed_size: u64,
+    chunk_size: Option<usize>,
+    reset_interval: u32,
+    reset_table: Vec<(u64, u64)>,
+    dictionary: Vec<u8>,
+    delta: bool,
+    raw_chunks: bool,
+    nonblocking: bool,
+    max_output: u64,
+    max_memory: usize,
+    lenient_eof: bool,
+}
+
+impl DecoderBuilder {
+    /// Starts configuring a decoder with the given window size and exact
+    /// uncompressed size (see `Decoder::new`).  All other settings start at
+    /// their defaults.
+    pub fn new(window: u16, uncompressed_size: u64) -> DecoderBuilder {
+        DecoderBuilder {
+            window,
+            uncompressed_size,
+            chunk_size: None,
+            reset_interval: 0,
+            reset_table: Vec::new(),
+            dictionary: Vec::new(),
+            delta: false,
+            raw_chunks: false,
+            nonblocking: false,
+            max_output: u64::MAX,
+            max_memory: usize::MAX,
+            lenient_eof: false,
+        }
+    }
+
+    /// Returns the uncompressed size given to `DecoderBuilder::new`.
+    pub fn total_uncompressed_size(&self) -> u64 { self.uncompressed_size }
+
+    /// Returns the reset table given to `reset_table()`, if any.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn reset_points(&self) -> &[(u64, u64)] { &self.reset_table }
+
+    /// See `Decoder::set_chunk_size`.
+    pub fn chunk_size(mut self, chunk_size: usize) -> DecoderBuilder {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// See `Decoder::set_reset_interval`.
+    pub fn reset_interval(mut self, num_chunks: u32) -> DecoderBuilder {
+        self.reset_interval = num_chunks;
+        self
+    }
+
+    /// See `Decoder::set_reset_table`.
+    pub fn reset_table(mut self, table: &[(u64, u64)]) -> DecoderBuilder {
+        self.reset_table = table.to_vec();
+        self
+    }
+
+    /// See `Decoder::set_dictionary`.
+    pub fn dictionary(mut self, dictionary: &[u8]) -> DecoderBuilder {
+        self.dictionary = dictionary.to_vec();
+        self
+    }
+
+    /// See `Decoder::set_delta`.
+    pub fn delta(mut self, enable: bool) -> DecoderBuilder {
+        self.delta = enable;
+        self
+    }
+
+    /// Reads a raw stream, without chunk size prefixes (see
+    /// `Decoder::new_raw`).
+    pub fn raw_chunks(mut self, enable: bool) -> DecoderBuilder {
+        self.raw_chunks = enable;
+        self
+    }
+
+    /// See `Decoder::set_lenient_eof`.
+    pub fn lenient_eof(mut self, enable: bool) -> DecoderBuilder {
+        self.lenient_eof = enable;
+        self
+    }
+
+    /// See `Decoder::set_max_output`.
+    pub fn max_output(mut self, max_bytes: u64) -> DecoderBuilder {
+        self.max_output = max_bytes;
+        self
+    }
+
+    /// Limits how much memory the decoder may allocate (no limit by
+    /// default).  If a decoder with these settings would need more than
+    /// `max_bytes` (as estimated by `memory_usage()`), `build()` fails with
+    /// `Error::MemoryLimitExceeded` instead of allocating anything.  The
+    /// decoder also keeps to the limit afterwards (see
+    /// `Decoder::set_max_memory`).
+    pub fn max_memory(mut self, max_bytes: usize) -> DecoderBuilder {
+        self.max_memory = max_bytes;
+        self
+    }
+
+    /// Returns roughly how many bytes of memory a decoder with these
+    /// settings would allocate, mostly for its window.
+    pub fn memory_usage(&self) -> usize {
+        let window_max = if self.delta {
+            consts::WINDOW_MAX_DELTA
+        } else {
+            consts::WINDOW_MAX
+        };
+        let window = self.window.clamp(consts::WINDOW_MIN, window_max);
+        decoder::memory_usage(window,
+                              self.uncompressed_size,
+                              self.dictionary.len(),
+                              self.chunk_size.unwrap_or(consts::CHUNK_SIZE),
+                              self.nonblocking) +
+            16 * self.reset_table.len()
+    }
+
+    /// See `Decoder::set_nonblocking`.
+    pub fn nonblocking(mut self, enable: bool) -> DecoderBuilder {
+        self.nonblocking = enable;
+        self
+    }
+
+    /// Creates a decoder with these settings that reads compressed data from
+    /// `reader`.  Returns an error if any of the settings are invalid, or if
+    /// the stream header can't be read.
+    pub fn build<R: Read>(&self, reader: R) -> io::Result<Decoder<R>> {
+        let memory_usage = self.memory_usage();
+        if memory_usage > self.max_memory {
+            return Err(Error::MemoryLimitExceeded(memory_usage).into());
+        }
+        let mut decoder = Decoder::with_variant(reader,
+                                                self.window,
+                                                self.uncompressed_size,
+                                                self.delta,
+                                                self.raw_chunks)?;
+        if let Some(chunk_size) = self.chunk_size {
+            decoder.set_chunk_size(chunk_size)?;
+        }
+        decoder.set_reset_interval(self.reset_interval)?;
+        decoder.set_reset_table(&self.reset_table)?;
+        if !self.dictionary.is_empty() {
+            decoder.set_dictionary(&self.dictionary)?;
+        }
+        decoder.set_nonblocking(self.nonblocking);
+        decoder.set_max_output(self.max_output);
+        decoder.set_max_memory(self.max_memory);
+        decoder.set_lenient_eof(self.lenient_eof);
+        Ok(decoder)
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{DecoderBuilder, EncoderBuilder};
+    use internal::encoder::compress;
+    use internal::error::Error;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn round_trip_with_builders() {
+        let dictionary = b"The quick brown fox jumps over the lazy dog. ";
+        let data = dictionary.repeat(40);
+        let encoder_builder = EncoderBuilder::new(16)
+            .uncompressed_size(data.len() as u64)
+            .chunk_size(0x400)
+            .reset_interval(1)
+            .two_pass(true)
+            .raw_chunks(true)
+            .dictionary(dictionary);
+        let mut encoder = encoder_builder.build(Vec::new()).unwrap();
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.into_inner().unwrap();
+        let decoder_builder = DecoderBuilder::new(16, data.len() as u64)
+            .chunk_size(0x400)
+            .reset_interval(1)
+            .raw_chunks(true)
+            .dictionary(dictionary);
+        let mut decoder = decoder_builder.build(compressed.as_slice())
+            .unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn invalid_settings_fail_to_build() {
+        let error = EncoderBuilder::new(3).build(Vec::new()).err().unwrap();
+        assert_eq!(Error::from_io(&error), Some(&Error::InvalidWindow(3)));
+        let builder = EncoderBuilder::new(15).chunk_size(1000);
+        assert!(builder.build(Vec::new()).is_err());
+        let builder = DecoderBuilder::new(15, 0).dictionary(&[0; 0x10000]);
+        assert!(builder.build(&[0u8, 0, 0, 0][..]).is_err());
+    }
+
+    #[test]
+    fn memory_limit() {
+        let small = DecoderBuilder::new(21, 100);
+        assert!(small.memory_usage() < 0x10000);
+        let builder = DecoderBuilder::new(21, 0x300000);
+        assert!(builder.memory_usage() > 0x200000);
+        assert!(builder.memory_usage() < 0x240000);
+        let nonblocking = builder.clone().nonblocking(true);
+        assert!(nonblocking.memory_usage() > builder.memory_usage());
+        let limit = builder.memory_usage();
+        let builder = builder.max_memory(limit - 1);
+        let error = builder.build(&[0u8, 0, 0, 0][..]).err().unwrap();
+        assert_eq!(Error::from_io(&error),
+                   Some(&Error::MemoryLimitExceeded(limit)));
+        let builder = builder.max_memory(limit);
+        assert!(builder.build(&[0u8, 0, 0, 0][..]).is_ok());
+    }
+
+    #[test]
+    fn memory_usage_counts_chunk_size() {
+        let builder = DecoderBuilder::new(15, 0x100000);
+        let small_chunks = builder.clone().chunk_size(0x1000);
+        assert_eq!(builder.memory_usage() - small_chunks.memory_usage(),
+                   0x8000 - 0x1000);
+        let nonblocking = builder.clone().nonblocking(true);
+        assert_eq!(nonblocking.memory_usage() - builder.memory_usage(),
+                   2 * 0x8000);
+    }
+
+    #[test]
+    fn memory_limit_applies_to_lookahead() {
+        let data = b"Looking ahead within a memory limit. ".repeat(5000);
+        let compressed = compress(&data, 15).unwrap();
+        let builder = DecoderBuilder::new(15, data.len() as u64);
+        let limit = builder.memory_usage() + 0x10000;
+        let mut decoder = builder.max_memory(limit)
+            .build(compressed.as_slice())
+            .unwrap();
+        let error = decoder.fill_lookahead(0x10001).unwrap_err();
+        assert_eq!(Error::from_io(&error),
+                   Some(&Error::MemoryLimitExceeded(limit + 1)));
+        assert_eq!(decoder.fill_lookahead(0x10000).unwrap().len(), 0x10000);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,666 @@
+//! Support for the LZX-compressed folders of Windows cabinet files.
+//!
+//! In a cabinet, a folder's compressed data is stored as a series of CFDATA
+//! blocks, each holding one chunk of the LZXD stream, with a header giving
+//! its compressed and uncompressed sizes in place of the chunk's size prefix.
+//! The types in this module convert between that layout and plain
+//! uncompressed data.
+//!
+//! With the `cab-adapter` feature, this module also provides
+//! `BlockDecompressor` and `BlockCompressor`, which work on one CFDATA
+//! block's data at a time, for cabinet libraries (such as the `cab` crate)
+//! that read and write the block headers themselves.
+
+use byteorder::{ByteOrder, LittleEndian};
+use internal::consts;
+use internal::decoder::Decoder;
+use internal::encoder::Encoder;
+use internal::error::{self, Error};
+use std::io::{self, Read, Write};
+#[cfg(feature = "cab-adapter")]
+use std::mem;
+
+// ========================================================================= //
+
+const HEADER_SIZE: usize = 8;
+
+/// Computes the checksum used in cabinet files over `data`, continuing from
+/// `seed` (which should be zero to start a new checksum).
+pub fn checksum(data: &[u8], seed: u32) -> u32 {
+    let mut words = data.chunks_exact(4);
+    let mut sum = seed;
+    for word in &mut words {
+        sum ^= LittleEndian::read_u32(word);
+    }
+    // Any leftover bytes are combined in big-endian order.
+    let tail = words
+        .remainder()
+        .iter()
+        .fold(0, |value, &byte| (value << 8) | byte as u32);
+    sum ^ tail
+}
+
+/// Computes the checksum for a CFDATA block with the given header fields and
+/// data (not counting its reserved area).
+fn block_checksum(header: &[u8], data: &[u8]) -> u32 {
+    checksum(&header[4..HEADER_SIZE], checksum(data, 0))
+}
+
+// ========================================================================= //
+
+/// Reads the CFDATA blocks of a folder, and presents their data as an LZXD
+/// stream with a size prefix before each chunk.
+struct DataBlockReader<R: Read> {
+    reader: R,
+    reserve_size: u8,
+    chunk: Vec<u8>,
+    position: usize,
+    num_blocks: u64,
+}
+
+impl<R: Read> DataBlockReader<R> {
+    fn new(reader: R, reserve_size: u8) -> DataBlockReader<R> {
+        DataBlockReader {
+            reader,
+            reserve_size,
+            chunk: Vec::new(),
+            position: 0,
+            num_blocks: 0,
+        }
+    }
+
+    /// Reads the next chunk, returning false if there are no more blocks.
+    /// A block with an uncompressed size of zero continues into the next
+    /// block (as happens when a folder is split across cabinet files).
+    fn read_chunk(&mut self) -> io::Result<bool> {
+        self.chunk.clear();
+        self.chunk.extend_from_slice(&[0, 0]);
+        self.position = 0;
+        loop {
+            let mut header = [0u8; HEADER_SIZE];
+            if !self.read_header(&mut header)? {
+                if self.chunk.len() == 2 {
+                    return Ok(false);
+                }
+                return Err(Error::Truncated.into());
+            }
+            let expected = LittleEndian::read_u32(&header[0..4]);
+            let compressed_size = LittleEndian::read_u16(&header[4..6]);
+            let uncompressed_size = LittleEndian::read_u16(&header[6..8]);
+            let mut reserve = [0u8; 255];
+            let reserve = &mut reserve[..self.reserve_size as usize];
+            self.reader
+                .read_exact(reserve)
+                .map_err(error::eof_as_truncated)?;
+            let start = self.chunk.len();
+            self.chunk.resize(start + compressed_size as usize, 0);
+            self.reader
+                .read_exact(&mut self.chunk[start..])
+                .map_err(error::eof_as_truncated)?;
+            if expected != 0 {
+                let actual = block_checksum(&header, &self.chunk[start..]);
+                if actual != expected {
+                    invalid_data!("CAB data block checksum mismatch \
+                                   ({:08x} != {:08x})",
+                                  actual,
+                                  expected);
+                }
+            }
+            self.num_blocks += 1;
+            if uncompressed_size != 0 {
+                break;
+            }
+        }
+        let chunk_len = self.chunk.len() - 2;
+        if chunk_len > u16::MAX as usize {
+            invalid_data!("CAB data block is too large ({} bytes)",
+                          chunk_len);
+        }
+        LittleEndian::write_u16(&mut self.chunk[0..2], chunk_len as u16);
+        Ok(true)
+    }
+
+    /// Reads a CFDATA header, returning false if the reader is already at
+    /// end-of-file.
+    fn read_header(&mut self, header: &mut [u8]) -> io::Result<bool> {
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(Error::Truncated.into()),
+                Ok(num_bytes) => filled += num_bytes,
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DataBlockReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() && !self.read_chunk()? {
+            return Ok(0);
+        }
+        let num_bytes = buf.len().min(self.chunk.len() - self.position);
+        let end = self.position + num_bytes;
+        buf[..num_bytes].copy_from_slice(&self.chunk[self.position..end]);
+        self.position = end;
+        Ok(num_bytes)
+    }
+}
+
+// ========================================================================= //
+
+/// Takes an LZXD stream, and writes each chunk of it as a CFDATA block.
+struct DataBlockWriter<W: Write> {
+    writer: W,
+    reserve_size: u8,
+    uncompressed_bytes_remaining: u64,
+    chunk: Vec<u8>,
+    num_blocks: u64,
+}
+
+impl<W: Write> DataBlockWriter<W> {
+    fn new(writer: W, reserve_size: u8, uncompressed_size: u64)
+           -> DataBlockWriter<W> {
+        DataBlockWriter {
+            writer,
+            reserve_size,
+            uncompressed_bytes_remaining: uncompressed_size,
+            chunk: Vec::new(),
+            num_blocks: 0,
+        }
+    }
+
+    /// Returns the number of bytes still needed to complete the size prefix
+    /// or data of the current chunk.
+    fn bytes_needed(&self) -> usize {
+        if self.chunk.len() < 2 {
+            2 - self.chunk.len()
+        } else {
+            2 + LittleEndian::read_u16(&self.chunk[0..2]) as usize -
+                self.chunk.len()
+        }
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let uncompressed_size =
+            self.uncompressed_bytes_remaining.min(consts::CHUNK_SIZE as u64);
+        self.uncompressed_bytes_remaining -= uncompressed_size;
+        let data = &self.chunk[2..];
+        let mut header = [0u8; HEADER_SIZE];
+        LittleEndian::write_u16(&mut header[4..6], data.len() as u16);
+        LittleEndian::write_u16(&mut header[6..8], uncompressed_size as u16);
+        let checksum = block_checksum(&header, data);
+        LittleEndian::write_u32(&mut header[0..4], checksum);
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&[0u8; 255][..self.reserve_size as usize])?;
+        self.writer.write_all(data)?;
+        self.chunk.clear();
+        self.num_blocks += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for DataBlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes_written = 0;
+        while bytes_written < buf.len() {
+            let num_bytes =
+                self.bytes_needed().min(buf.len() - bytes_written);
+            let end = bytes_written + num_bytes;
+            self.chunk.extend_from_slice(&buf[bytes_written..end]);
+            bytes_written = end;
+            if self.chunk.len() > 2 && self.bytes_needed() == 0 {
+                self.write_block()?;
+            }
+        }
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.writer.flush() }
+}
+
+// ========================================================================= //
+
+/// Decompresses the data of an LZX-compressed cabinet folder, read as a
+/// series of CFDATA blocks.
+pub struct FolderDecoder<R: Read> {
+    decoder: Decoder<DataBlockReader<R>>,
+}
+
+impl<R: Read> FolderDecoder<R> {
+    /// Starts decoding a folder whose first CFDATA block is next in
+    /// `reader`.  The `window` argument is the window exponent from the
+    /// folder's compression type, and `uncompressed_size` is the total size
+    /// of the folder's uncompressed data.
+    pub fn new(reader: R, window: u16, uncompressed_size: u64)
+               -> io::Result<FolderDecoder<R>> {
+        FolderDecoder::with_reserve_size(reader, window, uncompressed_size, 0)
+    }
+
+    /// Like `FolderDecoder::new`, but for a cabinet that reserves the given
+    /// number of bytes (its `cbCFData` field) in each CFDATA block.
+    pub fn with_reserve_size(reader: R, window: u16, uncompressed_size: u64,
+                             reserve_size: u8)
+                             -> io::Result<FolderDecoder<R>> {
+        let reader = DataBlockReader::new(reader, reserve_size);
+        Ok(FolderDecoder {
+               decoder: Decoder::new(reader, window, uncompressed_size)?,
+           })
+    }
+
+    /// Returns the number of CFDATA blocks read so far.
+    pub fn num_blocks(&self) -> u64 { self.decoder.get_ref().num_blocks }
+
+    /// Unwraps this object, returning the underlying reader.  If all of the
+    /// folder's data has been read, the reader is positioned just after its
+    /// last CFDATA block.
+    pub fn into_inner(self) -> io::Result<R> {
+        Ok(self.decoder.into_inner()?.reader)
+    }
+}
+
+impl<R: Read> Read for FolderDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+// ========================================================================= //
+
+/// Compresses data into an LZX-compressed cabinet folder, written as a
+/// series of CFDATA blocks (each with its checksum filled in).
+pub struct FolderEncoder<W: Write> {
+    encoder: Encoder<DataBlockWriter<W>>,
+}
+
+impl<W: Write> FolderEncoder<W> {
+    /// Starts encoding a folder with the given window exponent and total
+    /// uncompressed size.
+    pub fn new(writer: W, window: u16, uncompressed_size: u64)
+               -> io::Result<FolderEncoder<W>> {
+        FolderEncoder::with_reserve_size(writer, window, uncompressed_size, 0)
+    }
+
+    /// Like `FolderEncoder::new`, but for a cabinet that reserves the given
+    /// number of bytes (its `cbCFData` field) in each CFDATA block.  The
+    /// reserved bytes are written as zeros.
+    pub fn with_reserve_size(writer: W, window: u16, uncompressed_size: u64,
+                             reserve_size: u8)
+                             -> io::Result<FolderEncoder<W>> {
+        let writer =
+            DataBlockWriter::new(writer, reserve_size, uncompressed_size);
+        Ok(FolderEncoder {
+               encoder: Encoder::new(writer, window, uncompressed_size)?,
+           })
+    }
+
+    /// Enables or disables x86 E8 call translation (see
+    /// `Encoder::set_e8_translation`).
+    pub fn set_e8_translation(&mut self, enable: bool) -> io::Result<()> {
+        self.encoder.set_e8_translation(enable)
+    }
+
+    /// Returns the number of CFDATA blocks written so far.  Once the folder
+    /// is finished, this is the count to store in its CFFOLDER entry.
+    pub fn num_blocks(&self) -> u64 { self.encoder.get_ref().num_blocks }
+
+    /// Finishes the folder, returning the total number of uncompressed bytes
+    /// that were encoded.  Returns an error if fewer bytes were written than
+    /// were declared.
+    pub fn finish(&mut self) -> io::Result<u64> { self.encoder.finish() }
+
+    /// Finishes the folder (as with `finish()`) and returns the underlying
+    /// writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        let writer = self.encoder.into_inner()?;
+        debug_assert!(writer.chunk.is_empty());
+        Ok(writer.writer)
+    }
+}
+
+impl<W: Write> Write for FolderEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.encoder.flush() }
+}
+
+// ========================================================================= //
+
+/// Presents the data of CFDATA blocks that are handed to it one at a time as
+/// an LZXD stream with a size prefix before each chunk.
+#[cfg(feature = "cab-adapter")]
+struct BlockSource {
+    chunks: Vec<u8>,
+    position: usize,
+}
+
+#[cfg(feature = "cab-adapter")]
+impl BlockSource {
+    fn new() -> BlockSource {
+        BlockSource {
+            chunks: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn push_block(&mut self, data: &[u8]) {
+        self.chunks.drain(..self.position);
+        self.position = 0;
+        self.chunks.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        self.chunks.extend_from_slice(data);
+    }
+}
+
+#[cfg(feature = "cab-adapter")]
+impl Read for BlockSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = buf.len().min(self.chunks.len() - self.position);
+        let end = self.position + num_bytes;
+        buf[..num_bytes].copy_from_slice(&self.chunks[self.position..end]);
+        self.position = end;
+        Ok(num_bytes)
+    }
+}
+
+/// Decompresses an LZX-compressed cabinet folder one CFDATA block at a time.
+///
+/// This is for cabinet readers that parse the CFDATA headers (and verify
+/// their checksums) themselves.  Each block's data must be passed in order,
+/// with any block that was split across cabinets already joined back
+/// together.
+#[cfg(feature = "cab-adapter")]
+pub struct BlockDecompressor {
+    window: u16,
+    decoder: Option<Decoder<BlockSource>>,
+    output: Vec<u8>,
+}
+
+#[cfg(feature = "cab-adapter")]
+impl BlockDecompressor {
+    /// Creates a decompressor for a folder with the given window exponent
+    /// (see `WindowSize::for_cab_type_compress`).
+    pub fn new(window: u16) -> io::Result<BlockDecompressor> {
+        if !consts::is_valid_window(window, false) {
+            return Err(Error::InvalidWindow(window).into());
+        }
+        Ok(BlockDecompressor {
+               window,
+               decoder: None,
+               output: Vec::with_capacity(consts::CHUNK_SIZE),
+           })
+    }
+
+    /// Decompresses the data of the folder's next CFDATA block, whose header
+    /// gives its uncompressed size as `uncompressed_size`.
+    pub fn decompress_block(&mut self, data: &[u8], uncompressed_size: usize)
+                            -> io::Result<&[u8]> {
+        if data.len() > u16::MAX as usize {
+            invalid_input!("CAB data block is too large ({} bytes)",
+                           data.len());
+        }
+        if uncompressed_size > consts::CHUNK_SIZE {
+            invalid_input!("CAB data block uncompressed size is too large \
+                            ({} bytes)",
+                           uncompressed_size);
+        }
+        let decoder = match self.decoder.take() {
+            Some(mut decoder) => {
+                decoder.get_mut().push_block(data);
+                decoder
+            }
+            None => {
+                let mut source = BlockSource::new();
+                source.push_block(data);
+                Decoder::with_unknown_size(source, self.window)?
+            }
+        };
+        let decoder = self.decoder.get_or_insert(decoder);
+        self.output.resize(uncompressed_size, 0);
+        decoder.read_exact(&mut self.output).map_err(error::eof_as_truncated)?;
+        Ok(&self.output)
+    }
+}
+
+/// Compresses an LZX-compressed cabinet folder one CFDATA block at a time.
+///
+/// This is for cabinet writers that build the CFDATA headers themselves.
+/// Each call compresses the data for one block, which must be `CHUNK_SIZE`
+/// bytes long unless it is the last block of the folder.
+#[cfg(feature = "cab-adapter")]
+pub struct BlockCompressor {
+    encoder: Encoder<Vec<u8>>,
+}
+
+#[cfg(feature = "cab-adapter")]
+impl BlockCompressor {
+    /// Creates a compressor for a folder with the given window exponent
+    /// (see `WindowSize::cab_type_compress`).
+    pub fn new(window: u16) -> io::Result<BlockCompressor> {
+        let mut encoder = Encoder::with_unknown_size(Vec::new(), window)?;
+        encoder.set_raw_chunks(true)?;
+        Ok(BlockCompressor { encoder })
+    }
+
+    /// Enables or disables x86 E8 call translation (see
+    /// `Encoder::set_e8_translation`).  This must be set before the first
+    /// block is compressed.
+    pub fn set_e8_translation(&mut self, enable: bool) -> io::Result<()> {
+        self.encoder.set_e8_translation(enable)
+    }
+
+    /// Compresses the uncompressed data for the folder's next CFDATA block,
+    /// returning the block's compressed data.  If `is_last` is true, this
+    /// finishes the folder, and no more blocks can be compressed.
+    pub fn compress_block(&mut self, data: &[u8], is_last: bool)
+                          -> io::Result<Vec<u8>> {
+        if data.len() > consts::CHUNK_SIZE ||
+            (!is_last && data.len() != consts::CHUNK_SIZE)
+        {
+            invalid_input!("CAB data block must hold {} bytes, not {}",
+                           consts::CHUNK_SIZE,
+                           data.len());
+        }
+        self.encoder.write_all(data)?;
+        if is_last {
+            self.encoder.finish()?;
+        }
+        Ok(mem::take(self.encoder.get_mut()))
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "cab-adapter")]
+    use super::{BlockCompressor, BlockDecompressor};
+    use super::{FolderDecoder, FolderEncoder, checksum};
+    use byteorder::{ByteOrder, LittleEndian};
+    use internal::encoder::compress;
+    use std::io::{self, Read, Write};
+
+    /// Splits a folder's CFDATA blocks into (header, data) pairs.
+    fn split_blocks(mut folder: &[u8], reserve_size: usize)
+                    -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut blocks = Vec::new();
+        while !folder.is_empty() {
+            let compressed_size = LittleEndian::read_u16(&folder[4..6]);
+            let data_start = 8 + reserve_size;
+            let data_end = data_start + compressed_size as usize;
+            blocks.push((folder[..8].to_vec(),
+                         folder[data_start..data_end].to_vec()));
+            folder = &folder[data_end..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn checksum_values() {
+        assert_eq!(checksum(b"", 0), 0);
+        assert_eq!(checksum(b"\x01\x02\x03\x04", 0), 0x04030201);
+        assert_eq!(checksum(b"\x01\x02\x03\x04\x05\x06\x07", 0),
+                   0x04030201 ^ 0x050607);
+        assert_eq!(checksum(b"\x05", 0x100), 0x105);
+    }
+
+    #[test]
+    fn folder_round_trip() {
+        let data = b"Folder data, carved into CFDATA blocks. ".repeat(2000);
+        let mut encoder =
+            FolderEncoder::with_reserve_size(Vec::new(),
+                                             16,
+                                             data.len() as u64,
+                                             4)
+                .unwrap();
+        encoder.write_all(&data[..70000]).unwrap();
+        assert_eq!(encoder.num_blocks(), 2);
+        encoder.write_all(&data[70000..]).unwrap();
+        assert_eq!(encoder.finish().unwrap(), data.len() as u64);
+        assert_eq!(encoder.num_blocks(), 3);
+        let folder = encoder.into_inner().unwrap();
+        // Each block holds one chunk of the LZXD stream, minus its size
+        // prefix, and the last chunk is a partial one.
+        let blocks = split_blocks(&folder, 4);
+        assert_eq!(blocks.len(), 3);
+        let mut stream = Vec::new();
+        for (index, (header, body)) in blocks.iter().enumerate() {
+            let uncompressed_size = LittleEndian::read_u16(&header[6..8]);
+            if index < 2 {
+                assert_eq!(uncompressed_size, 0x8000);
+            } else {
+                assert_eq!(uncompressed_size, (data.len() % 0x8000) as u16);
+            }
+            assert_ne!(LittleEndian::read_u32(&header[0..4]), 0);
+            stream.extend_from_slice(&(body.len() as u16).to_le_bytes());
+            stream.extend_from_slice(body);
+        }
+        assert_eq!(stream, compress(&data, 16).unwrap());
+        let mut decoder =
+            FolderDecoder::with_reserve_size(folder.as_slice(),
+                                             16,
+                                             data.len() as u64,
+                                             4)
+                .unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+        assert_eq!(decoder.num_blocks(), 3);
+        assert!(decoder.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn block_split_across_cabinets() {
+        let data = b"Spanning two cabinets. ".repeat(2000);
+        let mut encoder =
+            FolderEncoder::new(Vec::new(), 15, data.len() as u64).unwrap();
+        encoder.write_all(&data).unwrap();
+        let folder = encoder.into_inner().unwrap();
+        // Split the first block in two, as when the folder continues into the
+        // next cabinet; the first piece has an uncompressed size of zero and
+        // no checksum.
+        let blocks = split_blocks(&folder, 0);
+        let (ref header, ref body) = blocks[0];
+        let split = body.len() / 2;
+        let mut spanned = Vec::new();
+        spanned.extend_from_slice(&[0, 0, 0, 0]);
+        spanned.extend_from_slice(&(split as u16).to_le_bytes());
+        spanned.extend_from_slice(&[0, 0]);
+        spanned.extend_from_slice(&body[..split]);
+        spanned.extend_from_slice(&[0, 0, 0, 0]);
+        spanned.extend_from_slice(&((body.len() - split) as u16)
+                                      .to_le_bytes());
+        spanned.extend_from_slice(&header[6..8]);
+        spanned.extend_from_slice(&body[split..]);
+        spanned.extend_from_slice(&folder[(8 + body.len())..]);
+        let mut decoder =
+            FolderDecoder::new(spanned.as_slice(), 15, data.len() as u64)
+                .unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+        assert_eq!(decoder.num_blocks(), blocks.len() as u64 + 1);
+    }
+
+    #[cfg(feature = "cab-adapter")]
+    #[test]
+    fn block_adapter_matches_folder_layout() {
+        let data = b"One CFDATA block at a time. ".repeat(3000);
+        let mut encoder =
+            FolderEncoder::new(Vec::new(), 17, data.len() as u64).unwrap();
+        encoder.write_all(&data).unwrap();
+        let folder = encoder.into_inner().unwrap();
+        let blocks = split_blocks(&folder, 0);
+        let mut compressor = BlockCompressor::new(17).unwrap();
+        let pieces: Vec<&[u8]> = data.chunks(0x8000).collect();
+        for (index, piece) in pieces.iter().enumerate() {
+            let is_last = index + 1 == pieces.len();
+            let body = compressor.compress_block(piece, is_last).unwrap();
+            assert_eq!(body, blocks[index].1);
+        }
+        let mut decompressor = BlockDecompressor::new(17).unwrap();
+        let mut output = Vec::new();
+        for (header, body) in blocks.iter() {
+            let size = LittleEndian::read_u16(&header[6..8]) as usize;
+            output.extend_from_slice(decompressor.decompress_block(body, size)
+                                         .unwrap());
+        }
+        assert!(output == data);
+    }
+
+    #[cfg(feature = "cab-adapter")]
+    #[test]
+    fn block_adapter_round_trip() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|n| if n % 9 == 0 { 0xe8 } else { (n / 5) as u8 })
+            .collect();
+        let mut compressor = BlockCompressor::new(16).unwrap();
+        compressor.set_e8_translation(true).unwrap();
+        let error = compressor.compress_block(&data[..100], false)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let mut decompressor = BlockDecompressor::new(16).unwrap();
+        let mut output = Vec::new();
+        for (index, piece) in data.chunks(0x8000).enumerate() {
+            let body = compressor.compress_block(piece, index == 3).unwrap();
+            let piece_out = decompressor.decompress_block(&body, piece.len())
+                .unwrap();
+            output.extend_from_slice(piece_out);
+        }
+        assert!(output == data);
+        assert!(BlockDecompressor::new(30).is_err());
+        let mut decompressor = BlockDecompressor::new(16).unwrap();
+        let error = decompressor.decompress_block(&[0, 0], 0x8001)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn corrupt_block_is_detected() {
+        let data = b"Checksummed. ".repeat(100);
+        let mut encoder =
+            FolderEncoder::new(Vec::new(), 15, data.len() as u64).unwrap();
+        encoder.write_all(&data).unwrap();
+        let mut folder = encoder.into_inner().unwrap();
+        let last = folder.len() - 1;
+        folder[last] ^= 0x10;
+        let mut output = Vec::new();
+        let error = FolderDecoder::new(folder.as_slice(),
+                                       15,
+                                       data.len() as u64)
+            .and_then(|mut decoder| decoder.read_to_end(&mut output))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let truncated = &folder[..(folder.len() - 4)];
+        assert!(FolderDecoder::new(truncated, 15, data.len() as u64)
+                    .and_then(|mut decoder| decoder.read_to_end(&mut output))
+                    .is_err());
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,649 @@
+//! Support for the LZXC-compressed content of Microsoft Compiled HTML Help
+//! (CHM) files.
+//!
+//! A CHM file stores its compressed content as an LZXD stream without chunk
+//! size prefixes, along with LZXC control data (giving the window size and
+//! reset interval) and a reset table (giving the compressed offset of each
+//! chunk).  The types in this module read and write those, and encode or
+//! decode the content (with random access when decoding).
+
+use byteorder::{ByteOrder, LittleEndian};
+use internal::consts;
+use internal::decoder::Decoder;
+use internal::encoder::Encoder;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// ========================================================================= //
+
+/// The units in which version 2 control data gives sizes.
+const CONTROL_DATA_UNIT: u32 = 0x8000;
+
+const CONTROL_DATA_SIZE: usize = 0x1c;
+
+const RESET_TABLE_VERSION: u32 = 2;
+const RESET_TABLE_HEADER_SIZE: usize = 0x28;
+
+// ========================================================================= //
+
+/// The parameters from a CHM file's LZXC control data.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ControlData {
+    /// The size of the compression window, in bytes.
+    pub window_size: u32,
+    /// The number of uncompressed bytes between points at which the
+    /// compressor resets its state.
+    pub reset_interval: u32,
+}
+
+impl ControlData {
+    /// Parses LZXC control data (the contents of the `ControlData` file in
+    /// the CHM's `MSCompressed` storage).
+    pub fn parse(data: &[u8]) -> io::Result<ControlData> {
+        if data.len() < 20 || &data[4..8] != b"LZXC" {
+            invalid_data!("Invalid LZXC control data");
+        }
+        let version = LittleEndian::read_u32(&data[8..12]);
+        let unit = match version {
+            1 => 1,
+            2 => CONTROL_DATA_UNIT,
+            _ => {
+                invalid_data!("Unsupported LZXC version ({})", version);
+            }
+        };
+        let reset_interval = LittleEndian::read_u32(&data[12..16]);
+        let window_size = LittleEndian::read_u32(&data[16..20]);
+        match (reset_interval.checked_mul(unit),
+               window_size.checked_mul(unit)) {
+            (Some(reset_interval), Some(window_size)) => {
+                Ok(ControlData {
+                       window_size,
+                       reset_interval,
+                   })
+            }
+            _ => {
+                invalid_data!("Invalid LZXC control data");
+            }
+        }
+    }
+
+    /// Returns the window exponent corresponding to the window size, or an
+    /// error if the window size isn't valid for an LZXD stream.
+    pub fn window(&self) -> io::Result<u16> {
+        let window = self.window_size.trailing_zeros() as u16;
+        if !self.window_size.is_power_of_two() ||
+            !consts::is_valid_window(window, false)
+        {
+            invalid_data!("Invalid LZXC window size ({})", self.window_size);
+        }
+        Ok(window)
+    }
+
+    /// Encodes this control data in the layout of a CHM `ControlData` file.
+    /// Version 2 of the format is used if the sizes allow it, and version 1
+    /// otherwise.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (version, unit) =
+            if self.window_size.is_multiple_of(CONTROL_DATA_UNIT) &&
+                self.reset_interval.is_multiple_of(CONTROL_DATA_UNIT)
+            {
+                (2, CONTROL_DATA_UNIT)
+            } else {
+                (1, 1)
+            };
+        let mut data = vec![0u8; CONTROL_DATA_SIZE];
+        LittleEndian::write_u32(&mut data[0..4],
+                                (CONTROL_DATA_SIZE / 4 - 1) as u32);
+        data[4..8].copy_from_slice(b"LZXC");
+        LittleEndian::write_u32(&mut data[8..12], version);
+        LittleEndian::write_u32(&mut data[12..16], self.reset_interval / unit);
+        LittleEndian::write_u32(&mut data[16..20], self.window_size / unit);
+        // The number of windows per reset, which decoders ignore.
+        LittleEndian::write_u32(&mut data[20..24], 1);
+        data
+    }
+}
+
+// ========================================================================= //
+
+/// A CHM file's LZXC reset table.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ResetTable {
+    /// The total size of the uncompressed content.
+    pub uncompressed_len: u64,
+    /// The total size of the compressed content.
+    pub compressed_len: u64,
+    /// The number of uncompressed bytes in each chunk.
+    pub block_len: u64,
+    /// The compressed offset of the start of each chunk, measured from the
+    /// start of the compressed content.
+    pub block_offsets: Vec<u64>,
+}
+
+impl ResetTable {
+    /// Parses a reset table (the contents of the `ResetTable` file in the
+    /// CHM's LZXC transform storage).
+    pub fn parse(data: &[u8]) -> io::Result<ResetTable> {
+        if data.len() < RESET_TABLE_HEADER_SIZE {
+            invalid_data!("LZXC reset table is too short ({} bytes)",
+                          data.len());
+        }
+        let num_blocks = LittleEndian::read_u32(&data[4..8]) as usize;
+        let entry_size = LittleEndian::read_u32(&data[8..12]);
+        let table_offset = LittleEndian::read_u32(&data[12..16]) as usize;
+        if entry_size != 8 {
+            invalid_data!("Unsupported LZXC reset table entry size ({})",
+                          entry_size);
+        }
+        let table_len = num_blocks.saturating_mul(8);
+        if table_offset.saturating_add(table_len) > data.len() {
+            invalid_data!("LZXC reset table is truncated");
+        }
+        let table = &data[table_offset..(table_offset + table_len)];
+        Ok(ResetTable {
+               uncompressed_len: LittleEndian::read_u64(&data[16..24]),
+               compressed_len: LittleEndian::read_u64(&data[24..32]),
+               block_len: LittleEndian::read_u64(&data[32..40]),
+               block_offsets: table.chunks(8)
+                   .map(LittleEndian::read_u64)
+                   .collect(),
+           })
+    }
+
+    /// Encodes this reset table in the layout of a CHM `ResetTable` file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![0u8; RESET_TABLE_HEADER_SIZE];
+        LittleEndian::write_u32(&mut data[0..4], RESET_TABLE_VERSION);
+        LittleEndian::write_u32(&mut data[4..8],
+                                self.block_offsets.len() as u32);
+        LittleEndian::write_u32(&mut data[8..12], 8);
+        LittleEndian::write_u32(&mut data[12..16],
+                                RESET_TABLE_HEADER_SIZE as u32);
+        LittleEndian::write_u64(&mut data[16..24], self.uncompressed_len);
+        LittleEndian::write_u64(&mut data[24..32], self.compressed_len);
+        LittleEndian::write_u64(&mut data[32..40], self.block_len);
+        for &offset in &self.block_offsets {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data
+    }
+
+    /// Returns the number of chunks in the content.
+    fn num_chunks(&self) -> u64 {
+        if self.block_len == 0 {
+            0
+        } else {
+            self.uncompressed_len.div_ceil(self.block_len)
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// Reads CHM content, and presents it as an LZXD stream with a size prefix
+/// before each chunk.
+struct ContentReader<R: Read + Seek> {
+    reader: R,
+    /// The compressed offset of the start of each chunk, followed by the
+    /// compressed length of the content.
+    offsets: Vec<u64>,
+    /// The chunk being read.
+    chunk: usize,
+    /// The position within the chunk, counting its size prefix.
+    position: u64,
+}
+
+impl<R: Read + Seek> ContentReader<R> {
+    /// Returns the number of bytes of the prefixed stream that precede the
+    /// given chunk.
+    fn chunk_start(&self, chunk: usize) -> u64 {
+        self.offsets[chunk] + 2 * chunk as u64
+    }
+
+    /// Returns the position of the underlying reader, relative to the start
+    /// of the content.
+    fn inner_position(&self) -> u64 {
+        self.offsets[self.chunk] + self.position.saturating_sub(2)
+    }
+}
+
+impl<R: Read + Seek> Read for ContentReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunk + 1 >= self.offsets.len() || buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk_len =
+            self.offsets[self.chunk + 1] - self.offsets[self.chunk];
+        let num_bytes = if self.position < 2 {
+            let prefix = (chunk_len as u16).to_le_bytes();
+            buf[0] = prefix[self.position as usize];
+            1
+        } else {
+            let remaining = chunk_len - (self.position - 2);
+            let num_bytes = (buf.len() as u64).min(remaining) as usize;
+            self.reader.read(&mut buf[..num_bytes])?
+        };
+        self.position += num_bytes as u64;
+        if self.position == chunk_len + 2 {
+            self.chunk += 1;
+            self.position = 0;
+        }
+        Ok(num_bytes)
+    }
+}
+
+impl<R: Read + Seek> Seek for ContentReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let current = self.chunk_start(self.chunk) + self.position;
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => current.checked_add_signed(delta),
+            SeekFrom::End(delta) => {
+                let last = self.offsets.len() - 1;
+                self.chunk_start(last).checked_add_signed(delta)
+            }
+        };
+        let target = match target {
+            Some(target) => target,
+            None => {
+                invalid_input!("Cannot seek outside of CHM content");
+            }
+        };
+        let old_inner = self.inner_position();
+        let chunk = (0..self.offsets.len())
+            .rev()
+            .find(|&chunk| self.chunk_start(chunk) <= target)
+            .unwrap_or(0);
+        self.chunk = chunk;
+        self.position = target - self.chunk_start(chunk);
+        let delta = self.inner_position() as i64 - old_inner as i64;
+        self.reader.seek(SeekFrom::Current(delta))?;
+        Ok(target)
+    }
+}
+
+// ========================================================================= //
+
+/// Decompresses the LZXC-compressed content of a CHM file, resetting state at
+/// each reset interval and using the reset table for random access.
+pub struct ChmDecoder<R: Read + Seek> {
+    decoder: Decoder<ContentReader<R>>,
+}
+
+impl<R: Read + Seek> ChmDecoder<R> {
+    /// Starts decoding CHM content, given the file's control data and reset
+    /// table.  The `reader` must be positioned at the start of the
+    /// compressed content.
+    pub fn new(reader: R, control_data: &ControlData,
+               reset_table: &ResetTable)
+               -> io::Result<ChmDecoder<R>> {
+        let window = control_data.window()?;
+        let chunk_size = reset_table.block_len;
+        if !consts::is_valid_chunk_size(chunk_size as usize) {
+            invalid_data!("Invalid LZXC block length ({})", chunk_size);
+        }
+        let reset_interval = control_data.reset_interval as u64;
+        if reset_interval == 0 || !reset_interval.is_multiple_of(chunk_size) {
+            invalid_data!("Invalid LZXC reset interval ({})", reset_interval);
+        }
+        let num_chunks = reset_table.num_chunks() as usize;
+        if reset_table.block_offsets.len() < num_chunks {
+            invalid_data!("LZXC reset table is missing entries ({} < {})",
+                          reset_table.block_offsets.len(),
+                          num_chunks);
+        }
+        let mut offsets = reset_table.block_offsets[..num_chunks].to_vec();
+        offsets.push(reset_table.compressed_len);
+        let mut previous = 0;
+        for &offset in &offsets {
+            if offset < previous || offset - previous > u16::MAX as u64 {
+                invalid_data!("Invalid LZXC reset table entry ({})", offset);
+            }
+            previous = offset;
+        }
+        let reader = ContentReader {
+            reader,
+            offsets,
+            chunk: 0,
+            position: 0,
+        };
+        let chunks_per_reset = reset_interval / chunk_size;
+        let resets: Vec<(u64, u64)> = (0..num_chunks)
+            .step_by(chunks_per_reset as usize)
+            .map(|chunk| {
+                     (chunk as u64 * chunk_size, reader.chunk_start(chunk))
+                 })
+            .collect();
+        let mut decoder =
+            Decoder::new(reader, window, reset_table.uncompressed_len)?;
+        decoder.set_chunk_size(chunk_size as usize)?;
+        decoder.set_reset_interval(chunks_per_reset as u32)?;
+        decoder.set_reset_table(&resets)?;
+        Ok(ChmDecoder { decoder })
+    }
+
+    /// Moves to the given offset within the uncompressed content, starting
+    /// from the nearest reset point at or before it (see `Decoder::seek_to`).
+    pub fn seek_to(&mut self, offset: u64) -> io::Result<u64> {
+        self.decoder.seek_to(offset)
+    }
+
+    /// Decodes and returns `len` bytes of the uncompressed content starting
+    /// at `start` (see `Decoder::read_range`).
+    pub fn read_range(&mut self, start: u64, len: usize)
+                      -> io::Result<Vec<u8>> {
+        self.decoder.read_range(start, len)
+    }
+
+    /// Unwraps this object, returning the underlying reader.
+    pub fn into_inner(self) -> io::Result<R> {
+        Ok(self.decoder.into_inner()?.reader)
+    }
+}
+
+impl<R: Read + Seek> Read for ChmDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+/// Unlike for a `Decoder`, seeking backwards is supported, by way of the
+/// reset table.
+impl<R: Read + Seek> Seek for ChmDecoder<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let total = self.decoder.total_uncompressed_size();
+        let current = total - self.decoder.uncompressed_bytes_remaining();
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => current.checked_add_signed(delta),
+            SeekFrom::End(delta) => total.checked_add_signed(delta),
+        };
+        match target {
+            Some(target) => self.seek_to(target),
+            None => {
+                invalid_input!("Cannot seek outside of LZX stream");
+            }
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// Takes an LZXD stream, and writes it out without its chunk size prefixes,
+/// recording where each chunk starts.
+struct ContentWriter<W: Write> {
+    writer: W,
+    prefix: [u8; 2],
+    prefix_len: usize,
+    chunk_bytes_remaining: usize,
+    compressed_len: u64,
+    block_offsets: Vec<u64>,
+}
+
+impl<W: Write> Write for ContentWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.chunk_bytes_remaining == 0 {
+            self.prefix[self.prefix_len] = buf[0];
+            self.prefix_len += 1;
+            if self.prefix_len == 2 {
+                self.prefix_len = 0;
+                self.chunk_bytes_remaining =
+                    LittleEndian::read_u16(&self.prefix) as usize;
+                self.block_offsets.push(self.compressed_len);
+            }
+            return Ok(1);
+        }
+        let num_bytes = buf.len().min(self.chunk_bytes_remaining);
+        let num_bytes = self.writer.write(&buf[..num_bytes])?;
+        self.chunk_bytes_remaining -= num_bytes;
+        self.compressed_len += num_bytes as u64;
+        Ok(num_bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.writer.flush() }
+}
+
+// ========================================================================= //
+
+/// Compresses content for a CHM file, producing the LZXC-compressed content
+/// along with the reset table that goes with it.
+pub struct ChmEncoder<W: Write> {
+    encoder: Encoder<ContentWriter<W>>,
+}
+
+impl<W: Write> ChmEncoder<W> {
+    /// Starts encoding content with the window size and reset interval
+    /// given by `control_data` (whose reset interval must be a non-zero
+    /// multiple of the chunk size).
+    pub fn new(writer: W, control_data: &ControlData, uncompressed_size: u64)
+               -> io::Result<ChmEncoder<W>> {
+        let window = match control_data.window() {
+            Ok(window) => window,
+            Err(_) => {
+                invalid_input!("Invalid LZXC window size ({})",
+                               control_data.window_size);
+            }
+        };
+        let chunk_size = consts::CHUNK_SIZE as u32;
+        let reset_interval = control_data.reset_interval;
+        if reset_interval == 0 || !reset_interval.is_multiple_of(chunk_size) {
+            invalid_input!("Invalid LZXC reset interval ({})",
+                           reset_interval);
+        }
+        let writer = ContentWriter {
+            writer,
+            prefix: [0; 2],
+            prefix_len: 0,
+            chunk_bytes_remaining: 0,
+            compressed_len: 0,
+            block_offsets: Vec::new(),
+        };
+        let mut encoder = Encoder::new(writer, window, uncompressed_size)?;
+        encoder.set_reset_interval(reset_interval / chunk_size)?;
+        Ok(ChmEncoder { encoder })
+    }
+
+    /// Enables or disables x86 E8 call translation (see
+    /// `Encoder::set_e8_translation`).
+    pub fn set_e8_translation(&mut self, enable: bool) -> io::Result<()> {
+        self.encoder.set_e8_translation(enable)
+    }
+
+    /// Returns the reset table for the content written so far.  Once the
+    /// content is finished, this can be stored in the CHM file as is (see
+    /// `ResetTable::to_bytes`).
+    pub fn reset_table(&self) -> ResetTable {
+        let writer = self.encoder.get_ref();
+        ResetTable {
+            uncompressed_len: self.encoder.uncompressed_bytes_written(),
+            compressed_len: writer.compressed_len,
+            block_len: consts::CHUNK_SIZE as u64,
+            block_offsets: writer.block_offsets.clone(),
+        }
+    }
+
+    /// Finishes the content, returning the total number of uncompressed
+    /// bytes that were encoded.  Returns an error if fewer bytes were
+    /// written than were declared.
+    pub fn finish(&mut self) -> io::Result<u64> { self.encoder.finish() }
+
+    /// Finishes the content (as with `finish()`) and returns the underlying
+    /// writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        Ok(self.encoder.into_inner()?.writer)
+    }
+}
+
+impl<W: Write> Write for ChmEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.encoder.flush() }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{ChmDecoder, ChmEncoder, ControlData, ResetTable};
+    use byteorder::{ByteOrder, LittleEndian};
+    use internal::encoder::Encoder;
+    use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+    /// Compresses `data` the way a CHM file stores it, returning the content
+    /// and the encoded reset table.
+    fn compress_content(data: &[u8], control: &ControlData)
+                        -> (Vec<u8>, Vec<u8>) {
+        let mut encoder =
+            ChmEncoder::new(Vec::new(), control, data.len() as u64)
+                .unwrap();
+        encoder.set_e8_translation(true).unwrap();
+        encoder.write_all(data).unwrap();
+        let table = encoder.reset_table();
+        (encoder.into_inner().unwrap(), table.to_bytes())
+    }
+
+    fn control_data(reset_interval: u32, window_size: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 0x1c];
+        LittleEndian::write_u32(&mut data[0..4], 6);
+        data[4..8].copy_from_slice(b"LZXC");
+        LittleEndian::write_u32(&mut data[8..12], 2);
+        LittleEndian::write_u32(&mut data[12..16], reset_interval);
+        LittleEndian::write_u32(&mut data[16..20], window_size);
+        LittleEndian::write_u32(&mut data[20..24], 1);
+        data
+    }
+
+    #[test]
+    fn parse_control_data() {
+        let control = ControlData::parse(&control_data(2, 4)).unwrap();
+        assert_eq!(control,
+                   ControlData {
+                       window_size: 0x20000,
+                       reset_interval: 0x10000,
+                   });
+        assert_eq!(control.window().unwrap(), 17);
+        let mut version1 = control_data(0x10000, 0x8000);
+        LittleEndian::write_u32(&mut version1[8..12], 1);
+        let control = ControlData::parse(&version1).unwrap();
+        assert_eq!(control.window_size, 0x8000);
+        assert_eq!(control.window().unwrap(), 15);
+        let error = ControlData::parse(&version1[..16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let control = ControlData::parse(&control_data(2, 3)).unwrap();
+        assert!(control.window().is_err());
+    }
+
+    #[test]
+    fn decode_and_seek_content() {
+        let data: Vec<u8> = (0..200_000u32)
+            .map(|index| (index / 7 % 251) as u8 ^ (index >> 12) as u8)
+            .collect();
+        let control = ControlData::parse(&control_data(2, 2)).unwrap();
+        let (content, table) = compress_content(&data, &control);
+        let table = ResetTable::parse(&table).unwrap();
+        assert_eq!(table.block_offsets.len(), 7);
+        assert_eq!(table.compressed_len, content.len() as u64);
+        // The content need not be at the start of the reader.
+        let mut file = b"CHM file header".to_vec();
+        file.extend_from_slice(&content);
+        let mut reader = Cursor::new(file);
+        reader.seek(SeekFrom::Start(15)).unwrap();
+        let mut decoder = ChmDecoder::new(reader, &control, &table).unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+        for &offset in &[150_000u64, 70_000, 0, 131_072, 199_990] {
+            assert_eq!(decoder.seek(SeekFrom::Start(offset)).unwrap(),
+                       offset);
+            let mut buffer = [0u8; 10];
+            decoder.read_exact(&mut buffer).unwrap();
+            let offset = offset as usize;
+            assert_eq!(&buffer, &data[offset..(offset + 10)]);
+        }
+        assert_eq!(decoder.seek(SeekFrom::Current(-20)).unwrap(), 199_980);
+        let mut rest = Vec::new();
+        decoder.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[199_980..]);
+    }
+
+    #[test]
+    fn encoded_tables_match_chm_layout() {
+        let control = ControlData {
+            window_size: 0x10000,
+            reset_interval: 0x10000,
+        };
+        assert_eq!(control.to_bytes(), control_data(2, 2));
+        let control = ControlData {
+            window_size: 0x8000,
+            reset_interval: 0x18000,
+        };
+        assert_eq!(ControlData::parse(&control.to_bytes()).unwrap(), control);
+        // The content is the LZXD stream without its chunk size prefixes, and
+        // the reset table gives the offset of every chunk.
+        let data = b"Help topic. ".repeat(8000);
+        let (content, table) = compress_content(&data, &control);
+        let mut stream = Vec::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut stream, 15, data.len() as u64).unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.set_reset_interval(3).unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        let mut expected_content = Vec::new();
+        let mut expected_table = vec![0u8; 0x28];
+        LittleEndian::write_u32(&mut expected_table[0..4], 2);
+        LittleEndian::write_u32(&mut expected_table[4..8], 3);
+        LittleEndian::write_u32(&mut expected_table[8..12], 8);
+        LittleEndian::write_u32(&mut expected_table[12..16], 0x28);
+        LittleEndian::write_u64(&mut expected_table[16..24],
+                                data.len() as u64);
+        LittleEndian::write_u64(&mut expected_table[32..40], 0x8000);
+        let mut rest = stream.as_slice();
+        while !rest.is_empty() {
+            let chunk_len = LittleEndian::read_u16(rest) as usize;
+            expected_table
+                .extend_from_slice(&(expected_content.len() as u64)
+                                       .to_le_bytes());
+            expected_content.extend_from_slice(&rest[2..(2 + chunk_len)]);
+            rest = &rest[(2 + chunk_len)..];
+        }
+        LittleEndian::write_u64(&mut expected_table[24..32],
+                                expected_content.len() as u64);
+        assert!(content == expected_content);
+        assert_eq!(table, expected_table);
+        let error = ChmEncoder::new(Vec::new(),
+                                    &ControlData {
+                                        window_size: 0x8000,
+                                        reset_interval: 0x100,
+                                    },
+                                    10)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn invalid_reset_table() {
+        let control = ControlData::parse(&control_data(1, 1)).unwrap();
+        let (_, mut table) = compress_content(&[7; 0x9000], &control);
+        assert!(ResetTable::parse(&table[..0x30]).is_err());
+        let parsed = ResetTable::parse(&table).unwrap();
+        let reader = Cursor::new(Vec::new());
+        let error = ChmDecoder::new(reader, &control, &ResetTable {
+                                        block_offsets: vec![0],
+                                        ..parsed.clone()
+                                    })
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        LittleEndian::write_u32(&mut table[8..12], 4);
+        assert!(ResetTable::parse(&table).is_err());
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,77 @@
+use internal::btype::BlockType;
+
+// ========================================================================= //
+
+/// Information about one chunk of an LZXD stream, as emitted by an `Encoder`
+/// or read by a `Decoder`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ChunkInfo {
+    /// The position of the chunk's first byte within the uncompressed data.
+    pub uncompressed_offset: u64,
+    /// The number of uncompressed bytes in the chunk.
+    pub uncompressed_len: usize,
+    /// The position of the chunk's two-byte size prefix (or of its first
+    /// byte, in a raw stream) within the compressed stream.
+    pub compressed_offset: u64,
+    /// The number of compressed bytes in the chunk, not counting the size
+    /// prefix (that is, the value stored in the prefix, or in a raw stream,
+    /// the length that the container must record).
+    pub compressed_len: usize,
+}
+
+/// Information about one block of an LZXD stream, as read by a `Decoder`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct BlockInfo {
+    /// The type of the block.
+    pub block_type: BlockType,
+    /// The position of the block's first byte within the uncompressed data.
+    pub uncompressed_offset: u64,
+    /// The number of uncompressed bytes in the block.
+    pub uncompressed_len: usize,
+    /// The position of the block's header within the compressed stream, in
+    /// bits.
+    pub compressed_bit_offset: u64,
+}
+
+/// An index of where each chunk of an LZXD stream starts, as built by an
+/// `Encoder` (see `Encoder::set_chunk_index`).  Its fields are public so
+/// that callers can store it in whatever form they need.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ChunkIndex {
+    /// For each chunk, the position of its first byte within the
+    /// uncompressed data, paired with the position of its size prefix (or of
+    /// its first byte, in a raw stream) within the compressed stream.
+    pub entries: Vec<(u64, u64)>,
+    /// The total number of uncompressed bytes covered by the index.
+    pub uncompressed_len: u64,
+    /// The total number of compressed bytes covered by the index.
+    pub compressed_len: u64,
+}
+
+impl ChunkIndex {
+    /// Returns the entry for the chunk containing the given uncompressed
+    /// offset, or `None` if the offset is past the end of the index.
+    pub fn find(&self, offset: u64) -> Option<(u64, u64)> {
+        if offset >= self.uncompressed_len {
+            return None;
+        }
+        let index = match self.entries
+            .binary_search_by_key(&offset, |&(uncompressed, _)| uncompressed) {
+            Ok(index) => index,
+            Err(index) => index.checked_sub(1)?,
+        };
+        Some(self.entries[index])
+    }
+}
+
+// ========================================================================= //
+
+/// A function to be called after each chunk is processed, with the number of
+/// uncompressed and compressed bytes processed so far.
+pub type ProgressCallback = Box<dyn FnMut(u64, u64)>;
+
+/// A function to be called before each chunk is processed, which returns true
+/// if the operation should be aborted.
+pub type CancelCheck = Box<dyn FnMut() -> bool>;
+
+// ========================================================================= //
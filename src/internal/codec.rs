@@ -0,0 +1,121 @@
+use internal::decoder::decompress_to_writer;
+use internal::encoder::compress_from_reader;
+use internal::window::{ALL_WINDOW_SIZES, WindowSize};
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+/// A compression format, as seen by generic archive code.
+///
+/// This trait is object-safe, so that frameworks supporting several formats
+/// can hold each one as a `Box<dyn Codec>` and drive them all the same way.
+pub trait Codec {
+    /// Returns a short, human-readable name for the format.
+    fn name(&self) -> &str;
+
+    /// Returns the window sizes that the format supports.
+    fn window_sizes(&self) -> &[WindowSize];
+
+    /// Returns true if `decompress` must be told the uncompressed size of
+    /// the data up front.
+    fn needs_uncompressed_size(&self) -> bool;
+
+    /// Compresses all of the data from `reader` up to end-of-file, writing
+    /// the compressed stream to `writer`, and returns the number of
+    /// uncompressed bytes.
+    fn compress(&self, reader: &mut dyn Read, writer: &mut dyn Write)
+                -> io::Result<u64>;
+
+    /// Decompresses a stream from `reader`, writing the decompressed data to
+    /// `writer`, and returns the number of bytes written.  Returns an error
+    /// if the uncompressed size is required but not given.
+    fn decompress(&self, reader: &mut dyn Read, writer: &mut dyn Write,
+                  uncompressed_size: Option<u64>)
+                  -> io::Result<u64>;
+}
+
+// ========================================================================= //
+
+/// The LZXD format with a particular window size, as a `Codec`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LzxdCodec {
+    window: WindowSize,
+}
+
+impl LzxdCodec {
+    /// Returns a codec that compresses and decompresses LZXD streams using
+    /// the given window size.
+    pub fn new(window: WindowSize) -> LzxdCodec { LzxdCodec { window } }
+
+    /// Returns the window size used by this codec.
+    pub fn window(&self) -> WindowSize { self.window }
+}
+
+impl Codec for LzxdCodec {
+    fn name(&self) -> &str { "LZXD" }
+
+    fn window_sizes(&self) -> &[WindowSize] { &ALL_WINDOW_SIZES }
+
+    fn needs_uncompressed_size(&self) -> bool { true }
+
+    fn compress(&self, mut reader: &mut dyn Read,
+                mut writer: &mut dyn Write)
+                -> io::Result<u64> {
+        compress_from_reader(&mut reader, self.window.exponent(), &mut writer)
+    }
+
+    fn decompress(&self, reader: &mut dyn Read, mut writer: &mut dyn Write,
+                  uncompressed_size: Option<u64>)
+                  -> io::Result<u64> {
+        match uncompressed_size {
+            Some(size) => {
+                decompress_to_writer(reader,
+                                     self.window.exponent(),
+                                     size,
+                                     &mut writer)
+            }
+            None => {
+                invalid_input!("LZXD decompression requires the \
+                                uncompressed size");
+            }
+        }
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{Codec, LzxdCodec};
+    use internal::encoder::compress;
+    use internal::window::WindowSize;
+    use std::io;
+
+    #[test]
+    fn round_trip_through_trait_object() {
+        let codec: Box<dyn Codec> = Box::new(LzxdCodec::new(WindowSize::Kb64));
+        assert_eq!(codec.name(), "LZXD");
+        assert_eq!(codec.window_sizes().len(), 7);
+        assert!(codec.needs_uncompressed_size());
+        let data = b"Any codec will do. ".repeat(3000);
+        let mut compressed = Vec::new();
+        assert_eq!(codec.compress(&mut data.as_slice(), &mut compressed)
+                       .unwrap(),
+                   data.len() as u64);
+        assert_eq!(compressed, compress(&data, 16).unwrap());
+        let mut output = Vec::new();
+        assert_eq!(codec.decompress(&mut compressed.as_slice(),
+                                    &mut output,
+                                    Some(data.len() as u64))
+                       .unwrap(),
+                   data.len() as u64);
+        assert!(output == data);
+        let error = codec.decompress(&mut compressed.as_slice(),
+                                     &mut output,
+                                     None)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+// ========================================================================= //
@@ -6,6 +6,185 @@ pub const WINDOW_MIN: u16 = 15;
 /// The maximum permitted value for the `window` argument.
 pub const WINDOW_MAX: u16 = 21;
 
+/// The maximum permitted value for the `window` argument in an LZX DELTA
+/// stream.
+pub const WINDOW_MAX_DELTA: u16 = 25;
+
+/// The minimum value for the `window` argument that is accepted with the
+/// `nonstandard-windows` feature, which also allows any window up to
+/// `WINDOW_MAX_DELTA` in a standard LZXD stream.
+pub const WINDOW_MIN_NONSTANDARD: u16 = 10;
+
+/// The number of uncompressed bytes in each chunk of a standard LZXD stream
+/// (every chunk but the last).  Each compressed chunk is preceded by a
+/// two-byte size prefix.
 pub const CHUNK_SIZE: usize = 0x8000;
 
+/// The maximum number of uncompressed bytes that a single block can hold
+/// (blocks may span multiple chunks).
+pub const MAX_BLOCK_SIZE: usize = 0xff_ffff;
+
+pub const NUM_CHARS: usize = 256;
+
+/// The minimum length of a match, in bytes.
+pub const MIN_MATCH: usize = 2;
+
+/// The maximum length of a match, in bytes.
+pub const MAX_MATCH: usize = 257;
+
+/// The maximum length of a match in an LZX DELTA stream, in bytes.
+pub const MAX_DELTA_MATCH: usize = MAX_MATCH + 0x7fff;
+
+pub const NUM_PRIMARY_LENGTHS: usize = 7;
+pub const NUM_SECONDARY_LENGTHS: usize = 249;
+pub const NUM_ALIGNED_ELEMENTS: usize = 8;
+
+pub const MAX_CODE_LENGTH: u8 = 16;
+pub const MAX_ALIGNED_CODE_LENGTH: u8 = 7;
+pub const ALIGNED_LENGTH_BITS: u16 = 3;
+
+pub const MAX_POSITION_SLOTS: usize = 290;
+pub const MAX_MAIN_ELEMENTS: usize = NUM_CHARS + 8 * MAX_POSITION_SLOTS;
+
+pub const EXTRA_BITS: [u8; MAX_POSITION_SLOTS] = extra_bits_table();
+pub const POSITION_BASE: [u32; MAX_POSITION_SLOTS] = position_base_table();
+
+/// Each pair of position slots after the first two has one more extra bit
+/// than the pair before, up to a limit of 17.
+const fn extra_bits_table() -> [u8; MAX_POSITION_SLOTS] {
+    let mut table = [0u8; MAX_POSITION_SLOTS];
+    let mut slot = 4;
+    while slot < MAX_POSITION_SLOTS {
+        let bits = slot / 2 - 1;
+        table[slot] = if bits < 17 { bits as u8 } else { 17 };
+        slot += 1;
+    }
+    table
+}
+
+/// Each position slot starts where the one before it ends.
+const fn position_base_table() -> [u32; MAX_POSITION_SLOTS] {
+    let extra_bits = extra_bits_table();
+    let mut table = [0u32; MAX_POSITION_SLOTS];
+    let mut slot = 1;
+    while slot < MAX_POSITION_SLOTS {
+        table[slot] = table[slot - 1] + (1 << extra_bits[slot - 1]);
+        slot += 1;
+    }
+    table
+}
+
+/// Returns the number of position slots used for the given window size.
+pub fn num_position_slots(window: u16) -> usize {
+    if window < 20 {
+        2 * window as usize
+    } else {
+        // Past 2^18, every slot spans 2^17 offsets.
+        34 + (1 << (window - 17))
+    }
+}
+
+/// Returns the number of elements in the main tree for the given window size.
+pub fn num_main_elements(window: u16) -> usize {
+    NUM_CHARS + 8 * num_position_slots(window)
+}
+
+/// Returns the largest permitted window exponent for an LZX DELTA stream (if
+/// `delta` is true) or a standard LZXD stream (if not).
+pub fn max_window(delta: bool) -> u16 {
+    if delta || cfg!(feature = "nonstandard-windows") {
+        WINDOW_MAX_DELTA
+    } else {
+        WINDOW_MAX
+    }
+}
+
+/// Returns true if `window` is a permitted window exponent for an LZX DELTA
+/// stream (if `delta` is true) or a standard LZXD stream (if not).
+pub fn is_valid_window(window: u16, delta: bool) -> bool {
+    let min = if cfg!(feature = "nonstandard-windows") {
+        WINDOW_MIN_NONSTANDARD
+    } else {
+        WINDOW_MIN
+    };
+    (min..=max_window(delta)).contains(&window)
+}
+
+/// Returns the size of the buffer that a decoder needs for the given window:
+/// the window itself, but never less than a chunk, so that a chunk doesn't
+/// wrap around a (nonstandard) smaller window.
+pub fn window_buffer_size(window: u16) -> usize {
+    (1usize << window).max(CHUNK_SIZE)
+}
+
+/// Returns true if chunks of the given size never straddle the end of the
+/// window, and always fit in a chunk size prefix once compressed.
+pub fn is_valid_chunk_size(chunk_size: usize) -> bool {
+    chunk_size.is_power_of_two() && chunk_size <= CHUNK_SIZE
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{EXTRA_BITS, MAX_POSITION_SLOTS, POSITION_BASE,
+                WINDOW_MAX_DELTA, WINDOW_MIN_NONSTANDARD, is_valid_window,
+                num_position_slots};
+
+    #[test]
+    fn position_base_matches_extra_bits() {
+        for slot in 1..MAX_POSITION_SLOTS {
+            assert_eq!(POSITION_BASE[slot],
+                       POSITION_BASE[slot - 1] +
+                           (1 << EXTRA_BITS[slot - 1]));
+        }
+    }
+
+    #[test]
+    fn position_slots_cover_window() {
+        for window in WINDOW_MIN_NONSTANDARD..(WINDOW_MAX_DELTA + 1) {
+            let slots = num_position_slots(window);
+            let last = slots - 1;
+            let max_offset = POSITION_BASE[last] +
+                (1 << EXTRA_BITS[last]) - 1 - 2;
+            assert!(max_offset >= (1 << window) - 3);
+            assert!(POSITION_BASE[last] - 2 < (1 << window) - 3);
+        }
+        assert_eq!(num_position_slots(20), 42);
+        assert_eq!(num_position_slots(21), 50);
+        assert_eq!(num_position_slots(25), MAX_POSITION_SLOTS);
+    }
+
+    #[test]
+    fn table_values() {
+        assert_eq!(EXTRA_BITS[..8], [0, 0, 0, 0, 1, 1, 2, 2]);
+        assert_eq!(EXTRA_BITS[35], 16);
+        assert_eq!(EXTRA_BITS[36], 17);
+        assert_eq!(EXTRA_BITS[MAX_POSITION_SLOTS - 1], 17);
+        assert_eq!(POSITION_BASE[..8], [0, 1, 2, 3, 4, 6, 8, 12]);
+        assert_eq!(POSITION_BASE[49], 1966080);
+    }
+
+    #[cfg(not(feature = "nonstandard-windows"))]
+    #[test]
+    fn window_depends_on_variant() {
+        assert!(is_valid_window(21, false));
+        assert!(!is_valid_window(22, false));
+        assert!(is_valid_window(25, true));
+        assert!(!is_valid_window(26, true));
+        assert!(!is_valid_window(14, true));
+    }
+
+    #[cfg(feature = "nonstandard-windows")]
+    #[test]
+    fn nonstandard_windows_are_valid() {
+        assert!(is_valid_window(10, false));
+        assert!(is_valid_window(14, false));
+        assert!(is_valid_window(22, false));
+        assert!(is_valid_window(25, false));
+        assert!(!is_valid_window(9, true));
+        assert!(!is_valid_window(26, true));
+    }
+}
+
 // ========================================================================= //
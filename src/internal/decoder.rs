@@ -1,25 +1,227 @@
 use byteorder::{LittleEndian, ReadBytesExt};
-use internal::bits::BitReader;
+use internal::bits::{BitPosition, BitReader};
 use internal::btype::BlockType;
+use internal::chunk::{BlockInfo, CancelCheck, ChunkInfo, ProgressCallback};
 use internal::consts;
-use std::io::{self, Read};
+use internal::e8;
+use internal::error::{self, Error};
+use internal::huffman::{HuffmanTable, max_secondary_len};
+use internal::pretree::{self, PretreeTable};
+use std::fmt;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::mem;
+
+// ========================================================================= //
+
+type BlockCallback = Box<dyn FnMut(&BlockInfo)>;
+type ChunkCallback = Box<dyn FnMut(&ChunkInfo)>;
+
+type MainTable =
+    HuffmanTable<{ max_secondary_len(consts::MAX_MAIN_ELEMENTS) }>;
+type LengthTable =
+    HuffmanTable<{ max_secondary_len(consts::NUM_SECONDARY_LENGTHS) }>;
+type AlignedTable =
+    HuffmanTable<{ max_secondary_len(consts::NUM_ALIGNED_ELEMENTS) }>;
+
+// ========================================================================= //
+
+/// Memory that a `Decoder` keeps its window and E8-translated output in: a
+/// `Vec<u8>`, which grows as needed, for decoders that allocate their own
+/// buffers, or a caller-provided `&mut [u8]` for those created with
+/// `Decoder::with_buffers`, which never allocate.
+pub trait DecoderBuffer: AsRef<[u8]> + AsMut<[u8]> {
+    /// Returns true if the buffer is, or can grow to be, at least `len`
+    /// bytes long.
+    fn can_hold(&self, len: usize) -> bool;
+
+    /// Grows the buffer to at least `len` bytes, which `can_hold()` must
+    /// allow.
+    fn grow_to(&mut self, len: usize);
+}
+
+impl DecoderBuffer for Vec<u8> {
+    fn can_hold(&self, _len: usize) -> bool { true }
+
+    fn grow_to(&mut self, len: usize) {
+        if self.len() < len {
+            self.resize(len, 0);
+        }
+    }
+}
+
+impl DecoderBuffer for &mut [u8] {
+    fn can_hold(&self, len: usize) -> bool { self.len() >= len }
+
+    fn grow_to(&mut self, len: usize) { debug_assert!(self.can_hold(len)); }
+}
+
+// ========================================================================= //
+
+/// The code lengths of the main, length, and aligned offset trees.  These
+/// are fixed-size arrays (of which the main tree uses the first
+/// `num_main_elements`), so that a decoder never allocates them.
+#[derive(Clone, PartialEq)]
+struct CodeLengths {
+    num_main_elements: usize,
+    main: [u8; consts::MAX_MAIN_ELEMENTS],
+    length: [u8; consts::NUM_SECONDARY_LENGTHS],
+    aligned: [u8; consts::NUM_ALIGNED_ELEMENTS],
+}
+
+impl CodeLengths {
+    fn new(num_main_elements: usize) -> CodeLengths {
+        CodeLengths {
+            num_main_elements,
+            main: [0; consts::MAX_MAIN_ELEMENTS],
+            length: [0; consts::NUM_SECONDARY_LENGTHS],
+            aligned: [0; consts::NUM_ALIGNED_ELEMENTS],
+        }
+    }
+
+    /// Returns the code lengths of the main tree.
+    fn main(&self) -> &[u8] { &self.main[..self.num_main_elements] }
+}
+
+// ========================================================================= //
+
+/// The parts of a `Decoder`'s state that decoding a chunk can change, saved
+/// at the start of each chunk so that the chunk can be decoded again from the
+/// beginning if reading the compressed data fails partway through.
+#[derive(Clone)]
+struct ChunkCheckpoint {
+    total_uncompressed_bytes_remaining: u64,
+    chunk_compressed_bytes_remaining: usize,
+    chunk_uncompressed_bytes_remaining: usize,
+    header_filesize: u32,
+    block_type: BlockType,
+    block_uncompressed_bytes_remaining: usize,
+    block_overrun: usize,
+    recent: (u32, u32, u32),
+    lengths: CodeLengths,
+    window_position: usize,
+    window_contents: Vec<u8>,
+    bytes_decoded: u64,
+}
+
+impl ChunkCheckpoint {
+    fn new() -> ChunkCheckpoint {
+        ChunkCheckpoint {
+            total_uncompressed_bytes_remaining: 0,
+            chunk_compressed_bytes_remaining: 0,
+            chunk_uncompressed_bytes_remaining: 0,
+            header_filesize: 0,
+            block_type: BlockType::Verbatim,
+            block_uncompressed_bytes_remaining: 0,
+            block_overrun: 0,
+            recent: (1, 1, 1),
+            lengths: CodeLengths::new(0),
+            window_position: 0,
+            window_contents: Vec::new(),
+            bytes_decoded: 0,
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// A snapshot of a `Decoder`'s progress through a stream, which can later be
+/// restored to resume decoding from the same point.
+#[derive(Clone)]
+pub struct DecoderState {
+    position: BitPosition,
+    total_uncompressed_bytes_remaining: u64,
+    chunk_compressed_offset: u64,
+    chunk_compressed_bytes_remaining: usize,
+    chunk_uncompressed_bytes_remaining: usize,
+    header_filesize: u32,
+    block_type: BlockType,
+    block_uncompressed_bytes_remaining: usize,
+    block_overrun: usize,
+    recent: (u32, u32, u32),
+    lengths: CodeLengths,
+    main_tree: MainTable,
+    length_tree: LengthTable,
+    aligned_tree: AlignedTable,
+    window: Vec<u8>,
+    window_position: usize,
+    bytes_decoded: u64,
+    e8_buffer: Vec<u8>,
+    output_from_e8_buffer: bool,
+    output_start: usize,
+    output_end: usize,
+}
+
+impl DecoderState {
+    /// Returns the position within the uncompressed data of the next byte
+    /// that will be read after restoring this state.
+    pub fn uncompressed_offset(&self) -> u64 {
+        self.bytes_decoded - (self.output_end - self.output_start) as u64
+    }
+
+    /// Returns the number of bits of compressed data that had been consumed
+    /// when this state was saved.
+    pub fn compressed_bit_offset(&self) -> u64 { self.position.bit_offset() }
+}
 
 // ========================================================================= //
 
 /// An LZXD decoder/decompressor.
 ///
 /// Use the `Read` trait to read decompressed bytes from the `Decoder` stream.
+/// The `Seek` trait can be used to skip forward over decompressed bytes that
+/// aren't needed.
+///
+/// The decoder decodes a whole chunk at a time into its window, and then
+/// serves reads by copying out of the window, so reads of any size touch the
+/// compressed stream only once per chunk.  The `BufRead` trait gives direct
+/// access to the decoded data, so there is no need to wrap a `Decoder` in a
+/// `BufReader`.
 #[allow(dead_code)]
-pub struct Decoder<R: Read> {
+pub struct Decoder<R: Read, B: DecoderBuffer = Vec<u8>> {
     reader: BitReader<R>,
     total_uncompressed_bytes_remaining: u64,
+    chunk_size: usize,
+    reset_interval: u32,
+    chunk_compressed_offset: u64,
     chunk_compressed_bytes_remaining: usize,
     chunk_uncompressed_bytes_remaining: usize,
     header_filesize: u32,
     block_type: BlockType,
     block_uncompressed_bytes_remaining: usize,
+    /// The number of bytes of the next block that were already decoded, by
+    /// a match at the end of the previous one.
+    block_overrun: usize,
     recent: (u32, u32, u32),
-    window: Vec<u8>,
+    num_position_slots: usize,
+    max_window_size: usize,
+    delta: bool,
+    raw: bool,
+    wim: bool,
+    size_known: bool,
+    lengths: CodeLengths,
+    main_tree: MainTable,
+    length_tree: LengthTable,
+    aligned_tree: AlignedTable,
+    pretree: PretreeTable,
+    window: B,
+    window_position: usize,
+    dictionary: Vec<u8>,
+    reset_table: Vec<(u64, u64)>,
+    bytes_decoded: u64,
+    e8_buffer: B,
+    output_from_e8_buffer: bool,
+    output_start: usize,
+    output_end: usize,
+    nonblocking: bool,
+    max_output: u64,
+    max_memory: usize,
+    lenient_eof: bool,
+    ended_early: bool,
+    checkpoint: ChunkCheckpoint,
+    progress_callback: Option<ProgressCallback>,
+    cancel_check: Option<CancelCheck>,
+    block_callback: Option<BlockCallback>,
+    chunk_callback: Option<ChunkCallback>,
 }
 
 impl<R: Read> Decoder<R> {
@@ -27,122 +229,2641 @@ impl<R: Read> Decoder<R> {
     ///
     /// The `window` argument determines the size of the compression window,
     /// and its value must be between the `WINDOW_MIN` and `WINDOW_MAX`
-    /// constants (inclusive).
+    /// constants (inclusive); `WindowSize::exponent()` gives a valid value.
+    /// With the `nonstandard-windows` feature, any value from
+    /// `WINDOW_MIN_NONSTANDARD` to `WINDOW_MAX_DELTA` is accepted.
     ///
     /// The `uncompressed_size` argument must specify the exact size of the of
-    /// the original, uncompressed data, in bytes.
-    pub fn new(mut reader: R, window: u16, uncompressed_size: u64)
+    /// the original, uncompressed data, in bytes.  If the data is smaller
+    /// than the window, the decoder only allocates as much of the window as
+    /// the data can use.  An empty stream (with an `uncompressed_size` of
+    /// zero) has no compressed data at all, so the reader isn't read from.
+    pub fn new(reader: R, window: u16, uncompressed_size: u64)
                -> io::Result<Decoder<R>> {
-        if window < consts::WINDOW_MIN || window > consts::WINDOW_MAX {
-            invalid_input!("Invalid LZX window ({})", window);
+        Decoder::with_variant(reader, window, uncompressed_size, false, false)
+    }
+
+    /// Starts decoding an LZX DELTA stream (see `set_delta`).  This is the
+    /// same as `Decoder::new`, except that the `window` argument may be as
+    /// large as the `WINDOW_MAX_DELTA` constant.
+    pub fn new_delta(reader: R, window: u16, uncompressed_size: u64)
+                     -> io::Result<Decoder<R>> {
+        Decoder::with_variant(reader, window, uncompressed_size, true, false)
+    }
+
+    /// Starts decoding a raw LZXD stream, in which chunks have no size
+    /// prefixes, as stored by containers that record each chunk's compressed
+    /// length elsewhere (such as CHM files).  The decoder doesn't need those
+    /// lengths, since each chunk ends on a 16-bit boundary; the chunk callback
+    /// (see `set_chunk_callback`) reports them as chunks are decoded.
+    /// Otherwise, this is the same as `Decoder::new`.
+    pub fn new_raw(reader: R, window: u16, uncompressed_size: u64)
+                   -> io::Result<Decoder<R>> {
+        Decoder::with_variant(reader, window, uncompressed_size, false, true)
+    }
+
+    /// Starts decoding an LZXD stream whose uncompressed size is not known in
+    /// advance, as stored by containers that only record the compressed
+    /// length.  The decoder reads until the compressed input runs out: the
+    /// stream ends after a chunk that is shorter than the chunk size (as
+    /// found from its size prefix and block headers), or at end-of-file
+    /// after a full chunk.  The underlying reader must therefore contain
+    /// nothing after the stream.
+    ///
+    /// Until the end of the stream is reached, `total_uncompressed_size()`
+    /// returns `u64::MAX`.  Raw streams (see `Decoder::new_raw`) can't be
+    /// decoded this way, since their chunk boundaries can't be found.
+    pub fn with_unknown_size(reader: R, window: u16)
+                             -> io::Result<Decoder<R>> {
+        if !consts::is_valid_window(window, false) {
+            return Err(Error::InvalidWindow(window).into());
+        }
+        let window_buffer = vec![0u8; consts::window_buffer_size(window)];
+        let mut decoder = Decoder::create(reader,
+                                          window,
+                                          0,
+                                          window_buffer,
+                                          Vec::new(),
+                                          false,
+                                          false)?;
+        decoder.size_known = false;
+        if let Err(error) = decoder.start_unknown_size() {
+            return Err(decoder.annotate_error(error));
+        }
+        Ok(decoder)
+    }
+
+    pub(crate) fn with_variant(reader: R, window: u16,
+                               uncompressed_size: u64, delta: bool,
+                               raw: bool)
+                               -> io::Result<Decoder<R>> {
+        if !consts::is_valid_window(window, delta) {
+            return Err(Error::InvalidWindow(window).into());
+        }
+        let window_size =
+            stream_window_size(consts::window_buffer_size(window),
+                               uncompressed_size,
+                               0);
+        let window_buffer = vec![0u8; window_size];
+        let mut decoder = Decoder::create(reader,
+                                          window,
+                                          uncompressed_size,
+                                          window_buffer,
+                                          Vec::new(),
+                                          raw,
+                                          false)?;
+        decoder.delta = delta;
+        Ok(decoder)
+    }
+
+    /// Starts decoding a chunk of a WIM resource, which is a raw LZXD stream
+    /// with a different block header layout and no E8 translation header
+    /// (translation is always enabled, with a fixed translation size).
+    pub(crate) fn new_wim(reader: R, window: u16, uncompressed_size: u64)
+                          -> io::Result<Decoder<R>> {
+        if !consts::is_valid_window(window, false) {
+            return Err(Error::InvalidWindow(window).into());
+        }
+        let window_size =
+            stream_window_size(consts::window_buffer_size(window),
+                               uncompressed_size,
+                               0);
+        let window_buffer = vec![0u8; window_size];
+        Decoder::create(reader,
+                        window,
+                        uncompressed_size,
+                        window_buffer,
+                        Vec::new(),
+                        true,
+                        true)
+    }
+
+    /// Like `Decoder::new`, but uses the given buffer (which must hold at
+    /// least as many bytes as the window size) for the decoder's window,
+    /// instead of allocating one.  Only the first window-size bytes are
+    /// used, and their initial contents don't matter.  The buffer can be
+    /// recovered afterwards with `into_window_buffer()`, to reuse it for
+    /// another stream.  A `Box<[u8]>` can be passed with `into_vec()`.
+    ///
+    /// Apart from the window, the decoder only allocates a chunk-sized
+    /// buffer for E8-translated output, the first time it needs one (and,
+    /// in nonblocking mode, the copies of the input and window that let it
+    /// decode a chunk again).  `Decoder::with_buffers` takes the E8 buffer
+    /// from the caller too, and never allocates.
+    pub fn with_window_buffer(reader: R, window: u16,
+                              uncompressed_size: u64, mut buffer: Vec<u8>)
+                              -> io::Result<Decoder<R>> {
+        if !consts::is_valid_window(window, false) {
+            return Err(Error::InvalidWindow(window).into());
+        }
+        let window_size = consts::window_buffer_size(window);
+        if buffer.len() < window_size {
+            invalid_input!("LZX window buffer is too small ({} < {})",
+                           buffer.len(),
+                           window_size);
+        }
+        buffer.truncate(window_size);
+        Decoder::create(reader,
+                        window,
+                        uncompressed_size,
+                        buffer,
+                        Vec::new(),
+                        false,
+                        false)
+    }
+
+    /// Enables or disables nonblocking mode (disabled by default).
+    ///
+    /// In nonblocking mode, if reading from the underlying reader fails
+    /// partway through a chunk (for example, with `WouldBlock` from a
+    /// nonblocking socket), the decoder returns to the start of that chunk,
+    /// keeping any compressed bytes already read, so that the same call can
+    /// be retried later.  This makes decoding somewhat slower.  (The stream
+    /// header must still be available when the decoder is created.)
+    pub fn set_nonblocking(&mut self, enable: bool) {
+        self.nonblocking = enable;
+        if !enable {
+            self.reader.clear_rewind_point();
+        }
+    }
+
+    /// Decodes ahead until at least `num_bytes` bytes are buffered (or the
+    /// end of the data is reached), and returns the buffered bytes without
+    /// consuming them; they are returned again by the next read.  This lets a
+    /// caller sniff the start of the decompressed data (for example, to check
+    /// for a magic number) before handing the decoder on to a parser.
+    ///
+    /// The buffered bytes count towards the limit set with
+    /// `set_max_memory()`; looking further ahead than it allows fails with
+    /// `Error::MemoryLimitExceeded`.
+    pub fn fill_lookahead(&mut self, num_bytes: usize) -> io::Result<&[u8]> {
+        if self.output_end - self.output_start < num_bytes {
+            // The stream can't yield more than its remaining bytes, however
+            // many the caller asks for.
+            let capacity =
+                self.uncompressed_bytes_remaining().min(num_bytes as u64) as
+                    usize;
+            let needed = self.memory_usage().saturating_add(capacity);
+            if needed > self.max_memory {
+                return Err(Error::MemoryLimitExceeded(needed).into());
+            }
+            let mut lookahead = Vec::with_capacity(capacity);
+            lookahead.extend_from_slice(self.pending_output());
+            let mut result = Ok(());
+            while lookahead.len() < num_bytes {
+                self.output_start = self.output_end;
+                match self.decode_next_chunk() {
+                    Ok(true) => {
+                        lookahead.extend_from_slice(self.pending_output())
+                    }
+                    Ok(false) => break,
+                    Err(error) => {
+                        result = Err(error);
+                        break;
+                    }
+                }
+            }
+            // Serve the buffered bytes from the E8 buffer, which isn't used
+            // again until they have all been read.
+            self.e8_buffer = lookahead;
+            self.output_from_e8_buffer = true;
+            self.output_start = 0;
+            self.output_end = self.e8_buffer.len();
+            result?;
         }
-        let window_size: usize = 1 << window;
-        let chunk_compressed_size = reader.read_u16::<LittleEndian>()? as
-            usize;
+        Ok(self.pending_output())
+    }
+
+    /// Sets a limit on how many bytes of memory the decoder may allocate (no
+    /// limit by default), as estimated by `DecoderBuilder::memory_usage()`.
+    /// At present, only `fill_lookahead()` can grow the decoder's memory
+    /// after it has been created, and it checks this limit.
+    pub fn set_max_memory(&mut self, max_bytes: usize) {
+        self.max_memory = max_bytes;
+    }
+
+    /// Returns roughly how many bytes the decoder has allocated, estimated
+    /// as by `memory_usage()`.
+    fn memory_usage(&self) -> usize {
+        let chunk_len = self.total_uncompressed_size()
+            .min(self.chunk_size as u64) as usize;
+        let mut total = self.window.len() + self.dictionary.len() +
+            chunk_len.max(self.e8_buffer.capacity()) +
+            16 * self.reset_table.len() +
+            mem::size_of::<Self>();
+        if self.nonblocking {
+            total += 2 * chunk_len;
+        }
+        total
+    }
+
+    /// Copies as many of the next bytes of decompressed data into `buf` as
+    /// are available, without consuming them, and returns the number of bytes
+    /// copied.  This is fewer than `buf.len()` only at the end of the data.
+    /// See `fill_lookahead`.
+    pub fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let lookahead = self.fill_lookahead(buf.len())?;
+        let num_bytes = lookahead.len().min(buf.len());
+        buf[..num_bytes].copy_from_slice(&lookahead[..num_bytes]);
+        Ok(num_bytes)
+    }
+}
+
+impl<'a, R: Read> Decoder<R, &'a mut [u8]> {
+    /// Like `Decoder::new`, but decodes using only buffers provided by the
+    /// caller, so that decoding never allocates.  The window is kept in
+    /// `window_buffer`, which must hold at least as many bytes as the window
+    /// size (only that many are used, and their initial contents don't
+    /// matter).  Chunks that need E8 translation are translated into
+    /// `chunk_buffer`, which must hold at least a chunk (`CHUNK_SIZE` bytes,
+    /// or the uncompressed size if that's smaller).  The Huffman tables and
+    /// code lengths are fixed-size arrays within the `Decoder` itself.
+    ///
+    /// Nonblocking mode and lookahead aren't available for such a decoder,
+    /// since they need buffers that can grow.  Errors, callbacks that
+    /// capture data, and the methods that copy data into or out of the
+    /// decoder (such as `set_dictionary()` and `save_state()`) still
+    /// allocate.
+    pub fn with_buffers(reader: R, window: u16, uncompressed_size: u64,
+                        window_buffer: &'a mut [u8],
+                        chunk_buffer: &'a mut [u8])
+                        -> io::Result<Decoder<R, &'a mut [u8]>> {
+        if !consts::is_valid_window(window, false) {
+            return Err(Error::InvalidWindow(window).into());
+        }
+        let window_size = consts::window_buffer_size(window);
+        if window_buffer.len() < window_size {
+            invalid_input!("LZX window buffer is too small ({} < {})",
+                           window_buffer.len(),
+                           window_size);
+        }
+        let chunk_size =
+            uncompressed_size.min(consts::CHUNK_SIZE as u64) as usize;
+        if chunk_buffer.len() < chunk_size {
+            invalid_input!("LZX chunk buffer is too small ({} < {})",
+                           chunk_buffer.len(),
+                           chunk_size);
+        }
+        Decoder::create(reader,
+                        window,
+                        uncompressed_size,
+                        &mut window_buffer[..window_size],
+                        chunk_buffer,
+                        false,
+                        false)
+    }
+}
+
+impl<R: Read, B: DecoderBuffer> Decoder<R, B> {
+    fn create(reader: R, window: u16, uncompressed_size: u64,
+              window_buffer: B, e8_buffer: B, raw: bool, wim: bool)
+              -> io::Result<Decoder<R, B>> {
+        let mut reader = BitReader::new(reader);
+        // An empty stream has no chunks (and so no stream header either), so
+        // there's nothing to read.
+        let chunk_compressed_size = if raw || uncompressed_size == 0 {
+            0
+        } else {
+            reader.read_u16::<LittleEndian>()
+                .map_err(error::eof_as_truncated)? as usize
+        };
         let chunk_uncompressed_size =
             uncompressed_size.min(consts::CHUNK_SIZE as u64) as usize;
         let mut decoder = Decoder {
-            reader: BitReader::new(reader),
+            reader,
             total_uncompressed_bytes_remaining: uncompressed_size,
+            chunk_size: consts::CHUNK_SIZE,
+            reset_interval: 0,
+            chunk_compressed_offset: 0,
             chunk_compressed_bytes_remaining: chunk_compressed_size,
             chunk_uncompressed_bytes_remaining: chunk_uncompressed_size,
             header_filesize: 0,
             block_type: BlockType::Verbatim,
             block_uncompressed_bytes_remaining: 0,
-            recent: (0, 0, 0),
-            window: vec![0u8; window_size],
+            block_overrun: 0,
+            recent: (1, 1, 1),
+            num_position_slots: consts::num_position_slots(window),
+            max_window_size: consts::window_buffer_size(window),
+            delta: false,
+            raw,
+            wim,
+            size_known: true,
+            lengths: CodeLengths::new(consts::num_main_elements(window)),
+            main_tree: HuffmanTable::empty(),
+            length_tree: HuffmanTable::empty(),
+            aligned_tree: HuffmanTable::empty(),
+            pretree: HuffmanTable::empty(),
+            window: window_buffer,
+            window_position: 0,
+            dictionary: Vec::new(),
+            reset_table: Vec::new(),
+            bytes_decoded: 0,
+            e8_buffer,
+            output_from_e8_buffer: false,
+            output_start: 0,
+            output_end: 0,
+            nonblocking: false,
+            max_output: u64::MAX,
+            max_memory: usize::MAX,
+            lenient_eof: false,
+            ended_early: false,
+            checkpoint: ChunkCheckpoint::new(),
+            progress_callback: None,
+            cancel_check: None,
+            block_callback: None,
+            chunk_callback: None,
         };
-        if decoder.reader.read_bits(1)? != 0 {
-            decoder.header_filesize = decoder.reader.read_bits(32)?;
+        if uncompressed_size != 0 {
+            if let Err(error) = decoder.read_stream_header() {
+                return Err(decoder.annotate_error(error));
+            }
         }
         Ok(decoder)
     }
-}
 
-impl<R: Read> Read for Decoder<R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut bytes_read: usize = 0;
-        while self.total_uncompressed_bytes_remaining > 0 &&
-            bytes_read < buf.len()
+    /// Sets the number of uncompressed bytes in each chunk (32768 by default).
+    ///
+    /// This must match the value used when encoding the stream, and must be
+    /// set before any data is read from the decoder.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) -> io::Result<()> {
+        if !consts::is_valid_chunk_size(chunk_size) {
+            invalid_input!("Invalid LZX chunk size ({})", chunk_size);
+        }
+        if self.bytes_decoded != 0 {
+            invalid_input!("Cannot change LZX chunk size after reading data");
+        }
+        let chunk_len =
+            self.total_uncompressed_bytes_remaining.min(chunk_size as u64) as
+                usize;
+        if !self.e8_buffer.can_hold(chunk_len) {
+            invalid_input!("LZX chunk buffer is too small ({} < {})",
+                           self.e8_buffer.as_ref().len(),
+                           chunk_len);
+        }
+        self.chunk_size = chunk_size;
+        self.chunk_uncompressed_bytes_remaining = chunk_len;
+        Ok(())
+    }
+
+    /// Sets how many chunks the stream's reset interval spans (or zero, the
+    /// default, if the encoder never resets its state).  This must match the
+    /// value used when encoding the stream.
+    pub fn set_reset_interval(&mut self, num_chunks: u32) -> io::Result<()> {
+        if self.bytes_decoded != 0 {
+            invalid_input!("Cannot change LZX reset interval after reading \
+                            data");
+        }
+        self.reset_interval = num_chunks;
+        Ok(())
+    }
+
+    /// Enables or disables decoding the LZX DELTA variant (disabled by
+    /// default), in which a match of `MAX_MATCH` bytes is followed by bits
+    /// giving its extra length (see `Encoder::set_delta`).  The reference
+    /// data should be given with `set_dictionary`.  This must match the
+    /// setting used when encoding the stream, and must be set before any
+    /// data is read from the decoder.  It can't be disabled for a decoder
+    /// created by `Decoder::new_delta` with a window larger than `WINDOW_MAX`.
+    pub fn set_delta(&mut self, enable: bool) -> io::Result<()> {
+        if self.bytes_decoded != 0 {
+            invalid_input!("Cannot change LZX DELTA mode after reading data");
+        }
+        if !enable && self.max_window_size > 1 << consts::max_window(false) {
+            invalid_input!("LZX window is too large for a standard LZXD \
+                            stream ({} bytes)",
+                           self.max_window_size);
+        }
+        self.delta = enable;
+        Ok(())
+    }
+
+    /// Primes the decoder with the same dictionary that was given to the
+    /// encoder, which matches in the stream may refer back to.  The
+    /// dictionary may be at most the window size.  This must be set before
+    /// any data is read from the decoder.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        if dictionary.len() > self.max_window_size {
+            invalid_input!("LZX dictionary is larger than window ({} > {})",
+                           dictionary.len(),
+                           self.max_window_size);
+        }
+        if self.bytes_decoded != 0 {
+            invalid_input!("Cannot change LZX dictionary after reading data");
+        }
+        self.grow_window(dictionary.len());
+        // The window is circular, so placing the dictionary at the end makes
+        // it immediately precede the first decoded byte.
+        self.dictionary.clear();
+        self.dictionary.extend_from_slice(dictionary);
+        self.place_dictionary();
+        Ok(())
+    }
+
+    /// Like `set_dictionary`, but reads the dictionary from `reader` up to
+    /// end-of-file.  This is convenient for the reference file of an LZX
+    /// DELTA stream.
+    pub fn set_dictionary_from_reader<T: Read>(&mut self, reader: T)
+                                               -> io::Result<()> {
+        let mut dictionary = Vec::new();
+        reader.take(self.max_window_size as u64 + 1)
+            .read_to_end(&mut dictionary)?;
+        if dictionary.len() > self.max_window_size {
+            invalid_input!("LZX dictionary is larger than window ({} bytes)",
+                           self.max_window_size);
+        }
+        self.set_dictionary(&dictionary)
+    }
+
+    /// Enlarges the window, if it was allocated smaller than the full window
+    /// size, so that it can hold the whole stream plus a dictionary of the
+    /// given length.
+    fn grow_window(&mut self, dictionary_len: usize) {
+        let window_size = stream_window_size(self.max_window_size,
+                                             self.total_uncompressed_size(),
+                                             dictionary_len);
+        self.window.grow_to(window_size);
+    }
+
+    fn place_dictionary(&mut self) {
+        let window = self.window.as_mut();
+        let start = window.len() - self.dictionary.len();
+        window[start..].copy_from_slice(&self.dictionary);
+    }
+
+    /// Gives the decoder the stream's reset table, as pairs of (uncompressed
+    /// offset, compressed offset) in increasing order, in the same form
+    /// returned by `Encoder::reset_table()`.  Each compressed offset is the
+    /// position of the reset chunk's size prefix, measured from the start of
+    /// the stream.  This allows `seek_to()` to jump directly to the nearest
+    /// reset point.  The reset interval (and chunk size, if not the default)
+    /// must also be set.
+    pub fn set_reset_table(&mut self, table: &[(u64, u64)])
+                           -> io::Result<()> {
+        let mut previous = (0, 0);
+        for &entry in table {
+            if !entry.0.is_multiple_of(self.chunk_size as u64) ||
+                entry.0 < previous.0 || entry.1 < previous.1
+            {
+                invalid_input!("Invalid LZX reset table entry ({:?})",
+                               entry);
+            }
+            previous = entry;
+        }
+        self.reset_table.clear();
+        self.reset_table.extend_from_slice(table);
+        Ok(())
+    }
+
+    /// Sets a hard limit on the number of uncompressed bytes that the decoder
+    /// will produce (no limit by default), regardless of the uncompressed
+    /// size given when it was created.  Decoding a chunk that would go past
+    /// the limit fails with `Error::OutputLimitExceeded` instead.  This
+    /// guards against untrusted streams that claim an enormous size.
+    pub fn set_max_output(&mut self, max_bytes: u64) {
+        self.max_output = max_bytes;
+    }
+
+    /// Chooses what happens if the compressed data ends before the full
+    /// uncompressed size has been decoded.  By default (strict mode), reading
+    /// fails with `Error::Truncated`.  In lenient mode, the decoder instead
+    /// stops after the last chunk that was decoded in full, as though the
+    /// stream ended there, and `ended_early()` returns true.
+    pub fn set_lenient_eof(&mut self, enable: bool) {
+        self.lenient_eof = enable;
+    }
+
+    /// Returns true if the compressed data ended early in lenient mode (see
+    /// `set_lenient_eof`), so that the data read was incomplete.
+    pub fn ended_early(&self) -> bool { self.ended_early }
+
+    /// Sets a function to be called each time the decoder finishes decoding a
+    /// chunk, with the total number of uncompressed bytes decoded and
+    /// compressed bytes consumed so far.
+    pub fn set_progress_callback<F>(&mut self, callback: F)
+        where F: FnMut(u64, u64) + 'static
+    {
+        self.progress_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a function to be called each time the decoder reads a block
+    /// header, describing where the block lies in the compressed and
+    /// uncompressed streams.  This can be used to map out the structure of a
+    /// stream, for example when investigating corrupt archives.  In
+    /// nonblocking mode, if a chunk has to be decoded again after an error
+    /// from the underlying reader, the callback is called again for the
+    /// blocks in that chunk.
+    pub fn set_block_callback<F>(&mut self, callback: F)
+        where F: FnMut(&BlockInfo) + 'static
+    {
+        self.block_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a function to be called each time the decoder finishes decoding a
+    /// chunk, describing where the chunk lies in the compressed and
+    /// uncompressed streams.  The chunk's `compressed_len` is the number of
+    /// bytes actually consumed after its size prefix (if any), so that
+    /// container
+    /// formats (such as CAB, which stores each chunk in a CFDATA block) can
+    /// check it against their own records.  As with `set_block_callback`,
+    /// the callback is called again for a chunk that has to be decoded again
+    /// in nonblocking mode.
+    pub fn set_chunk_callback<F>(&mut self, callback: F)
+        where F: FnMut(&ChunkInfo) + 'static
+    {
+        self.chunk_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a function to be called before the decoder decodes each chunk.
+    /// If it returns true, the read fails with `Error::Cancelled`.
+    pub fn set_cancel_check<F>(&mut self, should_cancel: F)
+        where F: FnMut() -> bool + 'static
+    {
+        self.cancel_check = Some(Box::new(should_cancel));
+    }
+
+    /// Returns the total size of the uncompressed data, as given when the
+    /// decoder was created.
+    pub fn total_uncompressed_size(&self) -> u64 {
+        self.bytes_decoded + self.total_uncompressed_bytes_remaining
+    }
+
+    /// Returns the number of uncompressed bytes that have not yet been read
+    /// from the decoder.
+    pub fn uncompressed_bytes_remaining(&self) -> u64 {
+        self.total_uncompressed_bytes_remaining +
+            (self.output_end - self.output_start) as u64
+    }
+
+    /// Unwraps this decoder, returning its window buffer so that it can be
+    /// reused (see `Decoder::with_window_buffer`).
+    pub fn into_window_buffer(self) -> B { self.window }
+
+    /// Captures the decoder's current progress through the stream, including
+    /// its window, Huffman trees, and recent offsets, so that decoding can
+    /// later be resumed from this point with `restore_state()`.
+    pub fn save_state(&self) -> DecoderState {
+        DecoderState {
+            position: self.reader.save_position(),
+            total_uncompressed_bytes_remaining: self
+                .total_uncompressed_bytes_remaining,
+            chunk_compressed_offset: self.chunk_compressed_offset,
+            chunk_compressed_bytes_remaining: self
+                .chunk_compressed_bytes_remaining,
+            chunk_uncompressed_bytes_remaining: self
+                .chunk_uncompressed_bytes_remaining,
+            header_filesize: self.header_filesize,
+            block_type: self.block_type,
+            block_uncompressed_bytes_remaining: self
+                .block_uncompressed_bytes_remaining,
+            block_overrun: self.block_overrun,
+            recent: self.recent,
+            lengths: self.lengths.clone(),
+            main_tree: self.main_tree.clone(),
+            length_tree: self.length_tree.clone(),
+            aligned_tree: self.aligned_tree.clone(),
+            window: self.window.as_ref().to_vec(),
+            window_position: self.window_position,
+            bytes_decoded: self.bytes_decoded,
+            e8_buffer: self.e8_buffer.as_ref().to_vec(),
+            output_from_e8_buffer: self.output_from_e8_buffer,
+            output_start: self.output_start,
+            output_end: self.output_end,
+        }
+    }
+
+    /// Decodes all of the remaining data, writing it to `writer`, and returns
+    /// the number of bytes written.
+    ///
+    /// This writes each decoded chunk directly from the decoder's internal
+    /// buffers, which avoids copying the data through a separate buffer as
+    /// `io::copy` would.
+    pub fn copy_to<W: Write>(&mut self, writer: &mut W) -> io::Result<u64> {
+        let mut total: u64 = 0;
+        loop {
+            if self.output_start == self.output_end &&
+                !self.decode_next_chunk()?
+            {
+                return Ok(total);
+            }
+            let output = self.pending_output();
+            writer.write_all(output)?;
+            total += output.len() as u64;
+            self.output_start = self.output_end;
+        }
+    }
+
+    /// Decodes all of the remaining data into `output`, whose length must be
+    /// exactly the number of uncompressed bytes remaining (that is, the whole
+    /// uncompressed size, if nothing has been read yet).  This suits callers
+    /// that preallocate output from a container's directory metadata.
+    ///
+    /// Returns an error, without decoding anything, if `output` is larger or
+    /// smaller than the remaining data.
+    pub fn decompress_into(&mut self, output: &mut [u8]) -> io::Result<()> {
+        let remaining = self.uncompressed_bytes_remaining();
+        if output.len() as u64 != remaining {
+            invalid_input!("LZX output buffer has wrong size ({} bytes, but \
+                            {} bytes remain)",
+                           output.len(),
+                           remaining);
+        }
+        self.read_exact(output)
+    }
+
+    /// Returns the decoded bytes that haven't yet been read.
+    fn pending_output(&self) -> &[u8] {
+        let (start, end) = (self.output_start, self.output_end);
+        if self.output_from_e8_buffer {
+            &self.e8_buffer.as_ref()[start..end]
+        } else {
+            &self.window.as_ref()[start..end]
+        }
+    }
+
+    /// Returns the number of uncompressed bytes in each chunk.
+    pub fn chunk_size(&self) -> usize { self.chunk_size }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R { self.reader.get_ref() }
+
+    /// Returns a mutable reference to the underlying reader.
+    ///
+    /// Reading from the underlying reader directly will corrupt the decoded
+    /// data; this is intended for readers (such as a `VecDeque<u8>`) to which
+    /// more compressed data can be appended as it arrives.
+    pub fn get_mut(&mut self) -> &mut R { self.reader.get_mut() }
+
+    /// Unwraps this decoder, returning the underlying reader.
+    ///
+    /// If all of the uncompressed data has been read from the decoder, the
+    /// returned reader is positioned immediately after the end of the LZXD
+    /// stream (including any final padding), so that a container parser can
+    /// continue reading whatever follows it.  Otherwise, the position of the
+    /// returned reader within the stream is unspecified.
+    pub fn into_inner(self) -> io::Result<R> {
+        if self.total_uncompressed_bytes_remaining != 0 ||
+            self.output_start != self.output_end
         {
-            if self.chunk_uncompressed_bytes_remaining == 0 {
-                self.reader.align_to_16()?;
-                self.chunk_compressed_bytes_remaining =
-                    self.reader.read_u16::<LittleEndian>()? as usize;
-                self.chunk_uncompressed_bytes_remaining =
-                    self.total_uncompressed_bytes_remaining
-                        .min(consts::CHUNK_SIZE as u64) as
-                        usize;
+            return Ok(self.reader.into_inner_unaligned());
+        }
+        self.reader.into_inner().map_err(error::eof_as_truncated)
+    }
+
+    /// Returns the position of the next byte to be read from the decoder,
+    /// within the uncompressed data.
+    fn position(&self) -> u64 {
+        self.total_uncompressed_size() - self.uncompressed_bytes_remaining()
+    }
+
+    /// Decodes and discards bytes until reaching the given position, which
+    /// must not be before the current position or past the end of the data.
+    fn skip_to(&mut self, target: u64) -> io::Result<u64> {
+        debug_assert!(target <= self.total_uncompressed_size());
+        let mut bytes_to_skip = target - self.position();
+        while bytes_to_skip > 0 {
+            if self.output_start == self.output_end {
+                self.decode_next_chunk()?;
             }
-            while self.block_uncompressed_bytes_remaining == 0 {
-                if self.block_type == BlockType::Uncompressed {
-                    self.reader.align_to_16()?;
-                }
-                self.block_type = BlockType::from_bits(self.reader
-                                                           .read_bits(3)?)?;
-                self.block_uncompressed_bytes_remaining =
-                    self.reader.read_bits(24)? as usize;
-                match self.block_type {
-                    BlockType::Verbatim => unimplemented!(), // TODO
-                    BlockType::AlignedOffset => unimplemented!(), // TODO
-                    BlockType::Uncompressed => {
-                        self.reader.read_bits(1)?;
-                        self.reader.align_to_16()?;
-                        self.recent.0 = self.reader
-                            .read_u32::<LittleEndian>()?;
-                        self.recent.1 = self.reader
-                            .read_u32::<LittleEndian>()?;
-                        self.recent.2 = self.reader
-                            .read_u32::<LittleEndian>()?;
-                    }
-                }
+            let num_bytes = ((self.output_end - self.output_start) as u64)
+                .min(bytes_to_skip);
+            self.output_start += num_bytes as usize;
+            bytes_to_skip -= num_bytes;
+        }
+        Ok(target)
+    }
+
+    /// Decodes the next chunk into the output buffer, which must be empty.
+    /// Returns false if there are no more chunks in the stream.
+    fn decode_next_chunk(&mut self) -> io::Result<bool> {
+        debug_assert_eq!(self.output_start, self.output_end);
+        if self.total_uncompressed_bytes_remaining == 0 || self.ended_early {
+            return Ok(false);
+        }
+        if let Some(ref mut should_cancel) = self.cancel_check {
+            if should_cancel() {
+                return Err(Error::Cancelled.into());
             }
-            let bytes_to_read = self.block_uncompressed_bytes_remaining
-                .min(self.chunk_uncompressed_bytes_remaining)
-                .min(buf.len() - bytes_read);
-            debug_assert!(bytes_to_read > 0);
-            match self.block_type {
-                BlockType::Verbatim => unimplemented!(), // TODO
-                BlockType::AlignedOffset => unimplemented!(), // TODO
-                BlockType::Uncompressed => {
-                    let end = bytes_read + bytes_to_read;
-                    self.reader.read_exact(&mut buf[bytes_read..end])?;
-                    bytes_read += bytes_to_read;
-                    self.block_uncompressed_bytes_remaining -= bytes_to_read;
-                    self.chunk_uncompressed_bytes_remaining -= bytes_to_read;
-                    self.total_uncompressed_bytes_remaining -= bytes_to_read as
-                        u64;
-                }
+        }
+        enter_span!("lzxd_chunk",
+                    uncompressed_offset = self.bytes_decoded,
+                    compressed_bit_offset = self.reader.bit_position());
+        let chunk_len = if self.chunk_uncompressed_bytes_remaining == 0 {
+            self.total_uncompressed_bytes_remaining
+                .min(self.chunk_size as u64)
+        } else {
+            self.chunk_uncompressed_bytes_remaining as u64
+        };
+        if self.bytes_decoded + chunk_len > self.max_output {
+            return Err(Error::OutputLimitExceeded(self.max_output).into());
+        }
+        if self.nonblocking {
+            self.save_checkpoint();
+        }
+        let chunk_offset = self.bytes_decoded;
+        if let Err(error) = self.decode_chunk() {
+            let error = self.annotate_error(error);
+            #[cfg(feature = "tracing")]
+            ::tracing::debug!(%error, "LZX chunk failed to decode");
+            if self.lenient_eof &&
+                Error::from_io(&error).map(Error::without_offset) ==
+                    Some(&Error::Truncated)
+            {
+                // Forget the partly-decoded chunk, so that the decoder's
+                // position reflects the data actually returned.
+                let partial_len = self.bytes_decoded - chunk_offset;
+                self.bytes_decoded = chunk_offset;
+                self.total_uncompressed_bytes_remaining += partial_len;
+                log_event!(warn,
+                           "LZX stream ended early, after {} bytes",
+                           chunk_offset);
+                self.ended_early = true;
+                return Ok(false);
+            }
+            if self.nonblocking {
+                self.restore_checkpoint()?;
             }
+            return Err(error);
         }
-        Ok(bytes_read)
+        // A stream of unknown size can turn out to have ended before the
+        // chunk, leaving no output.
+        Ok(self.output_start != self.output_end)
     }
-}
 
-// ========================================================================= //
+    /// Saves the state needed to decode the next chunk again from the start.
+    fn save_checkpoint(&mut self) {
+        self.reader.set_rewind_point();
+        let checkpoint = &mut self.checkpoint;
+        checkpoint.total_uncompressed_bytes_remaining =
+            self.total_uncompressed_bytes_remaining;
+        checkpoint.chunk_compressed_bytes_remaining =
+            self.chunk_compressed_bytes_remaining;
+        checkpoint.chunk_uncompressed_bytes_remaining =
+            self.chunk_uncompressed_bytes_remaining;
+        checkpoint.header_filesize = self.header_filesize;
+        checkpoint.block_type = self.block_type;
+        checkpoint.block_uncompressed_bytes_remaining =
+            self.block_uncompressed_bytes_remaining;
+        checkpoint.block_overrun = self.block_overrun;
+        checkpoint.recent = self.recent;
+        checkpoint.lengths.clone_from(&self.lengths);
+        checkpoint.window_position = self.window_position;
+        checkpoint.bytes_decoded = self.bytes_decoded;
+        // Decoding the chunk overwrites the oldest part of the window, which
+        // matches in the chunk may still refer to.
+        let window = self.window.as_ref();
+        let start = self.window_position % window.len();
+        let len = self.total_uncompressed_bytes_remaining
+            .min(self.chunk_size as u64) as usize;
+        checkpoint.window_contents.clear();
+        checkpoint
+            .window_contents
+            .extend_from_slice(&window[start..(start + len)]);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::Decoder;
-    use internal::consts::WINDOW_MIN;
-    use std::io::Read;
+    /// Returns to the state saved by the last call to `save_checkpoint()`.
+    fn restore_checkpoint(&mut self) -> io::Result<()> {
+        self.reader.rewind();
+        let checkpoint = &self.checkpoint;
+        self.total_uncompressed_bytes_remaining =
+            checkpoint.total_uncompressed_bytes_remaining;
+        self.chunk_compressed_bytes_remaining =
+            checkpoint.chunk_compressed_bytes_remaining;
+        self.chunk_uncompressed_bytes_remaining =
+            checkpoint.chunk_uncompressed_bytes_remaining;
+        self.header_filesize = checkpoint.header_filesize;
+        self.block_type = checkpoint.block_type;
+        self.block_uncompressed_bytes_remaining =
+            checkpoint.block_uncompressed_bytes_remaining;
+        self.block_overrun = checkpoint.block_overrun;
+        self.recent = checkpoint.recent;
+        // The Huffman trees are always built from the current code lengths,
+        // so only rebuild them if the lengths have changed.
+        let lengths = &checkpoint.lengths;
+        if self.lengths.main() != lengths.main() {
+            self.main_tree.rebuild(lengths.main())?;
+        }
+        if self.lengths.length != lengths.length {
+            self.length_tree.rebuild(&lengths.length)?;
+        }
+        if self.lengths.aligned != lengths.aligned {
+            self.aligned_tree.rebuild(&lengths.aligned)?;
+        }
+        self.lengths.clone_from(lengths);
+        self.window_position = checkpoint.window_position;
+        self.bytes_decoded = checkpoint.bytes_decoded;
+        let window = self.window.as_mut();
+        let start = self.window_position % window.len();
+        let end = start + checkpoint.window_contents.len();
+        window[start..end].copy_from_slice(&checkpoint.window_contents);
+        Ok(())
+    }
 
-    #[test]
-    #[should_panic(expected = "Invalid LZX window (12345)")]
-    fn invalid_window_size() {
-        let input: &[u8] = b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
-            \x00\x00\x00\x01\x00\x00\x00\x61\x62\x63\x00";
-        Decoder::new(input, 12345, 3).unwrap();
+    /// Starts a new stream, reusing this decoder's allocations and settings
+    /// (chunk size, reset interval, dictionary, and callbacks, but not the
+    /// reset table), and returns the old underlying reader.
+    ///
+    /// This is cheaper than creating a new `Decoder` for each stream when
+    /// decoding many streams with the same window size, as in CHM and CAB
+    /// archives.  If the new stream's header can't be read, an error is
+    /// returned and the old reader is dropped.
+    pub fn reset(&mut self, reader: R, uncompressed_size: u64)
+                 -> io::Result<R> {
+        let old_reader = mem::replace(&mut self.reader, BitReader::new(reader))
+            .into_inner_unaligned();
+        self.reset_table.clear();
+        self.ended_early = false;
+        self.size_known = true;
+        self.bytes_decoded = 0;
+        self.total_uncompressed_bytes_remaining = uncompressed_size;
+        let dictionary_len = self.dictionary.len();
+        self.grow_window(dictionary_len);
+        if let Err(error) = self.start_at_reset_point(0) {
+            return Err(self.annotate_error(error));
+        }
+        Ok(old_reader)
     }
 
-    #[test]
-    fn decode_stream_with_one_uncompressed_block() {
-        let input: &[u8] = b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
-            \x00\x00\x00\x01\x00\x00\x00\x61\x62\x63\x00";
-        let mut decoder = Decoder::new(input, WINDOW_MIN, 3).unwrap();
-        let mut buffer = [0u8; 10];
-        assert_eq!(decoder.read(&mut buffer).unwrap(), 3);
-        assert_eq!(&buffer[..3], b"abc");
+    /// Reads the size prefix and stream header of the chunk at the given
+    /// reset point, which the underlying reader must be positioned at.
+    fn start_at_reset_point(&mut self, uncompressed_offset: u64)
+                            -> io::Result<()> {
+        let total = self.total_uncompressed_size();
+        self.chunk_compressed_offset = self.reader.bit_position() / 8;
+        self.chunk_compressed_bytes_remaining = if total == 0 {
+            0
+        } else {
+            self.read_chunk_prefix()?
+        };
+        self.bytes_decoded = uncompressed_offset;
+        self.total_uncompressed_bytes_remaining = total - uncompressed_offset;
+        self.chunk_uncompressed_bytes_remaining =
+            self.total_uncompressed_bytes_remaining
+                .min(self.chunk_size as u64) as usize;
+        self.block_uncompressed_bytes_remaining = 0;
+        self.block_overrun = 0;
+        self.window_position =
+            (uncompressed_offset % self.window.as_ref().len() as u64) as
+                usize;
+        self.output_from_e8_buffer = false;
+        self.output_start = 0;
+        self.output_end = 0;
+        if uncompressed_offset == 0 {
+            self.place_dictionary();
+        }
+        if total == 0 {
+            return Ok(());
+        }
+        self.reset_state()
+    }
+
+    /// Reads the first chunk's size prefix and stream header for a stream of
+    /// unknown size, unless the stream is empty.
+    fn start_unknown_size(&mut self) -> io::Result<()> {
+        if self.reader.is_at_eof()? {
+            return Ok(());
+        }
+        self.total_uncompressed_bytes_remaining = u64::MAX;
+        self.start_at_reset_point(0)
+    }
+
+    /// Returns true if, in a stream of unknown size, the current chunk's
+    /// compressed data (as given by its size prefix) has all been read, so
+    /// that the chunk ends here.
+    fn chunk_data_consumed(&self) -> bool {
+        !self.size_known && self.reader.bit_position().div_ceil(16) * 16 >=
+            self.chunk_end_bit_position()
+    }
+
+    /// Returns the bit position at which the current chunk's compressed data
+    /// ends, according to its size prefix.
+    fn chunk_end_bit_position(&self) -> u64 {
+        8 * (self.chunk_compressed_offset + 2 +
+                 self.chunk_compressed_bytes_remaining as u64)
+    }
+
+    /// Adds the current stream position to an error from this crate, so that
+    /// callers can report where the stream is corrupt.
+    fn annotate_error(&self, error: io::Error) -> io::Error {
+        let error = error::eof_as_truncated(error);
+        match Error::from_io(&error) {
+            Some(inner) => {
+                inner.clone()
+                    .at_offset(self.reader.bit_position(), self.bytes_decoded)
+                    .into()
+            }
+            None => error,
+        }
+    }
+
+    /// Reads the size prefix of the next chunk (or returns zero for a raw
+    /// stream, whose chunks have no prefixes).
+    fn read_chunk_prefix(&mut self) -> io::Result<usize> {
+        if self.raw {
+            return Ok(0);
+        }
+        Ok(self.reader.read_u16::<LittleEndian>()? as usize)
+    }
+
+    fn read_stream_header(&mut self) -> io::Result<()> {
+        self.header_filesize = if self.wim {
+            e8::DEFAULT_TRANSLATION_SIZE
+        } else if self.reader.read_bits(1)? != 0 {
+            self.reader.read_bits(32)?
+        } else {
+            0
+        };
+        Ok(())
+    }
+
+    /// Forgets the state carried over from previous chunks, at a reset point
+    /// in the stream.
+    fn reset_state(&mut self) -> io::Result<()> {
+        if self.block_uncompressed_bytes_remaining != 0 ||
+            self.block_overrun != 0
+        {
+            invalid_data!("LZX block runs past reset point");
+        }
+        self.block_type = BlockType::Verbatim;
+        self.recent = (1, 1, 1);
+        for len in self.lengths.main.iter_mut() {
+            *len = 0;
+        }
+        for len in self.lengths.length.iter_mut() {
+            *len = 0;
+        }
+        log_event!(debug,
+                   "LZX reset point at uncompressed offset {}",
+                   self.bytes_decoded);
+        self.read_stream_header()
+    }
+
+    fn read_block_header(&mut self) -> io::Result<()> {
+        if self.block_type == BlockType::Uncompressed {
+            self.reader.align_to_16()?;
+        }
+        let compressed_bit_offset = self.reader.bit_position();
+        self.block_type = BlockType::from_bits(self.reader.read_bits(3)?)?;
+        self.block_uncompressed_bytes_remaining = self.read_block_size()?;
+        match self.block_type {
+            BlockType::Verbatim => self.read_main_and_length_trees()?,
+            BlockType::AlignedOffset => {
+                for len in self.lengths.aligned.iter_mut() {
+                    *len = self.reader
+                        .read_bits(consts::ALIGNED_LENGTH_BITS)? as
+                        u8;
+                }
+                self.aligned_tree.rebuild(&self.lengths.aligned)?;
+                log_event!(trace, "Rebuilt LZX aligned offset tree");
+                self.read_main_and_length_trees()?;
+            }
+            BlockType::Uncompressed => {
+                self.reader.read_bits(1)?;
+                self.reader.align_to_16()?;
+                self.recent.0 = self.reader.read_u32::<LittleEndian>()?;
+                self.recent.1 = self.reader.read_u32::<LittleEndian>()?;
+                self.recent.2 = self.reader.read_u32::<LittleEndian>()?;
+            }
+        }
+        log_event!(debug,
+                   "LZX {} block of {} bytes at uncompressed offset {}",
+                   self.block_type,
+                   self.block_uncompressed_bytes_remaining,
+                   self.bytes_decoded);
+        if self.block_uncompressed_bytes_remaining == 0 {
+            log_event!(warn,
+                       "Skipping empty LZX block at compressed bit {}",
+                       compressed_bit_offset);
+        }
+        if let Some(ref mut callback) = self.block_callback {
+            let uncompressed_len = self.block_uncompressed_bytes_remaining;
+            callback(&BlockInfo {
+                         block_type: self.block_type,
+                         uncompressed_offset: self.bytes_decoded,
+                         uncompressed_len,
+                         compressed_bit_offset,
+                     });
+        }
+        Ok(())
+    }
+
+    fn read_main_and_length_trees(&mut self) -> io::Result<()> {
+        let main_lengths =
+            &mut self.lengths.main[..self.lengths.num_main_elements];
+        let (literals, matches) = main_lengths.split_at_mut(consts::NUM_CHARS);
+        pretree::read_lengths(&mut self.reader, &mut self.pretree, literals)?;
+        pretree::read_lengths(&mut self.reader, &mut self.pretree, matches)?;
+        self.main_tree.rebuild(self.lengths.main())?;
+        pretree::read_lengths(&mut self.reader,
+                              &mut self.pretree,
+                              &mut self.lengths.length)?;
+        self.length_tree.rebuild(&self.lengths.length)?;
+        log_event!(trace, "Rebuilt LZX main and length trees");
+        Ok(())
+    }
+
+    fn read_block_size(&mut self) -> io::Result<usize> {
+        if !self.wim {
+            return Ok(self.reader.read_bits(24)? as usize);
+        }
+        // WIM streams give the common size of a full chunk in a single bit,
+        // and other sizes in 16 bits (or 24, for larger windows).
+        if self.reader.read_bits(1)? != 0 {
+            return Ok(consts::CHUNK_SIZE);
+        }
+        let mut size = self.reader.read_bits(16)? as usize;
+        if self.max_window_size >= 1 << 16 {
+            size = (size << 8) | self.reader.read_bits(8)? as usize;
+        }
+        Ok(size)
+    }
+
+    fn decode_chunk(&mut self) -> io::Result<()> {
+        if self.chunk_uncompressed_bytes_remaining == 0 {
+            self.reader.align_to_16()?;
+            if !self.size_known && self.reader.is_at_eof()? {
+                // The stream ended after a full chunk.
+                self.total_uncompressed_bytes_remaining = 0;
+                return Ok(());
+            }
+            self.chunk_compressed_offset = self.reader.bit_position() / 8;
+            self.chunk_compressed_bytes_remaining =
+                self.read_chunk_prefix()?;
+            self.chunk_uncompressed_bytes_remaining =
+                self.total_uncompressed_bytes_remaining
+                    .min(self.chunk_size as u64) as usize;
+            let chunk_index = self.bytes_decoded / self.chunk_size as u64;
+            if self.reset_interval != 0 &&
+                chunk_index.is_multiple_of(self.reset_interval as u64)
+            {
+                self.reset_state()?;
+            }
+        }
+        // Don't let Huffman decoding read ahead past the end of the chunk,
+        // into data that may not belong to this stream.  The end of a raw
+        // chunk isn't known in advance, but only the final one needs a limit
+        // (and WIM chunks are always decoded from a buffer of just the chunk).
+        let peek_limit = if !self.raw {
+            Some(self.chunk_end_bit_position() / 8)
+        } else if !self.wim &&
+                   self.total_uncompressed_bytes_remaining <=
+                       self.chunk_size as u64
+        {
+            Some(0)
+        } else {
+            None
+        };
+        self.reader.set_peek_limit(peek_limit);
+        if self.window_position == self.window.as_ref().len() {
+            self.window_position = 0;
+        }
+        let chunk_start = self.window_position;
+        let chunk_offset = self.bytes_decoded;
+        while self.chunk_uncompressed_bytes_remaining > 0 {
+            if self.block_uncompressed_bytes_remaining == 0 &&
+                self.chunk_data_consumed()
+            {
+                // This is the short final chunk of a stream of unknown size.
+                self.chunk_uncompressed_bytes_remaining = 0;
+                self.total_uncompressed_bytes_remaining = 0;
+                break;
+            }
+            while self.block_uncompressed_bytes_remaining == 0 {
+                self.read_block_header()?;
+                // The last match of the previous block may have run over
+                // into this one.
+                if self.block_overrun >
+                    self.block_uncompressed_bytes_remaining
+                {
+                    invalid_data!("LZX match runs past end of block");
+                }
+                self.block_uncompressed_bytes_remaining -= self.block_overrun;
+                self.block_overrun = 0;
+            }
+            let bytes_to_decode = self.block_uncompressed_bytes_remaining
+                .min(self.chunk_uncompressed_bytes_remaining);
+            debug_assert!(bytes_to_decode > 0);
+            enter_span!("lzxd_block",
+                        block_type = %self.block_type,
+                        uncompressed_offset = self.bytes_decoded,
+                        uncompressed_len = bytes_to_decode,
+                        block_bytes_remaining =
+                            self.block_uncompressed_bytes_remaining,
+                        compressed_bit_offset = self.reader.bit_position());
+            let bytes_decoded = match self.block_type {
+                BlockType::Verbatim | BlockType::AlignedOffset => {
+                    let max_bytes = self.chunk_uncompressed_bytes_remaining;
+                    self.decode_symbols(bytes_to_decode, max_bytes)?
+                }
+                BlockType::Uncompressed => {
+                    let start = self.window_position;
+                    let end = start + bytes_to_decode;
+                    self.reader
+                        .read_exact(&mut self.window.as_mut()[start..end])?;
+                    bytes_to_decode
+                }
+            };
+            self.block_overrun = bytes_decoded - bytes_to_decode;
+            self.window_position += bytes_decoded;
+            self.bytes_decoded += bytes_decoded as u64;
+            self.block_uncompressed_bytes_remaining -= bytes_to_decode;
+            self.chunk_uncompressed_bytes_remaining -= bytes_decoded;
+            self.total_uncompressed_bytes_remaining -= bytes_decoded as u64;
+        }
+        if !self.size_known &&
+            self.reader.bit_position() > self.chunk_end_bit_position()
+        {
+            invalid_data!("LZX chunk is longer than its size prefix ({} \
+                           bytes)",
+                          self.chunk_compressed_bytes_remaining);
+        }
+        if chunk_start == self.window_position {
+            invalid_data!("LZX chunk has no data");
+        }
+        let chunk_len = self.window_position - chunk_start;
+        if e8::applies_to_chunk(self.header_filesize,
+                                chunk_offset,
+                                chunk_len)
+        {
+            if !self.e8_buffer.can_hold(chunk_len) {
+                invalid_input!("LZX chunk buffer is too small ({} < {})",
+                               self.e8_buffer.as_ref().len(),
+                               chunk_len);
+            }
+            self.e8_buffer.grow_to(chunk_len);
+            let chunk = &self.window.as_ref()[chunk_start..
+                                                  self.window_position];
+            let output = &mut self.e8_buffer.as_mut()[..chunk_len];
+            output.copy_from_slice(chunk);
+            e8::decode_chunk(self.header_filesize, chunk_offset, output);
+            self.output_from_e8_buffer = true;
+            self.output_start = 0;
+            self.output_end = chunk_len;
+        } else {
+            self.output_from_e8_buffer = false;
+            self.output_start = chunk_start;
+            self.output_end = self.window_position;
+        }
+        // Each chunk ends on a 16-bit boundary.
+        let compressed_bytes = self.reader.bit_position().div_ceil(16) * 2;
+        if let Some(ref mut callback) = self.chunk_callback {
+            let prefix_len = if self.raw { 0 } else { 2 };
+            let body_offset = self.chunk_compressed_offset + prefix_len;
+            callback(&ChunkInfo {
+                         uncompressed_offset: chunk_offset,
+                         uncompressed_len: chunk_len,
+                         compressed_offset: self.chunk_compressed_offset,
+                         compressed_len: (compressed_bytes - body_offset) as
+                             usize,
+                     });
+        }
+        if let Some(ref mut callback) = self.progress_callback {
+            callback(self.bytes_decoded, compressed_bytes);
+        }
+        Ok(())
+    }
+
+    /// Decodes at least `num_bytes` bytes of a verbatim or aligned offset
+    /// block into the window.  The last match may run on past them (into the
+    /// next block), but not past `max_bytes` (the end of the chunk).  Returns
+    /// the number of bytes decoded.
+    fn decode_symbols(&mut self, num_bytes: usize, max_bytes: usize)
+                      -> io::Result<usize> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("bmi2") {
+                // This is safe, since the CPU supports BMI2.
+                return unsafe {
+                    self.decode_symbols_bmi2(num_bytes, max_bytes)
+                };
+            }
+        }
+        self.decode_symbols_inline(num_bytes, max_bytes)
+    }
+
+    /// Like `decode_symbols_inline()`, but compiled to use BMI2 instructions,
+    /// whose variable shifts and bit field extraction speed up bit reading.
+    /// Must only be called if the CPU supports BMI2.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[target_feature(enable = "bmi2")]
+    unsafe fn decode_symbols_bmi2(&mut self, num_bytes: usize,
+                                  max_bytes: usize)
+                                  -> io::Result<usize> {
+        self.decode_symbols_inline(num_bytes, max_bytes)
+    }
+
+    #[inline(always)]
+    fn decode_symbols_inline(&mut self, num_bytes: usize, max_bytes: usize)
+                             -> io::Result<usize> {
+        let delta = self.delta;
+        let start = self.window_position;
+        let end = start + num_bytes;
+        let limit = start + max_bytes;
+        let mut position = start;
+        while position < end {
+            let main_element = self.main_tree.decode(&mut self.reader)? as
+                usize;
+            if main_element < consts::NUM_CHARS {
+                self.window.as_mut()[position] = main_element as u8;
+                position += 1;
+                continue;
+            }
+            let match_element = main_element - consts::NUM_CHARS;
+            let mut match_length = match_element & 7;
+            if match_length == consts::NUM_PRIMARY_LENGTHS {
+                match_length += self.length_tree.decode(&mut self.reader)? as
+                    usize;
+            }
+            match_length += consts::MIN_MATCH;
+            let position_slot = match_element >> 3;
+            let match_offset = self.decode_match_offset(position_slot)? as
+                usize;
+            if delta && match_length == consts::MAX_MATCH {
+                match_length += self.read_length_extension()?;
+            }
+            if position + match_length > limit {
+                invalid_data!("LZX match runs past end of chunk");
+            }
+            let decoded = self.dictionary.len() as u64 + self.bytes_decoded +
+                (position - start) as u64;
+            if match_offset == 0 || match_offset as u64 > decoded ||
+                match_offset > self.window.as_ref().len()
+            {
+                return Err(Error::OffsetOutOfRange(match_offset as u32)
+                               .into());
+            }
+            copy_match(self.window.as_mut(),
+                       position,
+                       match_offset,
+                       match_length);
+            position += match_length;
+        }
+        Ok(position - start)
+    }
+
+    /// Reads the extra length that follows a match of `MAX_MATCH` bytes in
+    /// an LZX DELTA stream.
+    fn read_length_extension(&mut self) -> io::Result<usize> {
+        // The number of leading one bits (up to three) selects how many bits
+        // of extra length follow, and what to add to them.
+        let mut prefix = 0;
+        while prefix < 3 && self.reader.read_bits(1)? == 1 {
+            prefix += 1;
+        }
+        let (num_bits, base) = [(8, 0), (10, 0x100), (12, 0x500), (15, 0)]
+            [prefix];
+        Ok(base + self.reader.read_bits(num_bits)? as usize)
+    }
+
+    #[inline(always)]
+    fn decode_match_offset(&mut self, position_slot: usize)
+                           -> io::Result<u32> {
+        match position_slot {
+            0 => Ok(self.recent.0),
+            1 => {
+                mem::swap(&mut self.recent.0, &mut self.recent.1);
+                Ok(self.recent.0)
+            }
+            2 => {
+                mem::swap(&mut self.recent.0, &mut self.recent.2);
+                Ok(self.recent.0)
+            }
+            _ => {
+                if position_slot >= self.num_position_slots {
+                    invalid_data!("Invalid LZX position slot ({})",
+                                  position_slot);
+                }
+                let extra_bits = consts::EXTRA_BITS[position_slot] as u16;
+                let footer = if self.block_type == BlockType::AlignedOffset &&
+                    extra_bits >= 3
+                {
+                    let verbatim = self.reader.read_bits(extra_bits - 3)?;
+                    let aligned = self.aligned_tree.decode(&mut self.reader)?;
+                    (verbatim << 3) | aligned as u32
+                } else {
+                    self.reader.read_bits(extra_bits)?
+                };
+                let offset = consts::POSITION_BASE[position_slot] + footer -
+                    2;
+                self.recent.2 = self.recent.1;
+                self.recent.1 = self.recent.0;
+                self.recent.0 = offset;
+                Ok(offset)
+            }
+        }
+    }
+}
+
+/// Cloning a decoder copies its entire state (including the window), so that
+/// the clone can read ahead independently of the original.  Callbacks are not
+/// copied to the clone.
+impl<R: Read + Clone> Clone for Decoder<R> {
+    fn clone(&self) -> Decoder<R> {
+        Decoder {
+            reader: self.reader.clone(),
+            total_uncompressed_bytes_remaining: self
+                .total_uncompressed_bytes_remaining,
+            chunk_size: self.chunk_size,
+            reset_interval: self.reset_interval,
+            chunk_compressed_offset: self.chunk_compressed_offset,
+            chunk_compressed_bytes_remaining: self
+                .chunk_compressed_bytes_remaining,
+            chunk_uncompressed_bytes_remaining: self
+                .chunk_uncompressed_bytes_remaining,
+            header_filesize: self.header_filesize,
+            block_type: self.block_type,
+            block_uncompressed_bytes_remaining: self
+                .block_uncompressed_bytes_remaining,
+            block_overrun: self.block_overrun,
+            recent: self.recent,
+            num_position_slots: self.num_position_slots,
+            max_window_size: self.max_window_size,
+            delta: self.delta,
+            raw: self.raw,
+            wim: self.wim,
+            size_known: self.size_known,
+            lengths: self.lengths.clone(),
+            main_tree: self.main_tree.clone(),
+            length_tree: self.length_tree.clone(),
+            aligned_tree: self.aligned_tree.clone(),
+            pretree: self.pretree.clone(),
+            window: self.window.clone(),
+            window_position: self.window_position,
+            dictionary: self.dictionary.clone(),
+            reset_table: self.reset_table.clone(),
+            bytes_decoded: self.bytes_decoded,
+            e8_buffer: self.e8_buffer.clone(),
+            output_from_e8_buffer: self.output_from_e8_buffer,
+            output_start: self.output_start,
+            output_end: self.output_end,
+            nonblocking: self.nonblocking,
+            max_output: self.max_output,
+            max_memory: self.max_memory,
+            lenient_eof: self.lenient_eof,
+            ended_early: self.ended_early,
+            checkpoint: self.checkpoint.clone(),
+            progress_callback: None,
+            cancel_check: None,
+            block_callback: None,
+            chunk_callback: None,
+        }
+    }
+}
+
+impl<R: Read, B: DecoderBuffer> fmt::Debug for Decoder<R, B> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("Decoder")
+            .field("window_size", &self.max_window_size)
+            .field("chunk_size", &self.chunk_size)
+            .field("reset_interval", &self.reset_interval)
+            .field("e8_translation_size", &self.header_filesize)
+            .field("delta", &self.delta)
+            .field("raw", &self.raw)
+            .field("wim", &self.wim)
+            .field("size_known", &self.size_known)
+            .field("bytes_decoded", &self.bytes_decoded)
+            .field("total_uncompressed_bytes_remaining",
+                   &self.total_uncompressed_bytes_remaining)
+            .field("chunk_uncompressed_bytes_remaining",
+                   &self.chunk_uncompressed_bytes_remaining)
+            .field("block_type", &self.block_type)
+            .field("block_uncompressed_bytes_remaining",
+                   &self.block_uncompressed_bytes_remaining)
+            .field("compressed_bit_offset", &self.reader.bit_position())
+            .finish()
+    }
+}
+
+impl<R: Read, B: DecoderBuffer> Read for Decoder<R, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut bytes_read: usize = 0;
+        while bytes_read < buf.len() {
+            if self.output_start == self.output_end {
+                match self.decode_next_chunk() {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    // The chunk will be decoded again on the next call, so
+                    // return the data already read rather than losing it.
+                    Err(_) if self.nonblocking && bytes_read > 0 => break,
+                    Err(error) => return Err(error),
+                }
+            }
+            let bytes_to_read = (self.output_end - self.output_start)
+                .min(buf.len() - bytes_read);
+            debug_assert!(bytes_to_read > 0);
+            let output = &self.pending_output()[..bytes_to_read];
+            buf[bytes_read..(bytes_read + bytes_to_read)]
+                .copy_from_slice(output);
+            bytes_read += bytes_to_read;
+            self.output_start += bytes_to_read;
+        }
+        Ok(bytes_read)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut])
+                     -> io::Result<usize> {
+        let mut bytes_read: usize = 0;
+        for buf in bufs.iter_mut() {
+            let num_bytes = self.read(buf)?;
+            bytes_read += num_bytes;
+            if num_bytes < buf.len() {
+                break;
+            }
+        }
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read, B: DecoderBuffer> BufRead for Decoder<R, B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.output_start == self.output_end {
+            self.decode_next_chunk()?;
+        }
+        Ok(self.pending_output())
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.output_start = (self.output_start + amount).min(self.output_end);
+    }
+}
+
+impl<R: Read + Seek, B: DecoderBuffer> Decoder<R, B> {
+    /// Moves to the given offset within the uncompressed data, which may be
+    /// before the current position.
+    ///
+    /// Rather than decoding everything up to the new position, the decoder
+    /// jumps to the nearest reset point at or before it (see
+    /// `set_reset_table()`), or to the start of the stream, whenever that
+    /// saves work.  Reset points must be at the compressed offsets the reset
+    /// table gives, relative to where the underlying reader was positioned
+    /// when the decoder was created.
+    pub fn seek_to(&mut self, offset: u64) -> io::Result<u64> {
+        let total = self.total_uncompressed_size();
+        if offset > total {
+            invalid_input!("Cannot seek past end of LZX stream ({} > {})",
+                           offset,
+                           total);
+        }
+        let (reset_uncompressed, reset_compressed) = self.reset_table
+            .iter()
+            .cloned()
+            .rev()
+            .find(|&(uncompressed, _)| {
+                      uncompressed <= offset && uncompressed < total
+                  })
+            .unwrap_or((0, 0));
+        let current = self.position();
+        if offset < current || reset_uncompressed > current {
+            if let Err(error) =
+                self.jump_to_reset_point(reset_uncompressed, reset_compressed)
+            {
+                return Err(self.annotate_error(error));
+            }
+        }
+        self.skip_to(offset)
+    }
+
+    /// Decodes and returns the `len` bytes of uncompressed data starting at
+    /// offset `start`.  Using the reset table (see `seek_to()`), only the
+    /// chunks from the nearest reset point at or before `start` through the
+    /// end of the range are decoded.  Afterwards, the decoder is positioned
+    /// at the end of the range.
+    pub fn read_range(&mut self, start: u64, len: usize)
+                      -> io::Result<Vec<u8>> {
+        let total = self.total_uncompressed_size();
+        match start.checked_add(len as u64) {
+            Some(end) if end <= total => {}
+            _ => {
+                invalid_input!("LZX byte range {}+{} is past end of stream \
+                                ({})",
+                               start,
+                               len,
+                               total);
+            }
+        }
+        self.seek_to(start)?;
+        let mut output = vec![0u8; len];
+        self.read_exact(&mut output)?;
+        Ok(output)
+    }
+
+    /// Resumes decoding from a state previously captured with
+    /// `save_state()`, seeking the underlying reader back (or forward) to the
+    /// corresponding point in the compressed stream.
+    ///
+    /// The state may come from a different decoder, as long as it was
+    /// reading the same stream with the same settings, and its reader was
+    /// positioned in the same place relative to the stream when it was
+    /// created.
+    pub fn restore_state(&mut self, state: &DecoderState) -> io::Result<()> {
+        if state.window.len() != self.window.as_ref().len() ||
+            state.lengths.num_main_elements != self.lengths.num_main_elements
+        {
+            invalid_input!("LZX decoder state has wrong window size ({})",
+                           state.window.len());
+        }
+        if !self.e8_buffer.can_hold(state.e8_buffer.len()) {
+            invalid_input!("LZX chunk buffer is too small ({} < {})",
+                           self.e8_buffer.as_ref().len(),
+                           state.e8_buffer.len());
+        }
+        self.reader.restore_position(&state.position)?;
+        self.total_uncompressed_bytes_remaining =
+            state.total_uncompressed_bytes_remaining;
+        self.chunk_compressed_offset = state.chunk_compressed_offset;
+        self.chunk_compressed_bytes_remaining =
+            state.chunk_compressed_bytes_remaining;
+        self.chunk_uncompressed_bytes_remaining =
+            state.chunk_uncompressed_bytes_remaining;
+        self.header_filesize = state.header_filesize;
+        self.block_type = state.block_type;
+        self.block_uncompressed_bytes_remaining =
+            state.block_uncompressed_bytes_remaining;
+        self.block_overrun = state.block_overrun;
+        self.recent = state.recent;
+        self.lengths.clone_from(&state.lengths);
+        self.main_tree.clone_from(&state.main_tree);
+        self.length_tree.clone_from(&state.length_tree);
+        self.aligned_tree.clone_from(&state.aligned_tree);
+        self.window.as_mut().copy_from_slice(&state.window);
+        self.window_position = state.window_position;
+        self.bytes_decoded = state.bytes_decoded;
+        let e8_len = state.e8_buffer.len();
+        self.e8_buffer.grow_to(e8_len);
+        self.e8_buffer.as_mut()[..e8_len].copy_from_slice(&state.e8_buffer);
+        self.output_from_e8_buffer = state.output_from_e8_buffer;
+        self.output_start = state.output_start;
+        self.output_end = state.output_end;
+        Ok(())
+    }
+
+    fn jump_to_reset_point(&mut self, uncompressed_offset: u64,
+                           compressed_offset: u64)
+                           -> io::Result<()> {
+        self.reader.seek_to_byte(compressed_offset)?;
+        self.start_at_reset_point(uncompressed_offset)
+    }
+}
+
+/// Seeking is only supported in the forward direction (see
+/// `Decoder::seek_to` for random access); the decoder decodes and discards
+/// everything up to the new position.
+impl<R: Read, B: DecoderBuffer> Seek for Decoder<R, B> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let current = self.position();
+        let total = self.total_uncompressed_size();
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => current.checked_add_signed(delta),
+            SeekFrom::End(delta) => total.checked_add_signed(delta),
+        };
+        let target = match target {
+            Some(target) if target <= total => target,
+            _ => invalid_input!("Cannot seek outside of LZX stream"),
+        };
+        if target < current {
+            invalid_input!("Cannot seek backwards in LZX stream ({} < {}); \
+                            use seek_to() instead",
+                           target,
+                           current);
+        }
+        self.skip_to(target)
+    }
+}
+
+// ========================================================================= //
+
+/// Returns the size of window that a stream of the given size (preceded by a
+/// dictionary of the given length) needs: the full window size, or the
+/// smallest power of two that can hold the whole stream, if that's smaller.
+fn stream_window_size(max_window_size: usize, uncompressed_size: u64,
+                      dictionary_len: usize)
+                      -> usize {
+    let needed = uncompressed_size.saturating_add(dictionary_len as u64);
+    if needed >= max_window_size as u64 {
+        max_window_size
+    } else {
+        (needed as usize).next_power_of_two()
+    }
+}
+
+/// Copies a match of `length` bytes, starting `offset` bytes back (at least
+/// one, and at most the window size), to `position` in the window (whose
+/// size is a power of two).  The match must not run past the end of the
+/// window, but its source may wrap around from the end of the window to the
+/// start.
+fn copy_match(window: &mut [u8], position: usize, offset: usize,
+              length: usize) {
+    let end = position + length;
+    let mask = window.len() - 1;
+    if offset >= length {
+        // The source doesn't overlap the match, so copy it all at once (in
+        // two pieces, if it wraps around the window).
+        let src = position.wrapping_sub(offset) & mask;
+        let wrapped = (src + length).saturating_sub(window.len());
+        window.copy_within(src..(src + length - wrapped), position);
+        window.copy_within(0..wrapped, end - wrapped);
+        return;
+    }
+    // Copy a byte at a time while the source wraps around the window.
+    let mut dst = position;
+    while dst < end && dst < offset {
+        window[dst] = window[dst.wrapping_sub(offset) & mask];
+        dst += 1;
+    }
+    // The match repeats every `offset` bytes, so a short offset can be
+    // replaced with a whole number of repetitions of at least eight bytes,
+    // once that many have been copied.
+    let distance = offset * 8usize.div_ceil(offset);
+    let word_start = (position + distance - offset).max(distance);
+    while dst < end && dst < word_start {
+        window[dst] = window[dst - offset];
+        dst += 1;
+    }
+    if distance >= 16 {
+        while dst + 16 <= end {
+            window.copy_within((dst - distance)..(dst - distance + 16), dst);
+            dst += 16;
+        }
+    }
+    while dst + 8 <= end {
+        window.copy_within((dst - distance)..(dst - distance + 8), dst);
+        dst += 8;
+    }
+    while dst < end {
+        window[dst] = window[dst - distance];
+        dst += 1;
+    }
+}
+
+/// Returns roughly how many bytes a `Decoder` for the given (valid) window
+/// size, stream size and chunk size allocates: its window, dictionary, E8
+/// output buffer and the decoder itself (which holds its code lengths and
+/// Huffman tables inline), plus (in nonblocking mode) the copies of a
+/// chunk's input and window contents kept to restart it.
+pub fn memory_usage(window: u16, uncompressed_size: u64,
+                    dictionary_len: usize, chunk_size: usize,
+                    nonblocking: bool)
+                    -> usize {
+    let window_size = stream_window_size(consts::window_buffer_size(window),
+                                         uncompressed_size,
+                                         dictionary_len);
+    let chunk_len = uncompressed_size.min(chunk_size as u64) as usize;
+    let mut total = window_size + dictionary_len + chunk_len +
+        mem::size_of::<Decoder<&[u8]>>();
+    if nonblocking {
+        total += 2 * chunk_len;
+    }
+    total
+}
+
+// ========================================================================= //
+
+/// Decompresses an entire in-memory LZXD stream, whose uncompressed size must
+/// be known.  The `window` argument is the same as for `Decoder::new`.
+///
+/// A `Decoder` reading from a `&[u8]` (as this function does) takes the
+/// compressed data straight from the slice without copying it.  There is no
+/// separate slice-based decoder: refilling the bit buffer directly from
+/// memory measured no faster than refilling several words at a time through
+/// `io::Read`, which the decoder does for every reader.
+pub fn decompress(data: &[u8], window: u16, uncompressed_size: u64)
+                  -> io::Result<Vec<u8>> {
+    let mut decoder = Decoder::new(data, window, uncompressed_size)?;
+    let capacity = uncompressed_size.min(consts::CHUNK_SIZE as u64 * 64);
+    let mut output = Vec::with_capacity(capacity as usize);
+    decoder.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Decompresses an entire in-memory LZXD stream into `output`, whose length
+/// must be the exact uncompressed size.  The `window` argument is the same as
+/// for `Decoder::new`.
+///
+/// This is handy when the caller already owns the output buffer (such as
+/// memory shared with JavaScript when targeting WebAssembly).
+pub fn decompress_into(data: &[u8], window: u16, output: &mut [u8])
+                       -> io::Result<()> {
+    let mut decoder = Decoder::new(data, window, output.len() as u64)?;
+    decoder.decompress_into(output)
+}
+
+/// Decompresses an entire LZXD stream from `reader`, writing the
+/// decompressed data to `writer`, and returns the number of bytes written.
+/// The `window` and `uncompressed_size` arguments are the same as for
+/// `Decoder::new`.
+pub fn decompress_to_writer<R: Read, W: Write>(reader: R, window: u16,
+                                               uncompressed_size: u64,
+                                               writer: &mut W)
+                                               -> io::Result<u64> {
+    let mut decoder = Decoder::new(reader, window, uncompressed_size)?;
+    decoder.copy_to(writer)
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, copy_match, decompress, decompress_to_writer};
+    use internal::bits::BitWriter;
+    use internal::consts::{self, WINDOW_MIN};
+    use internal::btype::BlockType;
+    use internal::chunk::{BlockInfo, ChunkInfo};
+    use internal::encoder::{Encoder, compress};
+    use internal::error::Error;
+    use internal::huffman::canonical_codes;
+    use internal::pretree;
+    use internal::testutil::{self, FlakyReader, Noise, noise};
+    use std::cell::RefCell;
+    use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+    use std::rc::Rc;
+
+    /// A nonblocking reader whose data arrives a little at a time; it fails
+    /// with `WouldBlock` whenever the data that has arrived so far runs out.
+    struct TrickleReader<'a> {
+        data: &'a [u8],
+        available: usize,
+    }
+
+    impl<'a> Read for TrickleReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.available == 0 && !self.data.is_empty() {
+                self.available = 1000;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let num_bytes =
+                buf.len().min(self.data.len()).min(self.available);
+            buf[..num_bytes].copy_from_slice(&self.data[..num_bytes]);
+            self.data = &self.data[num_bytes..];
+            self.available -= num_bytes;
+            Ok(num_bytes)
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidWindow(12345)")]
+    fn invalid_window_size() {
+        let input: &[u8] = b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
+            \x00\x00\x00\x01\x00\x00\x00\x61\x62\x63\x00";
+        Decoder::new(input, 12345, 3).unwrap();
+    }
+
+    #[test]
+    fn decode_stream_with_one_uncompressed_block() {
+        let input: &[u8] = b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
+            \x00\x00\x00\x01\x00\x00\x00\x61\x62\x63\x00";
+        let mut decoder = Decoder::new(input, WINDOW_MIN, 3).unwrap();
+        let mut buffer = [0u8; 10];
+        assert_eq!(decoder.read(&mut buffer).unwrap(), 3);
+        assert_eq!(&buffer[..3], b"abc");
+    }
+
+    /// Writes a verbatim block of `block_len` bytes, with the given main tree
+    /// code lengths (and an empty length tree), made up of `symbols`.
+    fn write_verbatim_block(writer: &mut BitWriter<&mut Vec<u8>>,
+                            block_len: u32, prev_lengths: &[u8],
+                            lengths: &[u8], symbols: &[usize]) {
+        let no_lengths = [0u8; consts::NUM_SECONDARY_LENGTHS];
+        let (prev_literals, prev_matches) =
+            prev_lengths.split_at(consts::NUM_CHARS);
+        let (literals, matches) = lengths.split_at(consts::NUM_CHARS);
+        writer.write_bits(3, BlockType::Verbatim.to_bits()).unwrap();
+        writer.write_bits(24, block_len).unwrap();
+        pretree::write_lengths(writer, prev_literals, literals).unwrap();
+        pretree::write_lengths(writer, prev_matches, matches).unwrap();
+        pretree::write_lengths(writer, &no_lengths, &no_lengths).unwrap();
+        let codes = canonical_codes(lengths);
+        for &symbol in symbols {
+            writer
+                .write_bits(lengths[symbol] as u16, codes[symbol] as u32)
+                .unwrap();
+        }
+    }
+
+    /// Builds a stream of two verbatim blocks.  The first is four bytes
+    /// long, but holds a literal "a" and then a five-byte match that runs two
+    /// bytes over into the second, which is `second_len` bytes long and ends
+    /// with the literals "bcd".
+    fn match_across_blocks(second_len: u32) -> Vec<u8> {
+        let no_lengths = vec![0u8; consts::num_main_elements(WINDOW_MIN)];
+        // A match of five bytes at the most recent offset (initially one).
+        let repeat = consts::NUM_CHARS + 3;
+        let mut first_lengths = no_lengths.clone();
+        first_lengths[b'a' as usize] = 1;
+        first_lengths[repeat] = 1;
+        let mut second_lengths = no_lengths.clone();
+        second_lengths[b'b' as usize] = 1;
+        second_lengths[b'c' as usize] = 2;
+        second_lengths[b'd' as usize] = 2;
+        let literals = [b'b' as usize, b'c' as usize, b'd' as usize];
+        let mut body = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut body);
+            writer.write_bits(1, 0).unwrap();
+            write_verbatim_block(&mut writer,
+                                 4,
+                                 &no_lengths,
+                                 &first_lengths,
+                                 &[b'a' as usize, repeat]);
+            write_verbatim_block(&mut writer,
+                                 second_len,
+                                 &first_lengths,
+                                 &second_lengths,
+                                 &literals);
+            writer.align_to_16().unwrap();
+        }
+        let mut stream = (body.len() as u16).to_le_bytes().to_vec();
+        stream.extend_from_slice(&body);
+        stream
+    }
+
+    #[test]
+    fn match_runs_over_into_next_block() {
+        let stream = match_across_blocks(5);
+        assert_eq!(decompress(&stream, WINDOW_MIN, 9).unwrap(), b"aaaaaabcd");
+        // The match can't run past the end of the next block, or of the
+        // chunk.
+        let stream = match_across_blocks(1);
+        let error = decompress(&stream, WINDOW_MIN, 9).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("past end of block"));
+        let stream = match_across_blocks(5);
+        let error = decompress(&stream, WINDOW_MIN, 5).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("past end of chunk"));
+    }
+
+    #[test]
+    fn zero_match_offset() {
+        // An uncompressed block that sets the most recent offset to zero,
+        // followed by a verbatim block that repeats it.
+        let no_lengths = vec![0u8; consts::num_main_elements(WINDOW_MIN)];
+        let repeat = consts::NUM_CHARS + 3;
+        let mut lengths = no_lengths.clone();
+        lengths[b'a' as usize] = 1;
+        lengths[repeat] = 1;
+        let mut body = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut body);
+            writer.write_bits(1, 0).unwrap();
+            writer
+                .write_bits(3, BlockType::Uncompressed.to_bits())
+                .unwrap();
+            writer.write_bits(24, 2).unwrap();
+            writer.write_bits(1, 0).unwrap();
+            writer.align_to_16().unwrap();
+            writer.write_all(&[0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]).unwrap();
+            writer.write_all(b"ab").unwrap();
+            write_verbatim_block(&mut writer,
+                                 5,
+                                 &no_lengths,
+                                 &lengths,
+                                 &[repeat]);
+            writer.align_to_16().unwrap();
+        }
+        let mut stream = (body.len() as u16).to_le_bytes().to_vec();
+        stream.extend_from_slice(&body);
+        // The bytes already in the window must not be passed off as output.
+        let buffer = vec![0x55u8; 1 << WINDOW_MIN];
+        let mut decoder = Decoder::with_window_buffer(stream.as_slice(),
+                                                      WINDOW_MIN,
+                                                      7,
+                                                      buffer)
+            .unwrap();
+        let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("offset out of range (0)"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid LZX chunk size (12288)")]
+    fn invalid_chunk_size() {
+        let input: &[u8] = b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
+            \x00\x00\x00\x01\x00\x00\x00\x61\x62\x63\x00";
+        let mut decoder = Decoder::new(input, WINDOW_MIN, 3).unwrap();
+        decoder.set_chunk_size(0x3000).unwrap();
+    }
+
+    #[test]
+    fn decompress_in_memory() {
+        let input: &[u8] = b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
+            \x00\x00\x00\x01\x00\x00\x00\x61\x62\x63\x00";
+        assert_eq!(decompress(input, WINDOW_MIN, 3).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn into_inner_after_end_of_stream() {
+        let noise = noise(0x1234_5678, 1001);
+        let text = b"Hello, world! Hello, world! Hello!".repeat(30);
+        // The noise compresses to an odd-length uncompressed block, which is
+        // followed by a padding byte.
+        for data in &[noise, text] {
+            let mut stream = compress(data, WINDOW_MIN).unwrap();
+            stream.extend_from_slice(b"TRAILER");
+            let mut decoder =
+                Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(&output, data);
+            assert_eq!(decoder.into_inner().unwrap(), b"TRAILER");
+        }
+    }
+
+    #[test]
+    fn decode_with_unknown_size() {
+        let noise = noise(0x1234_5678, 1001);
+        let text = b"Size unknown. ".repeat(8000);
+        let inputs: [&[u8]; 6] = [b"",
+                                  b"abc",
+                                  &noise,
+                                  &text[..0x8000],
+                                  &text[..0x10000],
+                                  &text];
+        for data in inputs.iter() {
+            let stream = compress(data, WINDOW_MIN).unwrap();
+            let mut decoder =
+                Decoder::with_unknown_size(stream.as_slice(), WINDOW_MIN)
+                    .unwrap();
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert!(&output == data);
+            assert_eq!(decoder.total_uncompressed_size(), data.len() as u64);
+            assert_eq!(decoder.uncompressed_bytes_remaining(), 0);
+        }
+    }
+
+    #[test]
+    fn unknown_size_checks_chunk_lengths() {
+        let text = b"Size unknown. ".repeat(5000);
+        let mut stream = compress(&text, WINDOW_MIN).unwrap();
+        // Understate the first chunk's length in its size prefix.
+        stream[0] -= 4;
+        let mut decoder =
+            Decoder::with_unknown_size(stream.as_slice(), WINDOW_MIN)
+                .unwrap();
+        let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("longer than its size prefix"));
+        // A truncated stream is still an error.
+        let stream = compress(&text, WINDOW_MIN).unwrap();
+        let truncated = &stream[..(stream.len() - 5)];
+        let mut decoder =
+            Decoder::with_unknown_size(truncated, WINDOW_MIN).unwrap();
+        let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn empty_stream_round_trip() {
+        // An empty stream has no compressed data at all.
+        let mut encoder = Encoder::new(Vec::new(), WINDOW_MIN, 0).unwrap();
+        encoder.write_all(b"").unwrap();
+        assert_eq!(encoder.finish().unwrap(), 0);
+        assert!(encoder.into_inner().unwrap().is_empty());
+        let mut encoder = Encoder::with_unknown_size(Vec::new(), WINDOW_MIN)
+            .unwrap();
+        assert_eq!(encoder.finish().unwrap(), 0);
+        assert!(encoder.into_inner().unwrap().is_empty());
+        assert!(compress(b"", 21).unwrap().is_empty());
+        assert!(decompress(b"", 21, 0).unwrap().is_empty());
+        // Decoding one doesn't read anything from the reader.
+        let trailer: &[u8] = b"TRAILER";
+        let mut decoder = Decoder::new(trailer, WINDOW_MIN, 0).unwrap();
+        let mut output = Vec::new();
+        assert_eq!(decoder.read_to_end(&mut output).unwrap(), 0);
+        assert_eq!(decoder.into_inner().unwrap(), b"TRAILER");
+        let decoder = Decoder::new_raw(trailer, WINDOW_MIN, 0).unwrap();
+        assert_eq!(decoder.into_inner().unwrap(), b"TRAILER");
+        let data = b"Not empty. ".repeat(10);
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        decoder.read_to_end(&mut output).unwrap();
+        decoder.reset(trailer, 0).unwrap();
+        assert_eq!(decoder.read(&mut [0u8; 10]).unwrap(), 0);
+        assert_eq!(decoder.into_inner().unwrap(), b"TRAILER");
+    }
+
+    #[test]
+    fn debug_shows_progress() {
+        let data = b"Hello, world! Hello, world! Hello!".repeat(3000);
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        assert_eq!(decoder.total_uncompressed_size(), data.len() as u64);
+        assert_eq!(decoder.uncompressed_bytes_remaining(), data.len() as u64);
+        let mut output = vec![0u8; 1000];
+        decoder.read_exact(&mut output).unwrap();
+        assert_eq!(decoder.total_uncompressed_size(), data.len() as u64);
+        assert_eq!(decoder.uncompressed_bytes_remaining(),
+                   data.len() as u64 - 1000);
+        let debug = format!("{:?}", decoder);
+        assert!(debug.starts_with("Decoder { window_size: 32768, "));
+        assert!(debug.contains("bytes_decoded: 32768, "));
+        assert!(debug.contains("total_uncompressed_bytes_remaining: 69232, "));
+        assert!(debug.contains("block_type: Verbatim, "));
+    }
+
+    #[test]
+    fn block_callback_reports_each_block() {
+        let data = b"Hello, world! Hello, world! Hello!".repeat(1000);
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let blocks = Rc::new(RefCell::new(Vec::<BlockInfo>::new()));
+        {
+            let blocks = blocks.clone();
+            decoder.set_block_callback(move |info| {
+                                           blocks.borrow_mut().push(*info)
+                                       });
+        }
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+        let blocks = blocks.borrow();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].block_type, BlockType::Verbatim);
+        assert_eq!(blocks[0].uncompressed_offset, 0);
+        assert_eq!(blocks[0].uncompressed_len, 0x8000);
+        // The first block header follows the chunk size prefix and the
+        // one-bit E8 header.
+        assert_eq!(blocks[0].compressed_bit_offset, 17);
+        assert_eq!(blocks[1].uncompressed_offset, 0x8000);
+        assert_eq!(blocks[1].uncompressed_len, data.len() - 0x8000);
+        assert!(blocks[1].compressed_bit_offset > 17);
+        assert_eq!(blocks[1].compressed_bit_offset % 16, 0);
+    }
+
+    #[test]
+    fn chunk_callback_matches_encoder() {
+        // Mix compressible text with noise (which the encoder stores in
+        // uncompressed blocks), and end on an odd-length chunk.
+        let mut data = b"Chunks within chunks. ".repeat(2000);
+        let mut seed: u32 = 12345;
+        data.extend((0..50_001).map(|_| {
+                                        seed = seed.wrapping_mul(1103515245)
+                                            .wrapping_add(12345);
+                                        (seed >> 16) as u8
+                                    }));
+        let encoded = Rc::new(RefCell::new(Vec::<ChunkInfo>::new()));
+        let mut stream = Vec::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut stream, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            let encoded = encoded.clone();
+            encoder.set_chunk_callback(move |info| {
+                                           encoded.borrow_mut().push(*info)
+                                       });
+            encoder.write_all(&data).unwrap();
+        }
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let decoded = Rc::new(RefCell::new(Vec::<ChunkInfo>::new()));
+        {
+            let decoded = decoded.clone();
+            decoder.set_chunk_callback(move |info| {
+                                           decoded.borrow_mut().push(*info)
+                                       });
+        }
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+        assert_eq!(decoded.borrow().len(), 3);
+        assert_eq!(*decoded.borrow(), *encoded.borrow());
+    }
+
+    #[test]
+    fn tiny_reads_decode_each_chunk_once() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|n| (n / 7 + n / 1000) as u8)
+            .collect();
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let num_chunks = Rc::new(RefCell::new(0));
+        {
+            let num_chunks = num_chunks.clone();
+            decoder.set_chunk_callback(move |_| *num_chunks.borrow_mut() += 1);
+        }
+        let mut output = Vec::new();
+        let mut byte = [0u8; 1];
+        while decoder.read(&mut byte).unwrap() == 1 {
+            output.push(byte[0]);
+            assert_eq!(*num_chunks.borrow(), 1 + (output.len() - 1) / 32768);
+        }
+        assert!(output == data);
+        assert_eq!(*num_chunks.borrow(), 4);
+    }
+
+    #[test]
+    fn buf_read_lines() {
+        let text: String = (0..5000)
+            .map(|n| format!("Line number {}\n", n))
+            .collect();
+        let stream = compress(text.as_bytes(), WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, text.len() as u64)
+                .unwrap();
+        assert_eq!(decoder.fill_buf().unwrap().len(), 32768);
+        decoder.consume(5);
+        let mut word = Vec::new();
+        decoder.read_until(b' ', &mut word).unwrap();
+        assert_eq!(word, b"number ");
+        let mut lines = decoder.lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "0");
+        assert_eq!(lines.next().unwrap().unwrap(), "Line number 1");
+        assert_eq!(lines.last().unwrap().unwrap(), "Line number 4999");
+    }
+
+    #[test]
+    fn seek_forward() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|n| (n / 7 + n / 1000) as u8)
+            .collect();
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut buffer = [0u8; 100];
+        assert_eq!(decoder.seek(SeekFrom::Start(40_000)).unwrap(), 40_000);
+        decoder.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &data[40_000..40_100]);
+        assert_eq!(decoder.stream_position().unwrap(), 40_100);
+        assert_eq!(decoder.seek(SeekFrom::Current(50)).unwrap(), 40_150);
+        decoder.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &data[40_150..40_250]);
+        assert_eq!(decoder.seek(SeekFrom::End(-100)).unwrap(), 99_900);
+        decoder.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &data[99_900..]);
+        assert!(decoder.seek(SeekFrom::Start(5)).is_err());
+        assert!(decoder.seek(SeekFrom::End(1)).is_err());
+    }
+
+    #[test]
+    fn decompress_into_exact_buffer() {
+        let data = b"Exactly this many bytes. ".repeat(2000);
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        for &len in &[data.len() - 1, data.len() + 1] {
+            let mut output = vec![0u8; len];
+            let error = decoder.decompress_into(&mut output).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        let mut output = vec![0u8; data.len()];
+        decoder.read_exact(&mut output[..100]).unwrap();
+        decoder.decompress_into(&mut output[100..]).unwrap();
+        assert!(output == data);
+        decoder.decompress_into(&mut []).unwrap();
+    }
+
+    #[test]
+    fn peek_without_consuming() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|n| (n / 7 + n / 1000) as u8)
+            .collect();
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut magic = [0u8; 4];
+        assert_eq!(decoder.peek(&mut magic).unwrap(), 4);
+        assert_eq!(magic, data[..4]);
+        let mut buffer = vec![0u8; 0x8000 - 10];
+        decoder.read_exact(&mut buffer).unwrap();
+        assert!(buffer == data[..buffer.len()]);
+        // Look ahead across two chunk boundaries.
+        let lookahead = decoder.fill_lookahead(0x10000).unwrap();
+        assert_eq!(lookahead.len(), 0x10000 + 10);
+        let start = buffer.len();
+        assert!(lookahead == &data[start..(start + 0x10000 + 10)]);
+        assert_eq!(decoder.position(), buffer.len() as u64);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data[buffer.len()..]);
+        assert_eq!(decoder.fill_lookahead(10).unwrap(), &[]);
+    }
+
+    #[test]
+    fn lookahead_past_end_of_stream() {
+        let data = b"Asking for more than there is. ".repeat(100);
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut buffer = [0u8; 100];
+        decoder.read_exact(&mut buffer).unwrap();
+        let lookahead = decoder.fill_lookahead(usize::MAX).unwrap();
+        assert!(lookahead == &data[100..]);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data[100..]);
+        assert_eq!(decoder.fill_lookahead(usize::MAX).unwrap(), &[]);
+    }
+
+    #[test]
+    fn clone_reads_ahead_independently() {
+        let data: Vec<u8> = (0..50_000u32)
+            .map(|n| (n / 5 + n / 777) as u8)
+            .collect();
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut buffer = vec![0u8; 20_000];
+        decoder.read_exact(&mut buffer).unwrap();
+        let mut clone = decoder.clone();
+        let mut ahead = Vec::new();
+        clone.read_to_end(&mut ahead).unwrap();
+        assert_eq!(ahead, &data[20_000..]);
+        let mut rest = Vec::new();
+        decoder.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, ahead);
+    }
+
+    #[test]
+    fn reset_for_next_stream() {
+        let first = b"The first stream. ".repeat(3000);
+        let second = b"And now, the second stream! ".repeat(100);
+        let first_stream = compress(&first, WINDOW_MIN).unwrap();
+        let second_stream = compress(&second, WINDOW_MIN).unwrap();
+        let mut decoder = Decoder::new(first_stream.as_slice(),
+                                       WINDOW_MIN,
+                                       first.len() as u64)
+            .unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, first);
+        let old_reader = decoder
+            .reset(second_stream.as_slice(), second.len() as u64)
+            .unwrap();
+        assert!(old_reader.is_empty());
+        output.clear();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, second);
+    }
+
+    #[test]
+    fn small_streams_use_small_windows() {
+        let small = b"Tiny. ".repeat(150);
+        let stream = compress(&small, 21).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), 21, small.len() as u64).unwrap();
+        assert_eq!(decoder.window.len(), 1024);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, small);
+
+        let dictionary = b"A dictionary to start from. ".repeat(100);
+        let mut encoder =
+            Encoder::new(Vec::new(), 21, small.len() as u64).unwrap();
+        encoder.set_dictionary(&dictionary).unwrap();
+        encoder.write_all(&small).unwrap();
+        let dict_stream = encoder.into_inner().unwrap();
+        let mut decoder =
+            Decoder::new(dict_stream.as_slice(), 21, small.len() as u64)
+                .unwrap();
+        decoder.set_dictionary(&dictionary).unwrap();
+        assert_eq!(decoder.window.len(), 4096);
+        output.clear();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, small);
+
+        let large = b"Rather larger than before. ".repeat(10000);
+        let stream = compress(&large, 21).unwrap();
+        decoder.reset(stream.as_slice(), large.len() as u64).unwrap();
+        assert_eq!(decoder.window.len(), 0x80000);
+        output.clear();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == large);
+    }
+
+    #[test]
+    fn caller_provided_window_buffer() {
+        let data = b"Reuse the window buffer, please. ".repeat(2000);
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut buffer = vec![0xffu8; 0x10000];
+        for _ in 0..2 {
+            let mut decoder = Decoder::with_window_buffer(stream.as_slice(),
+                                                          WINDOW_MIN,
+                                                          data.len() as u64,
+                                                          buffer)
+                .unwrap();
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(output, data);
+            buffer = decoder.into_window_buffer();
+            assert_eq!(buffer.len(), 0x8000);
+        }
+        let error = Decoder::with_window_buffer(stream.as_slice(),
+                                                WINDOW_MIN + 1,
+                                                data.len() as u64,
+                                                buffer)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(),
+                   "LZX window buffer is too small (32768 < 65536)");
+    }
+
+    #[test]
+    fn decode_without_allocating() {
+        // Data with plenty of E8 bytes, translated so that every chunk goes
+        // through the chunk buffer.
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|n| if n % 13 == 0 { 0xe8 } else { (n / 7 % 251) as u8 })
+            .collect();
+        let mut stream = Vec::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut stream, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        let mut window = vec![0u8; 0x8000];
+        let mut chunk = vec![0u8; consts::CHUNK_SIZE];
+        let mut output = vec![0u8; data.len()];
+        let allocations = testutil::allocations();
+        {
+            let mut decoder = Decoder::with_buffers(stream.as_slice(),
+                                                    WINDOW_MIN,
+                                                    data.len() as u64,
+                                                    &mut window,
+                                                    &mut chunk)
+                .unwrap();
+            decoder.read_exact(&mut output).unwrap();
+            assert_eq!(decoder.read(&mut [0; 16]).unwrap(), 0);
+        }
+        assert_eq!(testutil::allocations(), allocations);
+        assert!(output == data);
+    }
+
+    #[test]
+    fn caller_buffers_too_small() {
+        let data = b"Not enough room. ".repeat(3000);
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut window = vec![0u8; 0x8000];
+        let mut chunk = vec![0u8; 0x8000];
+        let error = Decoder::with_buffers(stream.as_slice(),
+                                          WINDOW_MIN + 1,
+                                          data.len() as u64,
+                                          &mut window,
+                                          &mut chunk)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(),
+                   "LZX window buffer is too small (32768 < 65536)");
+        let error = Decoder::with_buffers(stream.as_slice(),
+                                          WINDOW_MIN,
+                                          data.len() as u64,
+                                          &mut window,
+                                          &mut chunk[..0x1000])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(),
+                   "LZX chunk buffer is too small (4096 < 32768)");
+        // A short stream only needs room for its data.
+        let short = compress(b"Short", WINDOW_MIN).unwrap();
+        let mut decoder = Decoder::with_buffers(short.as_slice(),
+                                                WINDOW_MIN,
+                                                5,
+                                                &mut window,
+                                                &mut chunk[..5])
+            .unwrap();
+        assert!(decoder.set_chunk_size(0x1000).is_ok());
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"Short");
+    }
+
+    #[test]
+    fn copy_to_writer() {
+        let data = b"Copy this to a writer. ".repeat(4000);
+        let stream = compress(&data, WINDOW_MIN).unwrap();
+        let mut decoder =
+            Decoder::new(stream.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut output = vec![0u8; 10];
+        decoder.read_exact(&mut output).unwrap();
+        assert_eq!(decoder.copy_to(&mut output).unwrap(),
+                   data.len() as u64 - 10);
+        assert_eq!(output, data);
+        assert_eq!(decoder.copy_to(&mut output).unwrap(), 0);
+        output.clear();
+        let num_bytes = decompress_to_writer(stream.as_slice(),
+                                             WINDOW_MIN,
+                                             data.len() as u64,
+                                             &mut output)
+            .unwrap();
+        assert_eq!(num_bytes, data.len() as u64);
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn retry_interrupted_reads() {
+        let mut data = b"Some compressible text. ".repeat(1500);
+        data.extend(noise(1, 0x9001));
+        let stream = compress(&data, 16).unwrap();
+        let reader = FlakyReader::new(&stream, 1);
+        let mut decoder =
+            Decoder::new(reader, 16, data.len() as u64).unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+    }
+
+    #[test]
+    fn resume_after_would_block() {
+        let mut random = Noise::new(1);
+        // Interleave random data with copies of data from nearly the window
+        // size earlier, so that matches refer to the part of the window that
+        // each chunk overwrites.
+        let mut data: Vec<u8> = random.by_ref().take(0x7ff0).collect();
+        for index in 0..0x600 {
+            let start = 32 * index;
+            let copy = data[start..(start + 16)].to_vec();
+            data.extend_from_slice(&copy);
+            data.extend(random.by_ref().take(16));
+        }
+        data.extend(b"Some compressible text. ".repeat(1000));
+        let stream = compress(&data, 15).unwrap();
+        let reader = TrickleReader {
+            data: &stream,
+            available: 1000,
+        };
+        let mut decoder =
+            Decoder::new(reader, 15, data.len() as u64).unwrap();
+        decoder.set_nonblocking(true);
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 1000];
+        let mut num_would_blocks = 0;
+        loop {
+            match decoder.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(num_bytes) => {
+                    output.extend_from_slice(&buffer[..num_bytes]);
+                }
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::WouldBlock => {
+                    num_would_blocks += 1;
+                }
+                Err(error) => panic!("{}", error),
+            }
+        }
+        assert!(num_would_blocks > 0);
+        assert!(output == data);
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let input: &[u8] = b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
+            \x00\x00\x00\x01\x00\x00\x00\x61";
+        let mut decoder = Decoder::new(input, WINDOW_MIN, 3).unwrap();
+        let mut output = Vec::new();
+        let error = decoder.read_to_end(&mut output).unwrap_err();
+        assert_eq!(Error::from_io(&error).unwrap().without_offset(),
+                   &Error::Truncated);
+        let error = Decoder::new(&input[..1], WINDOW_MIN, 3).err().unwrap();
+        assert_eq!(Error::from_io(&error), Some(&Error::Truncated));
+    }
+
+    #[test]
+    fn output_limit() {
+        let data = b"Bigger than it should be. ".repeat(5000);
+        let compressed = compress(&data, 16).unwrap();
+        let mut decoder =
+            Decoder::new(compressed.as_slice(), 16, data.len() as u64)
+                .unwrap();
+        decoder.set_max_output(0x10000 + 1);
+        let mut output = Vec::new();
+        let error = decoder.read_to_end(&mut output).unwrap_err();
+        assert_eq!(Error::from_io(&error),
+                   Some(&Error::OutputLimitExceeded(0x10001)));
+        assert_eq!(output, &data[..0x10000]);
+        let mut decoder =
+            Decoder::new(compressed.as_slice(), 16, data.len() as u64)
+                .unwrap();
+        decoder.set_max_output(data.len() as u64);
+        output.clear();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+    }
+
+    #[test]
+    fn lenient_eof() {
+        let data = b"Some of this will survive. ".repeat(5000);
+        let compressed = compress(&data, 16).unwrap();
+        let truncated = &compressed[..(compressed.len() * 3 / 4)];
+        let mut decoder =
+            Decoder::new(truncated, 16, data.len() as u64).unwrap();
+        let mut output = Vec::new();
+        let error = decoder.read_to_end(&mut output).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(!decoder.ended_early());
+        let mut decoder =
+            Decoder::new(truncated, 16, data.len() as u64).unwrap();
+        decoder.set_lenient_eof(true);
+        output.clear();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(decoder.ended_early());
+        assert!(!output.is_empty() && output.len() < data.len());
+        assert_eq!(output.len() % 0x8000, 0);
+        assert_eq!(output, &data[..output.len()]);
+        assert_eq!(decoder.uncompressed_bytes_remaining(),
+                   (data.len() - output.len()) as u64);
+        assert_eq!(decoder.read(&mut [0u8; 10]).unwrap(), 0);
+    }
+
+    #[test]
+    fn errors_report_stream_offsets() {
+        // No E8 translation, followed by an invalid block type of zero.
+        let input: &[u8] = b"\x04\x00\x00\x00\x00\x00";
+        let mut decoder = Decoder::new(input, WINDOW_MIN, 3).unwrap();
+        let mut output = Vec::new();
+        let error = decoder.read_to_end(&mut output).unwrap_err();
+        match *Error::from_io(&error).unwrap() {
+            Error::AtOffset {
+                compressed_bit_offset,
+                uncompressed_offset,
+                ref error,
+            } => {
+                assert_eq!(**error, Error::InvalidBlockType(0));
+                assert_eq!(compressed_bit_offset, 16 + 1 + 3);
+                assert_eq!(uncompressed_offset, 0);
+            }
+            ref other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{Event, Metadata, Subscriber, subscriber};
+        use tracing::span::{Attributes, Id, Record};
+
+        /// Records the names of the spans that are created.
+        struct SpanNames(Mutex<Vec<&'static str>>);
+
+        impl Subscriber for SpanNames {
+            fn enabled(&self, _metadata: &Metadata) -> bool { true }
+
+            fn new_span(&self, span: &Attributes) -> Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name());
+                Id::from_u64(names.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record) {}
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, _event: &Event) {}
+
+            fn enter(&self, _span: &Id) {}
+
+            fn exit(&self, _span: &Id) {}
+        }
+
+        let data = b"Traced. ".repeat(10000);
+        let compressed = compress(&data, 16).unwrap();
+        let names = Arc::new(SpanNames(Mutex::new(Vec::new())));
+        let output = subscriber::with_default(names.clone(), || {
+            decompress(&compressed, 16, data.len() as u64).unwrap()
+        });
+        assert!(output == data);
+        let names = names.0.lock().unwrap();
+        let num_chunks = names.iter().filter(|&&name| name == "lzxd_chunk");
+        assert_eq!(num_chunks.count(), 3);
+        assert!(names.contains(&"lzxd_block"));
+    }
+
+    #[test]
+    fn copy_match_matches_byte_loop() {
+        let original: Vec<u8> =
+            (0..64u32).map(|n| (n * 37 % 251) as u8).collect();
+        for &position in &[0, 5, 20, 40] {
+            for offset in 1..(original.len() + 1) {
+                for length in 0..(original.len() - position + 1) {
+                    let mut expected = original.clone();
+                    for index in position..(position + length) {
+                        expected[index] =
+                            expected[index.wrapping_sub(offset) & 63];
+                    }
+                    let mut window = original.clone();
+                    copy_match(&mut window, position, offset, length);
+                    assert_eq!(window, expected);
+                }
+            }
+        }
     }
 }
 
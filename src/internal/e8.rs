@@ -0,0 +1,177 @@
+// ========================================================================= //
+
+/// The translation size to use when the stream's size isn't known in advance
+/// (this is the value used by Microsoft's compressor in WIM files).
+pub const DEFAULT_TRANSLATION_SIZE: u32 = 12_000_000;
+
+/// The largest translation size the encoder writes.  The stream header field
+/// is 32 bits, but some decoders read it as a signed value, and translated
+/// CALL targets must fit in a signed 32-bit operand, so the sizes of larger
+/// streams (such as multi-gigabyte WIM images) are clamped to this.
+pub const MAX_TRANSLATION_SIZE: u32 = i32::MAX as u32;
+
+const E8_OPCODE: u8 = 0xe8;
+
+/// E8 translation is only applied to the first 2^30 bytes of the stream.
+const MAX_TRANSLATED_POSITION: u64 = 1 << 30;
+
+/// The last 10 bytes of each chunk are never translated.
+const CHUNK_TAIL_LEN: usize = 10;
+
+// ========================================================================= //
+
+/// Returns the translation size for the stream header of a stream of the
+/// given total size.  Since translation stops after the first 2^30 bytes,
+/// clamping only changes how CALL targets near the end of a huge stream's
+/// address space are treated, consistently in the encoder and decoder.
+pub fn translation_size(stream_size: u64) -> u32 {
+    stream_size.min(MAX_TRANSLATION_SIZE as u64) as u32
+}
+
+/// Returns true if E8 translation applies to a chunk of the given length that
+/// starts at the given position in the uncompressed stream.
+pub fn applies_to_chunk(translation_size: u32, chunk_start: u64,
+                        chunk_len: usize)
+                        -> bool {
+    translation_size != 0 && chunk_start < MAX_TRANSLATED_POSITION &&
+        chunk_len > CHUNK_TAIL_LEN
+}
+
+/// Converts relative x86 CALL targets in the chunk to absolute ones, as done
+/// by the encoder before compression.
+pub fn encode_chunk(translation_size: u32, chunk_start: u64,
+                    chunk: &mut [u8]) {
+    let size = translation_size as i64;
+    translate_chunk(chunk_start, chunk, |position, relative| {
+        if relative >= -position && relative < size {
+            if relative < size - position {
+                relative + position
+            } else {
+                relative - size
+            }
+        } else {
+            relative
+        }
+    });
+}
+
+/// Converts absolute x86 CALL targets in the chunk back to relative ones, as
+/// done by the decoder after decompression.
+pub fn decode_chunk(translation_size: u32, chunk_start: u64,
+                    chunk: &mut [u8]) {
+    let size = translation_size as i64;
+    translate_chunk(chunk_start, chunk, |position, absolute| {
+        if absolute >= -position && absolute < size {
+            if absolute >= 0 {
+                absolute - position
+            } else {
+                absolute + size
+            }
+        } else {
+            absolute
+        }
+    });
+}
+
+fn translate_chunk<F>(chunk_start: u64, chunk: &mut [u8], translate: F)
+    where F: Fn(i64, i64) -> i64
+{
+    if chunk.len() <= CHUNK_TAIL_LEN {
+        return;
+    }
+    let end = chunk.len() - CHUNK_TAIL_LEN;
+    let mut index = 0;
+    while index < end {
+        if chunk[index] != E8_OPCODE {
+            index += 1;
+            continue;
+        }
+        let position = (chunk_start + index as u64) as i64;
+        let operand = &mut chunk[(index + 1)..(index + 5)];
+        let value = i32::from_le_bytes([operand[0],
+                                        operand[1],
+                                        operand[2],
+                                        operand[3]]);
+        let translated = translate(position, value as i64) as i32;
+        operand.copy_from_slice(&translated.to_le_bytes());
+        index += 5;
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_TRANSLATION_SIZE, applies_to_chunk, decode_chunk,
+                encode_chunk, translation_size};
+
+    #[test]
+    fn translate_call_targets() {
+        let mut chunk = b"\x90\xe8\x10\x00\x00\x00\xe8\xfb\xff\xff\xff\
+                          \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00"
+            .to_vec();
+        encode_chunk(1000, 100, &mut chunk);
+        // A forward call from position 101 to 101 + 16.
+        assert_eq!(&chunk[1..6], b"\xe8\x75\x00\x00\x00");
+        // A backward call from position 106 to 106 - 5.
+        assert_eq!(&chunk[6..11], b"\xe8\x65\x00\x00\x00");
+        decode_chunk(1000, 100, &mut chunk);
+        assert_eq!(&chunk[1..6], b"\xe8\x10\x00\x00\x00");
+        assert_eq!(&chunk[6..11], b"\xe8\xfb\xff\xff\xff");
+    }
+
+    #[test]
+    fn round_trip_all_operands() {
+        let operands: &[i32] = &[0, 1, -1, 499, 500, 501, -500, -501, 999,
+                                 1000, 1001, i32::MAX, i32::MIN];
+        for &operand in operands {
+            let mut chunk = vec![0u8; 20];
+            chunk[0] = 0xe8;
+            chunk[1..5].copy_from_slice(&operand.to_le_bytes());
+            let original = chunk.clone();
+            encode_chunk(1000, 500, &mut chunk);
+            decode_chunk(1000, 500, &mut chunk);
+            assert_eq!(chunk, original);
+        }
+    }
+
+    #[test]
+    fn tail_is_not_translated() {
+        let mut chunk = b"\x00\xe8\x10\x00\x00\x00\x00\x00\x00\x00\x00"
+            .to_vec();
+        let original = chunk.clone();
+        encode_chunk(1000, 0, &mut chunk);
+        assert_eq!(chunk, original);
+    }
+
+    #[test]
+    fn applicability() {
+        assert!(applies_to_chunk(1000, 0, 11));
+        assert!(!applies_to_chunk(0, 0, 11));
+        assert!(!applies_to_chunk(1000, 0, 10));
+        assert!(!applies_to_chunk(1000, 1 << 30, 0x8000));
+        assert!(!applies_to_chunk(1000, 5 << 30, 0x8000));
+    }
+
+    #[test]
+    fn translation_size_is_clamped() {
+        assert_eq!(translation_size(0), 0);
+        assert_eq!(translation_size(12_000_000), 12_000_000);
+        assert_eq!(translation_size(0x7fff_ffff), MAX_TRANSLATION_SIZE);
+        assert_eq!(translation_size(0x8000_0000), MAX_TRANSLATION_SIZE);
+        assert_eq!(translation_size(5 << 30), MAX_TRANSLATION_SIZE);
+        // With the clamped size, even the largest operands round-trip (a
+        // larger size would push translated targets past i32::MAX).
+        for &operand in &[i32::MAX, i32::MIN, i32::MAX - 100] {
+            let mut chunk = vec![0u8; 20];
+            chunk[0] = 0xe8;
+            chunk[1..5].copy_from_slice(&operand.to_le_bytes());
+            let original = chunk.clone();
+            encode_chunk(MAX_TRANSLATION_SIZE, 100, &mut chunk);
+            decode_chunk(MAX_TRANSLATION_SIZE, 100, &mut chunk);
+            assert_eq!(chunk, original);
+        }
+    }
+}
+
+// ========================================================================= //
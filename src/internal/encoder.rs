@@ -1,17 +1,61 @@
 use byteorder::{LittleEndian, WriteBytesExt};
 use internal::bits::BitWriter;
 use internal::btype::BlockType;
+use internal::chunk::{CancelCheck, ChunkIndex, ChunkInfo, ProgressCallback};
 use internal::consts;
-use std::io::{self, Write};
+use internal::e8;
+use internal::error::Error;
+use internal::huffman;
+use internal::matcher::{MatchFinder, MatchParams};
+use internal::parse::{self, CostModel};
+use internal::pretree;
+use internal::stats::EncoderStats;
+use internal::token::{EncodedToken, Token};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+
+// ========================================================================= //
+
+type ChunkCallback = Box<dyn FnMut(&ChunkInfo)>;
 
 // ========================================================================= //
 
 /// An LZXD encoder/compressor.
+///
+/// The compressed output depends only on the input data and the encoder's
+/// settings, and never on the platform or on memory addresses, so repeated
+/// runs produce byte-identical streams.
 pub struct Encoder<W: Write> {
     writer: BitWriter<W>,
     wrote_header: bool,
+    e8_translation_size: u32,
+    size_declared: bool,
     total_uncompressed_bytes_remaining: u64,
+    chunk_size: usize,
+    reset_interval: u32,
+    reset_table: Vec<(u64, u64)>,
+    chunk_index: Option<ChunkIndex>,
+    dictionary: Vec<u8>,
+    two_pass: bool,
+    fixed_trees: Option<Trees>,
+    delta: bool,
+    raw: bool,
+    wim: bool,
+    flush_partial_chunks: bool,
+    chunk_offset: u64,
+    stats: EncoderStats,
+    chunk_callback: Option<ChunkCallback>,
+    progress_callback: Option<ProgressCallback>,
+    cancel_check: Option<CancelCheck>,
     chunk_buffer: Vec<u8>,
+    chunk_tokens: Vec<Token>,
+    tokenized_len: usize,
+    matcher: MatchFinder,
+    max_offset: u32,
+    recent: (u32, u32, u32),
+    main_lengths: Vec<u8>,
+    length_lengths: Vec<u8>,
 }
 
 impl<W: Write> Encoder<W> {
@@ -19,112 +63,2426 @@ impl<W: Write> Encoder<W> {
     ///
     /// The `window` argument determines the size of the compression window,
     /// and its value must be between the `WINDOW_MIN` and `WINDOW_MAX`
-    /// constants (inclusive).
+    /// constants (inclusive); `WindowSize::exponent()` gives a valid value.
+    /// With the `nonstandard-windows` feature, any value from
+    /// `WINDOW_MIN_NONSTANDARD` to `WINDOW_MAX_DELTA` is accepted.
     ///
     /// The `uncompressed_size` argument must specify the exact size of the of
-    /// the original, uncompressed data, in bytes.
+    /// the original, uncompressed data, in bytes.  If it is zero, the stream
+    /// is empty, and nothing is written to `writer`.
     pub fn new(writer: W, window: u16, uncompressed_size: u64)
                -> io::Result<Encoder<W>> {
-        if window < consts::WINDOW_MIN || window > consts::WINDOW_MAX {
-            invalid_input!("Invalid LZX window ({})", window);
+        Encoder::create(writer, window, Some(uncompressed_size), false)
+    }
+
+    /// Starts encoding an LZX DELTA stream (see `set_delta`).  This is the
+    /// same as `Encoder::new`, except that the `window` argument may be as
+    /// large as the `WINDOW_MAX_DELTA` constant.
+    pub fn new_delta(writer: W, window: u16, uncompressed_size: u64)
+                     -> io::Result<Encoder<W>> {
+        Encoder::create(writer, window, Some(uncompressed_size), true)
+    }
+
+    /// Starts encoding an LZXD-compressed data stream whose uncompressed size
+    /// is not known in advance.
+    ///
+    /// The `window` argument is the same as for `Encoder::new`.  Once all
+    /// data has been written, call `finish()` to emit the final partial chunk.
+    /// Since the decoder must be told the exact uncompressed size, containers
+    /// that store it (such as cabinet folders) should record the value that
+    /// `finish()` returns.
+    pub fn with_unknown_size(writer: W, window: u16)
+                             -> io::Result<Encoder<W>> {
+        Encoder::create(writer, window, None, false)
+    }
+
+    pub(crate) fn create(writer: W, window: u16,
+                         uncompressed_size: Option<u64>, delta: bool)
+                         -> io::Result<Encoder<W>> {
+        if !consts::is_valid_window(window, delta) {
+            return Err(Error::InvalidWindow(window).into());
         }
-        let encoder = Encoder {
+        let mut encoder = Encoder {
             writer: BitWriter::new(writer),
             wrote_header: false,
-            total_uncompressed_bytes_remaining: uncompressed_size,
+            e8_translation_size: 0,
+            size_declared: uncompressed_size.is_some(),
+            total_uncompressed_bytes_remaining: uncompressed_size
+                .unwrap_or(u64::MAX),
+            chunk_size: consts::CHUNK_SIZE,
+            reset_interval: 0,
+            reset_table: Vec::new(),
+            chunk_index: None,
+            dictionary: Vec::new(),
+            two_pass: false,
+            fixed_trees: None,
+            delta: false,
+            raw: false,
+            wim: false,
+            flush_partial_chunks: false,
+            chunk_offset: 0,
+            stats: EncoderStats::default(),
+            chunk_callback: None,
+            progress_callback: None,
+            cancel_check: None,
             chunk_buffer: Vec::with_capacity(consts::CHUNK_SIZE),
+            chunk_tokens: Vec::new(),
+            tokenized_len: 0,
+            matcher: MatchFinder::new(window),
+            max_offset: (1 << window) - 3,
+            recent: (1, 1, 1),
+            main_lengths: vec![0u8; consts::num_main_elements(window)],
+            length_lengths: vec![0u8; consts::NUM_SECONDARY_LENGTHS],
         };
+        encoder.set_delta(delta)?;
         Ok(encoder)
     }
 
-    fn emit_chunk(&mut self) -> io::Result<()> {
-        debug_assert!(!self.chunk_buffer.is_empty());
-        debug_assert!(self.chunk_buffer.len() <= consts::CHUNK_SIZE);
-        debug_assert!(self.chunk_buffer.len() == consts::CHUNK_SIZE ||
-                          self.total_uncompressed_bytes_remaining == 0);
-        // TODO: Don't always produce uncompressed blocks.
-        let chunk_compressed_size = 16 + self.chunk_buffer.len() +
-            (self.chunk_buffer.len() & 1);
-        self.writer.align_to_16()?;
-        self.writer.write_u16::<LittleEndian>(chunk_compressed_size as u16)?;
-        if !self.wrote_header {
-            self.writer.write_bits(1, 0)?;
-            self.wrote_header = true;
-        }
-        self.writer.write_bits(3, BlockType::Uncompressed.to_bits())?;
-        self.writer.write_bits(24, self.chunk_buffer.len() as u32)?;
-        self.writer.write_bits(1, 0)?;
+    /// Enables or disables x86 E8 call translation (disabled by default).
+    ///
+    /// When enabled, the relative targets of x86 CALL instructions in the
+    /// input are converted to absolute addresses before compression, which
+    /// typically improves compression of executable code.  The decoder
+    /// reverses the translation automatically.  This must be set before any
+    /// data is written to the encoder.
+    ///
+    /// The translation size recorded in the stream header is the declared
+    /// uncompressed size, clamped to `i32::MAX` for streams of 2 GiB or more
+    /// (or a fixed 12,000,000 if the size isn't declared).  Only the first
+    /// 1 GiB of a stream is ever translated.
+    pub fn set_e8_translation(&mut self, enable: bool) -> io::Result<()> {
+        if self.has_data() {
+            invalid_input!("Cannot change E8 translation after writing data");
+        }
+        self.e8_translation_size = if !enable {
+            0
+        } else if self.size_declared && !self.wim {
+            e8::translation_size(self.chunk_offset +
+                                     self.chunk_buffer.len() as u64 +
+                                     self.total_uncompressed_bytes_remaining)
+        } else {
+            e8::DEFAULT_TRANSLATION_SIZE
+        };
+        Ok(())
+    }
+
+    /// Switches to encoding a chunk of a WIM resource, which is a raw LZXD
+    /// stream with a different block header layout and no E8 translation
+    /// header (translation is always enabled, with a fixed translation size).
+    /// This must be set before any data is written to the encoder.
+    pub(crate) fn set_wim_format(&mut self) -> io::Result<()> {
+        self.set_raw_chunks(true)?;
+        self.wim = true;
+        self.set_e8_translation(true)
+    }
+
+    /// Sets the number of uncompressed bytes in each chunk (32768 by default).
+    ///
+    /// Some containers split LZXD data into smaller chunks than usual.  The
+    /// chunk size must be a power of two no larger than the default, and the
+    /// decoder must be configured with the same value.  This must be set
+    /// before any data is written to the encoder.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) -> io::Result<()> {
+        if !consts::is_valid_chunk_size(chunk_size) {
+            invalid_input!("Invalid LZX chunk size ({})", chunk_size);
+        }
+        if self.has_data() {
+            invalid_input!("Cannot change LZX chunk size after writing data");
+        }
+        self.chunk_size = chunk_size;
+        Ok(())
+    }
+
+    /// Enables or disables raw mode (disabled by default), in which chunks are
+    /// written without size prefixes, for containers that record each
+    /// chunk's compressed length elsewhere (such as CHM files).  The chunk
+    /// callback (see `set_chunk_callback`) reports each chunk's length as it
+    /// is written.  Such a stream must be decoded with `Decoder::new_raw`.
+    /// This must be set before any data is written to the encoder.
+    pub fn set_raw_chunks(&mut self, enable: bool) -> io::Result<()> {
+        if self.has_data() {
+            invalid_input!("Cannot change LZX raw mode after writing data");
+        }
+        self.raw = enable;
+        Ok(())
+    }
+
+    /// Makes the encoder fully reset its state every `num_chunks` chunks (or
+    /// never, if `num_chunks` is zero, which is the default), so that the
+    /// stream can be decoded starting from any reset point.
+    ///
+    /// At each reset point, the Huffman trees, repeated match offsets, and
+    /// stream header are all reset, and no match refers to data before that
+    /// point.  This is the scheme used by CHM files.  The decoder must be
+    /// configured with the same interval.  This must be set before any data
+    /// is written to the encoder.
+    pub fn set_reset_interval(&mut self, num_chunks: u32) -> io::Result<()> {
+        if self.has_data() {
+            invalid_input!("Cannot change LZX reset interval after writing \
+                            data");
+        }
+        self.reset_interval = num_chunks;
+        Ok(())
+    }
+
+    /// Enables or disables two-pass encoding (disabled by default).
+    ///
+    /// In two-pass mode, the encoder first parses each chunk greedily to
+    /// estimate the cost of each symbol, then parses it again to find the
+    /// cheapest encoding under those costs.  This gives a better compression
+    /// ratio at the expense of speed.
+    pub fn set_two_pass(&mut self, enable: bool) { self.two_pass = enable; }
+
+    /// Makes `flush()` emit any buffered data as a short chunk (disabled by
+    /// default), so that everything written so far reaches the underlying
+    /// writer.
+    ///
+    /// Normally only the last chunk of a stream is short, and decoders rely
+    /// on this to find chunk boundaries; a stream with short chunks in the
+    /// middle can only be decoded by a decoder that is told where each chunk
+    /// ends.  This is meant for protocols that frame LZX data themselves.  If
+    /// data is flushed at regular intervals, a `Decoder` can be given that
+    /// interval with `set_chunk_size`.
+    pub fn set_flush_partial_chunks(&mut self, enable: bool) {
+        self.flush_partial_chunks = enable;
+    }
+
+    /// Sets tuning parameters for the encoder's match finder, for finer
+    /// control over the speed/ratio tradeoff.
+    pub fn set_match_params(&mut self, params: MatchParams)
+                            -> io::Result<()> {
+        if !params.is_valid() {
+            invalid_input!("Invalid LZX match parameters ({:?})", params);
+        }
+        self.matcher.set_params(params);
+        Ok(())
+    }
+
+    /// Enables or disables fixed Huffman trees (disabled by default).
+    ///
+    /// When enabled, the encoder uses the same predefined Huffman trees for
+    /// every block, tuned for typical data, instead of building trees from
+    /// each block's contents.  This speeds up encoding at some cost in
+    /// compression ratio.  The output remains a standard LZXD stream.
+    pub fn set_fixed_trees(&mut self, enable: bool) {
+        self.fixed_trees = if enable {
+            Some(Trees::fixed(self.main_lengths.len()))
+        } else {
+            None
+        };
+    }
+
+    /// Primes the encoder with data that matches in the stream may refer back
+    /// to, as if it had been written just before the start of the stream
+    /// (but without it being included in the compressed output).  This can
+    /// greatly improve compression of small inputs that share content with
+    /// the dictionary.
+    ///
+    /// The dictionary may be at most the window size, and the decoder must be
+    /// primed with the same dictionary.  This must be set before any data is
+    /// written to the encoder.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        if dictionary.len() > self.max_offset as usize + 3 {
+            invalid_input!("LZX dictionary is larger than window ({} > {})",
+                           dictionary.len(),
+                           self.max_offset as usize + 3);
+        }
+        if self.has_data() {
+            invalid_input!("Cannot change LZX dictionary after writing data");
+        }
+        self.dictionary.clear();
+        self.dictionary.extend_from_slice(dictionary);
+        self.matcher.reset();
+        self.prime_dictionary();
+        Ok(())
+    }
+
+    /// Like `set_dictionary`, but reads the dictionary from `reader` up to
+    /// end-of-file.  This is convenient for the reference file of an LZX
+    /// DELTA stream.
+    pub fn set_dictionary_from_reader<R: Read>(&mut self, reader: R)
+                                               -> io::Result<()> {
+        let window_size = self.max_offset as usize + 3;
+        let mut dictionary = Vec::new();
+        reader.take(window_size as u64 + 1).read_to_end(&mut dictionary)?;
+        if dictionary.len() > window_size {
+            invalid_input!("LZX dictionary is larger than window ({} bytes)",
+                           window_size);
+        }
+        self.set_dictionary(&dictionary)
+    }
+
+    /// Enables or disables encoding in the LZX DELTA variant (disabled by
+    /// default), as used by Microsoft's patch format to store a file as
+    /// changes to a reference file.  An LZX DELTA stream can contain matches
+    /// longer than `MAX_MATCH`, up to `MAX_DELTA_MATCH` bytes.  The reference
+    /// data should be given with `set_dictionary`, so that matches can refer
+    /// back to it.
+    ///
+    /// The decoder must have the same setting.  This must be set before any
+    /// data is written to the encoder.  It can't be disabled for an encoder
+    /// created by `Encoder::new_delta` with a window larger than `WINDOW_MAX`.
+    pub fn set_delta(&mut self, enable: bool) -> io::Result<()> {
+        if self.has_data() {
+            invalid_input!("Cannot change LZX DELTA mode after writing data");
+        }
+        let window_size = self.max_offset as usize + 3;
+        if !enable && window_size > 1 << consts::max_window(false) {
+            invalid_input!("LZX window is too large for a standard LZXD \
+                            stream ({} bytes)",
+                           window_size);
+        }
+        self.delta = enable;
+        self.matcher.set_max_match(self.max_match());
+        Ok(())
+    }
+
+    /// Returns the reset points emitted so far for the current stream, as
+    /// pairs of (uncompressed offset, compressed offset).  The compressed
+    /// offset is the position of the reset chunk's size prefix (or of its
+    /// first byte, in raw mode).  This is empty unless a reset interval has
+    /// been set.
+    pub fn reset_table(&self) -> &[(u64, u64)] { &self.reset_table }
+
+    /// Enables or disables building an index of every chunk boundary in the
+    /// stream (disabled by default), which can then be retrieved with
+    /// `chunk_index()`.  Unlike the reset table, this covers every chunk, so
+    /// with a reset interval of one, its entries can be passed directly to
+    /// `Decoder::set_reset_table`.  This must be set before any data is
+    /// written to the encoder.
+    pub fn set_chunk_index(&mut self, enable: bool) -> io::Result<()> {
+        if self.has_data() {
+            invalid_input!("Cannot change LZX chunk indexing after writing \
+                            data");
+        }
+        self.chunk_index = if enable {
+            Some(ChunkIndex::default())
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    /// Returns the index of the chunks emitted so far for the current
+    /// stream, or `None` if indexing wasn't enabled with
+    /// `set_chunk_index()`.
+    pub fn chunk_index(&self) -> Option<&ChunkIndex> {
+        self.chunk_index.as_ref()
+    }
+
+    /// Sets a function to be called each time the encoder emits a chunk,
+    /// describing where the chunk lies in the compressed and uncompressed
+    /// streams.  Cabinet writers can use this to fill in CFDATA headers as
+    /// the data is compressed.
+    pub fn set_chunk_callback<F>(&mut self, callback: F)
+        where F: FnMut(&ChunkInfo) + 'static
+    {
+        self.chunk_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a function to be called each time the encoder emits a chunk, with
+    /// the total number of uncompressed bytes encoded and compressed bytes
+    /// emitted so far for the current stream.
+    pub fn set_progress_callback<F>(&mut self, callback: F)
+        where F: FnMut(u64, u64) + 'static
+    {
+        self.progress_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a function to be called before the encoder emits each chunk.  If
+    /// it returns true, the write fails with `Error::Cancelled`, and the
+    /// stream should be abandoned.
+    pub fn set_cancel_check<F>(&mut self, should_cancel: F)
+        where F: FnMut() -> bool + 'static
+    {
+        self.cancel_check = Some(Box::new(should_cancel));
+    }
+
+    /// Writes a sequence of literals and matches that have already been
+    /// computed by the caller, bypassing the encoder's own match finder.
+    ///
+    /// The encoder still takes care of dividing the tokens into chunks and
+    /// blocks, building Huffman trees, and emitting the bitstream.  Matches
+    /// may refer back to any data previously written to this stream (whether
+    /// as tokens or as bytes), up to the window size.  Tokens can't be used
+    /// together with E8 translation.
+    pub fn write_tokens(&mut self, tokens: &[Token]) -> io::Result<()> {
+        if self.e8_translation_size != 0 {
+            invalid_input!("Cannot write LZX tokens with E8 translation \
+                            enabled");
+        }
+        let total_len: u64 =
+            tokens.iter().map(|token| token.uncompressed_len() as u64).sum();
+        let remaining = self.total_uncompressed_bytes_remaining;
+        if total_len > remaining {
+            invalid_input!("LZX tokens exceed declared size by {} bytes",
+                           total_len - remaining);
+        }
+        self.tokenize_pending_bytes();
+        let mut bytes = Vec::with_capacity(consts::MAX_MATCH);
+        for &token in tokens {
+            bytes.clear();
+            match token {
+                Token::Literal(byte) => bytes.push(byte),
+                Token::Match { offset, length } => {
+                    let length = length as usize;
+                    if !(consts::MIN_MATCH..=self.max_match())
+                        .contains(&length)
+                    {
+                        invalid_input!("Invalid LZX match length ({})",
+                                       length);
+                    }
+                    let history = self.matcher.history();
+                    if offset == 0 || offset > self.max_offset ||
+                        offset as usize > history.len()
+                    {
+                        invalid_input!("Invalid LZX match offset ({})",
+                                       offset);
+                    }
+                    let start = history.len() - offset as usize;
+                    for index in start..(start + length) {
+                        let byte = if index < history.len() {
+                            history[index]
+                        } else {
+                            bytes[index - history.len()]
+                        };
+                        bytes.push(byte);
+                    }
+                }
+            }
+            // Split the token if it crosses a chunk boundary.
+            let mut bytes = bytes.as_slice();
+            while !bytes.is_empty() {
+                let space = self.chunk_size - self.chunk_buffer.len();
+                let (piece, rest) = bytes.split_at(bytes.len().min(space));
+                let piece_token = match token {
+                    Token::Match { offset, .. } if piece.len() > 1 => {
+                        Token::Match {
+                            offset,
+                            length: piece.len() as u16,
+                        }
+                    }
+                    _ => Token::Literal(piece[0]),
+                };
+                debug_assert_eq!(piece_token.uncompressed_len(), piece.len());
+                self.chunk_buffer.extend_from_slice(piece);
+                self.chunk_tokens.push(piece_token);
+                self.matcher.append(piece);
+                self.matcher.skip(piece.len());
+                self.tokenized_len += piece.len();
+                self.total_uncompressed_bytes_remaining -= piece.len() as u64;
+                if self.chunk_buffer.len() == self.chunk_size ||
+                    self.total_uncompressed_bytes_remaining == 0
+                {
+                    self.emit_chunk()?;
+                }
+                bytes = rest;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes all of the given buffers, in order, as if they were one
+    /// contiguous buffer.  This is a stable counterpart to
+    /// `Write::write_all_vectored`, for callers that assemble data from many
+    /// small pieces (such as file headers and contents); each piece is copied
+    /// straight into the current chunk.
+    ///
+    /// Returns an error if the buffers hold more data than the declared
+    /// uncompressed size allows.
+    pub fn write_all_vectored(&mut self, bufs: &[io::IoSlice])
+                              -> io::Result<()> {
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        if self.write_vectored(bufs)? < total {
+            return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                      "data exceeds declared LZX stream \
+                                       size"));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of uncompressed bytes written so far to the current
+    /// stream (including data still buffered for the current chunk).
+    pub fn uncompressed_bytes_written(&self) -> u64 {
+        self.chunk_offset + self.chunk_buffer.len() as u64
+    }
+
+    /// Returns the number of uncompressed bytes that must still be written to
+    /// complete the current stream, or `None` if the encoder was created
+    /// without a declared size.
+    pub fn uncompressed_bytes_remaining(&self) -> Option<u64> {
+        if self.size_declared {
+            Some(self.total_uncompressed_bytes_remaining)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of compressed bytes emitted so far for the current
+    /// stream (not counting data still buffered for the current chunk).
+    ///
+    /// To measure how large a stream would be without storing the output,
+    /// write to an `io::Sink` and check this value after calling `finish()`.
+    pub fn compressed_len(&self) -> u64 { self.stats.compressed_bytes }
+
+    /// Returns statistics about the data encoded so far for the current
+    /// stream (not counting data still buffered for the current chunk).
+    pub fn stats(&self) -> EncoderStats { self.stats }
+
+    /// Finishes the compressed stream, emitting any buffered partial chunk,
+    /// and returns the total number of uncompressed bytes that were encoded.
+    ///
+    /// For an encoder created with `Encoder::new`, this returns an error if
+    /// fewer bytes were written than were declared.  Any further writes after
+    /// calling `finish()` will be ignored.
+    pub fn finish(&mut self) -> io::Result<u64> {
+        if self.size_declared && self.total_uncompressed_bytes_remaining > 0 {
+            invalid_input!("LZX stream is missing {} bytes of declared size",
+                           self.total_uncompressed_bytes_remaining);
+        }
+        self.total_uncompressed_bytes_remaining = 0;
+        if !self.chunk_buffer.is_empty() {
+            self.emit_chunk()?;
+        }
         self.writer.align_to_16()?;
-        self.writer.write_u32::<LittleEndian>(1)?; // R0
-        self.writer.write_u32::<LittleEndian>(1)?; // R1
-        self.writer.write_u32::<LittleEndian>(1)?; // R2
-        self.writer.write_all(&self.chunk_buffer)?;
+        self.writer.flush()?;
+        Ok(self.chunk_offset)
+    }
+
+    /// Reads uncompressed data from `reader` until it reaches end-of-file (or,
+    /// if the uncompressed size was declared, until that many bytes have been
+    /// written in total), then finishes the stream as with `finish()`.
+    /// Returns the number of bytes read from `reader`.
+    ///
+    /// Data is read directly into the encoder's chunk buffer, so no separate
+    /// buffer is needed.  Short reads and `Interrupted` errors from `reader`
+    /// are handled transparently.
+    pub fn copy_from<R: Read>(&mut self, reader: &mut R) -> io::Result<u64> {
+        let start = self.uncompressed_bytes_written();
+        while self.total_uncompressed_bytes_remaining > 0 {
+            let len = self.chunk_buffer.len();
+            let num_bytes = self.total_uncompressed_bytes_remaining
+                .min((self.chunk_size - len) as u64) as usize;
+            self.chunk_buffer.resize(len + num_bytes, 0);
+            let result = reader.read(&mut self.chunk_buffer[len..]);
+            let num_read = *result.as_ref().unwrap_or(&0);
+            self.chunk_buffer.truncate(len + num_read);
+            match result {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+            self.total_uncompressed_bytes_remaining -= num_read as u64;
+            if self.chunk_buffer.len() == self.chunk_size ||
+                self.total_uncompressed_bytes_remaining == 0
+            {
+                self.emit_chunk()?;
+            }
+        }
+        let end = self.uncompressed_bytes_written();
+        self.finish()?;
+        Ok(end - start)
+    }
+
+    /// Resets the encoder to start a new, independent compressed stream with
+    /// the given uncompressed size, written to the same underlying writer.
+    ///
+    /// This reuses the encoder's internal allocations, which makes it cheaper
+    /// than creating a new `Encoder` for each stream.  The E8 translation,
+    /// chunk size, reset interval, chunk indexing, and dictionary settings
+    /// are preserved.  Any buffered data from the previous stream that has
+    /// not yet been emitted is discarded, so call `finish()` first.
+    pub fn reset(&mut self, uncompressed_size: u64) -> io::Result<()> {
         self.writer.align_to_16()?;
+        let e8_translation = self.e8_translation_size != 0;
+        self.size_declared = true;
+        self.total_uncompressed_bytes_remaining = uncompressed_size;
+        self.chunk_offset = 0;
+        self.stats = EncoderStats::default();
+        self.reset_table.clear();
+        if let Some(ref mut index) = self.chunk_index {
+            *index = ChunkIndex::default();
+        }
         self.chunk_buffer.clear();
-        debug_assert_eq!(self.chunk_buffer.capacity(), consts::CHUNK_SIZE);
-        Ok(())
+        self.chunk_tokens.clear();
+        self.tokenized_len = 0;
+        self.reset_state();
+        self.prime_dictionary();
+        self.set_e8_translation(e8_translation)
     }
-}
 
-impl<W: Write> Write for Encoder<W> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    /// Like `reset()`, but also replaces the underlying writer, returning the
+    /// old one.
+    pub fn reset_with_writer(&mut self, writer: W, uncompressed_size: u64)
+                             -> io::Result<W> {
+        self.reset(uncompressed_size)?;
+        mem::replace(&mut self.writer, BitWriter::new(writer)).into_inner()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W { self.writer.get_ref() }
+
+    /// Returns a mutable reference to the underlying writer.
+    ///
+    /// Writing to the underlying writer directly will corrupt the compressed
+    /// stream; this is intended for writers (such as a `Vec<u8>`) from which
+    /// the compressed data can be taken as it is produced.
+    pub fn get_mut(&mut self) -> &mut W { self.writer.get_mut() }
+
+    /// Finishes the compressed stream (as with `finish()`) and returns the
+    /// underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.finish()?;
+        self.writer.into_inner()
+    }
+
+    /// Returns the maximum length of a match in the stream.
+    fn max_match(&self) -> usize {
+        if self.delta {
+            consts::MAX_DELTA_MATCH
+        } else {
+            consts::MAX_MATCH
+        }
+    }
+
+    fn has_data(&self) -> bool {
+        self.chunk_offset != 0 || !self.chunk_buffer.is_empty()
+    }
+
+    /// Returns the number of uncompressed bytes from one reset point to the
+    /// next, or zero if there is no reset interval.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn reset_segment_len(&self) -> u64 {
+        self.chunk_size as u64 * self.reset_interval as u64
+    }
+
+    /// Turns this newly-created encoder, whose declared size is that of a
+    /// whole stream, into one for just the `len` bytes of the stream that
+    /// start at the reset point `offset`.  The compressed segments of a
+    /// stream can then be concatenated to give the whole compressed stream.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn start_segment(&mut self, offset: u64, len: u64) {
+        debug_assert!(!self.has_data());
+        debug_assert!(len <= self.total_uncompressed_bytes_remaining);
+        self.chunk_offset = offset;
+        self.total_uncompressed_bytes_remaining = len;
+        if offset != 0 {
+            debug_assert!(self.is_reset_point());
+            // Matches after a reset point can't refer to the dictionary.
+            self.dictionary.clear();
+            self.matcher.reset();
+        }
+    }
+
+    /// Returns true if the chunk starting at `chunk_offset` must be decodable
+    /// without reference to earlier chunks.
+    fn is_reset_point(&self) -> bool {
+        let interval = self.chunk_size as u64 * self.reset_interval as u64;
+        interval != 0 && self.chunk_offset.is_multiple_of(interval)
+    }
+
+    /// Forgets all previously-encoded data, so that the next chunk can be
+    /// decoded independently of the ones before it.
+    fn reset_state(&mut self) {
+        self.wrote_header = false;
+        self.matcher.reset();
+        self.recent = (1, 1, 1);
+        for len in self.main_lengths.iter_mut() {
+            *len = 0;
+        }
+        for len in self.length_lengths.iter_mut() {
+            *len = 0;
+        }
+    }
+
+    fn prime_dictionary(&mut self) {
+        self.matcher.append(&self.dictionary);
+        self.matcher.skip(self.dictionary.len());
+    }
+
+    /// Runs the match finder over any bytes in the current chunk that
+    /// haven't yet been converted into tokens.
+    fn tokenize_pending_bytes(&mut self) {
+        let pending = &self.chunk_buffer[self.tokenized_len..];
+        self.matcher.append(pending);
+        if self.two_pass {
+            let mut matches = Vec::with_capacity(pending.len());
+            self.matcher.find_matches(pending.len(), &mut matches);
+            let mut first_pass = Vec::new();
+            parse::greedy(pending, &matches, &mut first_pass);
+            let mut recent = self.recent;
+            let delta = self.delta;
+            let encoded: Vec<EncodedToken> = first_pass
+                .iter()
+                .map(|&token| EncodedToken::new(token, &mut recent, delta))
+                .collect();
+            let trees = Trees::new(&encoded, self.main_lengths.len());
+            let costs = CostModel::new(&trees.main_lengths,
+                                       &trees.length_lengths,
+                                       delta);
+            parse::optimal(pending, &matches, &costs, &mut self.chunk_tokens);
+        } else {
+            self.matcher.find_tokens(pending.len(), &mut self.chunk_tokens);
+        }
+        self.tokenized_len = self.chunk_buffer.len();
+    }
+
+    /// Copies as much of `buf` into the chunk buffer as the declared size
+    /// allows, emitting each chunk as it fills, and returns the number of
+    /// bytes taken.
+    fn buffer_input(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut bytes_written = 0;
         while self.total_uncompressed_bytes_remaining > 0 &&
             bytes_written < buf.len()
         {
-            debug_assert!(self.chunk_buffer.len() < consts::CHUNK_SIZE);
+            debug_assert!(self.chunk_buffer.len() < self.chunk_size);
             let num_bytes =
                 (self.total_uncompressed_bytes_remaining
-                     .min((consts::CHUNK_SIZE - self.chunk_buffer.len()) as
+                     .min((self.chunk_size - self.chunk_buffer.len()) as
                               u64) as usize)
                     .min(buf.len() - bytes_written);
             let end = bytes_written + num_bytes;
-            self.chunk_buffer.write_all(&buf[bytes_written..end])?;
-            debug_assert!(self.chunk_buffer.len() <= consts::CHUNK_SIZE);
+            self.chunk_buffer.extend_from_slice(&buf[bytes_written..end]);
+            debug_assert!(self.chunk_buffer.len() <= self.chunk_size);
             bytes_written += num_bytes;
             self.total_uncompressed_bytes_remaining -= num_bytes as u64;
-            if self.chunk_buffer.len() == consts::CHUNK_SIZE {
+            if self.chunk_buffer.len() == self.chunk_size {
                 self.emit_chunk()?;
             }
         }
+        Ok(bytes_written)
+    }
+
+    /// Emits the final partial chunk, once the declared size is reached.
+    fn emit_final_chunk(&mut self) -> io::Result<()> {
         if self.total_uncompressed_bytes_remaining == 0 &&
             !self.chunk_buffer.is_empty()
         {
             self.emit_chunk()?;
         }
-        Ok(bytes_written)
+        Ok(())
+    }
+
+    fn emit_chunk(&mut self) -> io::Result<()> {
+        debug_assert!(!self.chunk_buffer.is_empty());
+        debug_assert!(self.chunk_buffer.len() <= self.chunk_size);
+        debug_assert!(self.chunk_buffer.len() == self.chunk_size ||
+                          self.total_uncompressed_bytes_remaining == 0 ||
+                          self.flush_partial_chunks);
+        if let Some(ref mut should_cancel) = self.cancel_check {
+            if should_cancel() {
+                return Err(Error::Cancelled.into());
+            }
+        }
+        if self.is_reset_point() {
+            self.reset_table
+                .push((self.chunk_offset, self.stats.compressed_bytes));
+        }
+        if e8::applies_to_chunk(self.e8_translation_size,
+                                self.chunk_offset,
+                                self.chunk_buffer.len())
+        {
+            debug_assert_eq!(self.tokenized_len, 0);
+            e8::encode_chunk(self.e8_translation_size,
+                             self.chunk_offset,
+                             &mut self.chunk_buffer);
+        }
+        self.tokenize_pending_bytes();
+        let mut recent = self.recent;
+        let delta = self.delta;
+        let encoded: Vec<EncodedToken> = self.chunk_tokens
+            .iter()
+            .map(|&token| EncodedToken::new(token, &mut recent, delta))
+            .collect();
+        let trees = match self.fixed_trees {
+            Some(ref trees) => trees.clone(),
+            None => Trees::new(&encoded, self.main_lengths.len()),
+        };
+        let mut block_type = BlockType::Verbatim;
+        let mut body = Vec::<u8>::new();
+        {
+            let mut bits = BitWriter::new(&mut body);
+            self.write_stream_header(&mut bits)?;
+            self.write_compressed_block(&mut bits,
+                                        BlockType::Verbatim,
+                                        &encoded,
+                                        &trees)?;
+            bits.align_to_16()?;
+        }
+        if trees.uses_aligned_offsets() {
+            let mut aligned_body = Vec::<u8>::new();
+            {
+                let mut bits = BitWriter::new(&mut aligned_body);
+                self.write_stream_header(&mut bits)?;
+                self.write_compressed_block(&mut bits,
+                                            BlockType::AlignedOffset,
+                                            &encoded,
+                                            &trees)?;
+                bits.align_to_16()?;
+            }
+            if aligned_body.len() < body.len() {
+                block_type = BlockType::AlignedOffset;
+                body = aligned_body;
+            }
+        }
+        // If compression didn't help (e.g. because the data was already
+        // compressed), emit an uncompressed block instead.
+        let mut uncompressed_body = Vec::<u8>::new();
+        {
+            let mut bits = BitWriter::new(&mut uncompressed_body);
+            self.write_stream_header(&mut bits)?;
+            self.write_uncompressed_block(&mut bits)?;
+            bits.align_to_16()?;
+        }
+        if uncompressed_body.len() < body.len() {
+            block_type = BlockType::Uncompressed;
+            body = uncompressed_body;
+        } else {
+            self.main_lengths = trees.main_lengths;
+            self.length_lengths = trees.length_lengths;
+            self.recent = recent;
+        }
+        let info = ChunkInfo {
+            uncompressed_offset: self.chunk_offset,
+            uncompressed_len: self.chunk_buffer.len(),
+            compressed_offset: self.stats.compressed_bytes,
+            compressed_len: body.len(),
+        };
+        self.record_stats(block_type, body.len());
+        self.wrote_header = true;
+        debug_assert!(body.len() <= u16::MAX as usize);
+        self.writer.align_to_16()?;
+        if !self.raw {
+            self.writer.write_u16::<LittleEndian>(body.len() as u16)?;
+        }
+        self.writer.write_all(&body)?;
+        if let Some(ref mut index) = self.chunk_index {
+            index.entries
+                .push((info.uncompressed_offset, info.compressed_offset));
+            index.uncompressed_len += info.uncompressed_len as u64;
+            index.compressed_len = self.stats.compressed_bytes;
+        }
+        if let Some(ref mut callback) = self.chunk_callback {
+            callback(&info);
+        }
+        self.chunk_offset += self.chunk_buffer.len() as u64;
+        if let Some(ref mut callback) = self.progress_callback {
+            callback(self.chunk_offset, self.stats.compressed_bytes);
+        }
+        self.chunk_buffer.clear();
+        self.chunk_tokens.clear();
+        self.tokenized_len = 0;
+        debug_assert_eq!(self.chunk_buffer.capacity(), consts::CHUNK_SIZE);
+        if self.is_reset_point() {
+            self.reset_state();
+        }
+        Ok(())
+    }
+
+    fn record_stats(&mut self, block_type: BlockType, body_len: usize) {
+        self.stats.uncompressed_bytes += self.chunk_buffer.len() as u64;
+        let prefix_len = if self.raw { 0 } else { 2 };
+        self.stats.compressed_bytes += prefix_len + body_len as u64;
+        match block_type {
+            BlockType::Verbatim => self.stats.verbatim_blocks += 1,
+            BlockType::AlignedOffset => self.stats.aligned_blocks += 1,
+            BlockType::Uncompressed => {
+                self.stats.uncompressed_blocks += 1;
+                return;
+            }
+        }
+        for token in self.chunk_tokens.iter() {
+            match *token {
+                Token::Literal(_) => self.stats.literals += 1,
+                Token::Match { length, .. } => {
+                    self.stats.matches += 1;
+                    self.stats.match_bytes += length as u64;
+                }
+            }
+        }
+    }
+
+    fn write_stream_header<B: Write>(&self, bits: &mut BitWriter<B>)
+                                     -> io::Result<()> {
+        if !self.wrote_header && !self.wim {
+            if self.e8_translation_size != 0 {
+                bits.write_bits(1, 1)?;
+                bits.write_bits(32, self.e8_translation_size)?;
+            } else {
+                bits.write_bits(1, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_block_size<B: Write>(&self, bits: &mut BitWriter<B>)
+                                  -> io::Result<()> {
+        let size = self.chunk_buffer.len() as u32;
+        if !self.wim {
+            return bits.write_bits(24, size);
+        }
+        if size == consts::CHUNK_SIZE as u32 {
+            return bits.write_bits(1, 1);
+        }
+        bits.write_bits(1, 0)?;
+        if self.max_offset as usize + 3 >= 1 << 16 {
+            bits.write_bits(24, size)
+        } else {
+            bits.write_bits(16, size)
+        }
+    }
+
+    fn write_uncompressed_block<B: Write>(&self, bits: &mut BitWriter<B>)
+                                          -> io::Result<()> {
+        bits.write_bits(3, BlockType::Uncompressed.to_bits())?;
+        self.write_block_size(bits)?;
+        bits.write_bits(1, 0)?;
+        bits.align_to_16()?;
+        bits.write_u32::<LittleEndian>(self.recent.0)?;
+        bits.write_u32::<LittleEndian>(self.recent.1)?;
+        bits.write_u32::<LittleEndian>(self.recent.2)?;
+        bits.write_all(&self.chunk_buffer)?;
+        Ok(())
     }
 
-    fn flush(&mut self) -> io::Result<()> { self.writer.flush() }
+    fn write_compressed_block<B: Write>(&self, bits: &mut BitWriter<B>,
+                                        block_type: BlockType,
+                                        encoded: &[EncodedToken],
+                                        trees: &Trees)
+                                        -> io::Result<()> {
+        let aligned = block_type == BlockType::AlignedOffset;
+        bits.write_bits(3, block_type.to_bits())?;
+        self.write_block_size(bits)?;
+        if aligned {
+            for &len in trees.aligned_lengths.iter() {
+                bits.write_bits(consts::ALIGNED_LENGTH_BITS, len as u32)?;
+            }
+        }
+        let chars = consts::NUM_CHARS;
+        pretree::write_lengths(bits,
+                               &self.main_lengths[..chars],
+                               &trees.main_lengths[..chars])?;
+        pretree::write_lengths(bits,
+                               &self.main_lengths[chars..],
+                               &trees.main_lengths[chars..])?;
+        pretree::write_lengths(bits,
+                               &self.length_lengths,
+                               &trees.length_lengths)?;
+        let main_codes = huffman::canonical_codes(&trees.main_lengths);
+        let length_codes = huffman::canonical_codes(&trees.length_lengths);
+        let aligned_codes = huffman::canonical_codes(&trees.aligned_lengths);
+        for token in encoded.iter() {
+            let main = token.main_element as usize;
+            bits.write_bits(trees.main_lengths[main] as u16,
+                            main_codes[main] as u32)?;
+            if let Some(length) = token.length_element {
+                let length = length as usize;
+                bits.write_bits(trees.length_lengths[length] as u16,
+                                length_codes[length] as u32)?;
+            }
+            if aligned && token.extra_bits >= 3 {
+                bits.write_bits(token.extra_bits - 3, token.footer >> 3)?;
+                let symbol = (token.footer & 7) as usize;
+                bits.write_bits(trees.aligned_lengths[symbol] as u16,
+                                aligned_codes[symbol] as u32)?;
+            } else {
+                bits.write_bits(token.extra_bits, token.footer)?;
+            }
+            if token.extension_bits > 0 {
+                bits.write_bits(token.extension_bits, token.extension)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 // ========================================================================= //
 
-#[cfg(test)]
-mod tests {
-    use super::Encoder;
-    use internal::consts::WINDOW_MIN;
-    use std::io::Write;
+/// Huffman code lengths for one block.
+#[derive(Clone)]
+struct Trees {
+    main_lengths: Vec<u8>,
+    length_lengths: Vec<u8>,
+    aligned_lengths: Vec<u8>,
+}
 
-    #[test]
-    #[should_panic(expected = "Invalid LZX window (12345)")]
-    fn invalid_window_size() {
-        let mut output = Vec::<u8>::new();
-        Encoder::new(&mut output, 12345, 3).unwrap();
+impl Trees {
+    fn new(encoded: &[EncodedToken], num_main_elements: usize) -> Trees {
+        let mut main_freqs = vec![0u32; num_main_elements];
+        let mut length_freqs = vec![0u32; consts::NUM_SECONDARY_LENGTHS];
+        let mut aligned_freqs = vec![0u32; consts::NUM_ALIGNED_ELEMENTS];
+        for token in encoded.iter() {
+            main_freqs[token.main_element as usize] += 1;
+            if let Some(length) = token.length_element {
+                length_freqs[length as usize] += 1;
+            }
+            if token.extra_bits >= 3 {
+                aligned_freqs[(token.footer & 7) as usize] += 1;
+            }
+        }
+        Trees {
+            main_lengths: huffman::build_lengths(&main_freqs,
+                                                 consts::MAX_CODE_LENGTH),
+            length_lengths: huffman::build_lengths(&length_freqs,
+                                                   consts::MAX_CODE_LENGTH),
+            aligned_lengths:
+                huffman::build_lengths(&aligned_freqs,
+                                       consts::MAX_ALIGNED_CODE_LENGTH),
+        }
     }
 
-    #[test]
-    fn encode_tiny_stream() {
-        let mut output = Vec::<u8>::new();
+    /// Builds fixed trees from a rough model of typical data, in which every
+    /// symbol has a code: text characters are more common than other bytes,
+    /// and short matches at small offsets are more common than long or
+    /// distant ones.
+    fn fixed(num_main_elements: usize) -> Trees {
+        let mut main_freqs = vec![1u32; num_main_elements];
+        for (byte, freq) in main_freqs[..consts::NUM_CHARS]
+            .iter_mut()
+            .enumerate()
         {
-            let mut encoder = Encoder::new(&mut output, WINDOW_MIN, 3)
-                .unwrap();
-            encoder.write_all(b"abc").unwrap();
+            let byte = byte as u8;
+            if byte.is_ascii_lowercase() || byte == b' ' {
+                *freq = 64;
+            } else if byte.is_ascii_graphic() || byte.is_ascii_whitespace() ||
+                       byte == 0 || byte == 0xff
+            {
+                *freq = 16;
+            }
         }
-        let expected: &[u8] =
-            b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
-              \x00\x00\x00\x01\x00\x00\x00\x61\x62\x63\x00";
-        assert_eq!(output.as_slice(), expected);
+        for (element, freq) in main_freqs[consts::NUM_CHARS..]
+            .iter_mut()
+            .enumerate()
+        {
+            let slot = element >> 3;
+            let length_header = element & 7;
+            *freq = (1024 >> (slot / 3)) / (length_header as u32 + 2) + 1;
+        }
+        let length_freqs: Vec<u32> = (0..consts::NUM_SECONDARY_LENGTHS)
+            .map(|element| (256 >> (element / 16).min(8)) + 1)
+            .collect();
+        Trees {
+            main_lengths: huffman::build_lengths(&main_freqs,
+                                                 consts::MAX_CODE_LENGTH),
+            length_lengths: huffman::build_lengths(&length_freqs,
+                                                   consts::MAX_CODE_LENGTH),
+            aligned_lengths: vec![0; consts::NUM_ALIGNED_ELEMENTS],
+        }
+    }
+
+    fn uses_aligned_offsets(&self) -> bool {
+        self.aligned_lengths.iter().any(|&len| len != 0)
+    }
+}
+
+// ========================================================================= //
+
+impl<W: Write> fmt::Debug for Encoder<W> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("Encoder")
+            .field("window_size", &(self.max_offset as usize + 3))
+            .field("chunk_size", &self.chunk_size)
+            .field("reset_interval", &self.reset_interval)
+            .field("e8_translation_size", &self.e8_translation_size)
+            .field("two_pass", &self.two_pass)
+            .field("fixed_trees", &self.fixed_trees.is_some())
+            .field("delta", &self.delta)
+            .field("raw", &self.raw)
+            .field("wim", &self.wim)
+            .field("dictionary_len", &self.dictionary.len())
+            .field("match_params", &self.matcher.params())
+            .field("chunk_offset", &self.chunk_offset)
+            .field("chunk_buffered_bytes", &self.chunk_buffer.len())
+            .field("total_uncompressed_bytes_remaining",
+                   &self.uncompressed_bytes_remaining())
+            .field("compressed_len", &self.stats.compressed_bytes)
+            .finish()
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes_written = self.buffer_input(buf)?;
+        self.emit_final_chunk()?;
+        Ok(bytes_written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        let mut bytes_written: usize = 0;
+        for buf in bufs.iter() {
+            let num_bytes = self.buffer_input(buf)?;
+            bytes_written += num_bytes;
+            if num_bytes < buf.len() {
+                break;
+            }
+        }
+        self.emit_final_chunk()?;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.flush_partial_chunks && !self.chunk_buffer.is_empty() {
+            self.emit_chunk()?;
+        }
+        self.writer.flush()
+    }
+}
+
+// ========================================================================= //
+
+/// The most that a single chunk can grow by: the chunk size prefix, the block
+/// header and its padding, the three repeated offsets of an uncompressed
+/// block, and a pad byte for odd-length chunks.
+const MAX_CHUNK_OVERHEAD: usize = 2 + 4 + 12 + 1;
+
+/// The most that the stream header can add beyond what's counted in
+/// `MAX_CHUNK_OVERHEAD` (that is, the E8 translation size).
+const MAX_STREAM_OVERHEAD: usize = 4;
+
+/// Returns the largest possible size, in bytes, of the LZXD stream that
+/// `Encoder` can produce for `uncompressed_len` bytes of input, regardless of
+/// the data, window size, or E8 translation setting (assuming the default
+/// chunk size).  Saturates at `usize::MAX` if the bound doesn't fit.
+pub fn compress_bound(uncompressed_len: usize) -> usize {
+    if uncompressed_len == 0 {
+        return 0;
+    }
+    let num_chunks = uncompressed_len.div_ceil(consts::CHUNK_SIZE);
+    num_chunks.checked_mul(MAX_CHUNK_OVERHEAD)
+        .and_then(|overhead| overhead.checked_add(MAX_STREAM_OVERHEAD))
+        .and_then(|overhead| overhead.checked_add(uncompressed_len))
+        .unwrap_or(usize::MAX)
+}
+
+/// Compresses an entire in-memory buffer into an LZXD stream.  The `window`
+/// argument is the same as for `Encoder::new`.
+pub fn compress(data: &[u8], window: u16) -> io::Result<Vec<u8>> {
+    let output = Vec::with_capacity(compress_bound(data.len()));
+    let mut encoder = Encoder::new(output, window, data.len() as u64)?;
+    encoder.write_all(data)?;
+    encoder.into_inner()
+}
+
+/// Compresses an entire in-memory buffer into `output`, and returns the
+/// number of bytes of `output` used.  The `window` argument is the same as for
+/// `Encoder::new`.  Returns an error if `output` is too small; an `output` of
+/// at least `compress_bound(data.len())` bytes is always big enough.
+pub fn compress_into(data: &[u8], window: u16, output: &mut [u8])
+                     -> io::Result<usize> {
+    let capacity = output.len();
+    let mut encoder = Encoder::new(output, window, data.len() as u64)?;
+    encoder.write_all(data)?;
+    let remaining = encoder.into_inner()?;
+    Ok(capacity - remaining.len())
+}
+
+/// Compresses all of the data from `reader` up to end-of-file, writing the
+/// LZXD stream to `writer`, and returns the number of uncompressed bytes,
+/// which the decoder will need to be told.  The `window` argument is the
+/// same as for `Encoder::new`.
+pub fn compress_from_reader<R: Read, W: Write>(reader: &mut R, window: u16,
+                                               writer: &mut W)
+                                               -> io::Result<u64> {
+    let mut encoder = Encoder::with_unknown_size(writer, window)?;
+    encoder.copy_from(reader)
+}
+
+/// Returns the size, in bytes, of the LZXD stream that `Encoder` would
+/// produce for the given data and window size, without storing the output.
+///
+/// This runs the full compression pipeline, so it is no faster than
+/// compressing the data for real.
+pub fn compressed_size(data: &[u8], window: u16) -> io::Result<u64> {
+    let mut encoder = Encoder::new(io::sink(), window, data.len() as u64)?;
+    encoder.write_all(data)?;
+    encoder.finish()?;
+    Ok(encoder.compressed_len())
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoder, compress_bound, compress_from_reader, compress_into,
+                compressed_size};
+    use internal::chunk::ChunkInfo;
+    use internal::consts::{CHUNK_SIZE, WINDOW_MIN};
+    use internal::decoder::{Decoder, decompress_into};
+    use internal::error::Error;
+    use internal::matcher::MatchParams;
+    use internal::stats::EncoderStats;
+    use internal::testutil::FlakyReader;
+    use internal::token::Token;
+    use std::cell::{Cell, RefCell};
+    use std::io::{self, Cursor, IoSlice, IoSliceMut, Read, Write};
+    use std::rc::Rc;
+
+    fn compress(window: u16, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, window, data.len() as u64)
+                    .unwrap();
+            encoder.write_all(data).unwrap();
+        }
+        output
+    }
+
+    fn round_trip(window: u16, data: &[u8]) -> Vec<u8> {
+        let compressed = compress(window, data);
+        let mut decoder =
+            Decoder::new(compressed.as_slice(), window, data.len() as u64)
+                .unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed.as_slice(), data);
+        compressed
+    }
+
+    fn text_with_alphabet(alphabet: &[u8], len: usize, seed: u32)
+                          -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                     state = state.wrapping_mul(1103515245)
+                         .wrapping_add(12345);
+                     alphabet[(state >> 16) as usize % alphabet.len()]
+                 })
+            .collect()
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidWindow(12345)")]
+    fn invalid_window_size() {
+        let mut output = Vec::<u8>::new();
+        Encoder::new(&mut output, 12345, 3).unwrap();
+    }
+
+    #[test]
+    fn encode_tiny_stream() {
+        let expected: &[u8] =
+            b"\x14\x00\x00\x30\x30\x00\x01\x00\x00\x00\x01\
+              \x00\x00\x00\x01\x00\x00\x00\x61\x62\x63\x00";
+        assert_eq!(compress(WINDOW_MIN, b"abc").as_slice(), expected);
+    }
+
+    #[test]
+    fn copy_from_flaky_reader() {
+        let data = text_with_alphabet(b"abcdefgh", 0x9000, 7);
+        let expected = compress(16, &data);
+        let mut encoder =
+            Encoder::new(Vec::new(), 16, data.len() as u64).unwrap();
+        let mut reader = FlakyReader::new(&data, 7);
+        assert_eq!(encoder.copy_from(&mut reader).unwrap(),
+                   data.len() as u64);
+        assert_eq!(encoder.into_inner().unwrap(), expected);
+        let mut reader = FlakyReader::new(&data, 7);
+        let mut output = Vec::new();
+        assert_eq!(compress_from_reader(&mut reader, 16, &mut output)
+                       .unwrap(),
+                   data.len() as u64);
+        assert_eq!(output, expected);
+        let mut encoder =
+            Encoder::new(Vec::new(), 16, data.len() as u64 + 1).unwrap();
+        assert!(encoder.copy_from(&mut data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn slice_round_trip() {
+        let data = text_with_alphabet(b"slices", 0x11000, 5);
+        let expected = compress(17, &data);
+        let mut output = vec![0u8; compress_bound(data.len())];
+        let num_bytes = compress_into(&data, 17, &mut output).unwrap();
+        assert_eq!(&output[..num_bytes], expected.as_slice());
+        let mut too_small = vec![0u8; num_bytes - 1];
+        let error = compress_into(&data, 17, &mut too_small).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+
+        let mut decompressed = vec![0u8; data.len()];
+        decompress_into(&expected, 17, &mut decompressed).unwrap();
+        assert!(decompressed == data);
+        let mut too_big = vec![0u8; data.len() + 1];
+        assert!(decompress_into(&expected, 17, &mut too_big).is_err());
+    }
+
+    #[test]
+    fn vectored_io() {
+        let data = text_with_alphabet(b"vectored", 0x3000, 3);
+        let (first, rest) = data.split_at(0x1234);
+        let (second, third) = rest.split_at(0x1000);
+        let mut encoder =
+            Encoder::new(Vec::new(), 16, data.len() as u64 - 5).unwrap();
+        let bufs = [IoSlice::new(first), IoSlice::new(second),
+                    IoSlice::new(third)];
+        assert_eq!(encoder.write_vectored(&bufs).unwrap(), data.len() - 5);
+        let compressed = encoder.into_inner().unwrap();
+        let mut encoder = Encoder::new(Vec::new(), 16, data.len() as u64)
+            .unwrap();
+        let pieces: Vec<IoSlice> = data.chunks(7).map(IoSlice::new).collect();
+        encoder.write_all_vectored(&pieces).unwrap();
+        assert_eq!(encoder.into_inner().unwrap(), compress(16, &data));
+        let mut encoder = Encoder::new(Vec::new(), 16, data.len() as u64 - 5)
+            .unwrap();
+        let error = encoder.write_all_vectored(&bufs).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+        let mut decoder =
+            Decoder::new(compressed.as_slice(), 16, data.len() as u64 - 5)
+                .unwrap();
+        let mut first = vec![0u8; 0x1001];
+        let mut second = vec![0u8; 0x2000];
+        let mut third = vec![0u8; 4];
+        let num_bytes = {
+            let mut bufs = [IoSliceMut::new(&mut first),
+                            IoSliceMut::new(&mut second),
+                            IoSliceMut::new(&mut third)];
+            decoder.read_vectored(&mut bufs).unwrap()
+        };
+        assert_eq!(num_bytes, data.len() - 5);
+        assert_eq!(first.as_slice(), &data[..0x1001]);
+        assert_eq!(&second[..0x1ffa], &data[0x1001..(data.len() - 5)]);
+        assert_eq!(&third, &[0; 4]);
+    }
+
+    #[test]
+    fn round_trip_tiny_stream() { round_trip(WINDOW_MIN, b"Hello, world!"); }
+
+    #[test]
+    fn round_trip_blocks_with_similar_trees() {
+        let mut data = Vec::new();
+        data.extend(text_with_alphabet(b"aaabbcdeeeeffgh  ", CHUNK_SIZE, 1));
+        data.extend(text_with_alphabet(b"aaabbcdeeeffgghh ", CHUNK_SIZE, 2));
+        data.extend(text_with_alphabet(b"aabbccdeeeeffgh  ", CHUNK_SIZE, 3));
+        data.extend(text_with_alphabet(b"aaabbcdeeeeffghi ", 1000, 4));
+        round_trip(WINDOW_MIN, &data);
+    }
+
+    fn x86_like_code(len: usize) -> Vec<u8> {
+        let mut data = text_with_alphabet(b"\x55\x89\xe5\x8b\x45\x08\xc3",
+                                          len,
+                                          6);
+        let mut index = 0;
+        while index + 5 <= len {
+            let target = (index as i32 % 5000) - 2500;
+            data[index] = 0xe8;
+            data[(index + 1)..(index + 5)]
+                .copy_from_slice(&(target - index as i32).to_le_bytes());
+            index += 37;
+        }
+        data
+    }
+
+    #[test]
+    fn round_trip_with_e8_translation() {
+        let data = x86_like_code(2 * CHUNK_SIZE + 1234);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        // The header bit should be set, followed by the translation size.
+        assert_eq!(output[3] >> 7, 1);
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn e8_translation_size_for_huge_streams() {
+        // A stream over 4 GiB records a translation size of i32::MAX, which
+        // every decoder reads as positive.
+        let size = 5u64 << 30;
+        let data = x86_like_code(CHUNK_SIZE);
+        let mut encoder = Encoder::new(Vec::new(), WINDOW_MIN, size).unwrap();
+        encoder.set_e8_translation(true).unwrap();
+        assert_eq!(encoder.e8_translation_size, i32::MAX as u32);
+        encoder.write_all(&data).unwrap();
+        let output = encoder.get_ref().clone();
+        // The stream header is the first 33 bits of the first chunk, packed
+        // into 16-bit little-endian words.
+        let words = output[2..8]
+            .chunks(2)
+            .fold(0u64, |bits, word| {
+                (bits << 16) | u16::from_le_bytes([word[0], word[1]]) as u64
+            });
+        assert_eq!(words >> 47, 1);
+        assert_eq!((words >> 15) & 0xffff_ffff, i32::MAX as u64);
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, size).unwrap();
+        let mut decompressed = vec![0u8; CHUNK_SIZE];
+        decoder.read_exact(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(decoder.uncompressed_bytes_remaining(),
+                   size - CHUNK_SIZE as u64);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot change E8 translation after writing")]
+    fn set_e8_translation_after_writing() {
+        let mut output = Vec::<u8>::new();
+        let mut encoder = Encoder::new(&mut output, WINDOW_MIN, 3).unwrap();
+        encoder.write_all(b"abc").unwrap();
+        encoder.set_e8_translation(true).unwrap();
+    }
+
+    #[test]
+    fn finish_stream_of_unknown_size() {
+        let data = text_with_alphabet(b"abcdefg ", CHUNK_SIZE + 100, 7);
+        let mut output = Vec::<u8>::new();
+        let size = {
+            let mut encoder = Encoder::with_unknown_size(&mut output,
+                                                         WINDOW_MIN)
+                .unwrap();
+            encoder.write_all(&data[..1000]).unwrap();
+            encoder.write_all(&data[1000..]).unwrap();
+            encoder.finish().unwrap()
+        };
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(output, compress(WINDOW_MIN, &data));
+    }
+
+    #[test]
+    #[should_panic(expected = "LZX stream is missing 2 bytes of declared \
+                               size")]
+    fn finish_before_declared_size() {
+        let mut output = Vec::<u8>::new();
+        let mut encoder = Encoder::new(&mut output, WINDOW_MIN, 5).unwrap();
+        encoder.write_all(b"abc").unwrap();
+        encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn into_inner_returns_finished_writer() {
+        let data = text_with_alphabet(b"xyz", 5000, 8);
+        let mut encoder = Encoder::with_unknown_size(Cursor::new(Vec::new()),
+                                                     WINDOW_MIN)
+            .unwrap();
+        encoder.write_all(&data).unwrap();
+        let cursor = encoder.into_inner().unwrap();
+        assert_eq!(cursor.position(), cursor.get_ref().len() as u64);
+        assert_eq!(cursor.into_inner(), compress(WINDOW_MIN, &data));
+    }
+
+    #[test]
+    fn reset_between_streams() {
+        let first = text_with_alphabet(b"abc", CHUNK_SIZE + 10, 9);
+        let second = text_with_alphabet(b"abcdef", 3000, 10);
+        let mut encoder =
+            Encoder::new(Vec::new(), WINDOW_MIN, first.len() as u64).unwrap();
+        encoder.set_e8_translation(true).unwrap();
+        encoder.write_all(&first).unwrap();
+        let old_output = encoder
+            .reset_with_writer(Vec::new(), second.len() as u64)
+            .unwrap();
+        encoder.write_all(&second).unwrap();
+        let new_output = encoder.into_inner().unwrap();
+        for (data, output) in [(first, old_output), (second, new_output)]
+            .iter()
+        {
+            let size = data.len() as u64;
+            let mut decoder =
+                Decoder::new(output.as_slice(), WINDOW_MIN, size).unwrap();
+            let mut decompressed = Vec::new();
+            decoder.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(&decompressed, data);
+        }
+    }
+
+    #[test]
+    fn incompressible_data_is_not_expanded() {
+        let mut state: u32 = 11;
+        let data: Vec<u8> = (0..(3 * CHUNK_SIZE + 500))
+            .map(|_| {
+                     state = state.wrapping_mul(1664525)
+                         .wrapping_add(1013904223);
+                     (state >> 24) as u8
+                 })
+            .collect();
+        let compressed = round_trip(WINDOW_MIN, &data);
+        assert!(compressed.len() <= data.len() + 4 * 18);
+    }
+
+    #[test]
+    fn unchanged_trees_are_cheap_to_send() {
+        let chunk = text_with_alphabet(b"abcdefghijklmnopqrstuvwxyz ",
+                                       CHUNK_SIZE,
+                                       5);
+        let one_chunk = round_trip(WINDOW_MIN, &chunk).len();
+        let mut data = chunk.clone();
+        data.extend_from_slice(&chunk);
+        let two_chunks = round_trip(WINDOW_MIN, &data).len();
+        assert!(two_chunks - one_chunk < one_chunk);
+    }
+
+    #[test]
+    fn round_trip_tokens() {
+        let mut data = b"abcd".to_vec();
+        data.extend(text_with_alphabet(b"xyz", CHUNK_SIZE - 10, 8));
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output,
+                             WINDOW_MIN,
+                             data.len() as u64 + 14 + 200)
+                    .unwrap();
+            encoder.write_all(&data).unwrap();
+            let tokens = [Token::Literal(b'!'),
+                          Token::Match {
+                              offset: data.len() as u32 + 1,
+                              length: 4,
+                          },
+                          Token::Match {
+                              offset: 1,
+                              length: 9,
+                          },
+                          // This match crosses into the next chunk.
+                          Token::Match {
+                              offset: 5,
+                              length: 200,
+                          }];
+            encoder.write_tokens(&tokens).unwrap();
+        }
+        data.extend_from_slice(b"!abcdddddddddd");
+        for _ in 0..200 {
+            let byte = data[data.len() - 5];
+            data.push(byte);
+        }
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid LZX match offset (4)")]
+    fn token_offset_before_start_of_stream() {
+        let mut output = Vec::<u8>::new();
+        let mut encoder = Encoder::new(&mut output, WINDOW_MIN, 10).unwrap();
+        encoder.write_all(b"abc").unwrap();
+        let token = Token::Match {
+            offset: 4,
+            length: 2,
+        };
+        encoder.write_tokens(&[token]).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid LZX match length (258)")]
+    fn token_match_too_long() {
+        let mut output = Vec::<u8>::new();
+        let mut encoder = Encoder::new(&mut output, WINDOW_MIN, 300).unwrap();
+        encoder.write_all(b"abc").unwrap();
+        let token = Token::Match {
+            offset: 1,
+            length: 258,
+        };
+        encoder.write_tokens(&[token]).unwrap();
+    }
+
+    #[test]
+    fn repetitive_data_compresses_well() {
+        let chunk = text_with_alphabet(b"0123456789abcdef", 1000, 9);
+        let mut data = Vec::new();
+        for _ in 0..100 {
+            data.extend_from_slice(&chunk);
+        }
+        let compressed = round_trip(WINDOW_MIN, &data);
+        assert!(compressed.len() < 2000);
+    }
+
+    #[test]
+    fn compressed_size_matches_output() {
+        let data = x86_like_code(2 * CHUNK_SIZE + 777);
+        let compressed = compress(16, &data);
+        assert_eq!(compressed_size(&data, 16).unwrap(),
+                   compressed.len() as u64);
+        assert_eq!(compressed_size(b"", 16).unwrap(), 0);
+    }
+
+    #[test]
+    fn compress_bound_holds_for_incompressible_data() {
+        let mut state: u32 = 12;
+        let data: Vec<u8> = (0..(2 * CHUNK_SIZE + 333))
+            .map(|_| {
+                     state = state.wrapping_mul(1664525)
+                         .wrapping_add(1013904223);
+                     (state >> 24) as u8
+                 })
+            .collect();
+        for &len in &[1, 2, 3, CHUNK_SIZE - 1, CHUNK_SIZE, data.len()] {
+            let mut output = Vec::<u8>::new();
+            {
+                let mut encoder =
+                    Encoder::new(&mut output, WINDOW_MIN, len as u64)
+                        .unwrap();
+                encoder.set_e8_translation(true).unwrap();
+                encoder.write_all(&data[..len]).unwrap();
+            }
+            assert!(output.len() <= compress_bound(len));
+        }
+        assert_eq!(compress_bound(0), 0);
+        assert_eq!(compress_bound(usize::MAX), usize::MAX);
+    }
+
+    fn fnv1a(data: &[u8]) -> u64 {
+        data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    #[test]
+    fn output_is_reproducible() {
+        // The encoder's output must depend only on its input and settings, so
+        // that repacked archives are byte-for-byte reproducible.  If this test
+        // fails after an intentional change to the encoder, update the
+        // expected values.
+        let mut data = text_with_alphabet(b"aaabbcdeeeeffgh  ", CHUNK_SIZE, 1);
+        data.extend(x86_like_code(CHUNK_SIZE + 4321));
+        let repeated = data[1000..6000].to_vec();
+        data.extend_from_slice(&repeated);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder = Encoder::new(&mut output, 16, data.len() as u64)
+                .unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        assert_eq!(output.len(), 35354);
+        assert_eq!(fnv1a(&output), 0x11e2ba2939fd7cbf);
+    }
+
+    #[test]
+    fn stats_describe_stream() {
+        let mut data = text_with_alphabet(b"abcdefgh", CHUNK_SIZE, 13);
+        let repeated = data[..5000].to_vec();
+        data.extend_from_slice(&repeated);
+        let mut output = Vec::<u8>::new();
+        let mut encoder =
+            Encoder::new(&mut output, WINDOW_MIN, data.len() as u64).unwrap();
+        encoder.write_all(&data).unwrap();
+        let stats = encoder.stats();
+        assert_eq!(stats.uncompressed_bytes, data.len() as u64);
+        assert_eq!(stats.compressed_bytes, encoder.compressed_len());
+        assert_eq!(stats.verbatim_blocks + stats.aligned_blocks, 2);
+        assert_eq!(stats.uncompressed_blocks, 0);
+        assert_eq!(stats.literals + stats.match_bytes, data.len() as u64);
+        assert!(stats.average_match_len() > 3.0);
+        encoder.reset(0).unwrap();
+        assert_eq!(encoder.stats(), EncoderStats::default());
+    }
+
+    #[test]
+    fn round_trip_with_small_chunks() {
+        let data = x86_like_code(5 * 0x1000 + 99);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_chunk_size(0x1000).unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.write_all(&data).unwrap();
+            assert_eq!(encoder.stats().verbatim_blocks +
+                           encoder.stats().aligned_blocks +
+                           encoder.stats().uncompressed_blocks,
+                       6);
+        }
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        decoder.set_chunk_size(0x1000).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn flush_partial_chunks() {
+        let data = x86_like_code(5 * 0x1000 + 99);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.flush().unwrap();
+            assert_eq!(encoder.compressed_len(), 0);
+            encoder.set_flush_partial_chunks(true);
+            for piece in data.chunks(0x1000) {
+                encoder.write_all(piece).unwrap();
+                encoder.flush().unwrap();
+                assert_eq!(encoder.uncompressed_bytes_written(),
+                           encoder.stats().uncompressed_bytes);
+            }
+            assert_eq!(encoder.stats().verbatim_blocks +
+                           encoder.stats().aligned_blocks +
+                           encoder.stats().uncompressed_blocks,
+                       6);
+        }
+        // Since the data was flushed at regular intervals, a decoder can find
+        // the chunk boundaries by using that interval as its chunk size.
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        decoder.set_chunk_size(0x1000).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot change LZX chunk size after writing")]
+    fn set_chunk_size_after_writing() {
+        let mut output = Vec::<u8>::new();
+        let mut encoder = Encoder::new(&mut output, WINDOW_MIN, 9).unwrap();
+        encoder.write_all(b"abc").unwrap();
+        encoder.set_chunk_size(0x1000).unwrap();
+    }
+
+    #[test]
+    fn chunk_callback_reports_each_chunk() {
+        let data = text_with_alphabet(b"abcdefgh ", 2 * CHUNK_SIZE + 5, 14);
+        let chunks = Rc::new(RefCell::new(Vec::<ChunkInfo>::new()));
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            let chunks = chunks.clone();
+            encoder.set_chunk_callback(move |info| {
+                                           chunks.borrow_mut().push(*info)
+                                       });
+            encoder.write_all(&data).unwrap();
+        }
+        let chunks = chunks.borrow();
+        assert_eq!(chunks.len(), 3);
+        let mut compressed_offset = 0;
+        for (index, info) in chunks.iter().enumerate() {
+            assert_eq!(info.uncompressed_offset,
+                       (index * CHUNK_SIZE) as u64);
+            assert_eq!(info.compressed_offset, compressed_offset);
+            let start = compressed_offset as usize;
+            let prefix = output[start] as usize |
+                ((output[start + 1] as usize) << 8);
+            assert_eq!(prefix, info.compressed_len);
+            compressed_offset += 2 + info.compressed_len as u64;
+        }
+        assert_eq!(chunks[2].uncompressed_len, 5);
+        assert_eq!(compressed_offset, output.len() as u64);
+    }
+
+    #[test]
+    fn raw_chunks_round_trip() {
+        let data = text_with_alphabet(b"abcdefgh ", 2 * CHUNK_SIZE + 5, 15);
+        let prefixed = compress(WINDOW_MIN, &data);
+        let lengths = Rc::new(RefCell::new(Vec::<usize>::new()));
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_raw_chunks(true).unwrap();
+            let lengths = lengths.clone();
+            encoder.set_chunk_callback(move |info| {
+                                           lengths.borrow_mut()
+                                               .push(info.compressed_len)
+                                       });
+            encoder.write_all(&data).unwrap();
+            assert_eq!(encoder.finish().unwrap(), data.len() as u64);
+            let written = encoder.get_ref().len() as u64;
+            assert_eq!(encoder.compressed_len(), written);
+            assert!(encoder.set_raw_chunks(false).is_err());
+        }
+        // The raw stream is the usual stream minus its chunk size prefixes.
+        let lengths = lengths.borrow().clone();
+        assert_eq!(lengths.len(), 3);
+        let mut stripped = Vec::new();
+        let mut rest = prefixed.as_slice();
+        for &len in lengths.iter() {
+            assert_eq!(rest[0] as usize | ((rest[1] as usize) << 8), len);
+            stripped.extend_from_slice(&rest[2..(2 + len)]);
+            rest = &rest[(2 + len)..];
+        }
+        assert!(rest.is_empty());
+        assert_eq!(output, stripped);
+        // The decoder reports the same lengths as it goes.
+        let decoded = Rc::new(RefCell::new(Vec::<usize>::new()));
+        let mut decoder =
+            Decoder::new_raw(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        {
+            let decoded = decoded.clone();
+            decoder.set_chunk_callback(move |info| {
+                                           decoded.borrow_mut()
+                                               .push(info.compressed_len)
+                                       });
+        }
+        let mut roundtrip = Vec::new();
+        decoder.read_to_end(&mut roundtrip).unwrap();
+        assert!(roundtrip == data);
+        assert_eq!(*decoded.borrow(), lengths);
+    }
+
+    #[test]
+    fn progress_callbacks_agree() {
+        let data = text_with_alphabet(b"abcdefgh ", 2 * CHUNK_SIZE + 5, 14);
+        let encoded = Rc::new(RefCell::new(Vec::<(u64, u64)>::new()));
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            let encoded = encoded.clone();
+            encoder.set_progress_callback(move |uncompressed, compressed| {
+                encoded.borrow_mut().push((uncompressed, compressed))
+            });
+            encoder.write_all(&data).unwrap();
+        }
+        let decoded = Rc::new(RefCell::new(Vec::<(u64, u64)>::new()));
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        {
+            let decoded = decoded.clone();
+            decoder.set_progress_callback(move |uncompressed, compressed| {
+                decoded.borrow_mut().push((uncompressed, compressed))
+            });
+        }
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+        let encoded = encoded.borrow();
+        assert_eq!(encoded.len(), 3);
+        assert_eq!(encoded[2], (data.len() as u64, output.len() as u64));
+        assert_eq!(*decoded.borrow(), *encoded);
+    }
+
+    #[test]
+    fn cancel_at_chunk_boundary() {
+        let data = text_with_alphabet(b"abcdefgh ", 3 * CHUNK_SIZE, 14);
+        let compressed = compress(WINDOW_MIN, &data);
+        let num_chunks = Rc::new(Cell::new(0));
+        let mut encoder =
+            Encoder::new(Vec::new(), WINDOW_MIN, data.len() as u64).unwrap();
+        {
+            let num_chunks = num_chunks.clone();
+            encoder.set_cancel_check(move || {
+                num_chunks.set(num_chunks.get() + 1);
+                num_chunks.get() > 2
+            });
+        }
+        let error = encoder.write_all(&data).unwrap_err();
+        assert_eq!(Error::from_io(&error), Some(&Error::Cancelled));
+        assert_eq!(encoder.stats().uncompressed_bytes, 2 * CHUNK_SIZE as u64);
+        let mut decoder =
+            Decoder::new(compressed.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        num_chunks.set(0);
+        {
+            let num_chunks = num_chunks.clone();
+            decoder.set_cancel_check(move || {
+                num_chunks.set(num_chunks.get() + 1);
+                num_chunks.get() > 1
+            });
+        }
+        let mut decompressed = Vec::<u8>::new();
+        let error = decoder.read_to_end(&mut decompressed).unwrap_err();
+        assert_eq!(Error::from_io(&error), Some(&Error::Cancelled));
+        assert_eq!(decompressed, &data[..CHUNK_SIZE]);
+    }
+
+    #[test]
+    fn round_trip_with_reset_interval() {
+        let data = x86_like_code(5 * CHUNK_SIZE + 1000);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.set_reset_interval(2).unwrap();
+            encoder.write_all(&data).unwrap();
+            let table = encoder.reset_table();
+            assert_eq!(table.len(), 3);
+            assert_eq!(table[0], (0, 0));
+            assert_eq!(table[1].0, 2 * CHUNK_SIZE as u64);
+            assert_eq!(table[2].0, 4 * CHUNK_SIZE as u64);
+        }
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        decoder.set_reset_interval(2).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn random_access_with_reset_table() {
+        let data = x86_like_code(6 * CHUNK_SIZE + 1000);
+        let mut output = b"HEADER".to_vec();
+        let table = {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.set_reset_interval(2).unwrap();
+            encoder.write_all(&data).unwrap();
+            encoder.reset_table().to_vec()
+        };
+        let mut cursor = Cursor::new(output);
+        cursor.set_position(6);
+        let mut decoder =
+            Decoder::new(cursor, WINDOW_MIN, data.len() as u64).unwrap();
+        decoder.set_reset_interval(2).unwrap();
+        decoder.set_reset_table(&table).unwrap();
+        let mut buffer = [0u8; 1000];
+        let offsets = [5 * CHUNK_SIZE + 7, 100, 4 * CHUNK_SIZE, 0, 30_000];
+        for &offset in offsets.iter() {
+            assert_eq!(decoder.seek_to(offset as u64).unwrap(),
+                       offset as u64);
+            decoder.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer[..], &data[offset..(offset + 1000)]);
+        }
+        let mut rest = Vec::new();
+        decoder.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[31_000..]);
+        assert!(decoder.seek_to(data.len() as u64 + 1).is_err());
+    }
+
+    #[test]
+    fn read_range_decodes_only_covering_chunks() {
+        let data = x86_like_code(8 * CHUNK_SIZE + 1000);
+        let mut output = Vec::<u8>::new();
+        let table = {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.set_reset_interval(2).unwrap();
+            encoder.write_all(&data).unwrap();
+            encoder.reset_table().to_vec()
+        };
+        let mut decoder =
+            Decoder::new(Cursor::new(output), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        decoder.set_reset_interval(2).unwrap();
+        decoder.set_reset_table(&table).unwrap();
+        let num_chunks = Rc::new(Cell::new(0));
+        {
+            let num_chunks = num_chunks.clone();
+            decoder.set_chunk_callback(move |_| {
+                                           num_chunks.set(num_chunks.get() + 1)
+                                       });
+        }
+        let start = 5 * CHUNK_SIZE + 100;
+        let range = decoder.read_range(start as u64, 50).unwrap();
+        assert_eq!(range, &data[start..(start + 50)]);
+        assert_eq!(num_chunks.get(), 2);
+        num_chunks.set(0);
+        let start = 2 * CHUNK_SIZE - 10;
+        let range = decoder.read_range(start as u64, 20).unwrap();
+        assert_eq!(range, &data[start..(start + 20)]);
+        assert_eq!(num_chunks.get(), 3);
+        let range = decoder.read_range(data.len() as u64 - 5, 5).unwrap();
+        assert_eq!(range, &data[(data.len() - 5)..]);
+        let error = decoder.read_range(data.len() as u64 - 5, 6).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(decoder.read_range(u64::MAX, 1).is_err());
+    }
+
+    #[test]
+    fn chunk_index_covers_every_chunk() {
+        let data = x86_like_code(5 * CHUNK_SIZE + 1000);
+        let chunks = Rc::new(RefCell::new(Vec::<ChunkInfo>::new()));
+        let mut encoder =
+            Encoder::new(Vec::new(), WINDOW_MIN, data.len() as u64).unwrap();
+        assert!(encoder.chunk_index().is_none());
+        encoder.set_chunk_index(true).unwrap();
+        encoder.set_reset_interval(1).unwrap();
+        {
+            let chunks = chunks.clone();
+            encoder.set_chunk_callback(move |info| {
+                                           chunks.borrow_mut().push(*info)
+                                       });
+        }
+        encoder.write_all(&data).unwrap();
+        assert!(encoder.set_chunk_index(false).is_err());
+        let index = encoder.chunk_index().unwrap().clone();
+        assert_eq!(index.entries.len(), 6);
+        for (&entry, info) in index.entries.iter().zip(chunks.borrow().iter())
+        {
+            assert_eq!(entry,
+                       (info.uncompressed_offset, info.compressed_offset));
+        }
+        assert_eq!(index.entries, encoder.reset_table());
+        assert_eq!(index.uncompressed_len, data.len() as u64);
+        assert_eq!(index.compressed_len, encoder.compressed_len());
+        assert_eq!(index.find(0), Some(index.entries[0]));
+        assert_eq!(index.find(3 * CHUNK_SIZE as u64 - 1),
+                   Some(index.entries[2]));
+        assert_eq!(index.find(data.len() as u64), None);
+        let stream = encoder.into_inner().unwrap();
+        let mut decoder =
+            Decoder::new(Cursor::new(stream), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        decoder.set_reset_interval(1).unwrap();
+        decoder.set_reset_table(&index.entries).unwrap();
+        let start = 4 * CHUNK_SIZE + 10;
+        assert_eq!(decoder.read_range(start as u64, 100).unwrap(),
+                   &data[start..(start + 100)]);
+    }
+
+    #[test]
+    fn save_and_restore_decoder_state() {
+        let data = x86_like_code(3 * CHUNK_SIZE + 1000);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_e8_translation(true).unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        let mut decoder =
+            Decoder::new(Cursor::new(&output), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut buffer = vec![0u8; CHUNK_SIZE + 1234];
+        decoder.read_exact(&mut buffer).unwrap();
+        let state = decoder.save_state();
+        assert_eq!(state.uncompressed_offset(), buffer.len() as u64);
+        let mut rest = Vec::new();
+        decoder.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[buffer.len()..]);
+        // Restore into the same decoder, and into a fresh one.
+        decoder.restore_state(&state).unwrap();
+        rest.clear();
+        decoder.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[buffer.len()..]);
+        let mut decoder =
+            Decoder::new(Cursor::new(&output), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        decoder.restore_state(&state).unwrap();
+        rest.clear();
+        decoder.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[buffer.len()..]);
+    }
+
+    #[test]
+    fn decode_from_reset_point() {
+        let data = text_with_alphabet(b"abcdefgh ", 3 * CHUNK_SIZE, 15);
+        let mut output = Vec::<u8>::new();
+        let table = {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_reset_interval(1).unwrap();
+            encoder.write_all(&data).unwrap();
+            encoder.reset_table().to_vec()
+        };
+        assert_eq!(table.len(), 3);
+        let (uncompressed_offset, compressed_offset) = table[1];
+        let tail = &data[(uncompressed_offset as usize)..];
+        let mut decoder =
+            Decoder::new(&output[(compressed_offset as usize)..],
+                         WINDOW_MIN,
+                         tail.len() as u64)
+                .unwrap();
+        decoder.set_reset_interval(1).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed.as_slice(), tail);
+    }
+
+    #[test]
+    fn round_trip_with_dictionary() {
+        let dictionary = text_with_alphabet(b"abcdefghijklmnop", 5000, 16);
+        let mut data = b"header:".to_vec();
+        data.extend_from_slice(&dictionary[1000..1300]);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_dictionary(&dictionary).unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        assert!(2 * output.len() < compress(WINDOW_MIN, &data).len());
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        decoder.set_dictionary(&dictionary).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    fn delta_round_trip(reference: &[u8], data: &[u8], two_pass: bool)
+                        -> Vec<u8> {
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, 16, data.len() as u64).unwrap();
+            encoder.set_delta(true).unwrap();
+            encoder.set_dictionary(reference).unwrap();
+            encoder.set_two_pass(two_pass);
+            encoder.write_all(data).unwrap();
+        }
+        let mut decoder =
+            Decoder::new(output.as_slice(), 16, data.len() as u64).unwrap();
+        decoder.set_delta(true).unwrap();
+        decoder.set_dictionary(reference).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+        output
+    }
+
+    #[test]
+    fn round_trip_delta() {
+        // A new version of a file, with a few bytes changed, takes long
+        // matches against the old version.
+        let reference = text_with_alphabet(b"abcdefghijklmnopqrstuvwxyz",
+                                           40000,
+                                           7);
+        let mut data = reference.clone();
+        for &(index, byte) in &[(300, b'1'), (1000, b'2'), (9000, b'3')] {
+            data[index] = byte;
+        }
+        data.extend_from_slice(&[0; 5000]);
+        let output = delta_round_trip(&reference, &data, false);
+        assert!(output.len() < 200);
+        let mut standard = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut standard, 16, data.len() as u64).unwrap();
+            encoder.set_dictionary(&reference).unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        assert!(output.len() < standard.len());
+        assert!(delta_round_trip(&reference, &data, true).len() <=
+                    output.len());
+        // Without DELTA mode, the decoder misreads the stream.
+        let mut decoder =
+            Decoder::new(output.as_slice(), 16, data.len() as u64).unwrap();
+        decoder.set_dictionary(&reference).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        assert!(decoder.read_to_end(&mut decompressed).is_err() ||
+                    decompressed != data);
+    }
+
+    #[test]
+    fn large_delta_windows() {
+        // Windows this large are only for LZX DELTA streams, unless the
+        // `nonstandard-windows` feature allows them for standard streams too.
+        let strict = !cfg!(feature = "nonstandard-windows");
+        assert_eq!(Encoder::new(io::sink(), 22, 10).is_err(), strict);
+        assert!(Encoder::new_delta(io::sink(), 26, 10).is_err());
+        let mut encoder = Encoder::new_delta(io::sink(), 22, 10).unwrap();
+        assert_eq!(encoder.set_delta(false).is_err(), strict);
+        // Refer back to reference data further away than a standard window
+        // allows, which needs position slots beyond those of standard LZXD.
+        let mut reference = text_with_alphabet(b"0123456789abcdef", 5000, 22);
+        let mut data = reference.clone();
+        reference.resize(3 << 20, 0);
+        data.extend_from_slice(b"new data");
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new_delta(&mut output, 22, data.len() as u64)
+                    .unwrap();
+            encoder.set_dictionary(&reference).unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        assert!(output.len() < 100);
+        let mut decoder =
+            Decoder::new_delta(output.as_slice(), 22, data.len() as u64)
+                .unwrap();
+        decoder.set_dictionary(&reference).unwrap();
+        if strict {
+            assert!(decoder.set_delta(false).is_err());
+        }
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+    }
+
+    #[cfg(feature = "nonstandard-windows")]
+    #[test]
+    fn round_trip_nonstandard_windows() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|n| (n / 7 + n / 1000 + (n >> 13) * (n % 3)) as u8)
+            .collect();
+        for &window in &[10, 14, 22, 25] {
+            let mut output = Vec::<u8>::new();
+            {
+                let mut encoder =
+                    Encoder::new(&mut output, window, data.len() as u64)
+                        .unwrap();
+                encoder.set_e8_translation(true).unwrap();
+                encoder.write_all(&data).unwrap();
+            }
+            let mut decompressed = vec![0u8; data.len()];
+            decompress_into(&output, window, &mut decompressed).unwrap();
+            assert!(decompressed == data);
+        }
+        assert!(Encoder::new(io::sink(), 9, 10).is_err());
+    }
+
+    #[test]
+    fn delta_copies_whole_chunks() {
+        // Data identical to the reference takes a single match per chunk,
+        // plus one more after each changed byte.
+        let alphabet: Vec<u8> = (b'0'..b'z').collect();
+        let reference = text_with_alphabet(&alphabet, 0x40000, 5);
+        let mut data = reference.clone();
+        data[0x30100] ^= 1;
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new_delta(&mut output, 19, data.len() as u64)
+                    .unwrap();
+            encoder.set_dictionary(&reference).unwrap();
+            encoder.write_all(&data).unwrap();
+            let stats = encoder.stats();
+            assert_eq!(stats.matches, 9);
+            assert_eq!(stats.literals, 1);
+        }
+        assert!(output.len() < 1000);
+        let mut decoder =
+            Decoder::new_delta(output.as_slice(), 19, data.len() as u64)
+                .unwrap();
+        decoder.set_dictionary(&reference).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+    }
+
+    #[test]
+    fn dictionary_from_reader() {
+        let reference = b"Reference data, as read from a file. ".repeat(100);
+        let mut data = reference.clone();
+        data.extend_from_slice(b"And a little more.");
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new_delta(&mut output, 16, data.len() as u64)
+                    .unwrap();
+            encoder.set_dictionary_from_reader(reference.as_slice())
+                .unwrap();
+            encoder.write_all(&data).unwrap();
+        }
+        let mut decoder =
+            Decoder::new_delta(output.as_slice(), 16, data.len() as u64)
+                .unwrap();
+        decoder.set_dictionary_from_reader(reference.as_slice()).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+        let too_large = vec![0u8; 0x10001];
+        let mut encoder = Encoder::new_delta(io::sink(), 16, 10).unwrap();
+        let error = encoder.set_dictionary_from_reader(too_large.as_slice())
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let mut decoder =
+            Decoder::new_delta(output.as_slice(), 16, data.len() as u64)
+                .unwrap();
+        assert!(decoder.set_dictionary_from_reader(too_large.as_slice())
+                    .is_err());
+    }
+
+    #[test]
+    fn long_delta_tokens() {
+        let tokens = [Token::Literal(b'x'),
+                      Token::Match {
+                          offset: 1,
+                          length: 9_999,
+                      }];
+        let mut encoder = Encoder::new(io::sink(), 16, 10_000).unwrap();
+        let error = encoder.write_tokens(&tokens).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder = Encoder::new(&mut output, 16, 10_000).unwrap();
+            encoder.set_delta(true).unwrap();
+            encoder.write_tokens(&tokens).unwrap();
+        }
+        let mut decoder =
+            Decoder::new(output.as_slice(), 16, 10_000).unwrap();
+        decoder.set_delta(true).unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == vec![b'x'; 10_000]);
+    }
+
+    #[test]
+    fn tokens_can_refer_to_dictionary() {
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder = Encoder::new(&mut output, WINDOW_MIN, 4)
+                .unwrap();
+            encoder.set_dictionary(b"xyzw").unwrap();
+            let token = Token::Match {
+                offset: 4,
+                length: 4,
+            };
+            encoder.write_tokens(&[token]).unwrap();
+        }
+        let mut decoder = Decoder::new(output.as_slice(), WINDOW_MIN, 4)
+            .unwrap();
+        decoder.set_dictionary(b"xyzw").unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, b"xyzw");
+    }
+
+    #[test]
+    fn two_pass_improves_compression() {
+        let mut data = Vec::new();
+        for seed in 0..40 {
+            let word = text_with_alphabet(b"abcdefghijklmnopqrstuvwxyz",
+                                          3 + seed as usize % 7,
+                                          seed);
+            data.extend_from_slice(&word);
+            data.push(b' ');
+        }
+        let vocabulary = data.clone();
+        let mut state = 17u32;
+        while data.len() < CHUNK_SIZE + 5000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let start = (state >> 16) as usize % (vocabulary.len() - 12);
+            data.extend_from_slice(&vocabulary[start..(start + 12)]);
+        }
+        let single_pass = round_trip(WINDOW_MIN, &data);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_two_pass(true);
+            encoder.write_all(&data).unwrap();
+        }
+        assert!(output.len() < single_pass.len());
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn round_trip_with_fixed_trees() {
+        let phrase = text_with_alphabet(b"the quick brown fox ", 2000, 18);
+        let mut data = Vec::new();
+        for _ in 0..20 {
+            data.extend_from_slice(&phrase);
+        }
+        let mut output = Vec::<u8>::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            encoder.set_fixed_trees(true);
+            encoder.write_all(&data).unwrap();
+            assert_eq!(encoder.stats().aligned_blocks, 0);
+        }
+        assert!(output.len() < data.len() / 4);
+        let mut decoder =
+            Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                .unwrap();
+        let mut decompressed = Vec::<u8>::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn round_trip_with_match_params() {
+        let data = x86_like_code(2 * CHUNK_SIZE + 100);
+        let default_len = compress(WINDOW_MIN, &data).len();
+        let params = [MatchParams {
+                          max_chain_len: 1,
+                          nice_match_len: 8,
+                          max_lazy: 0,
+                      },
+                      MatchParams {
+                          max_chain_len: 256,
+                          nice_match_len: 257,
+                          max_lazy: 32,
+                      }];
+        let mut lens = Vec::new();
+        for &params in params.iter() {
+            let mut output = Vec::<u8>::new();
+            {
+                let mut encoder =
+                    Encoder::new(&mut output, WINDOW_MIN, data.len() as u64)
+                        .unwrap();
+                encoder.set_match_params(params).unwrap();
+                encoder.write_all(&data).unwrap();
+            }
+            let mut decoder =
+                Decoder::new(output.as_slice(), WINDOW_MIN, data.len() as u64)
+                    .unwrap();
+            let mut decompressed = Vec::<u8>::new();
+            decoder.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, data);
+            lens.push(output.len());
+        }
+        assert!(lens[0] > default_len);
+        assert!(lens[1] < default_len);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid LZX match parameters")]
+    fn invalid_match_params() {
+        let mut output = Vec::<u8>::new();
+        let mut encoder = Encoder::new(&mut output, WINDOW_MIN, 3).unwrap();
+        let params = MatchParams {
+            nice_match_len: 2,
+            ..MatchParams::default()
+        };
+        encoder.set_match_params(params).unwrap();
+    }
+
+    #[test]
+    fn compress_in_memory() {
+        let data = text_with_alphabet(b"abcdefg ", CHUNK_SIZE + 10, 19);
+        assert_eq!(super::compress(&data, WINDOW_MIN).unwrap(),
+                   compress(WINDOW_MIN, &data));
+    }
+
+    #[test]
+    fn progress_with_declared_size() {
+        let mut encoder = Encoder::new(Vec::new(), WINDOW_MIN, 1000).unwrap();
+        assert_eq!(encoder.uncompressed_bytes_remaining(), Some(1000));
+        encoder.write_all(&[b'x'; 300]).unwrap();
+        assert_eq!(encoder.uncompressed_bytes_written(), 300);
+        assert_eq!(encoder.uncompressed_bytes_remaining(), Some(700));
+    }
+
+    #[test]
+    fn debug_shows_progress() {
+        let mut encoder = Encoder::with_unknown_size(Vec::new(), WINDOW_MIN)
+            .unwrap();
+        encoder.write_all(&[b'x'; CHUNK_SIZE + 100]).unwrap();
+        assert_eq!(encoder.uncompressed_bytes_written(),
+                   CHUNK_SIZE as u64 + 100);
+        assert_eq!(encoder.uncompressed_bytes_remaining(), None);
+        let debug = format!("{:?}", encoder);
+        assert!(debug.starts_with("Encoder { window_size: 32768, "));
+        assert!(debug.contains("chunk_offset: 32768, "));
+        assert!(debug.contains("chunk_buffered_bytes: 100, "));
+        assert!(debug.contains("total_uncompressed_bytes_remaining: None, "));
     }
 }
 
@@ -0,0 +1,198 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+// ========================================================================= //
+
+/// An error encountered while encoding or decoding an LZXD stream.
+///
+/// Functions in this crate return `io::Error`s, since they work with
+/// `io::Read` and `io::Write` streams; errors that originate in this crate
+/// (rather than in the underlying reader or writer) wrap one of these, which
+/// can be retrieved with `Error::from_io`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The given window size is not supported by the LZXD format.
+    InvalidWindow(u16),
+    /// The compressed data contains a block of unknown type.
+    InvalidBlockType(u32),
+    /// The compressed data contains an invalid Huffman tree.
+    CorruptHuffmanTable,
+    /// The compressed data contains a match that refers back before the
+    /// start of the stream or beyond the window.
+    OffsetOutOfRange(u32),
+    /// The compressed data ended before the full uncompressed size was
+    /// decoded.
+    Truncated,
+    /// The compressed data is corrupt in some other way.
+    InvalidData(String),
+    /// The encoder or decoder was used incorrectly.
+    InvalidInput(String),
+    /// The operation was aborted by a cancellation check.
+    Cancelled,
+    /// Decoding would produce more than the decoder's configured maximum
+    /// output size (given here, in bytes).
+    OutputLimitExceeded(u64),
+    /// Decoding the stream would need more memory (given here, in bytes)
+    /// than the decoder's configured limit.
+    MemoryLimitExceeded(usize),
+    /// Another error, annotated with where in the stream it occurred.
+    AtOffset {
+        /// The number of bits of compressed data consumed before the error.
+        compressed_bit_offset: u64,
+        /// The number of uncompressed bytes decoded before the error.
+        uncompressed_offset: u64,
+        /// The underlying error.
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Returns the LZXD error that caused an `io::Error` returned by this
+    /// crate, if any (that is, if the error didn't come from the underlying
+    /// reader or writer).
+    pub fn from_io(error: &io::Error) -> Option<&Error> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<Error>())
+    }
+
+    /// Returns the underlying error, without any offset annotation.
+    pub fn without_offset(&self) -> &Error {
+        match *self {
+            Error::AtOffset { ref error, .. } => error.without_offset(),
+            _ => self,
+        }
+    }
+
+    /// Annotates this error with where in the stream it occurred.
+    pub fn at_offset(self, compressed_bit_offset: u64,
+                     uncompressed_offset: u64)
+                     -> Error {
+        Error::AtOffset {
+            compressed_bit_offset,
+            uncompressed_offset,
+            error: Box::new(self),
+        }
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match *self {
+            Error::InvalidWindow(_) | Error::InvalidInput(_) => {
+                io::ErrorKind::InvalidInput
+            }
+            Error::Truncated => io::ErrorKind::UnexpectedEof,
+            Error::Cancelled => io::ErrorKind::Other,
+            Error::MemoryLimitExceeded(_) => io::ErrorKind::OutOfMemory,
+            Error::AtOffset { ref error, .. } => error.kind(),
+            _ => io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidWindow(window) => {
+                write!(formatter, "Invalid LZX window ({})", window)
+            }
+            Error::InvalidBlockType(bits) => {
+                write!(formatter, "Invalid LZX block type ({})", bits)
+            }
+            Error::CorruptHuffmanTable => {
+                formatter.write_str("Invalid LZX Huffman tree")
+            }
+            Error::OffsetOutOfRange(offset) => {
+                write!(formatter, "LZX match offset out of range ({})", offset)
+            }
+            Error::Truncated => formatter.write_str("LZX stream is truncated"),
+            Error::Cancelled => {
+                formatter.write_str("LZX stream was cancelled")
+            }
+            Error::OutputLimitExceeded(max_bytes) => {
+                write!(formatter,
+                       "LZX output exceeds the limit of {} bytes",
+                       max_bytes)
+            }
+            Error::MemoryLimitExceeded(num_bytes) => {
+                write!(formatter,
+                       "LZX decoder would need {} bytes of memory, which \
+                        exceeds the limit",
+                       num_bytes)
+            }
+            Error::InvalidData(ref message) |
+            Error::InvalidInput(ref message) => formatter.write_str(message),
+            Error::AtOffset {
+                compressed_bit_offset,
+                uncompressed_offset,
+                ref error,
+            } => {
+                write!(formatter,
+                       "{} (at compressed byte {:#x} bit {}, uncompressed \
+                        byte {:#x})",
+                       error,
+                       compressed_bit_offset / 8,
+                       compressed_bit_offset % 8,
+                       uncompressed_offset)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error { io::Error::new(error.kind(), error) }
+}
+
+/// Converts an unexpected end-of-file from the underlying reader into an
+/// `Error::Truncated`, leaving other errors unchanged.
+pub fn eof_as_truncated(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::UnexpectedEof &&
+        Error::from_io(&error).is_none()
+    {
+        Error::Truncated.into()
+    } else {
+        error
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, eof_as_truncated};
+    use std::io;
+
+    #[test]
+    fn convert_to_io_error() {
+        let error: io::Error = Error::InvalidWindow(12).into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "Invalid LZX window (12)");
+        assert_eq!(Error::from_io(&error), Some(&Error::InvalidWindow(12)));
+        let error: io::Error = Error::CorruptHuffmanTable.into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn error_at_offset() {
+        let error = Error::CorruptHuffmanTable.at_offset(0x4f21 * 8 + 3, 100);
+        assert_eq!(error.without_offset(), &Error::CorruptHuffmanTable);
+        assert_eq!(error.to_string(),
+                   "Invalid LZX Huffman tree (at compressed byte 0x4f21 bit \
+                    3, uncompressed byte 0x64)");
+        let error: io::Error = error.into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn underlying_errors_are_not_lzxd_errors() {
+        let error = io::Error::other("disk on fire");
+        assert_eq!(Error::from_io(&error), None);
+        let error = eof_as_truncated(io::Error::other("disk on fire"));
+        assert_eq!(Error::from_io(&error), None);
+        let error = io::Error::from(io::ErrorKind::UnexpectedEof);
+        let error = eof_as_truncated(error);
+        assert_eq!(Error::from_io(&error), Some(&Error::Truncated));
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,506 @@
+//! A C-compatible interface, for linking this crate into C or C++ programs.
+//!
+//! To build a shared library exposing these functions, compile the crate as
+//! a `cdylib` with the `ffi` feature enabled, for example with `cargo rustc
+//! --release --features ffi --crate-type cdylib`.
+//!
+//! Functions that can fail return `LZXD_OK` (zero) on success or one of the
+//! negative `LZXD_ERROR_*` codes.  Pointer arguments must be valid for the
+//! given lengths; a null pointer is permitted only with a length of zero.
+//! A panic inside the library never unwinds into the caller; it is reported
+//! as `LZXD_ERROR_INTERNAL` (or a null context) instead.
+
+use internal::consts;
+use internal::decoder::decompress_into;
+use internal::encoder::{Encoder, compress_bound, compress_into};
+use internal::sink::DecodeSink;
+use std::io::{self, Write};
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+// ========================================================================= //
+
+/// The operation succeeded.
+pub const LZXD_OK: c_int = 0;
+/// An argument was invalid (such as an unsupported window size or a null
+/// pointer), or a function was called at the wrong time.
+pub const LZXD_ERROR_INVALID_INPUT: c_int = -1;
+/// The compressed data is corrupt.
+pub const LZXD_ERROR_INVALID_DATA: c_int = -2;
+/// The compressed data ended before the full uncompressed size was decoded.
+pub const LZXD_ERROR_TRUNCATED: c_int = -3;
+/// The output buffer is too small.
+pub const LZXD_ERROR_BUFFER_TOO_SMALL: c_int = -4;
+/// The library failed internally (a bug).  A context that this is returned
+/// for must not be used again, except to free it.
+pub const LZXD_ERROR_INTERNAL: c_int = -5;
+
+/// Runs `body`, returning `on_panic` if it panics, since unwinding out of an
+/// `extern "C"` function would abort the caller's process.
+fn catch_panic<T, F: FnOnce() -> T>(on_panic: T, body: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(on_panic)
+}
+
+fn error_code(error: &io::Error) -> c_int {
+    match error.kind() {
+        io::ErrorKind::InvalidInput => LZXD_ERROR_INVALID_INPUT,
+        io::ErrorKind::UnexpectedEof => LZXD_ERROR_TRUNCATED,
+        io::ErrorKind::WriteZero => LZXD_ERROR_BUFFER_TOO_SMALL,
+        _ => LZXD_ERROR_INVALID_DATA,
+    }
+}
+
+fn result_code(result: io::Result<()>) -> c_int {
+    match result {
+        Ok(()) => LZXD_OK,
+        Err(error) => error_code(&error),
+    }
+}
+
+unsafe fn input_slice<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len == 0 {
+        Some(&[])
+    } else if data.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(data, len))
+    }
+}
+
+unsafe fn output_slice<'a>(data: *mut u8, len: usize)
+                           -> Option<&'a mut [u8]> {
+    if len == 0 {
+        Some(&mut [])
+    } else if data.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts_mut(data, len))
+    }
+}
+
+/// Copies as much of `output[*start..]` as fits into `dst`, and empties
+/// `output` once all of it has been copied.
+fn drain_output(output: &mut Vec<u8>, start: &mut usize, dst: &mut [u8])
+                -> usize {
+    let num_bytes = (output.len() - *start).min(dst.len());
+    dst[..num_bytes].copy_from_slice(&output[*start..(*start + num_bytes)]);
+    *start += num_bytes;
+    if *start == output.len() {
+        output.clear();
+        *start = 0;
+    }
+    num_bytes
+}
+
+// ========================================================================= //
+
+/// Returns the largest possible size of the compressed stream for
+/// `src_len` bytes of input (see `compress_bound`), or `SIZE_MAX` if that
+/// doesn't fit in a `size_t`.
+#[no_mangle]
+pub extern "C" fn lzxd_compress_bound(src_len: usize) -> usize {
+    catch_panic(usize::MAX, || compress_bound(src_len))
+}
+
+/// Compresses `src_len` bytes from `src` into the `dst_capacity`-byte
+/// buffer at `dst`, storing the compressed size in `*dst_len`.  A
+/// `dst_capacity` of `lzxd_compress_bound(src_len)` is always enough.
+///
+/// # Safety
+///
+/// `src` and `dst` must be valid for the given lengths, and `dst_len` must
+/// be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_compress(src: *const u8, src_len: usize,
+                                       window: u16, dst: *mut u8,
+                                       dst_capacity: usize,
+                                       dst_len: *mut usize)
+                                       -> c_int {
+    catch_panic(LZXD_ERROR_INTERNAL, || {
+        let (src, dst) = match (input_slice(src, src_len),
+                                output_slice(dst, dst_capacity)) {
+            (Some(src), Some(dst)) if !dst_len.is_null() => (src, dst),
+            _ => return LZXD_ERROR_INVALID_INPUT,
+        };
+        match compress_into(src, window, dst) {
+            Ok(num_bytes) => {
+                *dst_len = num_bytes;
+                LZXD_OK
+            }
+            Err(error) => error_code(&error),
+        }
+    })
+}
+
+/// Decompresses the `src_len`-byte stream at `src` into the buffer at `dst`,
+/// whose length `dst_len` must be the exact uncompressed size.
+///
+/// # Safety
+///
+/// `src` and `dst` must be valid for the given lengths.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_decompress(src: *const u8, src_len: usize,
+                                         window: u16, dst: *mut u8,
+                                         dst_len: usize)
+                                         -> c_int {
+    catch_panic(LZXD_ERROR_INTERNAL, || {
+        match (input_slice(src, src_len), output_slice(dst, dst_len)) {
+            (Some(src), Some(dst)) => result_code(decompress_into(src,
+                                                                  window,
+                                                                  dst)),
+            _ => LZXD_ERROR_INVALID_INPUT,
+        }
+    })
+}
+
+// ========================================================================= //
+
+/// A streaming decoder context, for use from C.
+pub struct LzxdDecoder {
+    sink: DecodeSink<Vec<u8>>,
+    output_start: usize,
+}
+
+/// Creates a streaming decoder for a stream of `uncompressed_size` bytes.
+/// Returns null if `window` is invalid.  The context must be freed with
+/// `lzxd_decoder_free`.
+#[no_mangle]
+pub extern "C" fn lzxd_decoder_new(window: u16, uncompressed_size: u64)
+                                   -> *mut LzxdDecoder {
+    catch_panic(ptr::null_mut(), || {
+        if !(consts::WINDOW_MIN..=consts::WINDOW_MAX).contains(&window) {
+            return ptr::null_mut();
+        }
+        let decoder = LzxdDecoder {
+            sink: DecodeSink::new(Vec::new(), window, uncompressed_size),
+            output_start: 0,
+        };
+        Box::into_raw(Box::new(decoder))
+    })
+}
+
+/// Feeds `src_len` bytes of compressed data to the decoder.  The
+/// decompressed data can then be retrieved with `lzxd_decoder_read`.
+///
+/// # Safety
+///
+/// `decoder` must have come from `lzxd_decoder_new`, and `src` must be
+/// valid for `src_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_decoder_feed(decoder: *mut LzxdDecoder,
+                                           src: *const u8, src_len: usize)
+                                           -> c_int {
+    catch_panic(LZXD_ERROR_INTERNAL, || {
+        match (decoder.as_mut(), input_slice(src, src_len)) {
+            (Some(decoder), Some(src)) => {
+                result_code(decoder.sink.write_all(src))
+            }
+            _ => LZXD_ERROR_INVALID_INPUT,
+        }
+    })
+}
+
+/// Copies up to `dst_capacity` bytes of decompressed data into `dst`, and
+/// stores the number of bytes copied in `*dst_len` (zero once all of the
+/// data decoded so far has been read).
+///
+/// # Safety
+///
+/// `decoder` must have come from `lzxd_decoder_new`, `dst` must be valid for
+/// `dst_capacity` bytes, and `dst_len` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_decoder_read(decoder: *mut LzxdDecoder,
+                                           dst: *mut u8, dst_capacity: usize,
+                                           dst_len: *mut usize)
+                                           -> c_int {
+    catch_panic(LZXD_ERROR_INTERNAL, || {
+        match (decoder.as_mut(), output_slice(dst, dst_capacity)) {
+            (Some(decoder), Some(dst)) if !dst_len.is_null() => {
+                *dst_len = drain_output(decoder.sink.get_mut(),
+                                        &mut decoder.output_start,
+                                        dst);
+                LZXD_OK
+            }
+            _ => LZXD_ERROR_INVALID_INPUT,
+        }
+    })
+}
+
+/// Checks that the entire stream has been fed to the decoder, returning
+/// `LZXD_ERROR_TRUNCATED` if not.
+///
+/// # Safety
+///
+/// `decoder` must have come from `lzxd_decoder_new`.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_decoder_finish(decoder: *const LzxdDecoder)
+                                             -> c_int {
+    catch_panic(LZXD_ERROR_INTERNAL, || {
+        match decoder.as_ref() {
+            Some(decoder) if decoder.sink.is_finished() => LZXD_OK,
+            Some(_) => LZXD_ERROR_TRUNCATED,
+            None => LZXD_ERROR_INVALID_INPUT,
+        }
+    })
+}
+
+/// Frees a decoder context.  Passing null does nothing.
+///
+/// # Safety
+///
+/// `decoder` must be null or have come from `lzxd_decoder_new`, and must not
+/// be used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_decoder_free(decoder: *mut LzxdDecoder) {
+    catch_panic((), || {
+        if !decoder.is_null() {
+            drop(Box::from_raw(decoder));
+        }
+    })
+}
+
+// ========================================================================= //
+
+/// A streaming encoder context, for use from C.
+pub struct LzxdEncoder {
+    encoder: Encoder<Vec<u8>>,
+    output_start: usize,
+}
+
+/// Creates a streaming encoder for exactly `uncompressed_size` bytes of
+/// input.  Returns null if `window` is invalid.  The context must be freed
+/// with `lzxd_encoder_free`.
+#[no_mangle]
+pub extern "C" fn lzxd_encoder_new(window: u16, uncompressed_size: u64)
+                                   -> *mut LzxdEncoder {
+    catch_panic(ptr::null_mut(), || {
+        match Encoder::new(Vec::new(), window, uncompressed_size) {
+            Ok(encoder) => {
+                let encoder = LzxdEncoder {
+                    encoder,
+                    output_start: 0,
+                };
+                Box::into_raw(Box::new(encoder))
+            }
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Feeds `src_len` bytes of uncompressed data to the encoder.  The
+/// compressed data can then be retrieved with `lzxd_encoder_read`.
+///
+/// # Safety
+///
+/// `encoder` must have come from `lzxd_encoder_new`, and `src` must be
+/// valid for `src_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_encoder_feed(encoder: *mut LzxdEncoder,
+                                           src: *const u8, src_len: usize)
+                                           -> c_int {
+    catch_panic(LZXD_ERROR_INTERNAL, || {
+        match (encoder.as_mut(), input_slice(src, src_len)) {
+            (Some(encoder), Some(src)) => {
+                result_code(encoder.encoder.write_all(src))
+            }
+            _ => LZXD_ERROR_INVALID_INPUT,
+        }
+    })
+}
+
+/// Copies up to `dst_capacity` bytes of compressed data into `dst`, and
+/// stores the number of bytes copied in `*dst_len` (zero once all of the
+/// data encoded so far has been read).
+///
+/// # Safety
+///
+/// `encoder` must have come from `lzxd_encoder_new`, `dst` must be valid for
+/// `dst_capacity` bytes, and `dst_len` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_encoder_read(encoder: *mut LzxdEncoder,
+                                           dst: *mut u8, dst_capacity: usize,
+                                           dst_len: *mut usize)
+                                           -> c_int {
+    catch_panic(LZXD_ERROR_INTERNAL, || {
+        match (encoder.as_mut(), output_slice(dst, dst_capacity)) {
+            (Some(encoder), Some(dst)) if !dst_len.is_null() => {
+                *dst_len = drain_output(encoder.encoder.get_mut(),
+                                        &mut encoder.output_start,
+                                        dst);
+                LZXD_OK
+            }
+            _ => LZXD_ERROR_INVALID_INPUT,
+        }
+    })
+}
+
+/// Finishes the compressed stream, after all of the input has been fed to
+/// the encoder.  The rest of the compressed data can then be retrieved with
+/// `lzxd_encoder_read`.
+///
+/// # Safety
+///
+/// `encoder` must have come from `lzxd_encoder_new`.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_encoder_finish(encoder: *mut LzxdEncoder)
+                                             -> c_int {
+    catch_panic(LZXD_ERROR_INTERNAL, || {
+        match encoder.as_mut() {
+            Some(encoder) => result_code(encoder.encoder.finish().map(|_| ())),
+            None => LZXD_ERROR_INVALID_INPUT,
+        }
+    })
+}
+
+/// Frees an encoder context.  Passing null does nothing.
+///
+/// # Safety
+///
+/// `encoder` must be null or have come from `lzxd_encoder_new`, and must not
+/// be used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lzxd_encoder_free(encoder: *mut LzxdEncoder) {
+    catch_panic((), || {
+        if !encoder.is_null() {
+            drop(Box::from_raw(encoder));
+        }
+    })
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{LZXD_ERROR_BUFFER_TOO_SMALL, LZXD_ERROR_INTERNAL,
+                LZXD_ERROR_INVALID_INPUT, LZXD_ERROR_TRUNCATED, LZXD_OK,
+                catch_panic, lzxd_compress, lzxd_compress_bound,
+                lzxd_decoder_feed, lzxd_decoder_finish, lzxd_decoder_free,
+                lzxd_decoder_new, lzxd_decoder_read, lzxd_decompress,
+                lzxd_encoder_feed, lzxd_encoder_finish, lzxd_encoder_free,
+                lzxd_encoder_new, lzxd_encoder_read};
+    use internal::encoder::compress;
+    use std::ptr;
+
+    #[test]
+    fn one_shot_round_trip() {
+        let data = b"Called from C. ".repeat(5000);
+        let mut compressed = vec![0u8; lzxd_compress_bound(data.len())];
+        let mut compressed_len = 0;
+        unsafe {
+            assert_eq!(lzxd_compress(data.as_ptr(),
+                                     data.len(),
+                                     16,
+                                     compressed.as_mut_ptr(),
+                                     compressed.len(),
+                                     &mut compressed_len),
+                       LZXD_OK);
+            assert_eq!(lzxd_compress(data.as_ptr(),
+                                     data.len(),
+                                     16,
+                                     compressed.as_mut_ptr(),
+                                     10,
+                                     &mut compressed_len),
+                       LZXD_ERROR_BUFFER_TOO_SMALL);
+        }
+        compressed.truncate(compressed_len);
+        assert_eq!(compressed, compress(&data, 16).unwrap());
+        let mut output = vec![0u8; data.len()];
+        unsafe {
+            assert_eq!(lzxd_decompress(compressed.as_ptr(),
+                                       compressed.len(),
+                                       16,
+                                       output.as_mut_ptr(),
+                                       output.len()),
+                       LZXD_OK);
+            assert_eq!(lzxd_decompress(ptr::null(),
+                                       compressed.len(),
+                                       16,
+                                       output.as_mut_ptr(),
+                                       output.len()),
+                       LZXD_ERROR_INVALID_INPUT);
+        }
+        assert!(output == data);
+    }
+
+    #[test]
+    fn huge_compress_bound() {
+        assert_eq!(lzxd_compress_bound(usize::MAX), usize::MAX);
+        assert_eq!(lzxd_compress_bound(usize::MAX - 0x8000), usize::MAX);
+    }
+
+    #[test]
+    fn panics_become_error_codes() {
+        assert_eq!(catch_panic(LZXD_ERROR_INTERNAL, || LZXD_OK), LZXD_OK);
+        assert_eq!(catch_panic(LZXD_ERROR_INTERNAL, || -> i32 {
+                                   panic!("internal failure")
+                               }),
+                   LZXD_ERROR_INTERNAL);
+        assert!(catch_panic(ptr::null_mut::<u8>(), || panic!("oops"))
+                    .is_null());
+    }
+
+    #[test]
+    fn streaming_round_trip() {
+        let data = b"Streamed through C. ".repeat(4000);
+        let mut compressed = Vec::new();
+        let mut buffer = [0u8; 1000];
+        let mut num_bytes = 0;
+        unsafe {
+            let encoder = lzxd_encoder_new(17, data.len() as u64);
+            assert!(!encoder.is_null());
+            for piece in data.chunks(3333) {
+                assert_eq!(lzxd_encoder_feed(encoder,
+                                             piece.as_ptr(),
+                                             piece.len()),
+                           LZXD_OK);
+            }
+            assert_eq!(lzxd_encoder_finish(encoder), LZXD_OK);
+            loop {
+                assert_eq!(lzxd_encoder_read(encoder,
+                                             buffer.as_mut_ptr(),
+                                             buffer.len(),
+                                             &mut num_bytes),
+                           LZXD_OK);
+                if num_bytes == 0 {
+                    break;
+                }
+                compressed.extend_from_slice(&buffer[..num_bytes]);
+            }
+            lzxd_encoder_free(encoder);
+        }
+        assert_eq!(compressed, compress(&data, 17).unwrap());
+
+        let mut output = Vec::new();
+        unsafe {
+            assert!(lzxd_decoder_new(14, 100).is_null());
+            let decoder = lzxd_decoder_new(17, data.len() as u64);
+            assert!(!decoder.is_null());
+            for piece in compressed.chunks(777) {
+                assert_eq!(lzxd_decoder_finish(decoder),
+                           LZXD_ERROR_TRUNCATED);
+                assert_eq!(lzxd_decoder_feed(decoder,
+                                             piece.as_ptr(),
+                                             piece.len()),
+                           LZXD_OK);
+                loop {
+                    assert_eq!(lzxd_decoder_read(decoder,
+                                                 buffer.as_mut_ptr(),
+                                                 buffer.len(),
+                                                 &mut num_bytes),
+                               LZXD_OK);
+                    if num_bytes == 0 {
+                        break;
+                    }
+                    output.extend_from_slice(&buffer[..num_bytes]);
+                }
+            }
+            assert_eq!(lzxd_decoder_finish(decoder), LZXD_OK);
+            lzxd_decoder_free(decoder);
+        }
+        assert!(output == data);
+    }
+}
+
+// ========================================================================= //
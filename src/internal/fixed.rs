@@ -0,0 +1,130 @@
+use internal::consts;
+use internal::decoder::Decoder;
+use internal::encoder::Encoder;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+/// Fails to compile (when evaluated) unless `window` is a permitted window
+/// exponent.
+const fn check_window(window: u16) {
+    assert!(window >= consts::WINDOW_MIN && window <= consts::WINDOW_MAX,
+            "LZX window exponent must be between WINDOW_MIN and WINDOW_MAX");
+}
+
+// ========================================================================= //
+
+/// A `Decoder` whose window exponent is fixed at compile time, so that an
+/// invalid window is a compile error rather than a runtime one.
+///
+/// It decodes with the same code as `Decoder`; the window size isn't used
+/// as a compile-time constant while decoding.  This type exists for callers
+/// whose container format fixes the window, so that it can't be passed
+/// wrongly.
+pub struct FixedWindowDecoder<R: Read, const WINDOW: u16> {
+    decoder: Decoder<R>,
+}
+
+impl<R: Read, const WINDOW: u16> FixedWindowDecoder<R, WINDOW> {
+    const VALID_WINDOW: () = check_window(WINDOW);
+
+    /// The size of the window, in bytes.
+    pub const WINDOW_BYTES: usize = 1 << WINDOW;
+
+    /// Starts decoding an LZXD-compressed data stream.  The
+    /// `uncompressed_size` argument is the same as for `Decoder::new`.
+    pub fn new(reader: R, uncompressed_size: u64)
+               -> io::Result<FixedWindowDecoder<R, WINDOW>> {
+        let () = Self::VALID_WINDOW;
+        Ok(FixedWindowDecoder {
+               decoder: Decoder::new(reader, WINDOW, uncompressed_size)?,
+           })
+    }
+
+    /// Returns a reference to the underlying `Decoder`.
+    pub fn get_ref(&self) -> &Decoder<R> { &self.decoder }
+
+    /// Returns a mutable reference to the underlying `Decoder`, for access
+    /// to its settings and state.
+    pub fn get_mut(&mut self) -> &mut Decoder<R> { &mut self.decoder }
+
+    /// Unwraps this object, returning the underlying `Decoder`.
+    pub fn into_inner(self) -> Decoder<R> { self.decoder }
+}
+
+impl<R: Read, const WINDOW: u16> Read for FixedWindowDecoder<R, WINDOW> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+// ========================================================================= //
+
+/// An `Encoder` whose window exponent is fixed at compile time, so that an
+/// invalid window is a compile error rather than a runtime one.
+pub struct FixedWindowEncoder<W: Write, const WINDOW: u16> {
+    encoder: Encoder<W>,
+}
+
+impl<W: Write, const WINDOW: u16> FixedWindowEncoder<W, WINDOW> {
+    const VALID_WINDOW: () = check_window(WINDOW);
+
+    /// The size of the window, in bytes.
+    pub const WINDOW_BYTES: usize = 1 << WINDOW;
+
+    /// Starts encoding an LZXD-compressed data stream.  The
+    /// `uncompressed_size` argument is the same as for `Encoder::new`.
+    pub fn new(writer: W, uncompressed_size: u64)
+               -> io::Result<FixedWindowEncoder<W, WINDOW>> {
+        let () = Self::VALID_WINDOW;
+        Ok(FixedWindowEncoder {
+               encoder: Encoder::new(writer, WINDOW, uncompressed_size)?,
+           })
+    }
+
+    /// Returns a reference to the underlying `Encoder`.
+    pub fn get_ref(&self) -> &Encoder<W> { &self.encoder }
+
+    /// Returns a mutable reference to the underlying `Encoder`, for access
+    /// to its settings.
+    pub fn get_mut(&mut self) -> &mut Encoder<W> { &mut self.encoder }
+
+    /// Unwraps this object, returning the underlying `Encoder`.
+    pub fn into_inner(self) -> Encoder<W> { self.encoder }
+}
+
+impl<W: Write, const WINDOW: u16> Write for FixedWindowEncoder<W, WINDOW> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.encoder.flush() }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{FixedWindowDecoder, FixedWindowEncoder};
+    use internal::encoder::compress;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn fixed_window_round_trip() {
+        assert_eq!(FixedWindowEncoder::<Vec<u8>, 17>::WINDOW_BYTES, 0x20000);
+        let data = b"Window fixed at compile time. ".repeat(3000);
+        let mut encoder: FixedWindowEncoder<_, 17> =
+            FixedWindowEncoder::new(Vec::new(), data.len() as u64).unwrap();
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.into_inner().into_inner().unwrap();
+        assert_eq!(compressed, compress(&data, 17).unwrap());
+        let mut decoder: FixedWindowDecoder<_, 17> =
+            FixedWindowDecoder::new(compressed.as_slice(), data.len() as u64)
+                .unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+    }
+}
+
+// ========================================================================= //
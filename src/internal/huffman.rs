@@ -0,0 +1,497 @@
+use internal::bits::BitReader;
+use internal::consts;
+use internal::error::Error;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Read};
+
+// ========================================================================= //
+
+/// Computes length-limited Huffman code lengths for the given symbol
+/// frequencies.  Symbols with a frequency of zero get a length of zero.  If
+/// only one symbol is used, a second symbol is given a code as well, so that
+/// the resulting code is always complete (or entirely empty).
+pub fn build_lengths(freqs: &[u32], max_len: u8) -> Vec<u8> {
+    debug_assert!(freqs.len() >= 2);
+    debug_assert!((1..=16).contains(&max_len));
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut used: Vec<usize> = (0..freqs.len())
+        .filter(|&symbol| freqs[symbol] > 0)
+        .collect();
+    if used.is_empty() {
+        return lengths;
+    }
+    if used.len() == 1 {
+        let other = if used[0] == 0 { 1 } else { 0 };
+        lengths[used[0]] = 1;
+        lengths[other] = 1;
+        return lengths;
+    }
+    // Build an unrestricted Huffman tree, breaking ties by node index so that
+    // the result is deterministic.
+    let num_leaves = used.len();
+    let mut parents = vec![0usize; 2 * num_leaves - 1];
+    let mut heap = BinaryHeap::with_capacity(num_leaves);
+    for (node, &symbol) in used.iter().enumerate() {
+        heap.push(Reverse((freqs[symbol] as u64, node)));
+    }
+    let mut next_node = num_leaves;
+    while heap.len() > 1 {
+        let Reverse((weight1, node1)) = heap.pop().unwrap();
+        let Reverse((weight2, node2)) = heap.pop().unwrap();
+        parents[node1] = next_node;
+        parents[node2] = next_node;
+        heap.push(Reverse((weight1 + weight2, next_node)));
+        next_node += 1;
+    }
+    let root = next_node - 1;
+    let mut depths = vec![0usize; next_node];
+    for node in (0..root).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    // Count the number of codes of each length, clamping overlong codes to
+    // the maximum, and then rebalance the counts until they once again
+    // describe a complete prefix code.
+    let max_len = max_len as usize;
+    let mut num_codes = vec![0u32; max_len + 1];
+    for &depth in depths.iter().take(num_leaves) {
+        num_codes[depth.min(max_len)] += 1;
+    }
+    let mut kraft_total: u64 = 0;
+    for (len, &count) in num_codes.iter().enumerate().skip(1) {
+        kraft_total += (count as u64) << (max_len - len);
+    }
+    while kraft_total > (1u64 << max_len) {
+        num_codes[max_len] -= 1;
+        for len in (1..max_len).rev() {
+            if num_codes[len] != 0 {
+                num_codes[len] -= 1;
+                num_codes[len + 1] += 2;
+                break;
+            }
+        }
+        kraft_total -= 1;
+    }
+    // Hand out the lengths, giving the shortest codes to the most frequent
+    // symbols.
+    used.sort_by_key(|&symbol| (Reverse(freqs[symbol]), symbol));
+    let mut symbols = used.into_iter();
+    for (len, &count) in num_codes.iter().enumerate().skip(1) {
+        for _ in 0..count {
+            lengths[symbols.next().unwrap()] = len as u8;
+        }
+    }
+    lengths
+}
+
+/// Assigns canonical Huffman codes to symbols with the given code lengths.
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut num_codes = [0u32; 17];
+    for &len in lengths.iter() {
+        num_codes[len as usize] += 1;
+    }
+    num_codes[0] = 0;
+    let mut next_code = [0u32; 17];
+    for len in 1..17 {
+        next_code[len] = (next_code[len - 1] + num_codes[len - 1]) << 1;
+    }
+    lengths
+        .iter()
+        .map(|&len| if len == 0 {
+                 0
+             } else {
+                 let code = next_code[len as usize];
+                 next_code[len as usize] += 1;
+                 code as u16
+             })
+        .collect()
+}
+
+// ========================================================================= //
+
+/// The number of bits looked up at once in the primary table.  Codes up to
+/// this length are decoded with a single table lookup, and longer codes with
+/// a second lookup in a secondary table.
+const PRIMARY_BITS: u16 = 10;
+
+/// Marks a primary table entry that refers to a secondary table.
+const LINK: u32 = 0x80;
+
+/// The most index bits that a secondary table can need.
+const MAX_INDEX_BITS: u16 = 16 - PRIMARY_BITS;
+
+/// Returns an upper bound on the total size of the secondary tables for a
+/// code over `num_symbols` symbols.  Canonical codes are handed out in order
+/// of length, so of the primary entries whose longest code has a given
+/// length, all but the first hold only codes of that length (one secondary
+/// entry per code).  The first has one entry per index bit value, which adds
+/// up to at most `2 + 4 + ... + (1 << MAX_INDEX_BITS)` more.
+pub const fn max_secondary_len(num_symbols: usize) -> usize {
+    if num_symbols < PRIMARY_BITS as usize + 2 {
+        // A complete code needs this many symbols for any code to be longer
+        // than `PRIMARY_BITS`.
+        return 0;
+    }
+    num_symbols + (2 << MAX_INDEX_BITS) - 2
+}
+
+/// A table for decoding canonical Huffman codes.  The primary table, indexed
+/// by the next `PRIMARY_BITS` bits, and the secondary tables for longer
+/// codes are both fixed-size, so a table never allocates.  `SECONDARY_LEN`
+/// is the room for secondary tables, which `max_secondary_len()` gives for
+/// the largest code that the table will be built for.
+#[derive(Clone)]
+pub struct HuffmanTable<const SECONDARY_LEN: usize> {
+    num_symbols: usize,
+    /// Each entry holds a symbol (in the high bits) and the length of its
+    /// code (in the low five bits), or else `LINK`, the start of a secondary
+    /// table (in the high bits), and the number of bits that index it (in
+    /// the low five bits).
+    primary: [u32; 1 << PRIMARY_BITS],
+    /// Symbol entries, as in `primary`, for the codes longer than
+    /// `PRIMARY_BITS`, indexed by the bits that follow the first
+    /// `PRIMARY_BITS`.  Only the first `secondary_len` entries are in use.
+    secondary: [u32; SECONDARY_LEN],
+    secondary_len: usize,
+}
+
+impl<const SECONDARY_LEN: usize> HuffmanTable<SECONDARY_LEN> {
+    #[cfg(test)]
+    pub fn new(lengths: &[u8]) -> io::Result<HuffmanTable<SECONDARY_LEN>> {
+        let mut table = HuffmanTable::empty();
+        table.rebuild(lengths)?;
+        Ok(table)
+    }
+
+    /// Creates an empty table, which must be rebuilt before decoding.
+    pub fn empty() -> HuffmanTable<SECONDARY_LEN> {
+        HuffmanTable {
+            num_symbols: 0,
+            primary: [0; 1 << PRIMARY_BITS],
+            secondary: [0; SECONDARY_LEN],
+            secondary_len: 0,
+        }
+    }
+
+    /// Rebuilds this table for a new set of code lengths, reusing its memory.
+    /// If the lengths are invalid, the table is left unchanged.
+    pub fn rebuild(&mut self, lengths: &[u8]) -> io::Result<()> {
+        if lengths.len() > consts::MAX_MAIN_ELEMENTS ||
+            max_secondary_len(lengths.len()) > SECONDARY_LEN
+        {
+            invalid_data!("Too many LZX Huffman symbols ({})", lengths.len());
+        }
+        let mut num_codes = [0u16; 17];
+        for &len in lengths.iter() {
+            if len > 16 {
+                invalid_data!("Invalid LZX Huffman code length ({})", len);
+            }
+            num_codes[len as usize] += 1;
+        }
+        num_codes[0] = 0;
+        let mut kraft_total: u32 = 0;
+        for (len, &count) in num_codes.iter().enumerate().skip(1) {
+            kraft_total += (count as u32) << (16 - len);
+        }
+        if kraft_total != 0 && kraft_total != 1 << 16 {
+            return Err(Error::CorruptHuffmanTable.into());
+        }
+        // Canonical codes are handed out in order of length, and then of
+        // symbol, so sort the symbols that way.
+        let mut offsets = [0usize; 17];
+        for len in 1..16 {
+            offsets[len + 1] = offsets[len] + num_codes[len] as usize;
+        }
+        let num_symbols = offsets[16] + num_codes[16] as usize;
+        let mut sorted_symbols = [0u16; consts::MAX_MAIN_ELEMENTS];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                sorted_symbols[offsets[len as usize]] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        self.num_symbols = num_symbols;
+        self.secondary_len = 0;
+        if num_symbols == 0 {
+            return Ok(());
+        }
+        // Fill in the primary table for the short codes, and note how many
+        // index bits each secondary table needs: enough for the longest code
+        // that starts with its primary entry, which is the last such code.
+        let mut code: usize = 0;
+        let mut index = 0;
+        let mut first_long_code = 0;
+        for len in 1..17 {
+            if len == PRIMARY_BITS + 1 {
+                first_long_code = code;
+            }
+            for _ in 0..num_codes[len as usize] {
+                if len <= PRIMARY_BITS {
+                    let symbol = sorted_symbols[index] as u32;
+                    index += 1;
+                    let fill = 1 << (PRIMARY_BITS - len);
+                    let start = code << (PRIMARY_BITS - len);
+                    for entry in self.primary[start..(start + fill)]
+                        .iter_mut()
+                    {
+                        *entry = (symbol << 8) | len as u32;
+                    }
+                } else {
+                    let extra_bits = len - PRIMARY_BITS;
+                    self.primary[code >> extra_bits] =
+                        LINK | extra_bits as u32;
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        // Lay out the secondary tables, and then fill them in for the long
+        // codes.
+        for entry in self.primary.iter_mut() {
+            if (*entry & LINK) != 0 {
+                let start = self.secondary_len;
+                let index_bits = *entry & 0x1f;
+                self.secondary_len += 1 << index_bits;
+                *entry = ((start as u32) << 8) | LINK | index_bits;
+            }
+        }
+        debug_assert!(self.secondary_len <= max_secondary_len(num_symbols));
+        let mut code = first_long_code;
+        for len in (PRIMARY_BITS + 1)..17 {
+            let extra_bits = len - PRIMARY_BITS;
+            for _ in 0..num_codes[len as usize] {
+                let symbol = sorted_symbols[index] as u32;
+                index += 1;
+                let link = self.primary[code >> extra_bits];
+                let index_bits = (link & 0x1f) as u16;
+                let fill = 1 << (index_bits - extra_bits);
+                let low_bits = code & ((1 << extra_bits) - 1);
+                let start = (link >> 8) as usize +
+                    (low_bits << (index_bits - extra_bits));
+                for entry in self.secondary[start..(start + fill)].iter_mut() {
+                    *entry = (symbol << 8) | len as u32;
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        debug_assert_eq!(index, num_symbols);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool { self.num_symbols == 0 }
+
+    #[inline(always)]
+    pub fn decode<R: Read>(&self, reader: &mut BitReader<R>)
+                           -> io::Result<u16> {
+        if self.is_empty() {
+            invalid_data!("Attempted to decode using empty LZX Huffman tree");
+        }
+        loop {
+            let bits = reader.peek_bits(16)?;
+            let mut entry = self.primary[(bits >> (16 - PRIMARY_BITS)) as
+                                             usize];
+            if (entry & LINK) != 0 {
+                let index_bits = entry & 0x1f;
+                let index = (bits >> (16 - PRIMARY_BITS as u32 - index_bits)) &
+                    ((1 << index_bits) - 1);
+                entry = self.secondary[(entry >> 8) as usize + index as usize];
+            }
+            let len = (entry & 0x1f) as u16;
+            if len <= reader.buffered_bits() {
+                reader.skip_bits(len)?;
+                return Ok((entry >> 8) as u16);
+            }
+            // Some of the peeked bits were zeros standing in for data past
+            // the end of the input (or of the chunk), so read as many real
+            // bits as this code would need, and look it up again.
+            reader.ensure_buffer_has_at_least(len)?;
+        }
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{HuffmanTable, build_lengths, canonical_codes,
+                max_secondary_len};
+    use internal::bits::{BitReader, BitWriter};
+    use internal::consts;
+
+    const MAX_LEN: usize = max_secondary_len(consts::MAX_MAIN_ELEMENTS);
+    type Table = HuffmanTable<MAX_LEN>;
+
+    fn kraft_total(lengths: &[u8]) -> u32 {
+        lengths
+            .iter()
+            .filter(|&&len| len > 0)
+            .map(|&len| 1 << (16 - len))
+            .sum()
+    }
+
+    #[test]
+    fn empty_lengths() {
+        assert_eq!(build_lengths(&[0, 0, 0, 0], 16), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn single_symbol_lengths() {
+        assert_eq!(build_lengths(&[0, 0, 5, 0], 16), vec![1, 0, 1, 0]);
+        assert_eq!(build_lengths(&[5, 0, 0, 0], 16), vec![1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn simple_lengths() {
+        assert_eq!(build_lengths(&[10, 1, 1, 2, 0], 16), vec![1, 3, 3, 2, 0]);
+    }
+
+    #[test]
+    fn limited_lengths() {
+        let freqs: Vec<u32> = (0..30).map(|n| 1 << n.min(29)).collect();
+        let lengths = build_lengths(&freqs, 7);
+        assert!(lengths.iter().all(|&len| (1..=7).contains(&len)));
+        assert_eq!(kraft_total(&lengths), 1 << 16);
+        let lengths = build_lengths(&freqs, 16);
+        assert!(lengths.iter().all(|&len| (1..=16).contains(&len)));
+        assert_eq!(kraft_total(&lengths), 1 << 16);
+    }
+
+    #[test]
+    fn canonical() {
+        assert_eq!(canonical_codes(&[2, 1, 3, 3, 0]),
+                   vec![0b10, 0b0, 0b110, 0b111, 0]);
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        let freqs = [7, 0, 3, 3, 1, 9, 2, 0, 1];
+        let lengths = build_lengths(&freqs, 16);
+        let codes = canonical_codes(&lengths);
+        let message = [0, 5, 2, 8, 3, 4, 6, 5, 0];
+        let mut output = Vec::<u8>::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            for &symbol in message.iter() {
+                writer
+                    .write_bits(lengths[symbol] as u16, codes[symbol] as u32)
+                    .unwrap();
+            }
+            writer.align_to_16().unwrap();
+        }
+        let table = Table::new(&lengths).unwrap();
+        let mut reader = BitReader::new(output.as_slice());
+        for &symbol in message.iter() {
+            assert_eq!(table.decode(&mut reader).unwrap() as usize, symbol);
+        }
+    }
+
+    #[test]
+    fn decode_long_codes() {
+        // Exponentially falling frequencies give codes of every length up to
+        // the maximum, most of them too long for the primary table.
+        let freqs: Vec<u32> = (0..20).map(|n| 1 << (19 - n)).collect();
+        let lengths = build_lengths(&freqs, 16);
+        assert_eq!(lengths.iter().max(), Some(&16));
+        let codes = canonical_codes(&lengths);
+        let message: Vec<usize> = (0..20).chain((0..20).rev()).collect();
+        let mut output = Vec::<u8>::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            for &symbol in message.iter() {
+                writer
+                    .write_bits(lengths[symbol] as u16, codes[symbol] as u32)
+                    .unwrap();
+            }
+            writer.align_to_16().unwrap();
+        }
+        let table = Table::new(&lengths).unwrap();
+        let mut reader = BitReader::new(output.as_slice());
+        for &symbol in message.iter() {
+            assert_eq!(table.decode(&mut reader).unwrap() as usize, symbol);
+        }
+        reader.align_to_16().unwrap();
+        assert!(reader.is_at_eof().unwrap());
+        assert!(table.decode(&mut reader).is_err());
+    }
+
+    #[test]
+    fn rebuild_table() {
+        // Codes of 11 to 16 bits need secondary tables of 2 to 64 entries.
+        let mut lengths = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14];
+        lengths.extend_from_slice(&[15, 16, 16]);
+        let codes = canonical_codes(&lengths);
+        let mut output = Vec::<u8>::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            for symbol in (0..lengths.len()).rev() {
+                writer
+                    .write_bits(lengths[symbol] as u16, codes[symbol] as u32)
+                    .unwrap();
+            }
+            writer.align_to_16().unwrap();
+        }
+        let mut table = Table::new(&[1, 1]).unwrap();
+        table.rebuild(&lengths).unwrap();
+        // Invalid lengths leave the table as it was.
+        assert!(table.rebuild(&[1, 1, 1]).is_err());
+        assert!(table.rebuild(&[17, 1]).is_err());
+        let mut reader = BitReader::new(output.as_slice());
+        for symbol in (0..lengths.len()).rev() {
+            assert_eq!(table.decode(&mut reader).unwrap() as usize, symbol);
+        }
+        table.rebuild(&[]).unwrap();
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn largest_secondary_tables() {
+        // Mostly long codes, so that most primary entries need secondary
+        // tables, and the last few of those have extra entries for codes
+        // shorter than the longest one.
+        let num_links = (consts::MAX_MAIN_ELEMENTS - 1024) / 6;
+        let mut lengths = vec![10u8; 1024 - num_links];
+        for _ in 0..num_links {
+            lengths.extend_from_slice(&[11, 12, 13, 14, 15, 16, 16]);
+        }
+        let codes = canonical_codes(&lengths);
+        let symbols: Vec<usize> =
+            (0..lengths.len()).rev().step_by(7).collect();
+        let mut output = Vec::<u8>::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            for &symbol in symbols.iter() {
+                writer
+                    .write_bits(lengths[symbol] as u16, codes[symbol] as u32)
+                    .unwrap();
+            }
+            writer.align_to_16().unwrap();
+        }
+        let mut table = Table::empty();
+        table.rebuild(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 13])
+            .unwrap();
+        table.rebuild(&lengths).unwrap();
+        assert!(table.secondary_len > 7 * num_links);
+        let mut reader = BitReader::new(output.as_slice());
+        for &symbol in symbols.iter() {
+            assert_eq!(table.decode(&mut reader).unwrap() as usize, symbol);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "CorruptHuffmanTable")]
+    fn oversubscribed_tree() { Table::new(&[1, 1, 1]).unwrap(); }
+
+    #[test]
+    fn too_many_symbols() {
+        let lengths = [0u8; consts::MAX_MAIN_ELEMENTS + 1];
+        assert!(Table::new(&lengths).is_err());
+        assert!(Table::new(&lengths[1..]).unwrap().is_empty());
+        // A smaller table has room for fewer symbols.
+        let mut table = HuffmanTable::<{ max_secondary_len(20) }>::empty();
+        assert!(table.rebuild(&lengths[..20]).is_ok());
+        assert!(table.rebuild(&lengths[..21]).is_err());
+    }
+}
+
+// ========================================================================= //
@@ -2,23 +2,44 @@
 
 macro_rules! invalid_data {
     ($e:expr) => {
-        return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData,
-                                         $e))
+        return Err(::internal::error::Error::InvalidData($e.to_string())
+                       .into())
     };
     ($fmt:expr, $($arg:tt)+) => {
-        return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData,
-                                         format!($fmt, $($arg)+)))
+        return Err(::internal::error::Error::InvalidData(format!($fmt,
+                                                                 $($arg)+))
+                       .into())
     };
 }
 
 macro_rules! invalid_input {
     ($e:expr) => {
-        return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput,
-                                         $e))
+        return Err(::internal::error::Error::InvalidInput($e.to_string())
+                       .into())
     };
     ($fmt:expr, $($arg:tt)+) => {
-        return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput,
-                                         format!($fmt, $($arg)+)))
+        return Err(::internal::error::Error::InvalidInput(format!($fmt,
+                                                                  $($arg)+))
+                       .into())
+    };
+}
+
+/// Logs a message at the given level via the `log` crate, if the `log`
+/// feature is enabled; otherwise does nothing.
+macro_rules! log_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        ::log::$level!($($arg)+);
+    };
+}
+
+/// Enters a `tracing` span (at debug level) with the given name and fields
+/// until the end of the enclosing block, if the `tracing` feature is enabled;
+/// otherwise does nothing.
+macro_rules! enter_span {
+    ($name:expr, $($fields:tt)+) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!($name, $($fields)+).entered();
     };
 }
 
@@ -0,0 +1,370 @@
+use internal::consts;
+use internal::token::Token;
+
+// ========================================================================= //
+
+const HASH_BITS: u32 = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const NIL: u32 = u32::MAX;
+
+const MIN_HASH_MATCH: usize = 3;
+
+// ========================================================================= //
+
+/// Tuning parameters for the encoder's match finder, trading speed for
+/// compression ratio.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MatchParams {
+    /// The maximum number of earlier positions to examine when searching for
+    /// a match.  Must be at least 1.
+    pub max_chain_len: usize,
+    /// Stop searching as soon as a match at least this long is found.  Must
+    /// be between 3 and 257 (inclusive).
+    pub nice_match_len: usize,
+    /// If a match shorter than this is found, check whether the next
+    /// position has a longer one before taking it (lazy matching).  Zero
+    /// disables lazy matching.
+    pub max_lazy: usize,
+}
+
+impl Default for MatchParams {
+    fn default() -> MatchParams {
+        MatchParams {
+            max_chain_len: 32,
+            nice_match_len: consts::MAX_MATCH,
+            max_lazy: 0,
+        }
+    }
+}
+
+impl MatchParams {
+    /// Returns true if these parameters are within the permitted ranges.
+    pub fn is_valid(&self) -> bool {
+        self.max_chain_len >= 1 &&
+            (MIN_HASH_MATCH..=consts::MAX_MATCH)
+                .contains(&self.nice_match_len)
+    }
+}
+
+// ========================================================================= //
+
+/// A hash-chain match finder over a sliding window of uncompressed data.
+pub struct MatchFinder {
+    params: MatchParams,
+    max_match: usize,
+    max_offset: usize,
+    buffer: Vec<u8>,
+    position: usize,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+impl MatchFinder {
+    pub fn new(window: u16) -> MatchFinder {
+        MatchFinder {
+            params: MatchParams::default(),
+            max_match: consts::MAX_MATCH,
+            max_offset: (1 << window) - 3,
+            buffer: Vec::new(),
+            position: 0,
+            head: vec![NIL; HASH_SIZE],
+            prev: Vec::new(),
+        }
+    }
+
+    pub fn params(&self) -> MatchParams { self.params }
+
+    pub fn set_params(&mut self, params: MatchParams) {
+        debug_assert!(params.is_valid());
+        self.params = params;
+    }
+
+    /// Sets the maximum length of the matches to find (`MAX_MATCH` by
+    /// default).
+    pub fn set_max_match(&mut self, max_match: usize) {
+        debug_assert!(max_match >= consts::MAX_MATCH);
+        self.max_match = max_match;
+    }
+
+    /// Discards all data, so that the match finder can be reused for a new
+    /// stream.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.prev.clear();
+        self.position = 0;
+        for entry in self.head.iter_mut() {
+            *entry = NIL;
+        }
+    }
+
+    /// Returns the bytes that have been processed so far (or at least, as
+    /// many of them as could still be referenced by a match).
+    pub fn history(&self) -> &[u8] { &self.buffer[..self.position] }
+
+    /// Adds bytes to the end of the buffer, to be processed later with
+    /// `skip` or `find_tokens`.
+    pub fn append(&mut self, bytes: &[u8]) {
+        if self.position > 2 * self.max_offset {
+            self.slide();
+        }
+        self.buffer.extend_from_slice(bytes);
+        self.prev.resize(self.buffer.len(), NIL);
+    }
+
+    /// Processes the next `num_bytes` appended bytes without looking for
+    /// matches, making them available for future matches to refer to.
+    pub fn skip(&mut self, num_bytes: usize) {
+        let end = self.position + num_bytes;
+        debug_assert!(end <= self.buffer.len());
+        while self.position < end {
+            let position = self.position;
+            self.insert(position);
+            self.position += 1;
+        }
+    }
+
+    /// Processes the next `num_bytes` appended bytes, appending tokens that
+    /// represent them to `tokens`.  Matches never extend past the end of the
+    /// processed bytes.
+    pub fn find_tokens(&mut self, num_bytes: usize, tokens: &mut Vec<Token>) {
+        let end = self.position + num_bytes;
+        debug_assert!(end <= self.buffer.len());
+        let mut next_match = None;
+        while self.position < end {
+            let (offset, length) = match next_match.take() {
+                Some(found) => found,
+                None => self.longest_match(end),
+            };
+            if length < MIN_HASH_MATCH {
+                tokens.push(Token::Literal(self.buffer[self.position]));
+                self.skip(1);
+                continue;
+            }
+            let mut remaining = length;
+            if length < self.params.max_lazy && self.position + 1 < end {
+                let literal = self.buffer[self.position];
+                self.skip(1);
+                remaining -= 1;
+                let lookahead = self.longest_match(end);
+                if lookahead.1 > length {
+                    tokens.push(Token::Literal(literal));
+                    next_match = Some(lookahead);
+                    continue;
+                }
+            }
+            tokens.push(Token::Match {
+                            offset: offset as u32,
+                            length: length as u16,
+                        });
+            self.skip(remaining);
+        }
+    }
+
+    /// Processes the next `num_bytes` appended bytes, appending the longest
+    /// match found at each position to `matches`, as an (offset, length) pair
+    /// (or (0, 0) if there is no match at that position).  Matches never
+    /// extend past the end of the processed bytes.
+    pub fn find_matches(&mut self, num_bytes: usize,
+                        matches: &mut Vec<(u32, u16)>) {
+        let end = self.position + num_bytes;
+        debug_assert!(end <= self.buffer.len());
+        let mut previous = (0, 0);
+        while self.position < end {
+            // Inside a match longer than `MAX_MATCH`, the rest of the match
+            // is as long as any other, and searching for one would make long
+            // runs take quadratic time.
+            let (offset, length) = if previous.1 > consts::MAX_MATCH {
+                (previous.0, previous.1 - 1)
+            } else {
+                self.longest_match(end)
+            };
+            previous = (offset, length);
+            if length >= MIN_HASH_MATCH {
+                matches.push((offset as u32, length as u16));
+            } else {
+                matches.push((0, 0));
+            }
+            self.skip(1);
+        }
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let bytes = &self.buffer[position..(position + MIN_HASH_MATCH)];
+        let value = ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) |
+            (bytes[2] as u32);
+        (value.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_HASH_MATCH <= self.buffer.len() {
+            let hash = self.hash(position);
+            self.prev[position] = self.head[hash];
+            self.head[hash] = position as u32;
+        }
+    }
+
+    fn longest_match(&self, end: usize) -> (usize, usize) {
+        let position = self.position;
+        let max_length = (end - position).min(self.max_match);
+        if max_length < MIN_HASH_MATCH {
+            return (0, 0);
+        }
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(position)];
+        let mut chain = 0;
+        while candidate != NIL && chain < self.params.max_chain_len {
+            let candidate_position = candidate as usize;
+            let offset = position - candidate_position;
+            if offset > self.max_offset {
+                break;
+            }
+            let length = self.buffer[candidate_position..]
+                .iter()
+                .zip(&self.buffer[position..(position + max_length)])
+                .take_while(|&(a, b)| a == b)
+                .count();
+            if length > best.1 {
+                best = (offset, length);
+                if length == max_length ||
+                    length >= self.params.nice_match_len
+                {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate_position];
+            chain += 1;
+        }
+        best
+    }
+
+    /// Discards old data that is too far back to be matched against.
+    fn slide(&mut self) {
+        let shift = self.position - self.max_offset;
+        self.buffer.drain(..shift);
+        self.prev.drain(..shift);
+        self.position -= shift;
+        let shift = shift as u32;
+        let rebase = |entry: &mut u32| {
+            *entry = if *entry == NIL || *entry < shift {
+                NIL
+            } else {
+                *entry - shift
+            };
+        };
+        self.head.iter_mut().for_each(rebase);
+        self.prev.iter_mut().for_each(rebase);
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchFinder, MatchParams};
+    use internal::token::Token;
+
+    fn expand(tokens: &[Token]) -> Vec<u8> {
+        let mut output = Vec::new();
+        for &token in tokens {
+            match token {
+                Token::Literal(byte) => output.push(byte),
+                Token::Match { offset, length } => {
+                    for _ in 0..length {
+                        let byte = output[output.len() - offset as usize];
+                        output.push(byte);
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn finds_repeated_strings() {
+        let data = b"abcdefg abcdefg abcdefg!";
+        let mut matcher = MatchFinder::new(15);
+        matcher.append(data);
+        let mut tokens = Vec::new();
+        matcher.find_tokens(data.len(), &mut tokens);
+        assert_eq!(tokens.len(), 10);
+        assert_eq!(tokens[8],
+                   Token::Match {
+                       offset: 8,
+                       length: 15,
+                   });
+        assert_eq!(expand(&tokens), data.to_vec());
+    }
+
+    #[test]
+    fn matches_against_history() {
+        let mut matcher = MatchFinder::new(15);
+        matcher.append(b"hello world");
+        matcher.skip(11);
+        assert_eq!(matcher.history(), b"hello world");
+        matcher.append(b"world");
+        let mut tokens = Vec::new();
+        matcher.find_tokens(5, &mut tokens);
+        assert_eq!(tokens,
+                   vec![Token::Match {
+                            offset: 5,
+                            length: 5,
+                        }]);
+    }
+
+    #[test]
+    fn lazy_matching_defers_to_longer_match() {
+        let data = b"abcXbcdefgYabcdefg";
+        let mut matcher = MatchFinder::new(15);
+        matcher.set_params(MatchParams {
+                               max_lazy: 8,
+                               ..MatchParams::default()
+                           });
+        matcher.append(data);
+        let mut tokens = Vec::new();
+        matcher.find_tokens(data.len(), &mut tokens);
+        assert_eq!(expand(&tokens), data.to_vec());
+        // Rather than matching "abc" and then "defg", emit a literal "a" and
+        // then match "bcdefg".
+        assert_eq!(&tokens[11..],
+                   &[Token::Literal(b'a'),
+                     Token::Match {
+                         offset: 8,
+                         length: 6,
+                     }]);
+    }
+
+    #[test]
+    fn finds_match_at_every_position() {
+        let mut matcher = MatchFinder::new(15);
+        matcher.append(b"abcdabcdab");
+        let mut matches = Vec::new();
+        matcher.find_matches(10, &mut matches);
+        assert_eq!(matches,
+                   vec![(0, 0), (0, 0), (0, 0), (0, 0), (4, 6), (4, 5),
+                        (4, 4), (4, 3), (0, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn slides_window() {
+        let mut matcher = MatchFinder::new(15);
+        let mut data = Vec::new();
+        let mut tokens = Vec::new();
+        for index in 0..10 {
+            let chunk: Vec<u8> = (0..0x8000)
+                .map(|n: u32| ((n * (index + 1)) >> 3) as u8)
+                .collect();
+            matcher.append(&chunk);
+            matcher.find_tokens(chunk.len(), &mut tokens);
+            data.extend_from_slice(&chunk);
+        }
+        assert!(matcher.history().len() < 3 * 0x8000);
+        assert_eq!(expand(&tokens), data);
+        for &token in tokens.iter() {
+            if let Token::Match { offset, .. } = token {
+                assert!(offset <= 0x8000 - 3);
+            }
+        }
+    }
+}
+
+// ========================================================================= //
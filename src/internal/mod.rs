@@ -1,8 +1,42 @@
 #[macro_use]
 mod macros;
 
+#[cfg(any(feature = "futures", feature = "tokio"))]
+pub mod asyncio;
+pub mod background;
+#[cfg(feature = "bitio")]
+pub mod bitio;
 pub mod bits;
+pub mod builder;
 pub mod btype;
+pub mod cab;
+pub mod chm;
+pub mod chunk;
+pub mod codec;
 pub mod consts;
 pub mod decoder;
+pub mod e8;
+pub mod error;
 pub mod encoder;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fixed;
+pub mod huffman;
+pub mod matcher;
+pub mod oab;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+pub mod parse;
+pub mod patch;
+pub mod pretree;
+pub mod reader;
+pub mod sink;
+pub mod stats;
+#[cfg(test)]
+pub mod testutil;
+pub mod token;
+pub mod transcode;
+pub mod wim;
+pub mod window;
+pub mod xmem;
+pub mod xnb;
@@ -0,0 +1,438 @@
+//! Support for the LZX-compressed files of an Exchange Offline Address Book
+//! (OAB) version 4.
+//!
+//! A full OAB file starts with a header giving the maximum block size and the
+//! total size of the decompressed file, followed by a series of blocks, each
+//! with its own header and CRC, and each either stored or compressed as a
+//! separate LZX DELTA stream.  A differential (patch) file has a similar
+//! layout, but each block is an LZX DELTA stream that refers back to the next
+//! part of the previous version of the file.
+
+use byteorder::{ByteOrder, LittleEndian};
+use internal::consts;
+use internal::decoder::Decoder;
+use internal::error;
+use std::io::{self, Read};
+
+// ========================================================================= //
+
+const VERSION_HI: u32 = 3;
+const VERSION_LO_FULL: u32 = 1;
+const VERSION_LO_PATCH: u32 = 2;
+
+const HEADER_SIZE: usize = 16;
+const PATCH_HEADER_SIZE: usize = 28;
+const BLOCK_HEADER_SIZE: usize = 16;
+
+const FLAG_STORED: u32 = 0;
+const FLAG_COMPRESSED: u32 = 1;
+
+/// The smallest window that Exchange uses for an OAB block.
+const MIN_WINDOW: u16 = 17;
+
+/// Computes the CRC used in OAB files over `data`, continuing from `seed`
+/// (which should be `0xffffffff` to start a new CRC).  This is the standard
+/// CRC-32, but without the final inversion.
+pub fn checksum(data: &[u8], seed: u32) -> u32 {
+    data.iter().fold(seed, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// Returns the window size exponent for a block that needs to hold `len`
+/// bytes of reference and output data.
+fn block_window(len: u64) -> io::Result<u16> {
+    match (MIN_WINDOW..=consts::WINDOW_MAX_DELTA)
+        .find(|&window| len <= 1 << window) {
+        Some(window) => Ok(window),
+        None => {
+            invalid_data!("OAB block is too large ({} bytes)", len);
+        }
+    }
+}
+
+fn read_block_data<R: Read>(reader: &mut R, len: u32) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(len as u64).read_to_end(&mut data)?;
+    if data.len() < len as usize {
+        return Err(error::Error::Truncated.into());
+    }
+    Ok(data)
+}
+
+fn check_version(header: &[u8], version_lo: u32) -> io::Result<()> {
+    let hi = LittleEndian::read_u32(&header[0..4]);
+    let lo = LittleEndian::read_u32(&header[4..8]);
+    if hi != VERSION_HI || lo != version_lo {
+        invalid_data!("Unsupported OAB file version ({}.{})", hi, lo);
+    }
+    Ok(())
+}
+
+fn check_block_crc(block: &[u8], expected: u32) -> io::Result<()> {
+    let actual = checksum(block, 0xffffffff);
+    if actual != expected {
+        invalid_data!("OAB block CRC mismatch ({:08x} != {:08x})",
+                      actual,
+                      expected);
+    }
+    Ok(())
+}
+
+// ========================================================================= //
+
+/// The header of a full OAB file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct OabHeader {
+    /// The largest number of uncompressed bytes in any block.
+    pub block_max: u32,
+    /// The total size of the decompressed file.
+    pub target_size: u32,
+}
+
+impl OabHeader {
+    /// Reads an OAB header from the start of a full OAB file.
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<OabHeader> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header).map_err(error::eof_as_truncated)?;
+        check_version(&header, VERSION_LO_FULL)?;
+        Ok(OabHeader {
+               block_max: LittleEndian::read_u32(&header[8..12]),
+               target_size: LittleEndian::read_u32(&header[12..16]),
+           })
+    }
+}
+
+/// Decompresses a full OAB file.
+pub struct OabDecoder<R: Read> {
+    reader: R,
+    header: OabHeader,
+    block: Vec<u8>,
+    position: usize,
+    bytes_remaining: u32,
+}
+
+impl<R: Read> OabDecoder<R> {
+    /// Reads the header of a full OAB file from `reader`, and starts
+    /// decompressing its blocks.
+    pub fn new(mut reader: R) -> io::Result<OabDecoder<R>> {
+        let header = OabHeader::read(&mut reader)?;
+        Ok(OabDecoder {
+               reader,
+               header,
+               block: Vec::new(),
+               position: 0,
+               bytes_remaining: header.target_size,
+           })
+    }
+
+    /// Returns the file's header.
+    pub fn header(&self) -> &OabHeader { &self.header }
+
+    /// Unwraps this object, returning the underlying reader.
+    pub fn into_inner(self) -> R { self.reader }
+
+    fn read_block(&mut self) -> io::Result<()> {
+        let mut header = [0u8; BLOCK_HEADER_SIZE];
+        self.reader
+            .read_exact(&mut header)
+            .map_err(error::eof_as_truncated)?;
+        let flags = LittleEndian::read_u32(&header[0..4]);
+        let compressed_size = LittleEndian::read_u32(&header[4..8]);
+        let uncompressed_size = LittleEndian::read_u32(&header[8..12]);
+        let crc = LittleEndian::read_u32(&header[12..16]);
+        if uncompressed_size > self.header.block_max ||
+            uncompressed_size > self.bytes_remaining
+        {
+            invalid_data!("OAB block is too large ({} bytes)",
+                          uncompressed_size);
+        }
+        let data = read_block_data(&mut self.reader, compressed_size)?;
+        match flags {
+            FLAG_STORED => {
+                if compressed_size != uncompressed_size {
+                    invalid_data!("Stored OAB block has mismatched sizes \
+                                   ({} != {})",
+                                  compressed_size,
+                                  uncompressed_size);
+                }
+                self.block = data;
+            }
+            FLAG_COMPRESSED => {
+                let window = block_window(uncompressed_size as u64)?;
+                let mut decoder = Decoder::new_delta(data.as_slice(),
+                                                     window,
+                                                     uncompressed_size as
+                                                         u64)?;
+                self.block.resize(uncompressed_size as usize, 0);
+                decoder.decompress_into(&mut self.block)?;
+            }
+            _ => {
+                invalid_data!("Invalid OAB block flags ({})", flags);
+            }
+        }
+        check_block_crc(&self.block, crc)?;
+        self.position = 0;
+        self.bytes_remaining -= uncompressed_size;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for OabDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            if self.bytes_remaining == 0 || buf.is_empty() {
+                return Ok(0);
+            }
+            self.read_block()?;
+        }
+        let num_bytes = buf.len().min(self.block.len() - self.position);
+        let end = self.position + num_bytes;
+        buf[..num_bytes].copy_from_slice(&self.block[self.position..end]);
+        self.position = end;
+        Ok(num_bytes)
+    }
+}
+
+// ========================================================================= //
+
+/// The header of a differential (patch) OAB file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct OabPatchHeader {
+    /// The largest number of target bytes in any block.
+    pub block_max: u32,
+    /// The size of the previous version of the file.
+    pub source_size: u32,
+    /// The size of the new version of the file.
+    pub target_size: u32,
+    /// The CRC of the previous version of the file (see `checksum`).
+    pub source_crc: u32,
+    /// The CRC of the new version of the file (see `checksum`).
+    pub target_crc: u32,
+}
+
+impl OabPatchHeader {
+    /// Reads an OAB patch header from the start of a differential OAB file.
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<OabPatchHeader> {
+        let mut header = [0u8; PATCH_HEADER_SIZE];
+        reader.read_exact(&mut header).map_err(error::eof_as_truncated)?;
+        check_version(&header, VERSION_LO_PATCH)?;
+        Ok(OabPatchHeader {
+               block_max: LittleEndian::read_u32(&header[8..12]),
+               source_size: LittleEndian::read_u32(&header[12..16]),
+               target_size: LittleEndian::read_u32(&header[16..20]),
+               source_crc: LittleEndian::read_u32(&header[20..24]),
+               target_crc: LittleEndian::read_u32(&header[24..28]),
+           })
+    }
+}
+
+/// Applies a differential OAB file to the previous version of a full OAB
+/// file (already decompressed), producing the new version.
+pub struct OabPatchDecoder<B: Read, R: Read> {
+    base: B,
+    reader: R,
+    header: OabPatchHeader,
+    block: Vec<u8>,
+    position: usize,
+    bytes_remaining: u32,
+    target_crc: u32,
+}
+
+impl<B: Read, R: Read> OabPatchDecoder<B, R> {
+    /// Reads the header of a differential OAB file from `reader`, and starts
+    /// applying it to the previous version of the file, which is read from
+    /// `base`.
+    pub fn new(base: B, mut reader: R) -> io::Result<OabPatchDecoder<B, R>> {
+        let header = OabPatchHeader::read(&mut reader)?;
+        Ok(OabPatchDecoder {
+               base,
+               reader,
+               header,
+               block: Vec::new(),
+               position: 0,
+               bytes_remaining: header.target_size,
+               target_crc: 0xffffffff,
+           })
+    }
+
+    /// Returns the patch file's header.
+    pub fn header(&self) -> &OabPatchHeader { &self.header }
+
+    /// Unwraps this object, returning the base and patch readers.
+    pub fn into_inner(self) -> (B, R) { (self.base, self.reader) }
+
+    fn read_block(&mut self) -> io::Result<()> {
+        let mut header = [0u8; BLOCK_HEADER_SIZE];
+        self.reader
+            .read_exact(&mut header)
+            .map_err(error::eof_as_truncated)?;
+        let patch_size = LittleEndian::read_u32(&header[0..4]);
+        let target_size = LittleEndian::read_u32(&header[4..8]);
+        let source_size = LittleEndian::read_u32(&header[8..12]);
+        let crc = LittleEndian::read_u32(&header[12..16]);
+        if target_size > self.header.block_max ||
+            target_size > self.bytes_remaining
+        {
+            invalid_data!("OAB block is too large ({} bytes)", target_size);
+        }
+        let window = block_window(source_size as u64 + target_size as u64)?;
+        let source = read_block_data(&mut self.base, source_size)?;
+        let patch = read_block_data(&mut self.reader, patch_size)?;
+        let mut decoder =
+            Decoder::new_delta(patch.as_slice(), window, target_size as u64)?;
+        decoder.set_dictionary(&source)?;
+        self.block.resize(target_size as usize, 0);
+        decoder.decompress_into(&mut self.block)?;
+        check_block_crc(&self.block, crc)?;
+        self.position = 0;
+        self.bytes_remaining -= target_size;
+        self.target_crc = checksum(&self.block, self.target_crc);
+        if self.bytes_remaining == 0 &&
+            self.target_crc != self.header.target_crc
+        {
+            invalid_data!("OAB target CRC mismatch ({:08x} != {:08x})",
+                          self.target_crc,
+                          self.header.target_crc);
+        }
+        Ok(())
+    }
+}
+
+impl<B: Read, R: Read> Read for OabPatchDecoder<B, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            if self.bytes_remaining == 0 || buf.is_empty() {
+                return Ok(0);
+            }
+            self.read_block()?;
+        }
+        let num_bytes = buf.len().min(self.block.len() - self.position);
+        let end = self.position + num_bytes;
+        buf[..num_bytes].copy_from_slice(&self.block[self.position..end]);
+        self.position = end;
+        Ok(num_bytes)
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{OabDecoder, OabPatchDecoder, checksum};
+    use internal::encoder::Encoder;
+    use std::io::{self, Read, Write};
+
+    fn push_u32s(file: &mut Vec<u8>, values: &[u32]) {
+        for &value in values {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn compress_block(reference: &[u8], block: &[u8], window: u16)
+                      -> Vec<u8> {
+        let mut encoder =
+            Encoder::new_delta(Vec::new(), window, block.len() as u64)
+                .unwrap();
+        encoder.set_dictionary(reference).unwrap();
+        encoder.write_all(block).unwrap();
+        encoder.into_inner().unwrap()
+    }
+
+    fn crc(data: &[u8]) -> u32 { checksum(data, 0xffffffff) }
+
+    fn sample_data(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|n| if n % 50 < 8 { (n / 50) as u8 } else { b"oab"[n % 3] })
+            .collect()
+    }
+
+    #[test]
+    fn checksum_values() {
+        assert_eq!(checksum(b"", 0xffffffff), 0xffffffff);
+        // The standard CRC-32 of "123456789" is 0xcbf43926, before its final
+        // inversion.
+        assert_eq!(checksum(b"123456789", 0xffffffff), !0xcbf43926);
+        assert_eq!(checksum(b"56789", checksum(b"1234", 0xffffffff)),
+                   !0xcbf43926);
+    }
+
+    #[test]
+    fn read_full_file() {
+        let data = sample_data(100_000);
+        let mut file = Vec::new();
+        push_u32s(&mut file, &[3, 1, 0x10000, data.len() as u32]);
+        let blocks: Vec<&[u8]> = data.chunks(0x10000).collect();
+        let compressed = compress_block(&[], blocks[0], 17);
+        push_u32s(&mut file,
+                  &[1, compressed.len() as u32, 0x10000, crc(blocks[0])]);
+        file.extend_from_slice(&compressed);
+        let stored = blocks[1];
+        push_u32s(&mut file,
+                  &[0, stored.len() as u32, stored.len() as u32, crc(stored)]);
+        file.extend_from_slice(stored);
+        let mut decoder = OabDecoder::new(file.as_slice()).unwrap();
+        assert_eq!(decoder.header().block_max, 0x10000);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+        // A corrupted block fails its CRC check.
+        let last = file.len() - 1;
+        file[last] ^= 1;
+        let mut decoder = OabDecoder::new(file.as_slice()).unwrap();
+        let error = decoder.read_to_end(&mut output).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // So does a truncated file.
+        let mut decoder = OabDecoder::new(&file[..(last - 10)]).unwrap();
+        assert!(decoder.read_to_end(&mut output).is_err());
+        // Only version 3.1 files are full files.
+        file[4] = 2;
+        assert!(OabDecoder::new(file.as_slice()).is_err());
+    }
+
+    #[test]
+    fn apply_patch_file() {
+        let source = sample_data(90_000);
+        let mut target = source[..40_000].to_vec();
+        target.extend_from_slice(b"A new address book entry.");
+        target.extend_from_slice(&source[40_000..]);
+        let mut file = Vec::new();
+        push_u32s(&mut file,
+                  &[3,
+                    2,
+                    0x10000,
+                    source.len() as u32,
+                    target.len() as u32,
+                    crc(&source),
+                    crc(&target)]);
+        let mut source_offset = 0;
+        for block in target.chunks(0x10000) {
+            let source_end = (source_offset + 0x10000).min(source.len());
+            let reference = &source[source_offset..source_end];
+            let patch = compress_block(reference, block, 17);
+            push_u32s(&mut file,
+                      &[patch.len() as u32,
+                        block.len() as u32,
+                        reference.len() as u32,
+                        crc(block)]);
+            file.extend_from_slice(&patch);
+            source_offset = source_end;
+        }
+        let mut decoder =
+            OabPatchDecoder::new(source.as_slice(), file.as_slice()).unwrap();
+        assert_eq!(decoder.header().source_crc, crc(&source));
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == target);
+        // The wrong base file produces an error.
+        let wrong_source = vec![0u8; source.len()];
+        let mut decoder =
+            OabPatchDecoder::new(wrong_source.as_slice(), file.as_slice())
+                .unwrap();
+        assert!(decoder.read_to_end(&mut output).is_err());
+    }
+}
+
+// ========================================================================= //
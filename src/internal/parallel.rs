@@ -0,0 +1,177 @@
+//! Compressing and decompressing LZXD streams on multiple threads, for
+//! streams with a reset interval (see `Encoder::set_reset_interval`).  The
+//! data between one reset point and the next is encoded without reference to
+//! any other part of the stream, so each such segment can be compressed or
+//! decompressed separately.
+
+use internal::builder::{DecoderBuilder, EncoderBuilder};
+use rayon::prelude::*;
+use std::convert::TryFrom;
+use std::io::{self, Cursor, Read, Write};
+use std::mem;
+
+// ========================================================================= //
+
+/// Compresses `data` with the settings of `builder` (except that the
+/// uncompressed size is always `data.len()`), compressing the segments
+/// between reset points in parallel, and writes the compressed stream to
+/// `writer`.  The settings must include a reset interval.
+///
+/// Returns the stream's reset table (as given by `Encoder::reset_table()`).
+/// Both the stream and the table are exactly the same as a single `Encoder`
+/// would produce.
+pub fn compress_parallel<W: Write>(data: &[u8], builder: &EncoderBuilder,
+                                   mut writer: W)
+                                   -> io::Result<Vec<(u64, u64)>> {
+    let builder = builder.clone().uncompressed_size(data.len() as u64);
+    let segment_len = builder.build(io::sink())?.reset_segment_len();
+    if segment_len == 0 {
+        invalid_input!("Parallel LZX compression requires a reset interval");
+    }
+    let segments = data.par_chunks(segment_len as usize)
+        .enumerate()
+        .map(|(index, segment)| {
+            let mut encoder = builder.build(Vec::new())?;
+            encoder.start_segment(index as u64 * segment_len,
+                                  segment.len() as u64);
+            encoder.write_all(segment)?;
+            encoder.into_inner()
+        })
+        .collect::<io::Result<Vec<Vec<u8>>>>()?;
+    let mut reset_table = Vec::with_capacity(segments.len());
+    let mut compressed_offset = 0;
+    for (index, segment) in segments.iter().enumerate() {
+        reset_table.push((index as u64 * segment_len, compressed_offset));
+        writer.write_all(segment)?;
+        compressed_offset += segment.len() as u64;
+    }
+    Ok(reset_table)
+}
+
+/// Decompresses `stream` with the settings of `builder`, which must include
+/// the stream's reset interval and reset table, decoding the segments
+/// between reset points in parallel.  Returns the decompressed data.
+pub fn decompress_parallel(stream: &[u8], builder: &DecoderBuilder)
+                           -> io::Result<Vec<u8>> {
+    // Report invalid settings before starting any workers.
+    builder.build(Cursor::new(stream))?;
+    let total = builder.total_uncompressed_size();
+    let table = builder.reset_points();
+    if table.is_empty() && total > 0 {
+        invalid_input!("Parallel LZX decompression requires a reset table");
+    }
+    let mut output = match usize::try_from(total) {
+        Ok(len) => vec![0u8; len],
+        Err(_) => {
+            invalid_input!("LZX stream is too large to decompress into \
+                            memory ({} bytes)",
+                           total);
+        }
+    };
+    let mut segments = Vec::with_capacity(table.len() + 1);
+    let mut rest = output.as_mut_slice();
+    let mut start = 0;
+    for offset in table.iter().map(|&(offset, _)| offset).chain(Some(total)) {
+        let offset = offset.min(total);
+        if offset > start {
+            let (segment, tail) =
+                mem::take(&mut rest).split_at_mut((offset - start) as usize);
+            segments.push((start, segment));
+            rest = tail;
+            start = offset;
+        }
+    }
+    // Each worker thread keeps one decoder, and seeks it to the reset point
+    // at the start of each segment it decodes.
+    segments.into_par_iter().try_for_each_init(|| None, |slot, segment| {
+        let (start, output) = segment;
+        if slot.is_none() {
+            *slot = Some(builder.build(Cursor::new(stream))?);
+        }
+        if let Some(ref mut decoder) = *slot {
+            decoder.seek_to(start)?;
+            decoder.read_exact(output)?;
+        }
+        Ok::<(), io::Error>(())
+    })?;
+    Ok(output)
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{compress_parallel, decompress_parallel};
+    use internal::builder::{DecoderBuilder, EncoderBuilder};
+    use std::io::{self, Write};
+
+    fn compress_serial(data: &[u8], builder: &EncoderBuilder)
+                       -> (Vec<u8>, Vec<(u64, u64)>) {
+        let mut encoder = builder.clone()
+            .uncompressed_size(data.len() as u64)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write_all(data).unwrap();
+        let reset_table = encoder.reset_table().to_vec();
+        (encoder.into_inner().unwrap(), reset_table)
+    }
+
+    #[test]
+    fn same_output_as_serial_encoder() {
+        let data: Vec<u8> = (0..300_000u32)
+            .map(|n| if n % 13 == 0 { 0xe8 } else { (n / 5 + n / 700) as u8 })
+            .collect();
+        let builders = [EncoderBuilder::new(16).reset_interval(1),
+                        EncoderBuilder::new(17)
+                            .reset_interval(2)
+                            .e8_translation(true)
+                            .dictionary(&data[..1000]),
+                        EncoderBuilder::new(15)
+                            .reset_interval(3)
+                            .chunk_size(8192)
+                            .raw_chunks(true)];
+        for builder in builders.iter() {
+            for &len in &[0, 5000, 65536, data.len()] {
+                let (stream, reset_table) =
+                    compress_serial(&data[..len], builder);
+                let mut output = Vec::new();
+                let table =
+                    compress_parallel(&data[..len], builder, &mut output)
+                        .unwrap();
+                assert_eq!(table, reset_table);
+                assert!(output == stream);
+            }
+        }
+    }
+
+    #[test]
+    fn decompress_segments_in_parallel() {
+        let data: Vec<u8> = (0..300_000u32)
+            .map(|n| if n % 13 == 0 { 0xe8 } else { (n / 5 + n / 700) as u8 })
+            .collect();
+        let encoder =
+            EncoderBuilder::new(16).reset_interval(2).e8_translation(true);
+        let mut stream = Vec::new();
+        let table = compress_parallel(&data, &encoder, &mut stream).unwrap();
+        let decoder =
+            DecoderBuilder::new(16, data.len() as u64).reset_interval(2);
+        let error = decompress_parallel(&stream, &decoder).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let decoder = decoder.reset_table(&table);
+        assert!(decompress_parallel(&stream, &decoder).unwrap() == data);
+        let truncated = &stream[..(stream.len() / 2)];
+        assert!(decompress_parallel(truncated, &decoder).is_err());
+        let empty = DecoderBuilder::new(16, 0).reset_interval(2);
+        assert!(decompress_parallel(&[], &empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn requires_reset_interval() {
+        let builder = EncoderBuilder::new(15);
+        let error =
+            compress_parallel(b"foobar", &builder, io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+// ========================================================================= //
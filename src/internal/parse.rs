@@ -0,0 +1,186 @@
+use internal::consts;
+use internal::token::{self, Token};
+
+// ========================================================================= //
+
+/// The cost, in bits, assumed for symbols that were absent from the first
+/// pass (and so have no code yet).
+const UNUSED_SYMBOL_COST: u32 = consts::MAX_CODE_LENGTH as u32;
+
+// ========================================================================= //
+
+/// Estimates of how many bits each kind of token will take to encode, based
+/// on Huffman code lengths from a previous pass.
+pub struct CostModel {
+    main_costs: Vec<u32>,
+    length_costs: Vec<u32>,
+    delta: bool,
+}
+
+impl CostModel {
+    pub fn new(main_lengths: &[u8], length_lengths: &[u8], delta: bool)
+               -> CostModel {
+        CostModel {
+            main_costs: main_lengths.iter().map(|&len| cost(len)).collect(),
+            length_costs: length_lengths
+                .iter()
+                .map(|&len| cost(len))
+                .collect(),
+            delta,
+        }
+    }
+
+    fn literal_cost(&self, byte: u8) -> u32 { self.main_costs[byte as usize] }
+
+    fn match_cost(&self, slot: usize, extra_bits: u32, length: usize)
+                  -> u32 {
+        let length_header = length.min(consts::MAX_MATCH) - consts::MIN_MATCH;
+        let main_element = consts::NUM_CHARS + (slot << 3) +
+            length_header.min(consts::NUM_PRIMARY_LENGTHS);
+        let mut cost = self.main_costs[main_element] + extra_bits;
+        if length_header >= consts::NUM_PRIMARY_LENGTHS {
+            cost += self.length_costs[length_header -
+                                          consts::NUM_PRIMARY_LENGTHS];
+        }
+        if self.delta && length >= consts::MAX_MATCH {
+            cost += token::length_extension(length).0 as u32;
+        }
+        cost
+    }
+}
+
+fn cost(len: u8) -> u32 {
+    if len == 0 {
+        UNUSED_SYMBOL_COST
+    } else {
+        len as u32
+    }
+}
+
+// ========================================================================= //
+
+/// Converts bytes into tokens by always taking the longest available match.
+/// `matches` gives the longest match at each position of `bytes`, as
+/// returned by `MatchFinder::find_matches`.
+pub fn greedy(bytes: &[u8], matches: &[(u32, u16)], tokens: &mut Vec<Token>) {
+    debug_assert_eq!(bytes.len(), matches.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        let (offset, length) = matches[position];
+        if length > 0 {
+            tokens.push(Token::Match { offset, length });
+            position += length as usize;
+        } else {
+            tokens.push(Token::Literal(bytes[position]));
+            position += 1;
+        }
+    }
+}
+
+/// Converts bytes into the sequence of tokens that is cheapest under the
+/// given cost model, considering a literal or any prefix of the longest
+/// match at each position (though of matches longer than `MAX_MATCH`, only
+/// the longest is considered).
+pub fn optimal(bytes: &[u8], matches: &[(u32, u16)], costs: &CostModel,
+               tokens: &mut Vec<Token>) {
+    debug_assert_eq!(bytes.len(), matches.len());
+    // Work backwards, finding the cheapest way to encode each suffix of the
+    // input, and how long the first token of that encoding is.
+    let mut suffix_costs = vec![0u32; bytes.len() + 1];
+    let mut choices = vec![1u16; bytes.len()];
+    for position in (0..bytes.len()).rev() {
+        let mut best = suffix_costs[position + 1] +
+            costs.literal_cost(bytes[position]);
+        let mut choice = 1;
+        let (offset, max_length) = matches[position];
+        if max_length > 0 {
+            let formatted_offset = offset + 2;
+            let slot = token::position_slot(formatted_offset);
+            let extra_bits = consts::EXTRA_BITS[slot] as u32;
+            let max_length = max_length as usize;
+            let short_lengths =
+                consts::MIN_MATCH..(max_length.min(consts::MAX_MATCH) + 1);
+            let long_length = Some(max_length)
+                .filter(|&length| length > consts::MAX_MATCH);
+            for length in short_lengths.chain(long_length) {
+                let cost = suffix_costs[position + length] +
+                    costs.match_cost(slot, extra_bits, length);
+                if cost < best {
+                    best = cost;
+                    choice = length as u16;
+                }
+            }
+        }
+        suffix_costs[position] = best;
+        choices[position] = choice;
+    }
+    let mut position = 0;
+    while position < bytes.len() {
+        let length = choices[position];
+        if length > 1 {
+            let offset = matches[position].0;
+            tokens.push(Token::Match { offset, length });
+        } else {
+            tokens.push(Token::Literal(bytes[position]));
+        }
+        position += length as usize;
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{CostModel, greedy, optimal};
+    use internal::consts;
+    use internal::token::Token;
+
+    #[test]
+    fn greedy_takes_longest_matches() {
+        let mut tokens = Vec::new();
+        greedy(b"abab", &[(0, 0), (0, 0), (2, 2), (0, 0)], &mut tokens);
+        assert_eq!(tokens,
+                   vec![Token::Literal(b'a'),
+                        Token::Literal(b'b'),
+                        Token::Match {
+                            offset: 2,
+                            length: 2,
+                        }]);
+    }
+
+    fn uniform_costs() -> CostModel {
+        let main_lengths = vec![8u8; consts::num_main_elements(15)];
+        let length_lengths = vec![8u8; consts::NUM_SECONDARY_LENGTHS];
+        CostModel::new(&main_lengths, &length_lengths, false)
+    }
+
+    #[test]
+    fn optimal_avoids_expensive_matches() {
+        // A distant 2-byte match costs 8 bits plus 13 extra offset bits,
+        // which is more than two 8-bit literals.
+        let mut tokens = Vec::new();
+        optimal(b"abab",
+                &[(0, 0), (0, 0), (20000, 2), (0, 0)],
+                &uniform_costs(),
+                &mut tokens);
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn optimal_takes_cheap_matches() {
+        let mut tokens = Vec::new();
+        optimal(b"abab",
+                &[(0, 0), (0, 0), (2, 2), (0, 0)],
+                &uniform_costs(),
+                &mut tokens);
+        assert_eq!(tokens,
+                   vec![Token::Literal(b'a'),
+                        Token::Literal(b'b'),
+                        Token::Match {
+                            offset: 2,
+                            length: 2,
+                        }]);
+    }
+}
+
+// ========================================================================= //
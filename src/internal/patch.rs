@@ -0,0 +1,138 @@
+//! Creating and applying LZX DELTA patches, which store a new version of a
+//! file as an LZX DELTA stream that refers back to the old (base) version.
+
+use internal::consts;
+use internal::decoder::Decoder;
+use internal::encoder::Encoder;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+/// Returns the window size exponent for a patch that turns a base file of
+/// `base_len` bytes into a new file of `new_len` bytes: the smallest window
+/// that holds both files, so that any part of the base file can be referred
+/// to.  Returns `None` if the files are too large for an LZX DELTA window.
+pub fn patch_window(base_len: u64, new_len: u64) -> Option<u16> {
+    let total = base_len.checked_add(new_len)?;
+    (consts::WINDOW_MIN..=consts::WINDOW_MAX_DELTA)
+        .find(|&window| total <= 1 << window)
+}
+
+/// Creates an LZX DELTA patch that turns `base` into `new`.  The base file
+/// is used as the encoder's dictionary, so that unchanged regions become long
+/// matches against it.  The window size is chosen with `patch_window()`.
+pub fn create_patch(base: &[u8], new: &[u8]) -> io::Result<Vec<u8>> {
+    let window = match patch_window(base.len() as u64, new.len() as u64) {
+        Some(window) => window,
+        None => {
+            invalid_input!("LZX DELTA patch is too large ({} + {} bytes)",
+                           base.len(),
+                           new.len());
+        }
+    };
+    let mut encoder =
+        Encoder::new_delta(Vec::new(), window, new.len() as u64)?;
+    encoder.set_dictionary(base)?;
+    encoder.write_all(new)?;
+    encoder.into_inner()
+}
+
+/// Applies an LZX DELTA patch to `base`, returning the new file, which must
+/// be `new_size` bytes long.  The window size is chosen with
+/// `patch_window()`.
+pub fn apply_patch(base: &[u8], patch: &[u8], new_size: u64)
+                   -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    apply_patch_to_writer(base, patch, new_size, &mut output)?;
+    Ok(output)
+}
+
+/// Like `apply_patch`, but reads the base file and the patch from readers
+/// (each up to end-of-file), and writes the new file to `writer`.  Returns
+/// the number of bytes written.
+pub fn apply_patch_to_writer<B: Read, P: Read, W: Write>(base: B, patch: P,
+                                                         new_size: u64,
+                                                         writer: &mut W)
+                                                         -> io::Result<u64> {
+    let max_base_len = 1u64 << consts::WINDOW_MAX_DELTA;
+    let mut dictionary = Vec::new();
+    base.take(max_base_len + 1).read_to_end(&mut dictionary)?;
+    let window = match patch_window(dictionary.len() as u64, new_size) {
+        Some(window) => window,
+        None => {
+            invalid_input!("LZX DELTA patch is too large ({} + {} bytes)",
+                           dictionary.len(),
+                           new_size);
+        }
+    };
+    let mut decoder = Decoder::new_delta(patch, window, new_size)?;
+    decoder.set_dictionary(&dictionary)?;
+    decoder.copy_to(writer)
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_patch, apply_patch_to_writer, create_patch,
+                patch_window};
+    use internal::encoder::compress;
+    use internal::testutil::noise;
+    use std::io::{self, Read};
+
+    #[test]
+    fn window_holds_both_files() {
+        assert_eq!(patch_window(0, 0), Some(15));
+        assert_eq!(patch_window(0x8000, 0), Some(15));
+        assert_eq!(patch_window(0x8000, 1), Some(16));
+        assert_eq!(patch_window(3 << 20, 1 << 20), Some(22));
+        assert_eq!(patch_window(1 << 25, 0), Some(25));
+        assert_eq!(patch_window(1 << 25, 1), None);
+        assert_eq!(patch_window(u64::MAX, 1), None);
+    }
+
+    #[test]
+    fn create_and_apply_patch() {
+        // Noisy data compresses poorly on its own, but a new version of it
+        // is mostly long matches against the old one.
+        let base = noise(0x2545f491, 50_000);
+        let mut new = base.clone();
+        new[1234] = 0xff;
+        new.extend_from_slice(b"Appended to the new version.");
+        let patch = create_patch(&base, &new).unwrap();
+        assert!(patch.len() < 200);
+        assert!(compress(&new, 17).unwrap().len() > new.len());
+        assert!(apply_patch(&base, &patch, new.len() as u64).unwrap() == new);
+        // The wrong base file gives the wrong output (or an error).
+        let wrong_base = vec![0u8; base.len()];
+        let wrong = apply_patch(&wrong_base, &patch, new.len() as u64);
+        assert!(wrong.map(|output| output != new).unwrap_or(true));
+    }
+
+    #[test]
+    fn apply_patch_with_large_base() {
+        // A base file larger than a standard LZXD window needs one of the
+        // larger windows that only LZX DELTA allows.
+        let mut base: Vec<u8> = (0..(3 << 20) as u32)
+            .map(|n| ((n / 3) ^ (n >> 12)) as u8)
+            .collect();
+        let new = base[..5000].to_vec();
+        base[5000..10000].copy_from_slice(&[0; 5000]);
+        let patch = create_patch(&base, &new).unwrap();
+        let mut output = Vec::new();
+        let num_bytes = apply_patch_to_writer(base.as_slice(),
+                                              patch.as_slice(),
+                                              new.len() as u64,
+                                              &mut output)
+            .unwrap();
+        assert_eq!(num_bytes, new.len() as u64);
+        assert!(output == new);
+        let huge = io::repeat(0).take(33 << 20);
+        let error =
+            apply_patch_to_writer(huge, patch.as_slice(), 1, &mut output)
+                .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+// ========================================================================= //
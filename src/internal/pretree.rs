@@ -0,0 +1,247 @@
+use internal::bits::{BitReader, BitWriter};
+use internal::huffman::{self, HuffmanTable};
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+const NUM_PRETREE_ELEMENTS: usize = 20;
+const PRETREE_LENGTH_BITS: u16 = 4;
+const PRETREE_MAX_LENGTH: u8 = 15;
+
+const ZEROS_SHORT: u16 = 17;
+const ZEROS_LONG: u16 = 18;
+const SAME_RUN: u16 = 19;
+
+const ZEROS_SHORT_MIN: usize = 4;
+const ZEROS_SHORT_MAX: usize = 19;
+const ZEROS_LONG_MIN: usize = 20;
+const ZEROS_LONG_MAX: usize = 51;
+const SAME_RUN_MIN: usize = 4;
+const SAME_RUN_MAX: usize = 5;
+
+/// A decoding table with room for any pretree.
+pub type PretreeTable =
+    HuffmanTable<{ huffman::max_secondary_len(NUM_PRETREE_ELEMENTS) }>;
+
+// ========================================================================= //
+
+#[derive(Clone, Copy)]
+enum PretreeOp {
+    Delta(u16),
+    ZerosShort(u32),
+    ZerosLong(u32),
+    SameRun(u32, u16),
+}
+
+fn delta_code(prev_len: u8, new_len: u8) -> u16 {
+    (17 + prev_len as u16 - new_len as u16) % 17
+}
+
+fn apply_delta(prev_len: u8, code: u16) -> u8 {
+    ((17 + prev_len as u16 - code) % 17) as u8
+}
+
+fn plan_ops(prev_lengths: &[u8], new_lengths: &[u8]) -> Vec<PretreeOp> {
+    let mut ops = Vec::new();
+    let mut index = 0;
+    while index < new_lengths.len() {
+        let len = new_lengths[index];
+        let run = new_lengths[index..]
+            .iter()
+            .take_while(|&&other| other == len)
+            .count();
+        if len == 0 && run >= ZEROS_LONG_MIN {
+            let run = run.min(ZEROS_LONG_MAX);
+            ops.push(PretreeOp::ZerosLong((run - ZEROS_LONG_MIN) as u32));
+            index += run;
+        } else if len == 0 && run >= ZEROS_SHORT_MIN {
+            let run = run.min(ZEROS_SHORT_MAX);
+            ops.push(PretreeOp::ZerosShort((run - ZEROS_SHORT_MIN) as u32));
+            index += run;
+        } else if len != 0 && run >= SAME_RUN_MIN {
+            let run = run.min(SAME_RUN_MAX);
+            let code = delta_code(prev_lengths[index], len);
+            ops.push(PretreeOp::SameRun((run - SAME_RUN_MIN) as u32, code));
+            index += run;
+        } else {
+            ops.push(PretreeOp::Delta(delta_code(prev_lengths[index], len)));
+            index += 1;
+        }
+    }
+    ops
+}
+
+/// Writes a pretree followed by the given code lengths, encoded as deltas
+/// from the previous lengths.
+pub fn write_lengths<W: Write>(writer: &mut BitWriter<W>, prev_lengths: &[u8],
+                               new_lengths: &[u8])
+                               -> io::Result<()> {
+    debug_assert_eq!(prev_lengths.len(), new_lengths.len());
+    let ops = plan_ops(prev_lengths, new_lengths);
+    let mut freqs = [0u32; NUM_PRETREE_ELEMENTS];
+    for &op in ops.iter() {
+        match op {
+            PretreeOp::Delta(code) => freqs[code as usize] += 1,
+            PretreeOp::ZerosShort(_) => freqs[ZEROS_SHORT as usize] += 1,
+            PretreeOp::ZerosLong(_) => freqs[ZEROS_LONG as usize] += 1,
+            PretreeOp::SameRun(_, code) => {
+                freqs[SAME_RUN as usize] += 1;
+                freqs[code as usize] += 1;
+            }
+        }
+    }
+    let lengths = huffman::build_lengths(&freqs, PRETREE_MAX_LENGTH);
+    let codes = huffman::canonical_codes(&lengths);
+    for &len in lengths.iter() {
+        writer.write_bits(PRETREE_LENGTH_BITS, len as u32)?;
+    }
+    let write_symbol = |writer: &mut BitWriter<W>, symbol: u16| {
+        let symbol = symbol as usize;
+        writer.write_bits(lengths[symbol] as u16, codes[symbol] as u32)
+    };
+    for &op in ops.iter() {
+        match op {
+            PretreeOp::Delta(code) => write_symbol(writer, code)?,
+            PretreeOp::ZerosShort(extra) => {
+                write_symbol(writer, ZEROS_SHORT)?;
+                writer.write_bits(4, extra)?;
+            }
+            PretreeOp::ZerosLong(extra) => {
+                write_symbol(writer, ZEROS_LONG)?;
+                writer.write_bits(5, extra)?;
+            }
+            PretreeOp::SameRun(extra, code) => {
+                write_symbol(writer, SAME_RUN)?;
+                writer.write_bits(1, extra)?;
+                write_symbol(writer, code)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a pretree (into `pretree`, which is rebuilt for it) followed by code
+/// lengths, which are encoded as deltas from the previous values of
+/// `lengths` and are updated in place.
+pub fn read_lengths<R: Read>(reader: &mut BitReader<R>,
+                             pretree: &mut PretreeTable, lengths: &mut [u8])
+                             -> io::Result<()> {
+    let mut pretree_lengths = [0u8; NUM_PRETREE_ELEMENTS];
+    for len in pretree_lengths.iter_mut() {
+        *len = reader.read_bits(PRETREE_LENGTH_BITS)? as u8;
+    }
+    pretree.rebuild(&pretree_lengths)?;
+    let mut index = 0;
+    while index < lengths.len() {
+        let (run, value) = match pretree.decode(reader)? {
+            ZEROS_SHORT => {
+                (ZEROS_SHORT_MIN + reader.read_bits(4)? as usize, 0)
+            }
+            ZEROS_LONG => (ZEROS_LONG_MIN + reader.read_bits(5)? as usize, 0),
+            SAME_RUN => {
+                let run = SAME_RUN_MIN + reader.read_bits(1)? as usize;
+                let code = pretree.decode(reader)?;
+                if code > 16 {
+                    invalid_data!("Invalid LZX pretree run code ({})", code);
+                }
+                (run, apply_delta(lengths[index], code))
+            }
+            code => (1, apply_delta(lengths[index], code)),
+        };
+        if index + run > lengths.len() {
+            invalid_data!("LZX pretree run overflows tree ({} > {})",
+                          index + run,
+                          lengths.len());
+        }
+        for len in lengths[index..(index + run)].iter_mut() {
+            *len = value;
+        }
+        index += run;
+    }
+    Ok(())
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{PretreeTable, read_lengths, write_lengths};
+    use internal::bits::{BitReader, BitWriter};
+
+    fn round_trip(prev_lengths: &[u8], new_lengths: &[u8]) -> usize {
+        let mut output = Vec::<u8>::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            write_lengths(&mut writer, prev_lengths, new_lengths).unwrap();
+            writer.align_to_16().unwrap();
+        }
+        let mut lengths = prev_lengths.to_vec();
+        {
+            let mut reader = BitReader::new(output.as_slice());
+            let mut pretree = PretreeTable::empty();
+            read_lengths(&mut reader, &mut pretree, &mut lengths).unwrap();
+        }
+        assert_eq!(lengths.as_slice(), new_lengths);
+        output.len()
+    }
+
+    #[test]
+    fn lengths_from_zero() {
+        let mut lengths = vec![0u8; 256];
+        for (index, len) in lengths.iter_mut().enumerate() {
+            *len = (index % 16) as u8;
+        }
+        round_trip(&[0; 256], &lengths);
+    }
+
+    #[test]
+    fn runs_of_zeros() {
+        let mut lengths = vec![0u8; 200];
+        lengths[3] = 5;
+        lengths[30] = 7;
+        lengths[199] = 1;
+        round_trip(&[0; 200], &lengths);
+        round_trip(&[9; 200], &lengths);
+        round_trip(&lengths, &[0; 200]);
+    }
+
+    #[test]
+    fn runs_of_same_length() {
+        let mut prev_lengths = vec![0u8; 40];
+        for (index, len) in prev_lengths.iter_mut().enumerate() {
+            *len = (index % 7) as u8;
+        }
+        let mut new_lengths = vec![8u8; 40];
+        new_lengths[13] = 3;
+        new_lengths[27] = 16;
+        round_trip(&prev_lengths, &new_lengths);
+    }
+
+    #[test]
+    fn unchanged_lengths_are_cheap() {
+        let mut lengths = vec![0u8; 256];
+        for (index, len) in lengths.iter_mut().enumerate() {
+            *len = 6 + (index % 5) as u8;
+        }
+        let from_zero = round_trip(&[0; 256], &lengths);
+        let from_same = round_trip(&lengths, &lengths);
+        assert!(from_same < from_zero);
+    }
+
+    #[test]
+    #[should_panic(expected = "LZX pretree run overflows tree (30 > 10)")]
+    fn run_overflow() {
+        let mut output = Vec::<u8>::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            write_lengths(&mut writer, &[0; 30], &[0; 30]).unwrap();
+            writer.align_to_16().unwrap();
+        }
+        let mut lengths = [0u8; 10];
+        let mut reader = BitReader::new(output.as_slice());
+        let mut pretree = PretreeTable::empty();
+        read_lengths(&mut reader, &mut pretree, &mut lengths).unwrap();
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,152 @@
+use internal::builder::EncoderBuilder;
+use internal::encoder::Encoder;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+const INPUT_BUFFER_SIZE: usize = 0x8000;
+
+// ========================================================================= //
+
+/// A pull-style LZXD encoder, which reads uncompressed data from an
+/// underlying reader and produces the compressed data via the `Read` trait.
+pub struct EncoderReader<R: Read> {
+    source: R,
+    encoder: Encoder<Vec<u8>>,
+    input: Vec<u8>,
+    output_start: usize,
+    finished: bool,
+}
+
+impl<R: Read> EncoderReader<R> {
+    /// Starts encoding the data read from `source`, which must produce
+    /// exactly `uncompressed_size` bytes.  The `window` argument is the same
+    /// as for `Encoder::new`.
+    pub fn new(source: R, window: u16, uncompressed_size: u64)
+               -> io::Result<EncoderReader<R>> {
+        let builder =
+            EncoderBuilder::new(window).uncompressed_size(uncompressed_size);
+        EncoderReader::with_builder(source, &builder)
+    }
+
+    /// Like `EncoderReader::new`, but creates the encoder with the given
+    /// settings.  If the builder doesn't declare an uncompressed size, all
+    /// of the data up to the end of `source` is encoded.
+    pub fn with_builder(source: R, builder: &EncoderBuilder)
+                        -> io::Result<EncoderReader<R>> {
+        Ok(EncoderReader {
+               source,
+               encoder: builder.build(Vec::new())?,
+               input: vec![0u8; INPUT_BUFFER_SIZE],
+               output_start: 0,
+               finished: false,
+           })
+    }
+
+    /// Returns the number of uncompressed bytes read from the source so far.
+    pub fn uncompressed_bytes_read(&self) -> u64 {
+        self.encoder.uncompressed_bytes_written()
+    }
+
+    /// Unwraps this encoder, returning the underlying reader.
+    pub fn into_inner(self) -> R { self.source }
+
+    /// Reads more data from the source into the encoder, finishing the
+    /// stream at the end of the source.
+    fn fill_output(&mut self) -> io::Result<()> {
+        let num_bytes = match self.encoder.uncompressed_bytes_remaining() {
+            Some(remaining) => remaining.min(self.input.len() as u64) as usize,
+            None => self.input.len(),
+        };
+        let num_read = if num_bytes == 0 {
+            0
+        } else {
+            loop {
+                match self.source.read(&mut self.input[..num_bytes]) {
+                    Ok(num_read) => break num_read,
+                    Err(ref error)
+                        if error.kind() == io::ErrorKind::Interrupted => {}
+                    Err(error) => return Err(error),
+                }
+            }
+        };
+        if num_read == 0 {
+            self.encoder.finish()?;
+            self.finished = true;
+        } else {
+            self.encoder.write_all(&self.input[..num_read])?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for EncoderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_start == self.encoder.get_mut().len() {
+            self.encoder.get_mut().clear();
+            self.output_start = 0;
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.fill_output()?;
+        }
+        let output = &self.encoder.get_mut()[self.output_start..];
+        let num_bytes = output.len().min(buf.len());
+        buf[..num_bytes].copy_from_slice(&output[..num_bytes]);
+        self.output_start += num_bytes;
+        Ok(num_bytes)
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::EncoderReader;
+    use internal::builder::EncoderBuilder;
+    use internal::decoder::decompress;
+    use internal::encoder::compress;
+    use std::io::{self, Read};
+
+    #[test]
+    fn matches_push_style_encoder() {
+        let data = b"Pulling compressed data on demand. ".repeat(3000);
+        let mut reader =
+            EncoderReader::new(data.as_slice(), 15, data.len() as u64)
+                .unwrap();
+        let mut compressed = Vec::new();
+        io::copy(&mut reader, &mut compressed).unwrap();
+        assert_eq!(reader.uncompressed_bytes_read(), data.len() as u64);
+        assert_eq!(compressed, compress(&data, 15).unwrap());
+    }
+
+    #[test]
+    fn unknown_size_source() {
+        let data = b"No declared size here. ".repeat(5000);
+        let builder = EncoderBuilder::new(16).e8_translation(true);
+        let mut reader = EncoderReader::with_builder(data.as_slice(),
+                                                     &builder)
+            .unwrap();
+        let mut compressed = Vec::new();
+        let mut buffer = [0u8; 100];
+        loop {
+            let num_bytes = reader.read(&mut buffer).unwrap();
+            if num_bytes == 0 {
+                break;
+            }
+            compressed.extend_from_slice(&buffer[..num_bytes]);
+        }
+        assert_eq!(decompress(&compressed, 16, data.len() as u64).unwrap(),
+                   data);
+    }
+
+    #[test]
+    fn short_source() {
+        let data = b"Not enough data";
+        let mut reader = EncoderReader::new(&data[..], 15, 100).unwrap();
+        let mut compressed = Vec::new();
+        assert!(reader.read_to_end(&mut compressed).is_err());
+    }
+}
+
+// ========================================================================= //
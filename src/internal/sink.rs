@@ -0,0 +1,215 @@
+use internal::builder::DecoderBuilder;
+use internal::decoder::Decoder;
+use internal::error::Error;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+/// A push-style LZXD decoder, which accepts compressed data via the `Write`
+/// trait and writes the decompressed data to an underlying writer.
+///
+/// Compressed data may be written in pieces of any size; each chunk is
+/// decoded as soon as all of its bytes have arrived.  Any data written after
+/// the end of the stream is ignored.
+pub struct DecodeSink<W: Write> {
+    writer: W,
+    builder: DecoderBuilder,
+    decoder: Option<Decoder<VecDeque<u8>>>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    uncompressed_bytes_remaining: u64,
+}
+
+impl<W: Write> DecodeSink<W> {
+    /// Starts decoding an LZXD stream, writing the decompressed data to
+    /// `writer`.  The `window` and `uncompressed_size` arguments are the same
+    /// as for `Decoder::new`.
+    pub fn new(writer: W, window: u16, uncompressed_size: u64)
+               -> DecodeSink<W> {
+        let builder = DecoderBuilder::new(window, uncompressed_size);
+        DecodeSink::with_builder(writer, builder)
+    }
+
+    /// Like `DecodeSink::new`, but creates the decoder with the given
+    /// settings, once the first chunk of data arrives.
+    pub fn with_builder(writer: W, builder: DecoderBuilder)
+                        -> DecodeSink<W> {
+        let uncompressed_size = builder.total_uncompressed_size();
+        DecodeSink {
+            writer,
+            builder,
+            decoder: None,
+            input: Vec::new(),
+            output: Vec::new(),
+            uncompressed_bytes_remaining: uncompressed_size,
+        }
+    }
+
+    /// Returns true once the entire stream has been decoded and written to
+    /// the underlying writer.
+    pub fn is_finished(&self) -> bool {
+        self.uncompressed_bytes_remaining == 0
+    }
+
+    /// Returns a mutable reference to the underlying writer.  Taking data
+    /// that has already been written out of it (for example, from a
+    /// `Vec<u8>`) is safe.
+    pub fn get_mut(&mut self) -> &mut W { &mut self.writer }
+
+    /// Checks that the entire stream has been decoded, flushes the
+    /// underlying writer, and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.is_finished() {
+            return Err(Error::Truncated.into());
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Decodes every complete chunk in the input buffer.
+    fn decode_complete_chunks(&mut self) -> io::Result<()> {
+        let mut start = 0;
+        while self.uncompressed_bytes_remaining > 0 &&
+            self.input.len() - start >= 2
+        {
+            let chunk_len = 2 +
+                (self.input[start] as usize |
+                     ((self.input[start + 1] as usize) << 8));
+            if self.input.len() - start < chunk_len {
+                break;
+            }
+            let chunk = &self.input[start..(start + chunk_len)];
+            start += chunk_len;
+            let decoder = match self.decoder {
+                Some(ref mut decoder) => {
+                    decoder.get_mut().extend(chunk);
+                    decoder
+                }
+                None => {
+                    let queue = chunk.iter().cloned().collect();
+                    self.decoder.get_or_insert(self.builder.build(queue)?)
+                }
+            };
+            let num_bytes = self.uncompressed_bytes_remaining
+                .min(decoder.chunk_size() as u64) as usize;
+            self.output.resize(num_bytes, 0);
+            decoder.read_exact(&mut self.output)?;
+            self.writer.write_all(&self.output)?;
+            self.uncompressed_bytes_remaining -= num_bytes as u64;
+        }
+        if self.uncompressed_bytes_remaining == 0 {
+            if start < self.input.len() {
+                log_event!(warn,
+                           "Ignoring {} bytes after the end of the LZX stream",
+                           self.input.len() - start);
+            }
+            self.input.clear();
+        } else {
+            self.input.drain(..start);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for DecodeSink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.is_finished() {
+            self.input.extend_from_slice(buf);
+            self.decode_complete_chunks()?;
+        } else if !buf.is_empty() {
+            log_event!(warn,
+                       "Ignoring {} bytes after the end of the LZX stream",
+                       buf.len());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.writer.flush() }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::DecodeSink;
+    use internal::builder::DecoderBuilder;
+    use internal::encoder::{Encoder, compress};
+    use std::io::Write;
+
+    #[test]
+    fn decode_in_small_pieces() {
+        let data = b"Pushing compressed data piece by piece. ".repeat(2000);
+        let mut encoder =
+            Encoder::new(Vec::new(), 16, data.len() as u64).unwrap();
+        encoder.set_chunk_size(0x1000).unwrap();
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.into_inner().unwrap();
+        let builder = DecoderBuilder::new(16, data.len() as u64)
+            .chunk_size(0x1000);
+        let mut sink = DecodeSink::with_builder(Vec::new(), builder);
+        for piece in compressed.chunks(7) {
+            assert!(!sink.is_finished());
+            sink.write_all(piece).unwrap();
+        }
+        assert!(sink.is_finished());
+        assert_eq!(sink.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn incomplete_stream() {
+        let data = b"Too short. ".repeat(5000);
+        let compressed = compress(&data, 15).unwrap();
+        let mut sink = DecodeSink::new(Vec::new(), 15, data.len() as u64);
+        sink.write_all(&compressed[..(compressed.len() - 1)]).unwrap();
+        assert!(!sink.is_finished());
+        assert!(sink.finish().is_err());
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn log_trailing_data() {
+        use log::{self, Log, Metadata, Record};
+        use std::cell::RefCell;
+
+        thread_local! {
+            static MESSAGES: RefCell<Vec<String>> = const {
+                RefCell::new(Vec::new())
+            };
+        }
+
+        struct TestLogger;
+
+        impl Log for TestLogger {
+            fn enabled(&self, _metadata: &Metadata) -> bool { true }
+
+            fn log(&self, record: &Record) {
+                let message = format!("{} {}", record.level(), record.args());
+                MESSAGES.with(|messages| messages.borrow_mut().push(message));
+            }
+
+            fn flush(&self) {}
+        }
+
+        let _ = log::set_logger(&TestLogger);
+        log::set_max_level(log::LevelFilter::Trace);
+        let data = b"Followed by junk. ".repeat(100);
+        let mut compressed = compress(&data, 15).unwrap();
+        compressed.extend_from_slice(b"junk");
+        let mut sink = DecodeSink::new(Vec::new(), 15, data.len() as u64);
+        sink.write_all(&compressed).unwrap();
+        sink.write_all(b"more").unwrap();
+        assert_eq!(sink.finish().unwrap(), data);
+        let messages = MESSAGES.with(|messages| messages.borrow().clone());
+        assert!(messages.iter().any(|message| message.starts_with("DEBUG")));
+        let warnings: Vec<&String> = messages
+            .iter()
+            .filter(|message| message.starts_with("WARN"))
+            .collect();
+        assert_eq!(warnings,
+                   ["WARN Ignoring 4 bytes after the end of the LZX stream",
+                    "WARN Ignoring 4 bytes after the end of the LZX stream"]);
+    }
+}
+
+// ========================================================================= //
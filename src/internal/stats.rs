@@ -0,0 +1,98 @@
+use internal::btype::BlockType;
+
+// ========================================================================= //
+
+/// Statistics about an LZXD stream produced by an `Encoder`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EncoderStats {
+    /// The number of uncompressed bytes that have been encoded.
+    pub uncompressed_bytes: u64,
+    /// The number of compressed bytes that have been emitted.
+    pub compressed_bytes: u64,
+    /// The number of verbatim blocks emitted.
+    pub verbatim_blocks: u64,
+    /// The number of aligned offset blocks emitted.
+    pub aligned_blocks: u64,
+    /// The number of uncompressed blocks emitted.
+    pub uncompressed_blocks: u64,
+    /// The number of literal bytes encoded in compressed blocks.
+    pub literals: u64,
+    /// The number of matches encoded in compressed blocks.
+    pub matches: u64,
+    /// The total number of uncompressed bytes covered by those matches.
+    pub match_bytes: u64,
+}
+
+impl EncoderStats {
+    /// Returns the number of blocks of the given type emitted.
+    pub fn blocks(&self, block_type: BlockType) -> u64 {
+        match block_type {
+            BlockType::Verbatim => self.verbatim_blocks,
+            BlockType::AlignedOffset => self.aligned_blocks,
+            BlockType::Uncompressed => self.uncompressed_blocks,
+        }
+    }
+
+    /// Returns the average length of the encoded matches, or zero if there
+    /// were none.
+    pub fn average_match_len(&self) -> f64 {
+        if self.matches == 0 {
+            0.0
+        } else {
+            self.match_bytes as f64 / self.matches as f64
+        }
+    }
+
+    /// Returns the ratio of compressed to uncompressed size, or zero if no
+    /// data has been encoded.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.uncompressed_bytes == 0 {
+            0.0
+        } else {
+            self.compressed_bytes as f64 / self.uncompressed_bytes as f64
+        }
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::EncoderStats;
+    use internal::btype::BlockType;
+
+    #[test]
+    fn ratios_of_empty_stats() {
+        let stats = EncoderStats::default();
+        assert_eq!(stats.average_match_len(), 0.0);
+        assert_eq!(stats.compression_ratio(), 0.0);
+    }
+
+    #[test]
+    fn ratios() {
+        let stats = EncoderStats {
+            uncompressed_bytes: 1000,
+            compressed_bytes: 250,
+            matches: 4,
+            match_bytes: 30,
+            ..EncoderStats::default()
+        };
+        assert_eq!(stats.average_match_len(), 7.5);
+        assert_eq!(stats.compression_ratio(), 0.25);
+    }
+
+    #[test]
+    fn blocks_by_type() {
+        let stats = EncoderStats {
+            verbatim_blocks: 3,
+            aligned_blocks: 2,
+            uncompressed_blocks: 1,
+            ..EncoderStats::default()
+        };
+        assert_eq!(stats.blocks(BlockType::Verbatim), 3);
+        assert_eq!(stats.blocks(BlockType::AlignedOffset), 2);
+        assert_eq!(stats.blocks(BlockType::Uncompressed), 1);
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,97 @@
+//! Helpers shared by the tests in several modules.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{self, Read};
+
+// ========================================================================= //
+
+/// A reader that returns at most `max_read` bytes at a time, and fails with
+/// `Interrupted` on every other call.
+pub struct FlakyReader<'a> {
+    pub data: &'a [u8],
+    pub max_read: usize,
+    pub interrupt: bool,
+}
+
+impl<'a> FlakyReader<'a> {
+    /// Creates a reader whose first call succeeds.
+    pub fn new(data: &'a [u8], max_read: usize) -> FlakyReader<'a> {
+        FlakyReader {
+            data,
+            max_read,
+            interrupt: false,
+        }
+    }
+}
+
+impl<'a> Read for FlakyReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        let num_bytes = buf.len().min(self.data.len()).min(self.max_read);
+        buf[..num_bytes].copy_from_slice(&self.data[..num_bytes]);
+        self.data = &self.data[num_bytes..];
+        Ok(num_bytes)
+    }
+}
+
+// ========================================================================= //
+
+/// An endless xorshift sequence of pseudo-random bytes, for test data that
+/// doesn't compress.  The seed must be nonzero.
+pub struct Noise {
+    state: u32,
+}
+
+impl Noise {
+    /// Starts the sequence from `seed`.
+    pub fn new(seed: u32) -> Noise { Noise { state: seed } }
+}
+
+impl Iterator for Noise {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        Some(self.state as u8)
+    }
+}
+
+/// Returns the first `len` bytes of the `Noise` sequence for `seed`.
+pub fn noise(seed: u32, len: usize) -> Vec<u8> {
+    Noise::new(seed).take(len).collect()
+}
+
+// ========================================================================= //
+
+/// An allocator that counts the allocations made on each thread, so that
+/// tests can check that some code doesn't allocate.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations made so far on this thread.
+pub fn allocations() -> u64 { ALLOCATIONS.with(Cell::get) }
+
+// ========================================================================= //
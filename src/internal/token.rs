@@ -0,0 +1,234 @@
+use internal::consts;
+
+// ========================================================================= //
+
+/// A single unit of LZXD-compressed data: either a literal byte, or a match
+/// that copies previously-seen bytes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Token {
+    /// A single literal byte.
+    Literal(u8),
+    /// A copy of `length` bytes, starting `offset` bytes back from the
+    /// current position.  The `offset` must be at least 1 and at most three
+    /// less than the window size, and the `length` must be between 2 and 257
+    /// (inclusive), or up to `MAX_DELTA_MATCH` in an LZX DELTA stream.  The
+    /// offset may be less than the length, in which case the copied bytes
+    /// overlap the bytes being produced.
+    Match {
+        /// How many bytes back from the current position the match starts.
+        offset: u32,
+        /// How many bytes the match copies.
+        length: u16,
+    },
+}
+
+impl Token {
+    /// Returns the number of uncompressed bytes that this token represents.
+    pub fn uncompressed_len(&self) -> usize {
+        match *self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => length as usize,
+        }
+    }
+}
+
+// ========================================================================= //
+
+/// A token, translated into the symbols that the LZXD format uses to encode
+/// it.
+#[derive(Clone, Copy)]
+pub struct EncodedToken {
+    pub main_element: u16,
+    pub length_element: Option<u16>,
+    pub extra_bits: u16,
+    pub footer: u32,
+    pub extension_bits: u16,
+    pub extension: u32,
+}
+
+impl EncodedToken {
+    /// Encodes a token, updating the recent offsets as the decoder will.  If
+    /// `delta` is true, matches of `MAX_MATCH` bytes or more are encoded as
+    /// in an LZX DELTA stream.
+    pub fn new(token: Token, recent: &mut (u32, u32, u32), delta: bool)
+               -> EncodedToken {
+        match token {
+            Token::Literal(byte) => {
+                EncodedToken {
+                    main_element: byte as u16,
+                    length_element: None,
+                    extra_bits: 0,
+                    footer: 0,
+                    extension_bits: 0,
+                    extension: 0,
+                }
+            }
+            Token::Match { offset, length } => {
+                let (position_slot, extra_bits, footer) =
+                    if offset == recent.0 {
+                        (0, 0, 0)
+                    } else if offset == recent.1 {
+                        recent.1 = recent.0;
+                        recent.0 = offset;
+                        (1, 0, 0)
+                    } else if offset == recent.2 {
+                        recent.2 = recent.0;
+                        recent.0 = offset;
+                        (2, 0, 0)
+                    } else {
+                        recent.2 = recent.1;
+                        recent.1 = recent.0;
+                        recent.0 = offset;
+                        let formatted_offset = offset + 2;
+                        let slot = position_slot(formatted_offset);
+                        (slot,
+                         consts::EXTRA_BITS[slot] as u16,
+                         formatted_offset - consts::POSITION_BASE[slot])
+                    };
+                let length = length as usize;
+                let (extension_bits, extension) =
+                    if delta && length >= consts::MAX_MATCH {
+                        length_extension(length)
+                    } else {
+                        (0, 0)
+                    };
+                let length_header = length.min(consts::MAX_MATCH) -
+                    consts::MIN_MATCH;
+                let (length_header, length_element) =
+                    if length_header < consts::NUM_PRIMARY_LENGTHS {
+                        (length_header, None)
+                    } else {
+                        let element = length_header -
+                            consts::NUM_PRIMARY_LENGTHS;
+                        (consts::NUM_PRIMARY_LENGTHS, Some(element as u16))
+                    };
+                EncodedToken {
+                    main_element: (consts::NUM_CHARS + (position_slot << 3) +
+                                       length_header) as
+                        u16,
+                    length_element,
+                    extra_bits,
+                    footer,
+                    extension_bits,
+                    extension,
+                }
+            }
+        }
+    }
+}
+
+/// Returns the number of bits that an LZX DELTA stream uses to extend a
+/// match of `length` bytes (which must be between `MAX_MATCH` and
+/// `MAX_DELTA_MATCH`), and their value.  A prefix code selects how many bits
+/// of extra length follow.
+pub fn length_extension(length: usize) -> (u16, u32) {
+    debug_assert!(length >= consts::MAX_MATCH);
+    debug_assert!(length <= consts::MAX_DELTA_MATCH);
+    let extra = (length - consts::MAX_MATCH) as u32;
+    if extra < 0x100 {
+        (1 + 8, extra)
+    } else if extra < 0x500 {
+        (2 + 10, (0b10 << 10) | (extra - 0x100))
+    } else if extra < 0x1500 {
+        (3 + 12, (0b110 << 12) | (extra - 0x500))
+    } else {
+        (3 + 15, (0b111 << 15) | extra)
+    }
+}
+
+/// Returns the position slot for a (non-repeated) formatted match offset.
+pub fn position_slot(formatted_offset: u32) -> usize {
+    match consts::POSITION_BASE.binary_search(&formatted_offset) {
+        Ok(slot) => slot,
+        Err(slot) => slot - 1,
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{EncodedToken, Token, length_extension};
+
+    #[test]
+    fn literal() {
+        let mut recent = (1, 1, 1);
+        let encoded = EncodedToken::new(Token::Literal(b'x'), &mut recent,
+                                         false);
+        assert_eq!(encoded.main_element, b'x' as u16);
+        assert_eq!(encoded.length_element, None);
+        assert_eq!(recent, (1, 1, 1));
+    }
+
+    #[test]
+    fn new_offsets() {
+        let mut recent = (1, 1, 1);
+        let token = Token::Match {
+            offset: 1000,
+            length: 5,
+        };
+        let encoded = EncodedToken::new(token, &mut recent, false);
+        // Formatted offset 1002 falls in slot 19 (base 768, 8 extra bits).
+        assert_eq!(encoded.main_element, 256 + (19 << 3) + 3);
+        assert_eq!(encoded.length_element, None);
+        assert_eq!(encoded.extra_bits, 8);
+        assert_eq!(encoded.footer, 1002 - 768);
+        assert_eq!(recent, (1000, 1, 1));
+    }
+
+    #[test]
+    fn repeated_offsets() {
+        let mut recent = (10, 20, 30);
+        let token = Token::Match {
+            offset: 30,
+            length: 100,
+        };
+        let encoded = EncodedToken::new(token, &mut recent, false);
+        assert_eq!(encoded.main_element, 256 + (2 << 3) + 7);
+        assert_eq!(encoded.length_element, Some(100 - 9));
+        assert_eq!(recent, (30, 20, 10));
+        let token = Token::Match {
+            offset: 20,
+            length: 2,
+        };
+        let encoded = EncodedToken::new(token, &mut recent, false);
+        assert_eq!(encoded.main_element, 256 + (1 << 3));
+        assert_eq!(recent, (20, 30, 10));
+        let token = Token::Match {
+            offset: 20,
+            length: 2,
+        };
+        let encoded = EncodedToken::new(token, &mut recent, false);
+        assert_eq!(encoded.main_element, 256);
+        assert_eq!(recent, (20, 30, 10));
+    }
+
+    #[test]
+    fn delta_lengths() {
+        let mut recent = (1, 1, 1);
+        let token = Token::Match {
+            offset: 1,
+            length: 257,
+        };
+        let encoded = EncodedToken::new(token, &mut recent, false);
+        assert_eq!(encoded.length_element, Some(248));
+        assert_eq!(encoded.extension_bits, 0);
+        let encoded = EncodedToken::new(token, &mut recent, true);
+        assert_eq!(encoded.length_element, Some(248));
+        assert_eq!((encoded.extension_bits, encoded.extension), (9, 0));
+        let token = Token::Match {
+            offset: 1,
+            length: 5000,
+        };
+        let encoded = EncodedToken::new(token, &mut recent, true);
+        assert_eq!(encoded.main_element, 256 + 7);
+        assert_eq!(encoded.length_element, Some(248));
+        assert_eq!((encoded.extension_bits, encoded.extension),
+                   (15, (0b110 << 12) | (5000 - 257 - 0x500)));
+        assert_eq!(length_extension(257 + 0xff), (9, 0xff));
+        assert_eq!(length_extension(257 + 0x100), (12, 0b10 << 10));
+        assert_eq!(length_extension(257 + 0x7fff), (18, 0x3ffff));
+    }
+}
+
+// ========================================================================= //
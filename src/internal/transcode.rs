@@ -0,0 +1,82 @@
+use internal::builder::{DecoderBuilder, EncoderBuilder};
+use internal::encoder::Encoder;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+/// Decodes an LZXD stream from `reader` and re-encodes it to `writer` in a
+/// single pass, for example to change its window size, match parameters, or
+/// reset interval.
+///
+/// The stream is decoded with the `from` settings and encoded with the `to`
+/// settings, except that the uncompressed size always comes from `from`.
+/// Data passes from the decoder to the encoder one chunk at a time, so memory
+/// use is bounded by the two windows rather than the size of the data.
+///
+/// Returns the finished encoder, from which the caller can get its
+/// statistics, reset table and chunk index before taking back `writer` with
+/// `into_inner()`.
+pub fn transcode<R: Read, W: Write>(reader: R, from: &DecoderBuilder,
+                                    writer: W, to: &EncoderBuilder)
+                                    -> io::Result<Encoder<W>> {
+    let mut decoder = from.build(reader)?;
+    let mut encoder = to.clone()
+        .uncompressed_size(from.total_uncompressed_size())
+        .build(writer)?;
+    decoder.copy_to(&mut encoder)?;
+    encoder.finish()?;
+    Ok(encoder)
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::transcode;
+    use internal::builder::{DecoderBuilder, EncoderBuilder};
+    use internal::decoder::Decoder;
+    use internal::encoder::compress;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn change_window_and_reset_interval() {
+        let data: Vec<u8> = (0..200_000u32)
+            .map(|n| if n % 11 == 0 { 0xe8 } else { (n / 3 + n / 500) as u8 })
+            .collect();
+        let original = compress(&data, 15).unwrap();
+        let from = DecoderBuilder::new(15, data.len() as u64);
+        let to =
+            EncoderBuilder::new(17).e8_translation(true).reset_interval(2);
+        let encoder = transcode(original.as_slice(), &from, Vec::new(), &to)
+            .unwrap();
+        assert_eq!(encoder.stats().uncompressed_bytes, data.len() as u64);
+        let table = encoder.reset_table().to_vec();
+        assert_eq!(table.len(), 4);
+        let stream = encoder.into_inner().unwrap();
+        let mut decoder =
+            Decoder::new(Cursor::new(stream), 17, data.len() as u64).unwrap();
+        decoder.set_reset_interval(2).unwrap();
+        decoder.set_reset_table(&table).unwrap();
+        assert_eq!(decoder.read_range(150_000, 10).unwrap(),
+                   &data[150_000..150_010]);
+        let mut output = Vec::new();
+        decoder.seek_to(0).unwrap();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+    }
+
+    #[test]
+    fn truncated_source_is_an_error() {
+        let data = b"Recompress me. ".repeat(5000);
+        let original = compress(&data, 16).unwrap();
+        let truncated = &original[..(original.len() / 2)];
+        let from = DecoderBuilder::new(16, data.len() as u64);
+        let to = EncoderBuilder::new(16);
+        let mut sink = Vec::new();
+        assert!(transcode(truncated, &from, &mut sink, &to).is_err());
+        sink.clear();
+        assert!(transcode(original.as_slice(), &from, &mut sink, &to).is_ok());
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,189 @@
+//! Support for the LZX-compressed resources of Windows Imaging (WIM) files.
+//!
+//! A WIM resource is split into chunks of `CHUNK_SIZE` uncompressed bytes
+//! (the last one possibly shorter), each compressed independently as its own
+//! LZXD stream with a window of `WINDOW` and a slightly different bitstream
+//! layout from other containers.  A chunk that doesn't shrink is stored
+//! uncompressed instead.  The compressed chunks follow a chunk table giving
+//! the offset of each chunk after the first.
+
+use byteorder::{ByteOrder, LittleEndian};
+use internal::consts;
+use internal::decoder::Decoder;
+use internal::encoder::Encoder;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+/// The number of uncompressed bytes in each chunk of a WIM resource.
+pub const CHUNK_SIZE: usize = consts::CHUNK_SIZE;
+
+/// The window exponent used for each chunk of a WIM resource.
+pub const WINDOW: u16 = 15;
+
+// ========================================================================= //
+
+/// Compresses one chunk of a WIM resource (at most `CHUNK_SIZE` bytes).  If
+/// LZX compression doesn't make the chunk smaller, it is returned as is.
+pub fn compress_chunk(chunk: &[u8]) -> io::Result<Vec<u8>> {
+    if chunk.len() > CHUNK_SIZE {
+        invalid_input!("WIM chunk is too large ({} bytes)", chunk.len());
+    }
+    let mut encoder = Encoder::new(Vec::new(), WINDOW, chunk.len() as u64)?;
+    encoder.set_wim_format()?;
+    encoder.write_all(chunk)?;
+    let compressed = encoder.into_inner()?;
+    if compressed.len() >= chunk.len() {
+        return Ok(chunk.to_vec());
+    }
+    Ok(compressed)
+}
+
+/// Decompresses one chunk of a WIM resource, given its uncompressed length.
+/// A chunk whose compressed length equals its uncompressed length was
+/// stored uncompressed.
+pub fn decompress_chunk(chunk: &[u8], uncompressed_len: usize)
+                        -> io::Result<Vec<u8>> {
+    if uncompressed_len > CHUNK_SIZE {
+        invalid_input!("WIM chunk is too large ({} bytes)", uncompressed_len);
+    }
+    if chunk.len() == uncompressed_len {
+        return Ok(chunk.to_vec());
+    }
+    let mut decoder =
+        Decoder::new_wim(chunk, WINDOW, uncompressed_len as u64)?;
+    let mut output = vec![0u8; uncompressed_len];
+    decoder.read_exact(&mut output)?;
+    Ok(output)
+}
+
+// ========================================================================= //
+
+/// Returns the size of each chunk table entry for a resource of the given
+/// uncompressed size (8 bytes for resources over 4 GiB, which are common in
+/// WIM images).
+fn entry_size(uncompressed_size: u64) -> usize {
+    if uncompressed_size > u32::MAX as u64 { 8 } else { 4 }
+}
+
+/// Compresses `data` as a WIM resource, returning the chunk table followed
+/// by the compressed chunks.
+pub fn compress_resource(data: &[u8]) -> io::Result<Vec<u8>> {
+    let entry_size = entry_size(data.len() as u64);
+    let num_chunks = data.len().div_ceil(CHUNK_SIZE);
+    let mut table = Vec::with_capacity(num_chunks.saturating_sub(1) *
+                                           entry_size);
+    let mut chunks = Vec::new();
+    for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+        if index > 0 {
+            let offset = chunks.len() as u64;
+            table.extend_from_slice(&offset.to_le_bytes()[..entry_size]);
+        }
+        chunks.extend_from_slice(&compress_chunk(chunk)?);
+    }
+    table.extend_from_slice(&chunks);
+    Ok(table)
+}
+
+/// Decompresses a WIM resource (a chunk table followed by the compressed
+/// chunks), given its uncompressed size.
+pub fn decompress_resource(resource: &[u8], uncompressed_size: u64)
+                           -> io::Result<Vec<u8>> {
+    let entry_size = entry_size(uncompressed_size);
+    let num_chunks = uncompressed_size.div_ceil(CHUNK_SIZE as u64);
+    let table_len = num_chunks.saturating_sub(1)
+        .saturating_mul(entry_size as u64);
+    if table_len > resource.len() as u64 {
+        invalid_data!("WIM chunk table is truncated");
+    }
+    let (table, chunks) = resource.split_at(table_len as usize);
+    let mut offsets: Vec<u64> = vec![0];
+    offsets.extend(table.chunks(entry_size).map(|entry| {
+        LittleEndian::read_uint(entry, entry_size)
+    }));
+    offsets.push(chunks.len() as u64);
+    let capacity = match usize::try_from(uncompressed_size) {
+        Ok(capacity) => capacity,
+        Err(_) => {
+            invalid_input!("WIM resource is too large to decompress into \
+                            memory ({} bytes)",
+                           uncompressed_size);
+        }
+    };
+    let mut output = Vec::with_capacity(capacity);
+    for index in 0..(num_chunks as usize) {
+        let (start, end) = (offsets[index], offsets[index + 1]);
+        if start > end || end > chunks.len() as u64 {
+            invalid_data!("Invalid WIM chunk table entry ({})", end);
+        }
+        let uncompressed_len = (uncompressed_size - output.len() as u64)
+            .min(CHUNK_SIZE as u64) as usize;
+        let chunk = &chunks[(start as usize)..(end as usize)];
+        output.extend_from_slice(&decompress_chunk(chunk,
+                                                   uncompressed_len)?);
+    }
+    Ok(output)
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{CHUNK_SIZE, compress_chunk, compress_resource,
+                decompress_chunk, decompress_resource, entry_size};
+    use byteorder::{ByteOrder, LittleEndian};
+    use internal::testutil::noise;
+    use std::io;
+
+    #[test]
+    fn chunk_round_trip() {
+        let mut chunk = b"\xe8\x10\x00\x00\x00 WIM chunk data. ".repeat(1500);
+        chunk.truncate(CHUNK_SIZE);
+        let compressed = compress_chunk(&chunk).unwrap();
+        assert!(compressed.len() < 4000);
+        assert_eq!(decompress_chunk(&compressed, chunk.len()).unwrap(),
+                   chunk);
+        let short = &chunk[..1000];
+        let compressed = compress_chunk(short).unwrap();
+        assert_eq!(decompress_chunk(&compressed, 1000).unwrap(), short);
+        // A chunk that doesn't compress is stored as is.
+        let noise = noise(1, 500);
+        assert_eq!(compress_chunk(&noise).unwrap(), noise);
+        assert_eq!(decompress_chunk(&noise, 500).unwrap(), noise);
+        let error = compress_chunk(&[0; CHUNK_SIZE + 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn resource_round_trip() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|index| (index / 5 % 199) as u8)
+            .collect();
+        let resource = compress_resource(&data).unwrap();
+        // Four chunks, so three chunk table entries, the first of which is
+        // the size of the first chunk.
+        let first = LittleEndian::read_u32(&resource[0..4]) as usize;
+        assert_eq!(compress_chunk(&data[..CHUNK_SIZE]).unwrap(),
+                   &resource[12..(12 + first)]);
+        assert_eq!(decompress_resource(&resource, data.len() as u64)
+                       .unwrap(),
+                   data);
+        assert!(decompress_resource(&resource[..10], data.len() as u64)
+                    .is_err());
+        assert_eq!(compress_resource(&[]).unwrap(), Vec::<u8>::new());
+        assert_eq!(decompress_resource(&[], 0).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn large_resource_chunk_table() {
+        assert_eq!(entry_size(u32::MAX as u64), 4);
+        assert_eq!(entry_size(1 << 32), 8);
+        // A 5 GiB resource has 163839 eight-byte chunk table entries.
+        let error = decompress_resource(&[0; 1000], 5 << 30).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "WIM chunk table is truncated");
+    }
+}
+
+// ========================================================================= //
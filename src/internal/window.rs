@@ -0,0 +1,240 @@
+use internal::chm::ControlData;
+use internal::consts;
+use internal::error::Error;
+use std::convert::TryFrom;
+
+// ========================================================================= //
+
+/// One of the window sizes permitted by the LZXD format.
+///
+/// This can be converted into the `window` exponent taken by `Encoder::new`
+/// and `Decoder::new`, avoiding the easy mistake of passing the window's size
+/// in bytes instead.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum WindowSize {
+    /// A 32 KiB window.
+    Kb32,
+    /// A 64 KiB window.
+    Kb64,
+    /// A 128 KiB window.
+    Kb128,
+    /// A 256 KiB window.
+    Kb256,
+    /// A 512 KiB window.
+    Kb512,
+    /// A 1 MiB window.
+    Mb1,
+    /// A 2 MiB window.
+    Mb2,
+}
+
+pub const ALL_WINDOW_SIZES: [WindowSize; 7] = [WindowSize::Kb32,
+                                               WindowSize::Kb64,
+                                               WindowSize::Kb128,
+                                               WindowSize::Kb256,
+                                               WindowSize::Kb512,
+                                               WindowSize::Mb1,
+                                               WindowSize::Mb2];
+
+/// The bits of a cabinet's `typeCompress` field giving the compression type.
+const CAB_TYPE_MASK: u16 = 0x000f;
+
+/// The compression type for LZX in a cabinet's `typeCompress` field.
+const CAB_TYPE_LZX: u16 = 0x0003;
+
+/// The bits of a cabinet's `typeCompress` field giving the LZX window
+/// exponent.
+const CAB_WINDOW_MASK: u16 = 0x1f00;
+
+impl WindowSize {
+    /// Returns the window size whose size in bytes is `2^exponent`, if the
+    /// exponent is between `WINDOW_MIN` and `WINDOW_MAX` (inclusive).
+    pub fn from_exponent(exponent: u16) -> Option<WindowSize> {
+        if (consts::WINDOW_MIN..=consts::WINDOW_MAX).contains(&exponent) {
+            Some(ALL_WINDOW_SIZES[(exponent - consts::WINDOW_MIN) as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the base-2 logarithm of the window's size in bytes, as taken
+    /// by the `window` argument of `Encoder::new` and `Decoder::new`.
+    pub fn exponent(self) -> u16 {
+        consts::WINDOW_MIN +
+            ALL_WINDOW_SIZES.iter().position(|&size| size == self).unwrap() as
+                u16
+    }
+
+    /// Returns the window's size in bytes.
+    pub fn bytes(self) -> usize { 1 << self.exponent() }
+
+    /// Returns the window size that is exactly `bytes` bytes, if there is
+    /// one.  This is useful for container formats (such as CHM or WIM) that
+    /// store the window size in bytes rather than as an exponent.
+    pub fn from_bytes(bytes: usize) -> Option<WindowSize> {
+        ALL_WINDOW_SIZES.iter().cloned().find(|size| size.bytes() == bytes)
+    }
+
+    /// Returns the window size given by the `typeCompress` field of a
+    /// cabinet's CFFOLDER entry, if the folder is LZX-compressed.  The
+    /// compression type is in the low four bits (3 for LZX), and the window
+    /// exponent is in bits 8 through 12 (so `0x1503` is a 2 MiB window).
+    pub fn for_cab_type_compress(type_compress: u16) -> Option<WindowSize> {
+        if type_compress & CAB_TYPE_MASK != CAB_TYPE_LZX {
+            return None;
+        }
+        WindowSize::from_exponent((type_compress & CAB_WINDOW_MASK) >> 8)
+    }
+
+    /// Returns the `typeCompress` value for an LZX-compressed cabinet folder
+    /// with this window size (the inverse of `for_cab_type_compress`).
+    pub fn cab_type_compress(self) -> u16 {
+        (self.exponent() << 8) | CAB_TYPE_LZX
+    }
+
+    /// Returns the window size given by a CHM file's LZXC control data (the
+    /// contents of its `ControlData` file), taking into account that newer
+    /// versions give the size in units of 32 KiB rather than bytes.
+    pub fn for_chm(control_data: &[u8]) -> Option<WindowSize> {
+        let control_data = ControlData::parse(control_data).ok()?;
+        WindowSize::from_bytes(control_data.window_size as usize)
+    }
+
+    /// Returns the window size used for a WIM resource with the given chunk
+    /// size: the smallest window that holds a whole chunk (32 KiB for the
+    /// usual chunk size).
+    pub fn for_wim_chunk_size(chunk_size: u32) -> Option<WindowSize> {
+        let bytes = chunk_size.checked_next_power_of_two()? as usize;
+        WindowSize::from_bytes(bytes.max(WindowSize::Kb32.bytes()))
+    }
+}
+
+impl From<WindowSize> for u16 {
+    fn from(size: WindowSize) -> u16 { size.exponent() }
+}
+
+impl TryFrom<u32> for WindowSize {
+    type Error = Error;
+
+    /// Converts a window size in bytes into a `WindowSize`.  The error for
+    /// an unsupported size lists the valid sizes.
+    fn try_from(bytes: u32) -> Result<WindowSize, Error> {
+        WindowSize::from_bytes(bytes as usize).ok_or_else(|| {
+            let valid: Vec<String> = ALL_WINDOW_SIZES
+                .iter()
+                .map(|size| size.bytes().to_string())
+                .collect();
+            Error::InvalidInput(format!("Invalid LZX window size ({} bytes); \
+                                         must be one of {}",
+                                        bytes,
+                                        valid.join(", ")))
+        })
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::WindowSize;
+    use internal::chm::ControlData;
+    use internal::consts::{WINDOW_MAX, WINDOW_MIN};
+    use internal::error::Error;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn exponent_round_trip() {
+        for exponent in WINDOW_MIN..(WINDOW_MAX + 1) {
+            let size = WindowSize::from_exponent(exponent).unwrap();
+            assert_eq!(size.exponent(), exponent);
+            assert_eq!(u16::from(size), exponent);
+        }
+        assert_eq!(WindowSize::from_exponent(WINDOW_MIN - 1), None);
+        assert_eq!(WindowSize::from_exponent(WINDOW_MAX + 1), None);
+        assert_eq!(WindowSize::from_exponent(32768), None);
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(WindowSize::Kb32.bytes(), 0x8000);
+        assert_eq!(WindowSize::Kb512.bytes(), 0x80000);
+        assert_eq!(WindowSize::Mb2.bytes(), 0x200000);
+    }
+
+    #[test]
+    fn from_bytes() {
+        assert_eq!(WindowSize::from_bytes(32768), Some(WindowSize::Kb32));
+        assert_eq!(WindowSize::from_bytes(0x100000), Some(WindowSize::Mb1));
+        assert_eq!(WindowSize::from_bytes(15), None);
+        assert_eq!(WindowSize::from_bytes(0x10001), None);
+        assert_eq!(WindowSize::try_from(0x20000u32), Ok(WindowSize::Kb128));
+        let error = WindowSize::try_from(40000u32).unwrap_err();
+        assert_eq!(error,
+                   Error::InvalidInput("Invalid LZX window size (40000 \
+                                        bytes); must be one of 32768, \
+                                        65536, 131072, 262144, 524288, \
+                                        1048576, 2097152"
+                                           .to_string()));
+    }
+
+    #[test]
+    fn for_cab_type_compress() {
+        assert_eq!(WindowSize::for_cab_type_compress(0x0f03),
+                   Some(WindowSize::Kb32));
+        assert_eq!(WindowSize::for_cab_type_compress(0x1503),
+                   Some(WindowSize::Mb2));
+        // Only the low four bits give the type, so the reserved bits
+        // between it and the window don't matter.
+        assert_eq!(WindowSize::for_cab_type_compress(0x1073),
+                   Some(WindowSize::Kb64));
+        // MSZIP and Quantum folders have no LZX window.
+        assert_eq!(WindowSize::for_cab_type_compress(0x0001), None);
+        assert_eq!(WindowSize::for_cab_type_compress(0x1002), None);
+        assert_eq!(WindowSize::for_cab_type_compress(0x0e03), None);
+        assert_eq!(WindowSize::for_cab_type_compress(0x1603), None);
+        for exponent in WINDOW_MIN..(WINDOW_MAX + 1) {
+            let size = WindowSize::from_exponent(exponent).unwrap();
+            let type_compress = size.cab_type_compress();
+            assert_eq!(WindowSize::for_cab_type_compress(type_compress),
+                       Some(size));
+        }
+    }
+
+    #[test]
+    fn for_chm() {
+        for &size in &[WindowSize::Kb32, WindowSize::Kb128, WindowSize::Mb2] {
+            let control_data = ControlData {
+                window_size: size.bytes() as u32,
+                reset_interval: 0x10000,
+            };
+            let bytes = control_data.to_bytes();
+            assert_eq!(WindowSize::for_chm(&bytes), Some(size));
+        }
+        // Version 1 gives the window size in bytes.
+        let mut bytes = ControlData {
+                            window_size: 0x10000,
+                            reset_interval: 0x10000,
+                        }
+                        .to_bytes();
+        bytes[8] = 1;
+        bytes[16..20].copy_from_slice(&0x10000u32.to_le_bytes());
+        assert_eq!(WindowSize::for_chm(&bytes), Some(WindowSize::Kb64));
+        assert_eq!(WindowSize::for_chm(b"not LZXC control data"), None);
+    }
+
+    #[test]
+    fn for_wim_chunk_size() {
+        assert_eq!(WindowSize::for_wim_chunk_size(32768),
+                   Some(WindowSize::Kb32));
+        assert_eq!(WindowSize::for_wim_chunk_size(4096),
+                   Some(WindowSize::Kb32));
+        assert_eq!(WindowSize::for_wim_chunk_size(0x40000),
+                   Some(WindowSize::Kb256));
+        assert_eq!(WindowSize::for_wim_chunk_size(0x30000),
+                   Some(WindowSize::Kb256));
+        assert_eq!(WindowSize::for_wim_chunk_size(0x400000), None);
+        assert_eq!(WindowSize::for_wim_chunk_size(u32::MAX), None);
+    }
+}
+
+// ========================================================================= //
@@ -0,0 +1,359 @@
+//! Support for the LZX streams produced by Xbox 360 XMemCompress/XCompress.
+//!
+//! These streams use the same bitstream as other LZXD streams (with E8
+//! translation disabled), but frame each chunk differently: the compressed
+//! size comes first as a big-endian `u16`, and a chunk with fewer than
+//! `CHUNK_SIZE` uncompressed bytes (normally only the last one) is preceded
+//! by a `0xFF` byte and its big-endian uncompressed size.  By convention,
+//! these streams use a 64 KiB window (`DEFAULT_WINDOW`).
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use internal::consts;
+use internal::decoder::Decoder;
+use internal::encoder::Encoder;
+use internal::error;
+use std::io::{self, Read, Write};
+
+// ========================================================================= //
+
+/// The window exponent conventionally used for XMemCompress streams.
+pub const DEFAULT_WINDOW: u16 = 16;
+
+/// The first byte of a frame header that gives an explicit uncompressed size.
+const EXPLICIT_SIZE_MARKER: u8 = 0xff;
+
+// ========================================================================= //
+
+/// Reads XMemCompress frames, and presents their data as an LZXD stream with
+/// a size prefix before each chunk.
+struct FrameReader<R: Read> {
+    reader: R,
+    uncompressed_bytes_remaining: u64,
+    chunk: Vec<u8>,
+    position: usize,
+    num_frames: u64,
+}
+
+impl<R: Read> FrameReader<R> {
+    fn new(reader: R, uncompressed_size: u64) -> FrameReader<R> {
+        FrameReader {
+            reader,
+            uncompressed_bytes_remaining: uncompressed_size,
+            chunk: Vec::new(),
+            position: 0,
+            num_frames: 0,
+        }
+    }
+
+    /// Reads the next frame, returning false if there are no more frames
+    /// (either at end-of-file or at a frame with a size of zero).
+    fn read_chunk(&mut self) -> io::Result<bool> {
+        self.chunk.clear();
+        self.position = 0;
+        let first = match self.read_first_byte()? {
+            Some(byte) => byte,
+            None => return Ok(false),
+        };
+        let (uncompressed_size, compressed_size) =
+            if first == EXPLICIT_SIZE_MARKER {
+                let mut header = [0u8; 4];
+                self.reader
+                    .read_exact(&mut header)
+                    .map_err(error::eof_as_truncated)?;
+                (BigEndian::read_u16(&header[0..2]) as usize,
+                 BigEndian::read_u16(&header[2..4]))
+            } else {
+                let mut second = [0u8; 1];
+                self.reader
+                    .read_exact(&mut second)
+                    .map_err(error::eof_as_truncated)?;
+                (consts::CHUNK_SIZE, BigEndian::read_u16(&[first, second[0]]))
+            };
+        if uncompressed_size == 0 || compressed_size == 0 {
+            return Ok(false);
+        }
+        let expected = self.uncompressed_bytes_remaining
+            .min(consts::CHUNK_SIZE as u64) as usize;
+        if uncompressed_size != expected {
+            invalid_data!("Unsupported XMem frame size ({} bytes, expected \
+                           {})",
+                          uncompressed_size,
+                          expected);
+        }
+        self.uncompressed_bytes_remaining -= expected as u64;
+        self.chunk.resize(2 + compressed_size as usize, 0);
+        LittleEndian::write_u16(&mut self.chunk[0..2], compressed_size);
+        self.reader
+            .read_exact(&mut self.chunk[2..])
+            .map_err(error::eof_as_truncated)?;
+        self.num_frames += 1;
+        Ok(true)
+    }
+
+    /// Reads the first byte of a frame header, returning `None` if the
+    /// reader is already at end-of-file.
+    fn read_first_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for FrameReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() && !self.read_chunk()? {
+            return Ok(0);
+        }
+        let num_bytes = buf.len().min(self.chunk.len() - self.position);
+        let end = self.position + num_bytes;
+        buf[..num_bytes].copy_from_slice(&self.chunk[self.position..end]);
+        self.position = end;
+        Ok(num_bytes)
+    }
+}
+
+// ========================================================================= //
+
+/// Takes an LZXD stream, and writes each chunk of it as an XMemCompress
+/// frame.
+struct FrameWriter<W: Write> {
+    writer: W,
+    uncompressed_bytes_remaining: u64,
+    chunk: Vec<u8>,
+    num_frames: u64,
+}
+
+impl<W: Write> FrameWriter<W> {
+    fn new(writer: W, uncompressed_size: u64) -> FrameWriter<W> {
+        FrameWriter {
+            writer,
+            uncompressed_bytes_remaining: uncompressed_size,
+            chunk: Vec::new(),
+            num_frames: 0,
+        }
+    }
+
+    /// Returns the number of bytes still needed to complete the size prefix
+    /// or data of the current chunk.
+    fn bytes_needed(&self) -> usize {
+        if self.chunk.len() < 2 {
+            2 - self.chunk.len()
+        } else {
+            2 + LittleEndian::read_u16(&self.chunk[0..2]) as usize -
+                self.chunk.len()
+        }
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let uncompressed_size =
+            self.uncompressed_bytes_remaining.min(consts::CHUNK_SIZE as u64);
+        self.uncompressed_bytes_remaining -= uncompressed_size;
+        let data = &self.chunk[2..];
+        if uncompressed_size != consts::CHUNK_SIZE as u64 {
+            let mut header = [EXPLICIT_SIZE_MARKER, 0, 0];
+            BigEndian::write_u16(&mut header[1..3], uncompressed_size as u16);
+            self.writer.write_all(&header)?;
+        }
+        self.writer.write_all(&(data.len() as u16).to_be_bytes())?;
+        self.writer.write_all(data)?;
+        self.chunk.clear();
+        self.num_frames += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes_written = 0;
+        while bytes_written < buf.len() {
+            let num_bytes =
+                self.bytes_needed().min(buf.len() - bytes_written);
+            let end = bytes_written + num_bytes;
+            self.chunk.extend_from_slice(&buf[bytes_written..end]);
+            bytes_written = end;
+            if self.chunk.len() > 2 && self.bytes_needed() == 0 {
+                self.write_frame()?;
+            }
+        }
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.writer.flush() }
+}
+
+// ========================================================================= //
+
+/// Decompresses an XMemCompress LZX stream.
+pub struct XmemDecoder<R: Read> {
+    decoder: Decoder<FrameReader<R>>,
+}
+
+impl<R: Read> XmemDecoder<R> {
+    /// Starts decoding a stream with the conventional 64 KiB window, given
+    /// its total uncompressed size.
+    pub fn new(reader: R, uncompressed_size: u64)
+               -> io::Result<XmemDecoder<R>> {
+        XmemDecoder::with_window(reader, DEFAULT_WINDOW, uncompressed_size)
+    }
+
+    /// Starts decoding a stream that was compressed with the given window
+    /// exponent.
+    pub fn with_window(reader: R, window: u16, uncompressed_size: u64)
+                       -> io::Result<XmemDecoder<R>> {
+        let reader = FrameReader::new(reader, uncompressed_size);
+        Ok(XmemDecoder {
+               decoder: Decoder::new(reader, window, uncompressed_size)?,
+           })
+    }
+
+    /// Returns the number of frames read so far.
+    pub fn num_frames(&self) -> u64 { self.decoder.get_ref().num_frames }
+
+    /// Unwraps this object, returning the underlying reader.  If all of the
+    /// stream's data has been read, the reader is positioned just after its
+    /// last frame.
+    pub fn into_inner(self) -> io::Result<R> {
+        Ok(self.decoder.into_inner()?.reader)
+    }
+}
+
+impl<R: Read> Read for XmemDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+// ========================================================================= //
+
+/// Compresses data into an XMemCompress LZX stream.
+pub struct XmemEncoder<W: Write> {
+    encoder: Encoder<FrameWriter<W>>,
+}
+
+impl<W: Write> XmemEncoder<W> {
+    /// Starts encoding a stream with the conventional 64 KiB window and the
+    /// given total uncompressed size.
+    pub fn new(writer: W, uncompressed_size: u64)
+               -> io::Result<XmemEncoder<W>> {
+        XmemEncoder::with_window(writer, DEFAULT_WINDOW, uncompressed_size)
+    }
+
+    /// Starts encoding a stream with the given window exponent.
+    pub fn with_window(writer: W, window: u16, uncompressed_size: u64)
+                       -> io::Result<XmemEncoder<W>> {
+        let writer = FrameWriter::new(writer, uncompressed_size);
+        Ok(XmemEncoder {
+               encoder: Encoder::new(writer, window, uncompressed_size)?,
+           })
+    }
+
+    /// Returns the number of frames written so far.
+    pub fn num_frames(&self) -> u64 { self.encoder.get_ref().num_frames }
+
+    /// Finishes the stream, returning the total number of uncompressed bytes
+    /// that were encoded.  Returns an error if fewer bytes were written than
+    /// were declared.
+    pub fn finish(&mut self) -> io::Result<u64> { self.encoder.finish() }
+
+    /// Finishes the stream (as with `finish()`) and returns the underlying
+    /// writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        let writer = self.encoder.into_inner()?;
+        debug_assert!(writer.chunk.is_empty());
+        Ok(writer.writer)
+    }
+}
+
+impl<W: Write> Write for XmemEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.encoder.flush() }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{DEFAULT_WINDOW, XmemDecoder, XmemEncoder};
+    use byteorder::{BigEndian, ByteOrder, LittleEndian};
+    use internal::encoder::compress;
+    use std::io::{self, Read, Write};
+
+    fn encode(data: &[u8]) -> Vec<u8> {
+        let mut encoder = XmemEncoder::new(Vec::new(), data.len() as u64)
+            .unwrap();
+        encoder.write_all(data).unwrap();
+        assert_eq!(encoder.finish().unwrap(), data.len() as u64);
+        encoder.into_inner().unwrap()
+    }
+
+    fn decode(stream: &[u8], uncompressed_size: u64) -> io::Result<Vec<u8>> {
+        let mut decoder = XmemDecoder::new(stream, uncompressed_size)?;
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let data = b"Xbox 360 game asset data. ".repeat(3000);
+        let stream = encode(&data);
+        // Full frames start with a big-endian compressed size, and the last
+        // (partial) frame with 0xFF and its big-endian uncompressed size.
+        let mut lzxd = Vec::new();
+        let mut rest = stream.as_slice();
+        for index in 0..3 {
+            if index < 2 {
+                assert_ne!(rest[0], 0xff);
+            } else {
+                assert_eq!(rest[0], 0xff);
+                assert_eq!(BigEndian::read_u16(&rest[1..3]) as usize,
+                           data.len() % 0x8000);
+                rest = &rest[3..];
+            }
+            let size = BigEndian::read_u16(&rest[0..2]) as usize;
+            lzxd.extend_from_slice(&(size as u16).to_le_bytes());
+            lzxd.extend_from_slice(&rest[2..(2 + size)]);
+            rest = &rest[(2 + size)..];
+        }
+        assert!(rest.is_empty());
+        assert_eq!(lzxd, compress(&data, DEFAULT_WINDOW).unwrap());
+        let mut decoder = XmemDecoder::new(stream.as_slice(),
+                                           data.len() as u64)
+            .unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+        assert_eq!(decoder.num_frames(), 3);
+        assert!(decoder.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn explicit_frame_sizes() {
+        // A full frame may still be given an explicit size, and a frame with
+        // a size of zero ends the stream.
+        let data = b"Explicit frame size. ".repeat(2000);
+        let lzxd = compress(&data[..0x8000], DEFAULT_WINDOW).unwrap();
+        let size = LittleEndian::read_u16(&lzxd[0..2]);
+        let mut stream = vec![0xff, 0x80, 0x00];
+        stream.extend_from_slice(&size.to_be_bytes());
+        stream.extend_from_slice(&lzxd[2..]);
+        stream.extend_from_slice(&[0, 0]);
+        assert!(decode(&stream, 0x8000).unwrap() == data[..0x8000]);
+        // A short frame in the middle of the stream isn't supported.
+        let stream = [0xff, 0x10, 0x00, 0x00, 0x02, 0x00, 0x00];
+        let error = decode(&stream, 0x9000).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+// ========================================================================= //
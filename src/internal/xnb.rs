@@ -0,0 +1,221 @@
+//! Support for reading the LZX-compressed content of XNA (XNB) asset files,
+//! as used by XNA, MonoGame and FNA.
+//!
+//! An XNB file starts with a short header giving its target platform, format
+//! version, flags and total size.  If the header's compression flag is set,
+//! it also gives the decompressed size of the content, and the content that
+//! follows is an XMemCompress stream (see the `xmem` module) with a 64 KiB
+//! window.
+
+use byteorder::{ByteOrder, LittleEndian};
+use internal::error;
+use internal::xmem::{self, XmemDecoder};
+use std::io::{self, Read, Take};
+
+// ========================================================================= //
+
+const MAGIC: &[u8] = b"XNB";
+
+/// The size of the header of an uncompressed XNB file.
+const HEADER_SIZE: u32 = 10;
+
+/// The header flag for content compressed with LZX.
+const FLAG_COMPRESSED_LZX: u8 = 0x80;
+
+/// The header flag for content compressed with LZ4 (a MonoGame extension).
+const FLAG_COMPRESSED_LZ4: u8 = 0x40;
+
+// ========================================================================= //
+
+/// The header of an XNB file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct XnbHeader {
+    /// The target platform identifier (e.g. `b'w'` for Windows).
+    pub platform: u8,
+    /// The XNB format version (5 for XNA 4.0).
+    pub version: u8,
+    /// The header flags.
+    pub flags: u8,
+    /// The total size of the file, including the header.
+    pub file_size: u32,
+    /// The size of the decompressed content, if the content is compressed.
+    pub decompressed_size: Option<u32>,
+}
+
+impl XnbHeader {
+    /// Reads an XNB header from the start of a file.
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<XnbHeader> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        reader.read_exact(&mut header).map_err(error::eof_as_truncated)?;
+        if &header[0..3] != MAGIC {
+            invalid_data!("Not an XNB file");
+        }
+        let flags = header[5];
+        if flags & FLAG_COMPRESSED_LZ4 != 0 {
+            invalid_data!("LZ4-compressed XNB files are not supported");
+        }
+        let decompressed_size = if flags & FLAG_COMPRESSED_LZX != 0 {
+            let mut size = [0u8; 4];
+            reader.read_exact(&mut size).map_err(error::eof_as_truncated)?;
+            Some(LittleEndian::read_u32(&size))
+        } else {
+            None
+        };
+        let header = XnbHeader {
+            platform: header[3],
+            version: header[4],
+            flags,
+            file_size: LittleEndian::read_u32(&header[6..10]),
+            decompressed_size,
+        };
+        if header.file_size < header.header_size() {
+            invalid_data!("Invalid XNB file size ({})", header.file_size);
+        }
+        Ok(header)
+    }
+
+    /// Returns true if the file's content is LZX-compressed.
+    pub fn is_compressed(&self) -> bool { self.decompressed_size.is_some() }
+
+    /// Returns the size of the header, in bytes.
+    pub fn header_size(&self) -> u32 {
+        if self.is_compressed() {
+            HEADER_SIZE + 4
+        } else {
+            HEADER_SIZE
+        }
+    }
+
+    /// Returns the size of the file's content once decompressed.
+    pub fn content_size(&self) -> u32 {
+        self.decompressed_size
+            .unwrap_or(self.file_size - self.header_size())
+    }
+}
+
+// ========================================================================= //
+
+enum Content<R: Read> {
+    Stored(Take<R>),
+    Compressed(Box<XmemDecoder<Take<R>>>),
+}
+
+/// Reads the content of an XNB file, decompressing it if necessary.
+pub struct XnbDecoder<R: Read> {
+    header: XnbHeader,
+    content: Content<R>,
+}
+
+impl<R: Read> XnbDecoder<R> {
+    /// Reads the header of an XNB file from `reader`, and starts reading its
+    /// content.
+    pub fn new(mut reader: R) -> io::Result<XnbDecoder<R>> {
+        let header = XnbHeader::read(&mut reader)?;
+        let data_size = header.file_size - header.header_size();
+        let data = reader.take(data_size as u64);
+        let content = match header.decompressed_size {
+            Some(size) => {
+                let window = xmem::DEFAULT_WINDOW;
+                let decoder =
+                    XmemDecoder::with_window(data, window, size as u64)?;
+                Content::Compressed(Box::new(decoder))
+            }
+            None => Content::Stored(data),
+        };
+        Ok(XnbDecoder { header, content })
+    }
+
+    /// Returns the file's header.
+    pub fn header(&self) -> &XnbHeader { &self.header }
+
+    /// Unwraps this object, returning the underlying reader.
+    pub fn into_inner(self) -> io::Result<R> {
+        Ok(match self.content {
+               Content::Stored(data) => data.into_inner(),
+               Content::Compressed(decoder) => decoder.into_inner()?
+                   .into_inner(),
+           })
+    }
+}
+
+impl<R: Read> Read for XnbDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.content {
+            Content::Stored(ref mut data) => data.read(buf),
+            Content::Compressed(ref mut decoder) => decoder.read(buf),
+        }
+    }
+}
+
+// ========================================================================= //
+
+#[cfg(test)]
+mod tests {
+    use super::{XnbDecoder, XnbHeader};
+    use internal::xmem::XmemEncoder;
+    use std::io::{self, Read, Write};
+
+    fn xnb_file(flags: u8, content: &[u8], decompressed_size: Option<u32>)
+                -> Vec<u8> {
+        let header_size = if decompressed_size.is_some() { 14 } else { 10 };
+        let mut file = b"XNBw\x05".to_vec();
+        file.push(flags);
+        let file_size = (header_size + content.len()) as u32;
+        file.extend_from_slice(&file_size.to_le_bytes());
+        if let Some(size) = decompressed_size {
+            file.extend_from_slice(&size.to_le_bytes());
+        }
+        file.extend_from_slice(content);
+        file
+    }
+
+    #[test]
+    fn read_compressed_content() {
+        let content = b"Texture2D content for an XNB asset. ".repeat(2000);
+        let mut encoder = XmemEncoder::new(Vec::new(), content.len() as u64)
+            .unwrap();
+        encoder.write_all(&content).unwrap();
+        let compressed = encoder.into_inner().unwrap();
+        let mut file = xnb_file(0x81, &compressed, Some(content.len() as u32));
+        file.extend_from_slice(b"trailing");
+        let mut decoder = XnbDecoder::new(file.as_slice()).unwrap();
+        assert_eq!(decoder.header().platform, b'w');
+        assert_eq!(decoder.header().version, 5);
+        assert!(decoder.header().is_compressed());
+        assert_eq!(decoder.header().content_size(), content.len() as u32);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == content);
+        assert_eq!(decoder.into_inner().unwrap(), b"trailing");
+    }
+
+    #[test]
+    fn read_uncompressed_content() {
+        let file = xnb_file(0, b"raw content", None);
+        let mut decoder = XnbDecoder::new(file.as_slice()).unwrap();
+        assert!(!decoder.header().is_compressed());
+        assert_eq!(decoder.header().content_size(), 11);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"raw content");
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let mut file = xnb_file(0, b"", None);
+        file[2] = b'A';
+        let error = XnbHeader::read(&mut file.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let file = xnb_file(0x40, b"lz4", Some(10));
+        let error = XnbHeader::read(&mut file.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let mut file = xnb_file(0, b"", None);
+        file[6] = 4;
+        let error = XnbHeader::read(&mut file.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = XnbHeader::read(&mut &b"XNBw"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
+
+// ========================================================================= //
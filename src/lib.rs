@@ -6,11 +6,56 @@
 #![warn(missing_docs)]
 
 extern crate byteorder;
+#[cfg(feature = "futures")]
+extern crate futures_io;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
 mod internal;
 
-pub use internal::consts::{WINDOW_MAX, WINDOW_MIN};
-pub use internal::decoder::Decoder;
-pub use internal::encoder::Encoder;
+#[cfg(any(feature = "futures", feature = "tokio"))]
+pub use internal::asyncio::{AsyncDecoder, AsyncEncoder};
+#[cfg(feature = "bitio")]
+pub use internal::bitio;
+pub use internal::background::BackgroundDecoder;
+pub use internal::btype::BlockType;
+pub use internal::cab;
+pub use internal::chm;
+pub use internal::builder::{DecoderBuilder, EncoderBuilder};
+pub use internal::chunk::{BlockInfo, ChunkIndex, ChunkInfo};
+pub use internal::codec::{Codec, LzxdCodec};
+pub use internal::consts::{CHUNK_SIZE, MAX_BLOCK_SIZE, MAX_DELTA_MATCH,
+                          MAX_MATCH, MIN_MATCH, WINDOW_MAX, WINDOW_MAX_DELTA,
+                          WINDOW_MIN, WINDOW_MIN_NONSTANDARD};
+pub use internal::decoder::{Decoder, DecoderBuffer, DecoderState, decompress,
+                            decompress_into, decompress_to_writer};
+pub use internal::encoder::{Encoder, compress, compress_bound,
+                            compress_from_reader, compress_into,
+                            compressed_size};
+pub use internal::error::Error;
+#[cfg(feature = "ffi")]
+pub use internal::ffi;
+pub use internal::fixed::{FixedWindowDecoder, FixedWindowEncoder};
+pub use internal::matcher::MatchParams;
+pub use internal::oab;
+#[cfg(feature = "rayon")]
+pub use internal::parallel::{compress_parallel, decompress_parallel};
+pub use internal::patch::{apply_patch, apply_patch_to_writer, create_patch,
+                          patch_window};
+pub use internal::reader::EncoderReader;
+pub use internal::sink::DecodeSink;
+pub use internal::stats::EncoderStats;
+pub use internal::token::Token;
+pub use internal::transcode::transcode;
+pub use internal::wim;
+pub use internal::window::WindowSize;
+pub use internal::xmem;
+pub use internal::xnb;
 
 // ========================================================================= //